@@ -1,3 +1,4 @@
+use dispatcher::BalanceCheck;
 use std::fs::File;
 use std::io::Read;
 use toml;
@@ -13,6 +14,45 @@ pub struct Config {
     pub block_packet_tx_limit: usize,
     pub prof_start: u64,
     pub prof_duration: u64,
+    pub predict_height: Option<bool>,
+    pub dup_with_height: Option<bool>,
+    pub reject_during_reorg: Option<bool>,
+    pub balance_check: Option<BalanceCheck>,
+    pub max_future_height: Option<u64>,
+    /// Treat a valid_until_block of 0 as never expiring, rejected by default
+    pub zero_valid_until_never_expires: Option<bool>,
+    pub max_tx_data_size: Option<u64>,
+    /// Longest nonce accepted in bytes, 128 if not set
+    pub max_nonce_len: Option<usize>,
+    /// Reject txs without a nonce
+    pub require_nonce: Option<bool>,
+    /// Most txs in a block to verify, a larger block is rejected without recovering any signature;
+    /// at least `block_packet_tx_limit`, so proposals packed within it are never refused
+    pub max_block_verify_txs: Option<usize>,
+    /// Tx format versions accepted, any version if not set
+    pub accepted_tx_versions: Option<Vec<u32>>,
+    /// Rejected new txs remembered for `cita_getRejectedTransactions`
+    pub rejection_log_size: Option<usize>,
+    /// Latest answered verify requests the rejection rate is computed over, cached answers included
+    pub rejection_window: Option<usize>,
+    /// Threads recovering tx signatures, `tx_verify_thread_num` if not set
+    pub sig_recover_threads: Option<usize>,
+    /// Signers cached by tx hash, so a tx verified again isn't recovered again
+    pub signer_cache_size: Option<usize>,
+    pub txhashs_req_batch: Option<usize>,
+    /// Multisig accounts, one `[[multisig]]` table each
+    pub multisig: Option<Vec<MultisigConfig>>,
+    /// Hex encoded addresses txs may not be sent to, reloaded as the config file changes
+    pub recipient_blocklist: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct MultisigConfig {
+    /// Hex encoded public key standing for the account, its address is the sender of the account's txs
+    pub account: String,
+    pub threshold: usize,
+    /// Hex encoded public keys allowed to sign txs of the account
+    pub keys: Vec<String>,
 }
 
 impl Config {
@@ -24,6 +64,26 @@ impl Config {
             .expect("Failed to load auth config.");
         toml::from_str(&buffer).unwrap()
     }
+
+    /// Read the config again while running, errors are returned instead of panicking
+    pub fn reload(path: &str) -> Result<Self, String> {
+        let mut buffer = String::new();
+        File::open(path)
+            .and_then(|mut config_file| config_file.read_to_string(&mut buffer))
+            .map_err(|err| format!("failed to read {}: {}", path, err))?;
+        toml::from_str(&buffer).map_err(|err| format!("failed to parse {}: {}", path, err))
+    }
+
+    /// `max_block_verify_txs`, refused if a block packed within `block_packet_tx_limit` would exceed it
+    pub fn block_verify_txs_limit(&self) -> Result<Option<usize>, String> {
+        match self.max_block_verify_txs {
+            Some(max_txs) if max_txs < self.block_packet_tx_limit => Err(format!(
+                "max_block_verify_txs {} is below block_packet_tx_limit {}, valid proposals would be refused",
+                max_txs, self.block_packet_tx_limit
+            )),
+            max_txs => Ok(max_txs),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -55,4 +115,57 @@ mod tests {
         assert_eq!(0, value.prof_start);
         assert_eq!(0, value.prof_duration);
     }
+
+    #[test]
+    fn block_verify_txs_limit_covers_packed_blocks() {
+        let toml_str = r#"
+           count_per_batch = 30
+           buffer_duration = 3000000
+           tx_verify_thread_num = 10
+           tx_verify_num_per_thread = 300
+           proposal_tx_verify_num_per_thread = 30
+           tx_pool_limit = 50000
+           block_packet_tx_limit = 30000
+           prof_start = 0
+           prof_duration = 0
+        "#;
+        let mut value: Config = toml::from_str(toml_str).expect("read Error");
+        assert_eq!(value.block_verify_txs_limit(), Ok(None));
+        value.max_block_verify_txs = Some(30000);
+        assert_eq!(value.block_verify_txs_limit(), Ok(Some(30000)));
+        value.max_block_verify_txs = Some(29999);
+        assert!(value.block_verify_txs_limit().is_err());
+    }
+
+    #[test]
+    fn read_multisig_accounts() {
+        let toml_str = r#"
+           count_per_batch = 30
+           buffer_duration = 3000000
+           tx_verify_thread_num = 10
+           tx_verify_num_per_thread = 300
+           proposal_tx_verify_num_per_thread = 30
+           tx_pool_limit = 50000
+           block_packet_tx_limit = 30000
+           prof_start = 0
+           prof_duration = 0
+
+           [[multisig]]
+           account = "0x01"
+           threshold = 2
+           keys = ["0x02", "0x03", "0x04"]
+
+           [[multisig]]
+           account = "0x05"
+           threshold = 1
+           keys = ["0x06"]
+        "#;
+        let value: Config = toml::from_str(toml_str).expect("read Error");
+        let multisig = value.multisig.unwrap();
+        assert_eq!(multisig.len(), 2);
+        assert_eq!(multisig[0].account, "0x01");
+        assert_eq!(multisig[0].threshold, 2);
+        assert_eq!(multisig[0].keys.len(), 3);
+        assert_eq!(multisig[1].account, "0x05");
+    }
 }
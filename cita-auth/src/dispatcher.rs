@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crypto::{pubkey_to_address, PubKey};
 use error::ErrorCode;
 use jsonrpc_types::rpctypes::TxResponse;
 use libproto::{BatchRequest, Message, Request, Response, SubModules};
@@ -32,7 +33,7 @@ use std::thread;
 use std::time::SystemTime;
 use tx_pool;
 use txwal::TxWal;
-use util::{H256, ToPretty};
+use util::{Address, H256, ToPretty};
 use uuid::Uuid;
 
 pub struct Dispatcher {
@@ -47,6 +48,45 @@ pub struct Dispatcher {
     response_jsonrpc_cnt: u64,
     start_verify_time: SystemTime,
     add_to_pool_cnt: u64,
+    package_limit: usize,
+    current_height: u64,
+    predict_height: bool,
+    balance_check: BalanceCheck,
+    /// Account gas limits of the latest block, None until the first block arrives
+    account_gas_limit: Option<AccountGasLimit>,
+}
+
+/// What to do with a tx whose quota is more than its signer is allowed to use.
+/// CITA accounts don't hold a spendable balance, the account gas limit is what the
+/// signer can afford per block.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceCheck {
+    Off,
+    Warn,
+    Reject,
+}
+
+impl BalanceCheck {
+    /// Whether the tx may go into the pool
+    pub fn allows(&self, account_gas_limit: &AccountGasLimit, signer: &Address, quota: u64) -> bool {
+        if *self == BalanceCheck::Off {
+            return true;
+        }
+        let balance = account_gas_limit
+            .get_specific_gas_limit()
+            .get(&signer.hex())
+            .cloned()
+            .unwrap_or_else(|| account_gas_limit.get_common_gas_limit());
+        if quota <= balance {
+            return true;
+        }
+        warn!(
+            "signer {:?} can't afford quota {}, its account gas limit is {}",
+            signer, quota, balance
+        );
+        *self == BalanceCheck::Warn
+    }
 }
 
 pub struct BatchForwardInfo {
@@ -63,6 +103,8 @@ impl Dispatcher {
         count_per_batch: usize,
         buffer_duration: u32,
         wal_enable: bool,
+        predict_height: bool,
+        balance_check: BalanceCheck,
     ) -> Self {
         let batch_forward_info = BatchForwardInfo {
             count_per_batch: count_per_batch,
@@ -83,6 +125,11 @@ impl Dispatcher {
             response_jsonrpc_cnt: 0,
             start_verify_time: SystemTime::now(),
             add_to_pool_cnt: 0,
+            package_limit: package_limit,
+            current_height: 0,
+            predict_height: predict_height,
+            balance_check: balance_check,
+            account_gas_limit: None,
         };
         if wal_enable {
             let num = dispatch.read_tx_from_wal();
@@ -107,6 +154,17 @@ impl Dispatcher {
         }
     }
 
+    fn check_balance(&self, tx: &SignedTransaction) -> bool {
+        match self.account_gas_limit {
+            Some(ref account_gas_limit) => {
+                let signer = pubkey_to_address(&PubKey::from_slice(tx.get_signer()));
+                let quota = tx.get_transaction_with_sig().get_transaction().get_quota();
+                self.balance_check.allows(account_gas_limit, &signer, quota)
+            }
+            None => true,
+        }
+    }
+
     pub fn deal_tx(
         &mut self,
         submodule: SubModules,
@@ -116,7 +174,9 @@ impl Dispatcher {
         mq_pub: &Sender<(String, Vec<u8>)>,
     ) {
         let mut error_msg: Option<String> = None;
-        if self.add_tx_to_pool(tx) {
+        if !self.check_balance(tx) {
+            error_msg = Some(String::from("InsufficientBalance"));
+        } else if self.add_tx_to_pool(tx) {
             self.update_capacity();
         } else {
             error_msg = Some(String::from("Dup"));
@@ -130,6 +190,16 @@ impl Dispatcher {
                 response.set_code(ErrorCode::tx_auth_error());
                 response.set_error_msg(error_msg.unwrap());
             } else {
+                let tx_response = if self.predict_height {
+                    let pool_len = self.txs_pool.borrow().len();
+                    tx_response.set_predicted_height(predicted_height(
+                        self.current_height,
+                        pool_len,
+                        self.package_limit,
+                    ))
+                } else {
+                    tx_response
+                };
                 let tx_state = serde_json::to_string(&tx_response).unwrap();
                 response.set_tx_state(tx_state);
 
@@ -187,12 +257,16 @@ impl Dispatcher {
     ) {
         let mut block_txs = BlockTxs::new();
         let mut body = BlockBody::new();
+        self.current_height = height as u64;
 
         trace!("deal_txs inner txs height {} ", txs.len());
         if !txs.is_empty() {
             self.del_txs_from_pool_with_hash(txs);
         }
 
+        if self.balance_check != BalanceCheck::Off {
+            self.account_gas_limit = Some(account_gas_limit.clone());
+        }
         let out_txs = self.get_txs_from_pool(height as u64, block_gas_limit, account_gas_limit);
         info!(
             "public block txs height {} with {:?} txs on timestamp: {:?}",
@@ -338,3 +412,54 @@ impl Dispatcher {
         self.batch_forward_info.new_tx_request_buffer.clear();
     }
 }
+
+/// Estimate the height of the block that will package a transaction which
+/// has just entered the pool, assuming the pool is drained `package_limit`
+/// transactions per block in FIFO order.
+pub fn predicted_height(current_height: u64, pool_len: usize, package_limit: usize) -> u64 {
+    let blocks_ahead = if package_limit == 0 || pool_len == 0 {
+        0
+    } else {
+        (pool_len - 1) / package_limit
+    };
+    current_height + 1 + blocks_ahead as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{predicted_height, BalanceCheck};
+    use libproto::blockchain::AccountGasLimit;
+    use std::collections::HashMap;
+    use util::Address;
+
+    #[test]
+    fn predicted_height_is_after_current_height() {
+        assert_eq!(predicted_height(10, 0, 100), 11);
+        assert_eq!(predicted_height(10, 1, 100), 11);
+        assert_eq!(predicted_height(10, 100, 100), 11);
+        assert_eq!(predicted_height(10, 101, 100), 12);
+        assert_eq!(predicted_height(10, 1000, 0), 11);
+        for pool_len in 0..500 {
+            assert!(predicted_height(10, pool_len, 30) >= 11);
+        }
+    }
+
+    #[test]
+    fn balance_check_against_account_gas_limit() {
+        let rich = Address::from("0x1111111111111111111111111111111111111111");
+        let poor = Address::from("0x2222222222222222222222222222222222222222");
+        let mut specific = HashMap::new();
+        specific.insert(rich.hex(), 10_000);
+        let mut account_gas_limit = AccountGasLimit::new();
+        account_gas_limit.set_common_gas_limit(1_000);
+        account_gas_limit.set_specific_gas_limit(specific);
+
+        for check in &[BalanceCheck::Off, BalanceCheck::Warn, BalanceCheck::Reject] {
+            assert!(check.allows(&account_gas_limit, &rich, 5_000));
+            assert!(check.allows(&account_gas_limit, &poor, 1_000));
+        }
+        assert!(BalanceCheck::Off.allows(&account_gas_limit, &poor, 5_000));
+        assert!(BalanceCheck::Warn.allows(&account_gas_limit, &poor, 5_000));
+        assert!(!BalanceCheck::Reject.allows(&account_gas_limit, &poor, 5_000));
+    }
+}
@@ -16,9 +16,13 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use error::ErrorCode;
-use jsonrpc_types::rpctypes::TxResponse;
-use libproto::{Message, MsgClass, Response, Ret, SubModules, VerifyBlockResp, VerifyTxResp};
-use libproto::blockchain::{AccountGasLimit, SignedTransaction};
+use jsonrpc_types::bytes::Bytes;
+use jsonrpc_types::rpctypes::{RejectedTransaction, RejectionRate, Reverification, TxResponse, VerifyCacheStats,
+                              VerifyStats};
+use libproto::{Message, MsgClass, Response, Ret, SubModules, VerifyBlockResp, VerifyTxReq, VerifyTxResp};
+use libproto::blockchain::{AccountGasLimit, SignedTransaction, UnverifiedTransaction};
+use rejections::unix_millis;
+use serde_json;
 use std::collections::{HashMap, HashSet};
 use std::convert::{Into, TryFrom, TryInto};
 use std::sync::Arc;
@@ -27,9 +31,11 @@ use std::sync::mpsc::{Receiver, Sender};
 use std::time::SystemTime;
 use std::vec::*;
 use threadpool::ThreadPool;
-use util::{H256, RwLock};
+use util::{H256, RwLock, U256};
 use verifier::*;
 
+pub const REORG_IN_PROGRESS: &str = "Reorg in progress";
+
 pub fn process_flow_control_failed(
     mut verify_info: VerifyRequestResponseInfo,
     resp_sender: &Sender<VerifyRequestResponseInfo>,
@@ -53,16 +59,32 @@ pub fn verify_tx_group_service(
     let now = SystemTime::now();
     let len = req_grp.len();
 
-    while let Some(mut req_info) = req_grp.pop() {
-        if let VerifyRequestResponse::AuthRequest(req) = req_info.req_resp {
-            let tx_hash = H256::from_slice(req.get_tx_hash());
-            let response = { verifier.read().verfiy_tx(&req) };
-            {
-                cache.write().insert(tx_hash, response.clone());
-            }
-            req_info.req_resp = VerifyRequestResponse::AuthResponse(response);
-            resp_sender.send(req_info).unwrap();
+    let reqs: Vec<(VerifyTxReq, VerifyType)> = req_grp
+        .iter()
+        .filter_map(|req_info| match req_info.req_resp {
+            VerifyRequestResponse::AuthRequest(ref req) => Some((req.clone(), req_info.verify_type)),
+            _ => None,
+        })
+        .collect();
+    req_grp.retain(|req_info| match req_info.req_resp {
+        VerifyRequestResponse::AuthRequest(_) => true,
+        _ => false,
+    });
+    // Only the checks need the verifier, the signatures are recovered after it's released
+    let pending = { verifier.read().check_batch(&reqs) };
+    let responses = pending.recover(&reqs);
+
+    for (mut req_info, (response, signer_cached)) in req_grp.into_iter().zip(responses) {
+        verifier.read().count_outcome(req_info.verify_type, response.get_ret());
+        let tx_hash = H256::from_slice(response.get_tx_hash());
+        // A blocked recipient only fails a new tx, the tx is still valid in a block
+        if response.get_ret() != Ret::BlockedRecipient {
+            cache.write().insert(tx_hash, response.clone());
         }
+        req_info.dup_height = verifier.read().get_dup_height(&response);
+        req_info.signer_cached = signer_cached;
+        req_info.req_resp = VerifyRequestResponse::AuthResponse(response);
+        resp_sender.send(req_info).unwrap();
     }
 
     trace!(
@@ -85,28 +107,41 @@ pub fn check_verify_request_preprocess(
         let mut result = VerifyResult::VerifyNotBegin;
         let is_single_verify = req_info.verify_type == VerifyType::SingleVerify;
 
-        if is_single_verify
-            && !verifier
+        let valid_until_block = if is_single_verify {
+            verifier
                 .read()
                 .verify_valid_until_block(req.get_valid_until_block())
-        {
+        } else {
+            Ok(())
+        };
+        if let Err(ret) = valid_until_block {
             let mut response = VerifyTxResp::new();
             response.set_tx_hash(req.get_tx_hash().to_vec());
-            response.set_ret(Ret::InvalidUntilBlock);
+            response.set_ret(ret);
             processed = true;
             final_response = response;
-        } else if let Some(resp) = get_resp_from_cache(&tx_hash, cache.clone()) {
-            processed = true;
-            final_response = resp;
+        } else {
+            let cached = get_resp_from_cache(&tx_hash, cache.clone());
+            verifier
+                .read()
+                .note_cache_lookup(cached.is_some(), cache.read().len());
+            if let Some(resp) = cached {
+                processed = true;
+                final_response = resp;
+            }
         }
 
         if processed {
+            verifier
+                .read()
+                .count_outcome(req_info.verify_type, final_response.get_ret());
             match final_response.get_ret() {
                 Ret::OK => result = VerifyResult::VerifySucceeded,
                 _ => result = VerifyResult::VerifyFailed,
             }
             //only send result when the verify type is single
             if is_single_verify {
+                req_info.dup_height = verifier.read().get_dup_height(&final_response);
                 req_info.req_resp = VerifyRequestResponse::AuthResponse(final_response);
                 resp_sender.send(req_info).unwrap();
             }
@@ -117,6 +152,50 @@ pub fn check_verify_request_preprocess(
     }
 }
 
+/// Detailed outcome of a forced re-verification
+#[derive(Debug, Clone)]
+pub struct ReverifyReport {
+    pub tx_hash: H256,
+    /// Whether a result of the tx was already cached
+    pub cache_hit: bool,
+    /// Whether the signer of the tx was already cached
+    pub signer_cache_hit: bool,
+    /// The cached result, which was bypassed
+    pub cached_ret: Option<Ret>,
+    pub ret: Ret,
+    /// Signer recovered from the signature, if it could be
+    pub signer: Option<Vec<u8>>,
+}
+
+/// Verify the tx again with `Verifier::reverify_tx`, bypassing both the cached
+/// result and the cached signer, and replace both with the new ones. Not counted
+/// in the outcome stats, as no verify request is answered.
+pub fn force_reverify(
+    req: &VerifyTxReq,
+    verifier: &RwLock<Verifier>,
+    cache: &RwLock<HashMap<H256, VerifyTxResp>>,
+) -> ReverifyReport {
+    let tx_hash = H256::from_slice(req.get_tx_hash());
+    let cached_ret = cache.read().get(&tx_hash).map(|resp| resp.get_ret());
+    let (response, signer_cache_hit) = { verifier.read().reverify_tx(req, VerifyType::SingleVerify) };
+    let signer = if response.get_signer().is_empty() {
+        None
+    } else {
+        Some(response.get_signer().to_vec())
+    };
+    let report = ReverifyReport {
+        tx_hash: tx_hash,
+        cache_hit: cached_ret.is_some(),
+        signer_cache_hit: signer_cache_hit,
+        cached_ret: cached_ret,
+        ret: response.get_ret(),
+        signer: signer,
+    };
+    cache.write().insert(tx_hash, response);
+    info!("force re-verification of tx: {:?}", report);
+    report
+}
+
 fn get_resp_from_cache(tx_hash: &H256, cache: Arc<RwLock<HashMap<H256, VerifyTxResp>>>) -> Option<VerifyTxResp> {
     if let Some(resp) = cache.read().get(tx_hash) {
         Some(resp.clone())
@@ -163,6 +242,12 @@ pub fn handle_remote_msg(
                     .write()
                     .update_hashes(height, tx_hashes_in_h256.clone(), tx_pub);
             }
+            {
+                let guard = verifier.read();
+                if !guard.is_inited() {
+                    info!("verifier is initializing, estimated time left {:?}", guard.init_eta());
+                }
+            }
             let mut flag = true;
             if let Some(h) = verifier.read().get_height_latest() {
                 if height != h {
@@ -175,6 +260,9 @@ pub fn handle_remote_msg(
                     height,
                     tx_hashes_in_h256.len()
                 );
+                info!("verify cache stats: {:?}", verifier.read().cache_stats());
+                info!("verify outcome stats: {:?}", verifier.read().outcome_stats());
+                info!("verify rejection rate: {:.3}", verifier.read().rejection_rate());
                 let block_gas_limit = block_tx_hashes.get_block_gas_limit();
                 let account_gas_limit = block_tx_hashes.get_account_gas_limit().clone();
                 info!(
@@ -199,7 +287,15 @@ pub fn handle_remote_msg(
             );
             let tx_cnt = blkreq.get_reqs().len();
             let mut tx_need_verify = Vec::new();
-            if tx_cnt > 0 {
+            if verifier.read().is_too_many_block_txs(tx_cnt) {
+                warn!(
+                    "reject block verify request_id: {} from sub_module: {} with too many txs: {}",
+                    blkreq.get_id(),
+                    submodule,
+                    tx_cnt
+                );
+                publish_block_verification_result(blkreq.get_id(), Ret::TooManyTxs, tx_pub);
+            } else if tx_cnt > 0 {
                 let request_id = blkreq.get_id();
                 let new_block_verify_status = BlockVerifyStatus {
                     request_id: request_id,
@@ -234,6 +330,8 @@ pub fn handle_remote_msg(
                             time_stamp: now,
                             req_resp: VerifyRequestResponse::AuthRequest(req.clone()),
                             un_tx: None,
+                            dup_height: None,
+                            signer_cached: false,
                         };
                         let result = check_verify_request_preprocess(
                             verify_request_info,
@@ -277,6 +375,8 @@ pub fn handle_remote_msg(
                                     time_stamp: now,
                                     req_resp: VerifyRequestResponse::AuthRequest(req.clone()),
                                     un_tx: None,
+                                    dup_height: None,
+                                    signer_cached: false,
                                 };
                                 tx_need_verify.push(verify_request_info);
                             }
@@ -328,7 +428,28 @@ pub fn handle_remote_msg(
             }
         }
         MsgClass::Request(newtx_req) => {
-            if newtx_req.has_batch_req() {
+            if newtx_req.has_rejected_txs() {
+                publish_rejected_txs(newtx_req.get_request_id().to_vec(), &verifier.read(), tx_pub);
+            } else if newtx_req.has_rejection_rate() {
+                publish_rejection_rate(newtx_req.get_request_id().to_vec(), &verifier.read(), tx_pub);
+            } else if newtx_req.has_verify_stats() {
+                publish_verify_stats(newtx_req.get_request_id().to_vec(), &verifier.read(), tx_pub);
+            } else if newtx_req.has_reverify_tx() {
+                let req = new_tx_verify_req(newtx_req.get_reverify_tx());
+                let report = force_reverify(&req, &verifier, &cache);
+                publish_reverification(newtx_req.get_request_id().to_vec(), report, tx_pub);
+            } else if verifier.read().is_rejecting_new_tx() {
+                warn!("reject new tx from module:{} during reorg", submodule);
+                if submodule == SubModules::Jsonrpc {
+                    if newtx_req.has_batch_req() {
+                        for tx_req in newtx_req.get_batch_req().get_new_tx_requests() {
+                            publish_reorg_rejection(tx_req.get_request_id().to_vec(), tx_pub);
+                        }
+                    } else if newtx_req.has_un_tx() {
+                        publish_reorg_rejection(newtx_req.get_request_id().to_vec(), tx_pub);
+                    }
+                }
+            } else if newtx_req.has_batch_req() {
                 let batch_new_tx = newtx_req.get_batch_req().get_new_tx_requests();
                 let now = SystemTime::now();
                 trace!(
@@ -339,7 +460,7 @@ pub fn handle_remote_msg(
                 );
 
                 for tx_req in batch_new_tx.iter() {
-                    let verify_tx_req = tx_req.get_un_tx().tx_verify_req_msg();
+                    let verify_tx_req = new_tx_verify_req(tx_req.get_un_tx());
                     let verify_request_info = VerifyRequestResponseInfo {
                         sub_module: submodule,
                         verify_type: VerifyType::SingleVerify,
@@ -347,16 +468,19 @@ pub fn handle_remote_msg(
                         time_stamp: now,
                         req_resp: VerifyRequestResponse::AuthRequest(verify_tx_req),
                         un_tx: Some(tx_req.get_un_tx().clone()),
+                        dup_height: None,
+                        signer_cached: false,
                     };
                     tx_req_single.send(verify_request_info).unwrap();
                 }
             } else if newtx_req.has_un_tx() {
                 let now = SystemTime::now();
                 trace!(
-                    "get single new tx request from peer node with system time :{:?}",
+                    "get single new tx request from module:{} with system time :{:?}",
+                    submodule,
                     now
                 );
-                let verify_tx_req = newtx_req.get_un_tx().tx_verify_req_msg();
+                let verify_tx_req = new_tx_verify_req(newtx_req.get_un_tx());
                 let verify_request_info = VerifyRequestResponseInfo {
                     sub_module: submodule,
                     verify_type: VerifyType::SingleVerify,
@@ -364,6 +488,8 @@ pub fn handle_remote_msg(
                     time_stamp: now,
                     req_resp: VerifyRequestResponse::AuthRequest(verify_tx_req),
                     un_tx: Some(newtx_req.get_un_tx().clone()),
+                    dup_height: None,
+                    signer_cached: false,
                 };
 
                 tx_req_single.send(verify_request_info).unwrap();
@@ -373,11 +499,126 @@ pub fn handle_remote_msg(
     }
 }
 
+/// The verify request of a tx sent to the node, with the size of its data checked against
+/// `max_tx_data_size`
+fn new_tx_verify_req(un_tx: &UnverifiedTransaction) -> VerifyTxReq {
+    let mut req = un_tx.tx_verify_req_msg();
+    req.set_data_len(un_tx.get_transaction().get_data().len() as u64);
+    req
+}
+
+fn publish_reorg_rejection(request_id: Vec<u8>, tx_pub: &Sender<(String, Vec<u8>)>) {
+    let mut response = Response::new();
+    response.set_request_id(request_id);
+    response.set_code(ErrorCode::tx_auth_error());
+    response.set_error_msg(REORG_IN_PROGRESS.to_string());
+
+    let msg: Message = response.into();
+    tx_pub
+        .send(("auth.rpc".to_string(), msg.try_into().unwrap()))
+        .unwrap();
+}
+
+/// Answer `cita_getRejectedTransactions` with the rejection log
+fn publish_rejected_txs(request_id: Vec<u8>, verifier: &Verifier, tx_pub: &Sender<(String, Vec<u8>)>) {
+    let rejected: Vec<RejectedTransaction> = verifier
+        .rejected_txs()
+        .into_iter()
+        .map(|rejected| RejectedTransaction {
+            hash: rejected.tx_hash,
+            reason: format!("{:?}", rejected.ret),
+            timestamp: U256::from(unix_millis(rejected.rejected_at)),
+        })
+        .collect();
+    let mut response = Response::new();
+    response.set_request_id(request_id);
+    response.set_rejected_txs(serde_json::to_string(&rejected).unwrap());
+
+    let msg: Message = response.into();
+    tx_pub
+        .send(("auth.rpc".to_string(), msg.try_into().unwrap()))
+        .unwrap();
+}
+
+/// Answer `cita_getRejectionRate` with the share of the latest verified txs rejected
+fn publish_rejection_rate(request_id: Vec<u8>, verifier: &Verifier, tx_pub: &Sender<(String, Vec<u8>)>) {
+    let (rejected, total) = verifier.recent_rejections();
+    let rate = RejectionRate {
+        rejected: U256::from(rejected),
+        total: U256::from(total),
+        rate: verifier.rejection_rate(),
+    };
+    let mut response = Response::new();
+    response.set_request_id(request_id);
+    response.set_rejection_rate(serde_json::to_string(&rate).unwrap());
+
+    let msg: Message = response.into();
+    tx_pub
+        .send(("auth.rpc".to_string(), msg.try_into().unwrap()))
+        .unwrap();
+}
+
+/// Answer `cita_getVerifyStats` with how many verify requests got each outcome
+/// and how the verify result cache did, along with how long the verifier may still take to init
+fn publish_verify_stats(request_id: Vec<u8>, verifier: &Verifier, tx_pub: &Sender<(String, Vec<u8>)>) {
+    let outcomes = verifier.outcome_stats();
+    let cache = verifier.cache_stats();
+    let init_eta = verifier
+        .init_eta()
+        .map(|eta| U256::from(eta.as_secs() * 1000 + u64::from(eta.subsec_nanos() / 1_000_000)));
+    let stats = VerifyStats {
+        by_reason: outcomes
+            .by_ret
+            .iter()
+            .map(|(ret, count)| (format!("{:?}", ret), U256::from(*count)))
+            .collect(),
+        by_type: outcomes
+            .by_type
+            .iter()
+            .map(|(verify_type, count)| (format!("{:?}", verify_type), U256::from(*count)))
+            .collect(),
+        cache: VerifyCacheStats {
+            hits: U256::from(cache.hits),
+            misses: U256::from(cache.misses),
+            size: U256::from(cache.size),
+        },
+        init_eta: init_eta,
+    };
+    let mut response = Response::new();
+    response.set_request_id(request_id);
+    response.set_verify_stats(serde_json::to_string(&stats).unwrap());
+
+    let msg: Message = response.into();
+    tx_pub
+        .send(("auth.rpc".to_string(), msg.try_into().unwrap()))
+        .unwrap();
+}
+
+/// Answer `cita_reverifyTransaction` with what verifying the tx again found
+fn publish_reverification(request_id: Vec<u8>, report: ReverifyReport, tx_pub: &Sender<(String, Vec<u8>)>) {
+    let reverification = Reverification {
+        hash: report.tx_hash,
+        cached_reason: report.cached_ret.map(|ret| format!("{:?}", ret)),
+        signer_cached: report.signer_cache_hit,
+        reason: format!("{:?}", report.ret),
+        signer: report.signer.map(Bytes::from),
+    };
+    let mut response = Response::new();
+    response.set_request_id(request_id);
+    response.set_reverification(serde_json::to_string(&reverification).unwrap());
+
+    let msg: Message = response.into();
+    tx_pub
+        .send(("auth.rpc".to_string(), msg.try_into().unwrap()))
+        .unwrap();
+}
+
 pub fn handle_verificaton_result(
     result_receiver: &Receiver<VerifyRequestResponseInfo>,
     tx_pub: &Sender<(String, Vec<u8>)>,
     block_verify_status: Arc<RwLock<BlockVerifyStatus>>,
     tx_sender: &Sender<(SubModules, Vec<u8>, TxResponse, SignedTransaction)>,
+    verifier: &RwLock<Verifier>,
 ) {
     match result_receiver.recv() {
         Ok(verify_response_info) => {
@@ -417,14 +658,21 @@ pub fn handle_verificaton_result(
                                     ));
                                     trace!("Send singed tx to txpool");
                                 }
-                                _ => {
+                                ret => {
+                                    verifier.read().note_rejected(tx_hash, ret);
                                     if verify_response_info.sub_module == SubModules::Jsonrpc {
                                         let tx_response = TxResponse::new(tx_hash, result);
 
                                         let mut response = Response::new();
                                         response.set_request_id(request_id);
-                                        response.set_code(ErrorCode::tx_auth_error());
-                                        response.set_error_msg(tx_response.status);
+                                        if let Some(height) = verify_response_info.dup_height {
+                                            // Point the client to the block which already includes the tx
+                                            let tx_response = tx_response.set_block_number(height);
+                                            response.set_tx_state(serde_json::to_string(&tx_response).unwrap());
+                                        } else {
+                                            response.set_code(ErrorCode::tx_auth_error());
+                                            response.set_error_msg(tx_response.status);
+                                        }
 
                                         trace!("response new tx {:?}", response);
                                         let msg: Message = response.into();
@@ -460,6 +708,9 @@ pub fn handle_verificaton_result(
                                         resp.get_tx_hash()
                                     );
                                     block_verify_status_guard.verify_success_cnt_capture += 1;
+                                    if verify_response_info.signer_cached {
+                                        block_verify_status_guard.cache_hit += 1;
+                                    }
                                     if block_verify_status_guard.verify_success_cnt_capture
                                         == block_verify_status_guard.verify_success_cnt_required
                                     {
@@ -966,13 +1217,14 @@ mod tests {
         let verify_req_info: VerifyRequestResponseInfo = req_receiver.recv().unwrap();
         let mut req_grp: Vec<VerifyRequestResponseInfo> = Vec::new();
         req_grp.push(verify_req_info);
-        verify_tx_group_service(req_grp, verifier, verify_cache, resp_sender);
+        verify_tx_group_service(req_grp, verifier.clone(), verify_cache, resp_sender);
 
         handle_verificaton_result(
             &resp_receiver,
             &tx_pub,
             block_verify_status,
             &pool_tx_sender,
+            &verifier,
         );
         let (_, _, resp_msg, _) = pool_tx_receiver.try_recv().unwrap();
         let ok_result = format!("{:?}", Ret::OK);
@@ -986,6 +1238,599 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rejected_txs_are_logged_with_reason() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, _req_receiver) = channel();
+        let (resp_sender, resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let verifier = Arc::new(RwLock::new(Verifier::new()));
+        let (pool_txs_sender, _) = channel();
+        let (pool_tx_sender, _pool_tx_receiver) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(1);
+
+        let rejections = vec![
+            (H256::from(1), Ret::BadSig),
+            (H256::from(2), Ret::Dup),
+            (H256::from(3), Ret::InvalidNonce),
+        ];
+        for &(tx_hash, ret) in &rejections {
+            let mut resp = VerifyTxResp::new();
+            resp.set_tx_hash(tx_hash.to_vec());
+            resp.set_ret(ret);
+            resp_sender
+                .send(VerifyRequestResponseInfo {
+                    sub_module: SubModules::Jsonrpc,
+                    verify_type: VerifyType::SingleVerify,
+                    request_id: VerifyRequestID::SingleVerifyRequestID(tx_hash.to_vec()),
+                    time_stamp: SystemTime::now(),
+                    req_resp: VerifyRequestResponse::AuthResponse(resp),
+                    un_tx: None,
+                    dup_height: None,
+                    signer_cached: false,
+                })
+                .unwrap();
+            handle_verificaton_result(
+                &resp_receiver,
+                &tx_pub,
+                block_verify_status.clone(),
+                &pool_tx_sender,
+                &verifier,
+            );
+            // the error answer of the new tx
+            rx_pub.try_recv().unwrap();
+        }
+
+        let mut request = Request::new();
+        request.set_request_id(vec![9]);
+        request.set_rejected_txs(true);
+        handle_remote_msg(
+            SubModules::Jsonrpc,
+            generate_msg_from_request(request),
+            Arc::new(AtomicBool::new(false)),
+            &pool,
+            30,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status,
+            cache,
+            &pool_txs_sender,
+            &resp_sender,
+        );
+
+        let (key, resp_msg) = rx_pub.try_recv().unwrap();
+        assert_eq!(key, "auth.rpc".to_owned());
+        let mut msg = Message::try_from(&resp_msg).unwrap();
+        match msg.take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_request_id(), &[9][..]);
+                let rejected: Vec<RejectedTransaction> = serde_json::from_str(resp.get_rejected_txs()).unwrap();
+                let reasons: Vec<(H256, String)> = rejected
+                    .into_iter()
+                    .map(|rejected| (rejected.hash, rejected.reason))
+                    .collect();
+                let expected: Vec<(H256, String)> = rejections
+                    .into_iter()
+                    .rev()
+                    .map(|(tx_hash, ret)| (tx_hash, format!("{:?}", ret)))
+                    .collect();
+                assert_eq!(reasons, expected);
+            }
+            _ => panic!("test failed"),
+        }
+    }
+
+    #[test]
+    fn handle_verificaton_result_committed_dup_tx() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, req_receiver) = channel();
+        let (resp_sender, resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let mut verifier = Verifier::new();
+        verifier.set_report_dup_height(true);
+        let verifier = Arc::new(RwLock::new(verifier));
+        let (pool_txs_sender, _) = channel();
+        let (pool_tx_sender, pool_tx_receiver) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(10);
+        let tx_verify_num_per_thread = 30;
+        let on_proposal = Arc::new(AtomicBool::new(false));
+
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let tx = generate_tx(vec![1], 99, privkey);
+        let tx_hash = H256::from_slice(tx.get_tx_hash());
+
+        // the tx has been committed at height 0
+        let mut block_tx_hashes = BlockTxHashes::new();
+        block_tx_hashes.set_height(0);
+        block_tx_hashes.set_tx_hashes(RepeatedField::from_vec(vec![tx_hash.to_vec()]));
+        let msg: Message = block_tx_hashes.into();
+        handle_remote_msg(
+            SubModules::Chain,
+            msg.try_into().unwrap(),
+            on_proposal.clone(),
+            &pool,
+            tx_verify_num_per_thread,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status.clone(),
+            cache.clone(),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+
+        // and is submitted again
+        handle_remote_msg(
+            SubModules::Jsonrpc,
+            generate_msg(tx),
+            on_proposal,
+            &pool,
+            tx_verify_num_per_thread,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status.clone(),
+            cache.clone(),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+
+        let verify_req_info: VerifyRequestResponseInfo = req_receiver.recv().unwrap();
+        verify_tx_group_service(vec![verify_req_info], verifier.clone(), cache, resp_sender);
+        handle_verificaton_result(
+            &resp_receiver,
+            &tx_pub,
+            block_verify_status,
+            &pool_tx_sender,
+            &verifier,
+        );
+
+        let (key, resp_msg) = rx_pub.try_recv().unwrap();
+        assert_eq!(key, "auth.rpc".to_owned());
+        let mut msg = Message::try_from(&resp_msg).unwrap();
+        match msg.take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_code(), 0);
+                let tx_response: TxResponse = serde_json::from_str(resp.get_tx_state()).unwrap();
+                assert_eq!(tx_response.hash, tx_hash);
+                assert_eq!(tx_response.status, format!("{:?}", Ret::Dup));
+                assert_eq!(tx_response.block_number, Some(U256::from(0)));
+            }
+            _ => panic!("test failed"),
+        }
+        assert!(pool_tx_receiver.try_recv().is_err());
+    }
+
+    fn generate_blk_tx_hashes_msg(height: u64, tx_hashes: Vec<H256>) -> Vec<u8> {
+        let mut block_tx_hashes = BlockTxHashes::new();
+        block_tx_hashes.set_height(height);
+        let tx_hashes: Vec<Vec<u8>> = tx_hashes.into_iter().map(|hash| hash.to_vec()).collect();
+        block_tx_hashes.set_tx_hashes(RepeatedField::from_vec(tx_hashes));
+        let msg: Message = block_tx_hashes.into();
+        msg.try_into().unwrap()
+    }
+
+    #[test]
+    fn reject_new_tx_during_reorg() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, req_receiver) = channel();
+        let (resp_sender, _resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let mut verifier = Verifier::new();
+        verifier.set_reject_during_reorg(true);
+        let verifier = Arc::new(RwLock::new(verifier));
+        let (pool_txs_sender, _pool_txs_receiver) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(10);
+        let tx_verify_num_per_thread = 30;
+        let on_proposal = Arc::new(AtomicBool::new(false));
+
+        let handle = |payload: Vec<u8>, submodule: SubModules| {
+            handle_remote_msg(
+                submodule,
+                payload,
+                on_proposal.clone(),
+                &pool,
+                tx_verify_num_per_thread,
+                verifier.clone(),
+                &req_sender,
+                &tx_pub,
+                block_verify_status.clone(),
+                cache.clone(),
+                &pool_txs_sender,
+                &resp_sender,
+            )
+        };
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+
+        handle(generate_blk_tx_hashes_msg(0, vec![]), SubModules::Chain);
+        handle(
+            generate_blk_tx_hashes_msg(1, vec![H256::from(U256::from(0x1234))]),
+            SubModules::Chain,
+        );
+        handle(generate_msg(generate_tx(vec![1], 99, privkey)), SubModules::Jsonrpc);
+        assert!(req_receiver.try_recv().is_ok());
+
+        // block 1 is replaced
+        handle(
+            generate_blk_tx_hashes_msg(1, vec![H256::from(U256::from(0x5678))]),
+            SubModules::Chain,
+        );
+        handle(generate_msg(generate_tx(vec![2], 99, privkey)), SubModules::Jsonrpc);
+        assert!(req_receiver.try_recv().is_err());
+        let (key, resp_msg) = rx_pub.try_recv().unwrap();
+        assert_eq!(key, "auth.rpc".to_owned());
+        let mut msg = Message::try_from(&resp_msg).unwrap();
+        match msg.take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_code(), ErrorCode::tx_auth_error());
+                assert_eq!(resp.get_error_msg(), REORG_IN_PROGRESS);
+            }
+            _ => panic!("test failed"),
+        }
+
+        // the head moves on
+        handle(generate_blk_tx_hashes_msg(2, vec![]), SubModules::Chain);
+        handle(generate_msg(generate_tx(vec![3], 99, privkey)), SubModules::Jsonrpc);
+        assert!(req_receiver.try_recv().is_ok());
+        assert!(rx_pub.try_recv().is_err());
+    }
+
+    #[test]
+    fn new_tx_data_size_is_checked() {
+        let keypair = KeyPair::gen_keypair();
+        let tx = generate_tx(vec![1; 1024], 99, keypair.privkey());
+        let req = new_tx_verify_req(tx.get_transaction_with_sig());
+        assert_eq!(req.get_data_len(), 1024);
+
+        let mut verifier = Verifier::new();
+        assert_ne!(verifier.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::InvalidDataSize);
+        verifier.set_max_data_size(Some(512));
+        assert_eq!(verifier.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::InvalidDataSize);
+    }
+
+    #[test]
+    fn force_reverify_bypasses_cache() {
+        let (tx_pub, _rx_pub) = channel();
+        let verifier = RwLock::new(Verifier::new());
+        verifier.write().update_hashes(0, HashSet::new(), &tx_pub);
+        let cache = RwLock::new(HashMap::new());
+
+        let keypair = KeyPair::gen_keypair();
+        let tx = generate_tx(vec![1], 99, keypair.privkey());
+        let req = tx.get_transaction_with_sig().tx_verify_req_msg();
+        let tx_hash = H256::from_slice(req.get_tx_hash());
+
+        // a stale result claiming the tx is a duplicate
+        let mut stale = VerifyTxResp::new();
+        stale.set_tx_hash(req.get_tx_hash().to_vec());
+        stale.set_ret(Ret::Dup);
+        cache.write().insert(tx_hash, stale);
+
+        let report = force_reverify(&req, &verifier, &cache);
+        assert_eq!(report.tx_hash, tx_hash);
+        assert!(report.cache_hit);
+        assert!(!report.signer_cache_hit);
+        assert_eq!(report.cached_ret, Some(Ret::Dup));
+        assert_eq!(report.ret, Ret::OK);
+        assert_eq!(report.signer, Some(keypair.pubkey().to_vec()));
+        assert_eq!(cache.read().get(&tx_hash).unwrap().get_ret(), Ret::OK);
+
+        cache.write().clear();
+        let report = force_reverify(&req, &verifier, &cache);
+        assert!(!report.cache_hit);
+        assert!(report.signer_cache_hit);
+        assert_eq!(report.cached_ret, None);
+        assert_eq!(report.ret, Ret::OK);
+    }
+
+    #[test]
+    fn reverify_request_is_answered() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, _req_receiver) = channel();
+        let (resp_sender, _resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let verifier = Arc::new(RwLock::new(Verifier::new()));
+        verifier.write().update_hashes(0, HashSet::new(), &tx_pub);
+        let (pool_txs_sender, _) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(1);
+
+        let keypair = KeyPair::gen_keypair();
+        let tx = generate_tx(vec![1], 99, keypair.privkey());
+        let tx_hash = H256::from_slice(tx.get_tx_hash());
+        let mut stale = VerifyTxResp::new();
+        stale.set_tx_hash(tx_hash.to_vec());
+        stale.set_ret(Ret::BadSig);
+        cache.write().insert(tx_hash, stale);
+
+        let mut request = Request::new();
+        request.set_request_id(vec![9]);
+        request.set_reverify_tx(tx.get_transaction_with_sig().clone());
+        handle_remote_msg(
+            SubModules::Jsonrpc,
+            generate_msg_from_request(request),
+            Arc::new(AtomicBool::new(false)),
+            &pool,
+            30,
+            verifier,
+            &req_sender,
+            &tx_pub,
+            Arc::new(RwLock::new(block_verify_status)),
+            cache.clone(),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+
+        let (key, resp_msg) = rx_pub.try_recv().unwrap();
+        assert_eq!(key, "auth.rpc".to_owned());
+        let mut msg = Message::try_from(&resp_msg).unwrap();
+        match msg.take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_request_id(), &[9][..]);
+                let reverification: Reverification = serde_json::from_str(resp.get_reverification()).unwrap();
+                assert_eq!(reverification.hash, tx_hash);
+                assert_eq!(reverification.cached_reason, Some("BadSig".to_owned()));
+                assert!(!reverification.signer_cached);
+                assert_eq!(reverification.reason, "OK".to_owned());
+                assert_eq!(reverification.signer, Some(Bytes::from(keypair.pubkey().to_vec())));
+            }
+            _ => panic!("test failed"),
+        }
+        assert_eq!(cache.read().get(&tx_hash).unwrap().get_ret(), Ret::OK);
+    }
+
+    #[test]
+    fn verify_cache_stats() {
+        let (tx_pub, _rx_pub) = channel();
+        let (req_sender, req_receiver) = channel();
+        let (resp_sender, resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let verifier = Arc::new(RwLock::new(Verifier::new()));
+        let (pool_txs_sender, _pool_txs_receiver) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(10);
+        let tx_verify_num_per_thread = 30;
+        let on_proposal = Arc::new(AtomicBool::new(false));
+
+        handle_remote_msg(
+            SubModules::Chain,
+            generate_sync_blk_hash_msg(0),
+            on_proposal.clone(),
+            &pool,
+            tx_verify_num_per_thread,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status.clone(),
+            cache.clone(),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        handle_remote_msg(
+            SubModules::Jsonrpc,
+            generate_msg(generate_tx(vec![1], 99, privkey)),
+            on_proposal,
+            &pool,
+            tx_verify_num_per_thread,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status,
+            cache.clone(),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+        let verify_req_info: VerifyRequestResponseInfo = req_receiver.recv().unwrap();
+
+        let result = check_verify_request_preprocess(
+            verify_req_info.clone(),
+            verifier.clone(),
+            cache.clone(),
+            &resp_sender,
+        );
+        assert_eq!(result, VerifyResult::VerifyNotBegin);
+        verify_tx_group_service(
+            vec![verify_req_info.clone()],
+            verifier.clone(),
+            cache.clone(),
+            resp_sender.clone(),
+        );
+
+        for _ in 0..2 {
+            let result = check_verify_request_preprocess(
+                verify_req_info.clone(),
+                verifier.clone(),
+                cache.clone(),
+                &resp_sender,
+            );
+            assert_eq!(result, VerifyResult::VerifySucceeded);
+        }
+
+        let stats = verifier.read().cache_stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.size, 1);
+        assert_eq!(resp_receiver.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn rejection_rate_counts_cached_answers() {
+        let (tx_pub, _rx_pub) = channel();
+        let (resp_sender, _resp_receiver) = channel();
+        let verifier = Arc::new(RwLock::new(Verifier::new()));
+        verifier.write().set_rejection_window(4);
+        verifier.write().update_hashes(0, HashSet::new(), &tx_pub);
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+
+        let keypair = KeyPair::gen_keypair();
+        let tx = generate_tx(vec![1], 99, keypair.privkey());
+        let req = tx.get_transaction_with_sig().tx_verify_req_msg();
+        let mut bad_sig = VerifyTxResp::new();
+        bad_sig.set_tx_hash(req.get_tx_hash().to_vec());
+        bad_sig.set_ret(Ret::BadSig);
+        cache.write().insert(H256::from_slice(req.get_tx_hash()), bad_sig);
+        let req_info = VerifyRequestResponseInfo {
+            sub_module: SubModules::Jsonrpc,
+            verify_type: VerifyType::SingleVerify,
+            request_id: VerifyRequestID::SingleVerifyRequestID(req.get_tx_hash().to_vec()),
+            time_stamp: SystemTime::now(),
+            req_resp: VerifyRequestResponse::AuthRequest(req.clone()),
+            un_tx: None,
+            dup_height: None,
+            signer_cached: false,
+        };
+
+        // a client resending a rejected tx is rejected from the cache each time
+        for _ in 0..2 {
+            let result =
+                check_verify_request_preprocess(req_info.clone(), verifier.clone(), cache.clone(), &resp_sender);
+            assert_eq!(result, VerifyResult::VerifyFailed);
+        }
+        assert_eq!(verifier.read().recent_rejections(), (2, 2));
+
+        force_reverify(&req, &verifier, &cache);
+        assert_eq!(verifier.read().recent_rejections(), (2, 2));
+        let result = check_verify_request_preprocess(req_info, verifier.clone(), cache, &resp_sender);
+        assert_eq!(result, VerifyResult::VerifySucceeded);
+        assert_eq!(verifier.read().recent_rejections(), (2, 3));
+    }
+
+    #[test]
+    fn verify_outcome_counted_once() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, req_receiver) = channel();
+        let (resp_sender, _resp_receiver) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let verifier = Arc::new(RwLock::new(Verifier::new()));
+        let (pool_txs_sender, _pool_txs_receiver) = channel();
+        let cache = Arc::new(RwLock::new(HashMap::new()));
+        let pool = threadpool::ThreadPool::new(10);
+        let on_proposal = Arc::new(AtomicBool::new(false));
+        let handle = |msg: Vec<u8>, submodule: SubModules| {
+            handle_remote_msg(
+                submodule,
+                msg,
+                on_proposal.clone(),
+                &pool,
+                30,
+                verifier.clone(),
+                &req_sender,
+                &tx_pub,
+                block_verify_status.clone(),
+                cache.clone(),
+                &pool_txs_sender,
+                &resp_sender,
+            );
+        };
+
+        handle(generate_sync_blk_hash_msg(0), SubModules::Chain);
+        let keypair = KeyPair::gen_keypair();
+        let tx = generate_tx(vec![1], 99, keypair.privkey());
+        handle(generate_msg(tx.clone()), SubModules::Jsonrpc);
+        let verify_req_info: VerifyRequestResponseInfo = req_receiver.recv().unwrap();
+
+        let preprocess = |req_info: VerifyRequestResponseInfo| {
+            check_verify_request_preprocess(req_info, verifier.clone(), cache.clone(), &resp_sender)
+        };
+        // verified once, then answered from the cache twice
+        assert_eq!(preprocess(verify_req_info.clone()), VerifyResult::VerifyNotBegin);
+        verify_tx_group_service(
+            vec![verify_req_info.clone()],
+            verifier.clone(),
+            cache.clone(),
+            resp_sender.clone(),
+        );
+        assert_eq!(preprocess(verify_req_info.clone()), VerifyResult::VerifySucceeded);
+        assert_eq!(preprocess(verify_req_info.clone()), VerifyResult::VerifySucceeded);
+        // refused before looking at the cache
+        let mut expired = verify_req_info.clone();
+        if let VerifyRequestResponse::AuthRequest(ref mut req) = expired.req_resp {
+            req.set_valid_until_block(0);
+        }
+        assert_eq!(preprocess(expired), VerifyResult::VerifyFailed);
+        // a forced re-verification answers no verify request
+        force_reverify(&tx.get_transaction_with_sig().tx_verify_req_msg(), &verifier, &cache);
+
+        let stats = verifier.read().outcome_stats();
+        assert_eq!(stats.of_ret(Ret::OK), 3);
+        assert_eq!(stats.of_ret(Ret::InvalidUntilBlock), 1);
+        assert_eq!(stats.of_type(VerifyType::SingleVerify), 4);
+        assert_eq!(stats.of_type(VerifyType::BlockVerify), 0);
+
+        let mut request = Request::new();
+        request.set_request_id(vec![7]);
+        request.set_verify_stats(true);
+        handle(generate_msg_from_request(request), SubModules::Jsonrpc);
+        let (key, resp_msg) = rx_pub.try_iter().last().unwrap();
+        assert_eq!(key, "auth.rpc".to_owned());
+        let mut msg = Message::try_from(&resp_msg).unwrap();
+        match msg.take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_request_id(), &[7][..]);
+                let stats: VerifyStats = serde_json::from_str(resp.get_verify_stats()).unwrap();
+                assert_eq!(stats.by_reason.get("OK"), Some(&U256::from(3)));
+                assert_eq!(stats.by_reason.get("InvalidUntilBlock"), Some(&U256::from(1)));
+                assert_eq!(stats.by_type.get("SingleVerify"), Some(&U256::from(4)));
+                assert_eq!(stats.by_type.get("BlockVerify"), None);
+                assert_eq!(stats.cache.hits, U256::from(2));
+                assert_eq!(stats.cache.misses, U256::from(1));
+                assert_eq!(stats.cache.size, U256::from(1));
+                // all the hashes up to height 0 are known
+                assert_eq!(stats.init_eta, Some(U256::zero()));
+            }
+            _ => panic!("test failed"),
+        }
+    }
+
     #[test]
     fn handle_verificaton_result_block_tx() {
         let (tx_pub, rx_pub) = channel();
@@ -1046,6 +1891,7 @@ mod tests {
             &tx_pub,
             block_verify_status,
             &pool_tx_sender,
+            &verifier,
         );
 
         let (_, resp_msg) = rx_pub.recv().unwrap();
@@ -1128,6 +1974,7 @@ mod tests {
             &tx_pub,
             block_verify_status,
             &pool_tx_sender,
+            &verifier,
         );
 
         let (_, resp_msg) = rx_pub.recv().unwrap();
@@ -1208,6 +2055,7 @@ mod tests {
             &tx_pub,
             block_verify_status.clone(),
             &pool_tx_sender,
+            &verifier,
         );
         let (_, resp_msg) = rx_pub.recv().unwrap();
         let mut msg = Message::try_from(&resp_msg).unwrap();
@@ -1246,4 +2094,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn reject_block_verify_over_max_txs() {
+        let (tx_pub, rx_pub) = channel();
+        let (req_sender, req_receiver) = channel();
+        let (resp_sender, _) = channel();
+        let block_verify_status = BlockVerifyStatus {
+            request_id: 0,
+            block_verify_result: VerifyResult::VerifyNotBegin,
+            verify_success_cnt_required: 0,
+            verify_success_cnt_capture: 0,
+            cache_hit: 0,
+        };
+        let block_verify_status = Arc::new(RwLock::new(block_verify_status));
+        let mut verifier = Verifier::new();
+        verifier.set_max_block_verify_txs(Some(1));
+        let verifier = Arc::new(RwLock::new(verifier));
+        let (pool_txs_sender, _) = channel();
+        let pool = threadpool::ThreadPool::new(10);
+        let on_proposal = Arc::new(AtomicBool::new(false));
+
+        let reqs: Vec<VerifyTxReq> = (1..3)
+            .map(|i| {
+                let mut req = VerifyTxReq::new();
+                req.set_tx_hash(H256::from(i).to_vec());
+                req
+            })
+            .collect();
+        let mut blkreq = VerifyBlockReq::new();
+        blkreq.set_id(BLOCK_REQUEST_ID);
+        blkreq.set_reqs(RepeatedField::from_vec(reqs));
+        let msg: Message = blkreq.into();
+
+        handle_remote_msg(
+            SubModules::Consensus,
+            msg.try_into().unwrap(),
+            on_proposal.clone(),
+            &pool,
+            30,
+            verifier.clone(),
+            &req_sender,
+            &tx_pub,
+            block_verify_status.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            &pool_txs_sender,
+            &resp_sender,
+        );
+
+        let (key, resp_msg) = rx_pub.try_recv().unwrap();
+        assert_eq!(key, "auth.verify_blk_res");
+        match Message::try_from(&resp_msg).unwrap().take_content() {
+            MsgClass::VerifyBlockResp(resp) => {
+                assert_eq!(resp.get_ret(), Ret::TooManyTxs);
+                assert_eq!(resp.get_id(), BLOCK_REQUEST_ID);
+            }
+            _ => panic!("expect a block verify response"),
+        }
+        assert!(req_receiver.try_recv().is_err());
+        assert_eq!(block_verify_status.read().request_id, 0);
+    }
 }
@@ -32,6 +32,7 @@ extern crate libproto;
 #[macro_use]
 extern crate log;
 extern crate logger;
+extern crate lru_cache;
 extern crate protobuf;
 extern crate pubsub;
 extern crate rustc_serialize;
@@ -50,14 +51,16 @@ pub mod verifier;
 pub mod dispatcher;
 pub mod txwal;
 pub mod config;
+pub mod rejections;
 use clap::App;
 use config::Config;
 use cpuprofiler::PROFILER;
-use dispatcher::Dispatcher;
+use dispatcher::{BalanceCheck, Dispatcher};
 use handler::*;
 use libproto::SubModules;
 use pubsub::start_pubsub;
 use std::collections::HashMap;
+use std::fs;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
@@ -66,6 +69,9 @@ use std::time::{Duration, SystemTime};
 use util::{set_panic_handler, Mutex, RwLock};
 use verifier::*;
 
+/// Seconds between checks of the config file for a changed recipient blocklist
+const BLOCKLIST_RELOAD_SECS: u64 = 10;
+
 fn profiler(flag_prof_start: u64, flag_prof_duration: u64) {
     //start profiling
     let start = flag_prof_start;
@@ -124,9 +130,98 @@ fn main() {
 
     profiler(flag_prof_start, flag_prof_duration);
 
-    let verifier = Arc::new(RwLock::new(Verifier::new()));
+    let mut verifier = Verifier::new();
+    verifier.set_report_dup_height(config.dup_with_height.unwrap_or(false));
+    verifier.set_reject_during_reorg(config.reject_during_reorg.unwrap_or(false));
+    verifier.set_max_future_height(config.max_future_height.unwrap_or(DEFAULT_MAX_FUTURE_HEIGHT));
+    verifier.set_max_data_size(config.max_tx_data_size);
+    if let Err(err) = verifier.set_max_nonce_len(config.max_nonce_len.unwrap_or(DEFAULT_MAX_NONCE_LEN)) {
+        error!("{}", err);
+        std::process::exit(-1);
+    }
+    verifier.set_require_nonce(config.require_nonce.unwrap_or(false));
+    match config.block_verify_txs_limit() {
+        Ok(max_txs) => verifier.set_max_block_verify_txs(max_txs),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(-1);
+        }
+    }
+    verifier.set_accepted_versions(config.accepted_tx_versions.clone());
+    verifier.set_rejection_log_size(
+        config
+            .rejection_log_size
+            .unwrap_or(rejections::DEFAULT_REJECTION_LOG_SIZE),
+    );
+    verifier.set_rejection_window(
+        config
+            .rejection_window
+            .unwrap_or(rejections::DEFAULT_REJECTION_WINDOW),
+    );
+    verifier.set_signer_cache_size(config.signer_cache_size.unwrap_or(DEFAULT_SIGNER_CACHE_SIZE));
+    verifier.set_sig_threads(config.sig_recover_threads.unwrap_or(tx_verify_thread_num));
+    verifier.set_txhashs_req_batch(config.txhashs_req_batch.unwrap_or(0));
+    verifier.set_zero_valid_until_never_expires(config.zero_valid_until_never_expires.unwrap_or(false));
+    if let Some(ref multisig) = config.multisig {
+        let mut accounts = HashMap::new();
+        for account in multisig {
+            let policy = match MultisigPolicy::from_hex_keys(&account.account, account.threshold, &account.keys) {
+                Ok(policy) => policy,
+                Err(err) => {
+                    error!("{}", err);
+                    std::process::exit(-1);
+                }
+            };
+            if accounts.insert(policy.account, policy).is_some() {
+                error!("multisig account {} is configured more than once", account.account);
+                std::process::exit(-1);
+            }
+        }
+        verifier.set_multisig(Some(accounts));
+    }
+    let recipient_blocklist = config.recipient_blocklist.as_ref().map(|addresses| addresses.as_slice());
+    if let Err(err) = verifier.set_recipient_blocklist(recipient_blocklist) {
+        error!("{}", err);
+        std::process::exit(-1);
+    }
+    let verifier = Arc::new(RwLock::new(verifier));
+
     let verify_cache = HashMap::new();
     let cache = Arc::new(RwLock::new(verify_cache));
+
+    // The recipient blocklist follows the config file, a bad one is ignored until fixed
+    let blocklist_verifier = verifier.clone();
+    let blocklist_cache = cache.clone();
+    let blocklist_config_path = config_path.to_owned();
+    thread::spawn(move || {
+        let modified_at = || {
+            fs::metadata(&blocklist_config_path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+        let mut modified = modified_at();
+        loop {
+            thread::sleep(Duration::from_secs(BLOCKLIST_RELOAD_SECS));
+            let now_modified = modified_at();
+            if now_modified == modified {
+                continue;
+            }
+            modified = now_modified;
+            let reloaded = Config::reload(&blocklist_config_path).and_then(|config| {
+                let addresses = config.recipient_blocklist.as_ref().map(|addresses| addresses.as_slice());
+                blocklist_verifier.write().set_recipient_blocklist(addresses)
+            });
+            match reloaded {
+                Ok(()) => {
+                    // Cached results of txs to a recipient blocked or unblocked now are stale
+                    blocklist_cache.write().clear();
+                    info!("recipient blocklist reloaded");
+                }
+                Err(err) => warn!("recipient blocklist isn't reloaded: {}", err),
+            }
+        }
+    });
+
     let block_verify_status = BlockVerifyStatus {
         request_id: 0,
         block_verify_result: VerifyResult::VerifyNotBegin,
@@ -143,7 +238,9 @@ fn main() {
         vec![
             "consensus.verify_blk_req",
             "chain.txhashes",
+            "jsonrpc.new_tx",
             "jsonrpc.new_tx_batch",
+            "jsonrpc.auth",
             "net.tx",
         ],
         tx_sub,
@@ -153,6 +250,7 @@ fn main() {
     let (single_req_sender, single_req_receiver) = channel();
     let (resp_sender, resp_receiver) = channel();
     let verifier_clone = verifier.clone();
+    let verifier_result = verifier.clone();
     let cache_clone = cache.clone();
     let resp_sender_main = resp_sender.clone();
     let mut timestamp_receive = SystemTime::now();
@@ -162,6 +260,8 @@ fn main() {
         count_per_batch,
         buffer_duration,
         wal_enable,
+        config.predict_height.unwrap_or(false),
+        config.balance_check.unwrap_or(BalanceCheck::Off),
     );
     let tx_pool_capacity = dispatch_origin.tx_pool_capacity();
     let on_proposal_clone = on_proposal.clone();
@@ -315,6 +415,7 @@ fn main() {
             &tx_pub,
             block_verify_status.clone(),
             &pool_tx_sender,
+            &verifier_result,
         );
     }
 }
@@ -0,0 +1,166 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Bounded log of the new txs auth rejected lately, the oldest is dropped first,
+//! and the share of the latest verified txs which were rejected.
+
+use libproto::Ret;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::H256;
+
+/// Rejections remembered if `rejection_log_size` isn't configured
+pub const DEFAULT_REJECTION_LOG_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedTx {
+    pub tx_hash: H256,
+    pub ret: Ret,
+    pub rejected_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct RejectionLog {
+    capacity: usize,
+    entries: VecDeque<RejectedTx>,
+}
+
+impl RejectionLog {
+    pub fn new(capacity: usize) -> Self {
+        RejectionLog {
+            capacity: capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, tx_hash: H256, ret: Ret) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(RejectedTx {
+            tx_hash: tx_hash,
+            ret: ret,
+            rejected_at: SystemTime::now(),
+        });
+    }
+
+    /// Rejections from the newest to the oldest
+    pub fn recent(&self) -> Vec<RejectedTx> {
+        self.entries.iter().rev().cloned().collect()
+    }
+}
+
+/// Verify requests the rejection rate is computed over if `rejection_window` isn't configured
+pub const DEFAULT_REJECTION_WINDOW: usize = 1000;
+
+/// Whether each of the latest `capacity` answered verify requests was rejected
+#[derive(Debug)]
+pub struct RejectionWindow {
+    capacity: usize,
+    outcomes: VecDeque<bool>,
+    rejected: usize,
+}
+
+impl RejectionWindow {
+    pub fn new(capacity: usize) -> Self {
+        RejectionWindow {
+            capacity: capacity,
+            outcomes: VecDeque::with_capacity(capacity),
+            rejected: 0,
+        }
+    }
+
+    /// Count a verified tx, rejected by any `Ret` but OK
+    pub fn push(&mut self, ret: Ret) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.outcomes.len() == self.capacity && self.outcomes.pop_front() == Some(true) {
+            self.rejected -= 1;
+        }
+        let rejected = ret != Ret::OK;
+        if rejected {
+            self.rejected += 1;
+        }
+        self.outcomes.push_back(rejected);
+    }
+
+    /// Rejected txs in the window and all txs in it
+    pub fn counts(&self) -> (usize, usize) {
+        (self.rejected, self.outcomes.len())
+    }
+
+    /// Share of the txs in the window which were rejected, 0 if there is none
+    pub fn rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            0.0
+        } else {
+            self.rejected as f64 / self.outcomes.len() as f64
+        }
+    }
+}
+
+/// Unix time in milliseconds
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_oldest() {
+        let mut log = RejectionLog::new(2);
+        log.push(H256::from(1), Ret::BadSig);
+        log.push(H256::from(2), Ret::Dup);
+        log.push(H256::from(3), Ret::InvalidNonce);
+        let recent: Vec<(H256, Ret)> = log.recent()
+            .into_iter()
+            .map(|rejected| (rejected.tx_hash, rejected.ret))
+            .collect();
+        assert_eq!(
+            recent,
+            vec![(H256::from(3), Ret::InvalidNonce), (H256::from(2), Ret::Dup)]
+        );
+    }
+
+    #[test]
+    fn rejection_window_slides() {
+        let mut window = RejectionWindow::new(3);
+        assert_eq!(window.rate(), 0.0);
+        for ret in vec![Ret::BadSig, Ret::OK, Ret::Dup] {
+            window.push(ret);
+        }
+        assert_eq!(window.counts(), (2, 3));
+        // The oldest rejection slides out
+        window.push(Ret::OK);
+        assert_eq!(window.counts(), (1, 3));
+        assert_eq!(window.rate(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut log = RejectionLog::new(0);
+        log.push(H256::from(1), Ret::BadSig);
+        assert!(log.recent().is_empty());
+    }
+}
@@ -15,14 +15,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crypto::{PubKey, Sign, Signature, SIGNATURE_BYTES_LEN};
+use crypto::{PubKey, Sign, Signature, PUBKEY_BYTES_LEN};
 use libproto::{BlockTxHashesReq, Crypto, Message, Ret, SubModules, UnverifiedTransaction, VerifyTxReq, VerifyTxResp};
+use lru_cache::LruCache;
+use rejections::{RejectedTx, RejectionLog, RejectionWindow, DEFAULT_REJECTION_LOG_SIZE, DEFAULT_REJECTION_WINDOW};
+use rustc_serialize::hex::FromHex;
 use std::collections::{HashMap, HashSet};
 use std::convert::{Into, TryInto};
 use std::result::Result;
-use std::sync::mpsc::Sender;
-use std::time::SystemTime;
-use util::{H256, BLOCKLIMIT};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant, SystemTime};
+use threadpool::ThreadPool;
+use util::{clean_0x, Address, Mutex, H256, BLOCKLIMIT};
+
+/// Bytes of a SECP signature, r, s and v
+pub const SECP_SIGNATURE_BYTES_LEN: usize = 65;
+/// Bytes of an SM2 signature, r, s and the signer's public key
+pub const SM2_SIGNATURE_BYTES_LEN: usize = 128;
+
+/// Longest nonce accepted if `max_nonce_len` isn't configured
+pub const DEFAULT_MAX_NONCE_LEN: usize = 128;
+
+/// Signers cached if `signer_cache_size` isn't configured
+pub const DEFAULT_SIGNER_CACHE_SIZE: usize = 10000;
+
+/// Default for how far past the latest height a block's hashes may arrive
+/// before they are treated as anomalous instead of re-requesting the gap
+pub const DEFAULT_MAX_FUTURE_HEIGHT: u64 = 10 * BLOCKLIMIT;
 
 #[derive(Debug, Clone)]
 pub enum VerifyRequestID {
@@ -44,6 +65,10 @@ pub struct VerifyRequestResponseInfo {
     pub time_stamp: SystemTime,
     pub req_resp: VerifyRequestResponse,
     pub un_tx: Option<UnverifiedTransaction>,
+    /// Height of the block including the tx when it is rejected as `Dup`
+    pub dup_height: Option<u64>,
+    /// The signer of the tx was cached, so its signature wasn't recovered again
+    pub signer_cached: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -63,18 +88,141 @@ pub struct BlockVerifyStatus {
     pub cache_hit: usize,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum VerifyType {
     SingleVerify,
     BlockVerify,
 }
 
+/// Effectiveness of the verify result cache
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// Number of cached results at the latest lookup
+    pub size: usize,
+}
+
+/// How many verify requests auth answered with each outcome, whether verified
+/// or taken from the cache. The counts only grow from the start of auth, so a
+/// rate is the difference of two readings.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutcomeStats {
+    pub by_ret: HashMap<Ret, usize>,
+    pub by_type: HashMap<VerifyType, usize>,
+}
+
+impl OutcomeStats {
+    pub fn of_ret(&self, ret: Ret) -> usize {
+        self.by_ret.get(&ret).cloned().unwrap_or(0)
+    }
+
+    pub fn of_type(&self, verify_type: VerifyType) -> usize {
+        self.by_type.get(&verify_type).cloned().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheCounter {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    size: AtomicUsize,
+}
+
+/// Keys of a multisig account: a tx of the account carrying several signatures is
+/// accepted when at least `threshold` of them are from distinct keys of the account
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultisigPolicy {
+    /// Public key standing for the account, the address of the account is derived from it
+    pub account: PubKey,
+    pub threshold: usize,
+    pub keys: Vec<PubKey>,
+}
+
+/// Policies of the multisig accounts, by the public key of the account
+pub type MultisigAccounts = HashMap<PubKey, MultisigPolicy>;
+
+/// The public key encoded in hex
+fn parse_pubkey(hex: &str) -> Result<PubKey, String> {
+    let bytes = clean_0x(hex)
+        .from_hex()
+        .map_err(|err| format!("invalid multisig key {}: {:?}", hex, err))?;
+    if bytes.len() != PUBKEY_BYTES_LEN {
+        return Err(format!("invalid multisig key length {}", hex));
+    }
+    Ok(PubKey::from_slice(&bytes))
+}
+
+/// The address encoded in hex, None if it isn't one
+fn parse_address(hex: &str) -> Option<Address> {
+    match clean_0x(hex).from_hex() {
+        Ok(ref bytes) if bytes.len() == 20 => Some(Address::from_slice(bytes)),
+        _ => None,
+    }
+}
+
+impl MultisigPolicy {
+    /// Build the policy of `account` from hex encoded public keys
+    pub fn from_hex_keys(account: &str, threshold: usize, keys: &[String]) -> Result<Self, String> {
+        if threshold == 0 || threshold > keys.len() {
+            return Err(format!(
+                "multisig threshold {} should be between 1 and the number of keys {}",
+                threshold,
+                keys.len()
+            ));
+        }
+        let mut pubkeys = Vec::with_capacity(keys.len());
+        for key in keys {
+            pubkeys.push(parse_pubkey(key)?);
+        }
+        Ok(MultisigPolicy {
+            account: parse_pubkey(account)?,
+            threshold: threshold,
+            keys: pubkeys,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Verifier {
     inited: bool,
     height_latest: Option<u64>,
     height_low: Option<u64>,
     hashes: HashMap<u64, HashSet<H256>>,
+    report_dup_height: bool,
+    reject_during_reorg: bool,
+    /// Latest height when a replaced block was seen, cleared once the head moves past it
+    reorg_height: Option<u64>,
+    cache_counter: Arc<CacheCounter>,
+    outcome_stats: Arc<Mutex<OutcomeStats>>,
+    /// Whether the latest answered verify requests were rejected, fed by `count_outcome`
+    rejection_window: Arc<Mutex<RejectionWindow>>,
+    max_future_height: u64,
+    /// Largest accepted size of a tx's data field, unlimited if None
+    max_data_size: Option<u64>,
+    /// Longest nonce accepted, in bytes
+    max_nonce_len: usize,
+    /// Reject txs without a nonce
+    require_nonce: bool,
+    /// Most txs in a block verify request, unlimited if None
+    max_block_verify_txs: Option<usize>,
+    /// Tx format versions accepted, any version if None
+    accepted_versions: Option<HashSet<u32>>,
+    /// Txs sent to these addresses are rejected, none is if None
+    recipient_blocklist: Option<HashSet<Address>>,
+    /// Most heights asked for in one BlockTxHashesReq, one per message if 0 or 1
+    txhashs_req_batch: usize,
+    multisig: Option<Arc<MultisigAccounts>>,
+    /// Accept a valid_until_block of 0 as never expiring instead of rejecting it
+    zero_valid_until_never_expires: bool,
+    /// When backfilling started and how many heights were known then
+    backfill_started: Option<(Instant, usize)>,
+    /// New txs rejected lately, for clients to find out why
+    rejection_log: Arc<Mutex<RejectionLog>>,
+    /// Recovers the signatures of a batch in parallel, one by one if None
+    sig_pool: Option<Arc<Mutex<ThreadPool>>>,
+    /// Signers recovered lately by tx hash, so a tx seen again isn't recovered again
+    signer_cache: Option<Arc<Mutex<LruCache<H256, PubKey>>>>,
 }
 
 impl Default for Verifier {
@@ -84,6 +232,26 @@ impl Default for Verifier {
             height_latest: None,
             height_low: None,
             hashes: HashMap::with_capacity(BLOCKLIMIT as usize),
+            report_dup_height: false,
+            reject_during_reorg: false,
+            reorg_height: None,
+            cache_counter: Arc::new(CacheCounter::default()),
+            outcome_stats: Arc::new(Mutex::new(OutcomeStats::default())),
+            rejection_window: Arc::new(Mutex::new(RejectionWindow::new(DEFAULT_REJECTION_WINDOW))),
+            max_future_height: DEFAULT_MAX_FUTURE_HEIGHT,
+            max_data_size: None,
+            max_nonce_len: DEFAULT_MAX_NONCE_LEN,
+            require_nonce: false,
+            max_block_verify_txs: None,
+            accepted_versions: None,
+            recipient_blocklist: None,
+            txhashs_req_batch: 0,
+            multisig: None,
+            zero_valid_until_never_expires: false,
+            backfill_started: None,
+            rejection_log: Arc::new(Mutex::new(RejectionLog::new(DEFAULT_REJECTION_LOG_SIZE))),
+            sig_pool: None,
+            signer_cache: Some(Arc::new(Mutex::new(LruCache::new(DEFAULT_SIGNER_CACHE_SIZE)))),
         }
     }
 }
@@ -93,6 +261,166 @@ impl Verifier {
         Verifier::default()
     }
 
+    pub fn set_report_dup_height(&mut self, enable: bool) {
+        self.report_dup_height = enable;
+    }
+
+    pub fn set_reject_during_reorg(&mut self, enable: bool) {
+        self.reject_during_reorg = enable;
+    }
+
+    pub fn set_max_future_height(&mut self, max_future_height: u64) {
+        self.max_future_height = max_future_height;
+    }
+
+    pub fn set_max_data_size(&mut self, max_data_size: Option<u64>) {
+        self.max_data_size = max_data_size;
+    }
+
+    /// A limit of 0 would reject every tx, so it's refused
+    pub fn set_max_nonce_len(&mut self, max_nonce_len: usize) -> Result<(), String> {
+        if max_nonce_len == 0 {
+            return Err("max_nonce_len must be at least 1".to_owned());
+        }
+        self.max_nonce_len = max_nonce_len;
+        Ok(())
+    }
+
+    pub fn set_require_nonce(&mut self, require_nonce: bool) {
+        self.require_nonce = require_nonce;
+    }
+
+    pub fn set_max_block_verify_txs(&mut self, max_txs: Option<usize>) {
+        self.max_block_verify_txs = max_txs;
+    }
+
+    /// Whether a block verify request of `tx_cnt` txs is too large to recover all its signatures
+    pub fn is_too_many_block_txs(&self, tx_cnt: usize) -> bool {
+        self.max_block_verify_txs.map_or(false, |max_txs| tx_cnt > max_txs)
+    }
+
+    pub fn set_accepted_versions(&mut self, versions: Option<Vec<u32>>) {
+        self.accepted_versions = versions.map(|versions| versions.into_iter().collect());
+    }
+
+    /// Reject txs sent to any of the hex encoded `addresses`. The list is kept as it is
+    /// if any of them isn't an address.
+    pub fn set_recipient_blocklist(&mut self, addresses: Option<&[String]>) -> Result<(), String> {
+        self.recipient_blocklist = match addresses {
+            Some(addresses) => {
+                let mut blocklist = HashSet::with_capacity(addresses.len());
+                for address in addresses {
+                    let parsed = parse_address(address).ok_or_else(|| format!("invalid blocked address {}", address))?;
+                    blocklist.insert(parsed);
+                }
+                Some(blocklist)
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn set_txhashs_req_batch(&mut self, batch: usize) {
+        self.txhashs_req_batch = batch;
+    }
+
+    pub fn set_multisig(&mut self, multisig: Option<MultisigAccounts>) {
+        self.multisig = multisig.map(Arc::new);
+        // Whether a multisig tx has a signer depends on the policies
+        if let Some(ref cache) = self.signer_cache {
+            cache.lock().clear();
+        }
+    }
+
+    /// Cache the signers of up to `size` txs, 0 recovers every tx seen
+    pub fn set_signer_cache_size(&mut self, size: usize) {
+        self.signer_cache = if size > 0 {
+            Some(Arc::new(Mutex::new(LruCache::new(size))))
+        } else {
+            None
+        };
+    }
+
+    pub fn set_zero_valid_until_never_expires(&mut self, enable: bool) {
+        self.zero_valid_until_never_expires = enable;
+    }
+
+    /// Recover the signatures of `verify_batch` on `threads` threads, 0 recovers them in the calling thread
+    pub fn set_sig_threads(&mut self, threads: usize) {
+        self.sig_pool = if threads > 0 {
+            Some(Arc::new(Mutex::new(ThreadPool::with_name(
+                "sig_recover".to_owned(),
+                threads,
+            ))))
+        } else {
+            None
+        };
+    }
+
+    pub fn set_rejection_log_size(&mut self, size: usize) {
+        self.rejection_log = Arc::new(Mutex::new(RejectionLog::new(size)));
+    }
+
+    /// Record a new tx which failed verification
+    pub fn note_rejected(&self, tx_hash: H256, ret: Ret) {
+        self.rejection_log.lock().push(tx_hash, ret);
+    }
+
+    /// New txs rejected lately, the newest first
+    pub fn rejected_txs(&self) -> Vec<RejectedTx> {
+        self.rejection_log.lock().recent()
+    }
+
+    /// Whether new txs should be rejected because the head isn't stable yet
+    pub fn is_rejecting_new_tx(&self) -> bool {
+        self.reject_during_reorg && self.reorg_height.is_some()
+    }
+
+    /// Record a lookup in the verify result cache
+    pub fn note_cache_lookup(&self, hit: bool, size: usize) {
+        if hit {
+            self.cache_counter.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_counter.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        self.cache_counter.size.store(size, Ordering::Relaxed);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.cache_counter.hits.load(Ordering::Relaxed),
+            misses: self.cache_counter.misses.load(Ordering::Relaxed),
+            size: self.cache_counter.size.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn outcome_stats(&self) -> OutcomeStats {
+        self.outcome_stats.lock().clone()
+    }
+
+    /// Compute the rejection rate over the latest `size` answered verify requests
+    pub fn set_rejection_window(&mut self, size: usize) {
+        self.rejection_window = Arc::new(Mutex::new(RejectionWindow::new(size)));
+    }
+
+    /// Requests rejected among the latest answered ones and how many those are
+    pub fn recent_rejections(&self) -> (usize, usize) {
+        self.rejection_window.lock().counts()
+    }
+
+    /// Share of the latest answered verify requests which were rejected, a spike hints at an attack or a broken client
+    pub fn rejection_rate(&self) -> f64 {
+        self.rejection_window.lock().rate()
+    }
+
+    /// Count the answer to a verify request, once for every request whichever way it was answered
+    pub fn count_outcome(&self, verify_type: VerifyType, ret: Ret) {
+        let mut stats = self.outcome_stats.lock();
+        *stats.by_ret.entry(ret).or_insert(0) += 1;
+        *stats.by_type.entry(verify_type).or_insert(0) += 1;
+        self.rejection_window.lock().push(ret);
+    }
+
     pub fn is_inited(&self) -> bool {
         self.inited
     }
@@ -105,14 +433,29 @@ impl Verifier {
         self.height_low
     }
 
-    pub fn send_txhashs_req(low: u64, high: u64, tx_pub: &Sender<(String, Vec<u8>)>) {
-        for i in low..high {
+    pub fn send_txhashs_req(&self, low: u64, high: u64, tx_pub: &Sender<(String, Vec<u8>)>) {
+        if self.txhashs_req_batch <= 1 {
+            for i in low..high {
+                let mut req = BlockTxHashesReq::new();
+                req.set_height(i);
+                let msg: Message = req.into();
+                tx_pub
+                    .send(("auth.blk_tx_hashs_req".to_string(), msg.try_into().unwrap()))
+                    .unwrap();
+            }
+            return;
+        }
+        let mut start = low;
+        while start < high {
+            let end = ::std::cmp::min(start + self.txhashs_req_batch as u64, high);
             let mut req = BlockTxHashesReq::new();
-            req.set_height(i);
+            req.set_height(start);
+            req.set_heights((start..end).collect());
             let msg: Message = req.into();
             tx_pub
                 .send(("auth.blk_tx_hashs_req".to_string(), msg.try_into().unwrap()))
                 .unwrap();
+            start = end;
         }
     }
 
@@ -124,11 +467,26 @@ impl Verifier {
             } else {
                 Some(h - BLOCKLIMIT + 1)
             };
-            Verifier::send_txhashs_req(self.height_low.unwrap(), h, tx_pub);
+            self.send_txhashs_req(self.height_low.unwrap(), h, tx_pub);
         } else {
             let current_height = self.height_latest.unwrap();
             let current_height_low = self.height_low.unwrap();
+            if h <= current_height {
+                if let Some(old_hashes) = self.hashes.get(&h) {
+                    if *old_hashes != hashes {
+                        warn!(
+                            "block at height {} is replaced, reorg detected at height {}",
+                            h, current_height
+                        );
+                        self.reorg_height = Some(current_height);
+                    }
+                }
+            }
             if h == current_height + 1 {
+                if self.reorg_height.map_or(false, |reorg_height| h > reorg_height) {
+                    info!("head is stable again at height {}", h);
+                    self.reorg_height = None;
+                }
                 self.height_latest = Some(h);
                 self.height_low = if h < BLOCKLIMIT {
                     Some(0)
@@ -138,10 +496,16 @@ impl Verifier {
                 for i in current_height_low..self.height_low.unwrap() {
                     self.hashes.remove(&i);
                 }
+            } else if h > current_height && h - current_height > self.max_future_height {
+                error!(
+                    "reject block tx hashes for height {}, too far ahead of latest height {}",
+                    h, current_height
+                );
+                return;
             } else if h > current_height + 1 {
                 /*if we lost some height blockhashs
                  we notify chain to re-trans txs*/
-                Verifier::send_txhashs_req(current_height + 1, h + 1, tx_pub);
+                self.send_txhashs_req(current_height + 1, h + 1, tx_pub);
                 return;
             }
             if h < self.height_low.unwrap() {
@@ -154,12 +518,42 @@ impl Verifier {
             self.height_low.unwrap(),
             self.height_latest.unwrap()
         );
+        // Txs in a block are rejected as Dup from now on, before their signatures are checked
+        if let Some(ref cache) = self.signer_cache {
+            let mut cache = cache.lock();
+            for hash in &hashes {
+                cache.remove(hash);
+            }
+        }
         self.hashes.insert(h, hashes);
+        if self.backfill_started.is_none() {
+            self.backfill_started = Some((Instant::now(), self.hashes.len()));
+        }
         if self.hashes.len() as u64 == (self.height_latest.unwrap() - self.height_low.unwrap() + 1) {
             self.inited = true;
         }
     }
 
+    /// Estimated time until inited, from the rate backfilled heights arrive.
+    /// None if nothing was backfilled yet.
+    pub fn init_eta(&self) -> Option<Duration> {
+        self.init_eta_at(Instant::now())
+    }
+
+    fn init_eta_at(&self, now: Instant) -> Option<Duration> {
+        if self.inited {
+            return Some(Duration::from_secs(0));
+        }
+        let (started, known) = self.backfill_started?;
+        let total = (self.height_latest? - self.height_low? + 1) as usize;
+        let done = self.hashes.len().saturating_sub(known);
+        if done == 0 || now < started {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.hashes.len());
+        Some((now - started) * remaining as u32 / done as u32)
+    }
+
     pub fn check_hash_exist(&self, hash: &H256) -> bool {
         if !self.inited {
             return true;
@@ -177,30 +571,214 @@ impl Verifier {
         false
     }
 
+    /// Get the height of the block which includes the tx, if it is in the recent blocks
+    pub fn get_tx_height(&self, hash: &H256) -> Option<u64> {
+        self.hashes
+            .iter()
+            .find(|&(_, hashes)| hashes.contains(hash))
+            .map(|(height, _)| *height)
+    }
+
+    /// Get the including height for a `Dup` response when reporting it is enabled
+    pub fn get_dup_height(&self, resp: &VerifyTxResp) -> Option<u64> {
+        if !self.report_dup_height || resp.get_ret() != Ret::Dup {
+            return None;
+        }
+        self.get_tx_height(&H256::from_slice(resp.get_tx_hash()))
+    }
+
     pub fn verify_sig(&self, req: &VerifyTxReq) -> Result<PubKey, ()> {
+        Verifier::recover_signer(req, self.multisig.as_ref())
+    }
+
+    fn recover_signer(req: &VerifyTxReq, multisig: Option<&Arc<MultisigAccounts>>) -> Result<PubKey, ()> {
+        if let Some(multisig) = multisig {
+            if !req.get_signatures().is_empty() {
+                return Verifier::verify_multisig(req, multisig);
+            }
+        }
         let hash = H256::from(req.get_hash());
-        let sig_bytes = req.get_signature();
-        if sig_bytes.len() != SIGNATURE_BYTES_LEN {
-            warn!("Unvalid signature bytes");
+        Verifier::recover(&hash, req.get_crypto(), req.get_signature())
+    }
+
+    /// Signer of a multisig tx is the account it names as its signer, so its sender is
+    /// the address of the account, whichever keys of the account signed it
+    fn verify_multisig(req: &VerifyTxReq, accounts: &MultisigAccounts) -> Result<PubKey, ()> {
+        if req.get_signer().len() != PUBKEY_BYTES_LEN {
+            warn!("multisig tx doesn't name its account");
+            return Err(());
+        }
+        let account = PubKey::from_slice(req.get_signer());
+        let policy = match accounts.get(&account) {
+            Some(policy) => policy,
+            None => {
+                warn!("multisig tx of unknown account {:?}", account);
+                return Err(());
+            }
+        };
+        let hash = H256::from(req.get_hash());
+        let mut signers: Vec<PubKey> = Vec::new();
+        for sig_bytes in req.get_signatures() {
+            if let Ok(pubkey) = Verifier::recover(&hash, req.get_crypto(), sig_bytes) {
+                if policy.keys.contains(&pubkey) && !signers.contains(&pubkey) {
+                    signers.push(pubkey);
+                }
+            }
+        }
+        if signers.len() < policy.threshold {
+            warn!(
+                "multisig tx of account {:?} has {} valid signatures, {} required",
+                account,
+                signers.len(),
+                policy.threshold
+            );
+            return Err(());
+        }
+        Ok(account)
+    }
+
+    /// Bytes a signature of the scheme has, checked before recovering it
+    pub fn signature_bytes_len(crypto: Crypto) -> usize {
+        match crypto {
+            Crypto::SECP => SECP_SIGNATURE_BYTES_LEN,
+            Crypto::SM2 => SM2_SIGNATURE_BYTES_LEN,
+        }
+    }
+
+    /// Only the crypto this node is built with, by the `secp256k1` or `sm2` feature, can be recovered
+    fn recover(hash: &H256, crypto: Crypto, sig_bytes: &[u8]) -> Result<PubKey, ()> {
+        let expected_len = Verifier::signature_bytes_len(crypto);
+        if sig_bytes.len() != expected_len {
+            warn!(
+                "Unvalid signature bytes, {} bytes for {:?} which takes {}",
+                sig_bytes.len(),
+                crypto,
+                expected_len
+            );
             return Err(());
         }
-        let sig = Signature::from(sig_bytes);
-        match req.get_crypto() {
-            Crypto::SECP => sig.recover(&hash).map_err(|_| ()),
+        match crypto {
+            #[cfg(feature = "secp256k1")]
+            Crypto::SECP => Signature::from(sig_bytes).recover(hash).map_err(|_| ()),
+            #[cfg(feature = "sm2")]
+            Crypto::SM2 => Signature::from(sig_bytes).recover(hash).map_err(|_| ()),
             _ => {
-                warn!("Unexpected crypto");
+                warn!("Unexpected crypto {:?}", crypto);
                 Err(())
             }
         }
     }
 
-    pub fn verfiy_tx(&self, req: &VerifyTxReq) -> VerifyTxResp {
+    pub fn verfiy_tx(&self, req: &VerifyTxReq, verify_type: VerifyType) -> VerifyTxResp {
+        self.check_before_sig(req, verify_type).unwrap_or_else(|| {
+            let tx_hash = H256::from_slice(req.get_tx_hash());
+            if let Some(signer) = self.cached_signer(&tx_hash) {
+                return Verifier::resp_of_signer(req, Ok(signer));
+            }
+            let signer = self.verify_sig(req);
+            self.cache_signer(tx_hash, &signer);
+            Verifier::resp_of_signer(req, signer)
+        })
+    }
+
+    /// Verify the tx like `verfiy_tx`, but always recover its signature instead of
+    /// taking the cached signer, and replace the cached signer with what was recovered.
+    /// Also tells whether the signer of the tx was cached.
+    pub fn reverify_tx(&self, req: &VerifyTxReq, verify_type: VerifyType) -> (VerifyTxResp, bool) {
+        let tx_hash = H256::from_slice(req.get_tx_hash());
+        let signer_cached = self.cached_signer(&tx_hash).is_some();
+        let resp = self.check_before_sig(req, verify_type).unwrap_or_else(|| {
+            let signer = self.verify_sig(req);
+            if signer.is_err() {
+                self.forget_signer(&tx_hash);
+            }
+            self.cache_signer(tx_hash, &signer);
+            Verifier::resp_of_signer(req, signer)
+        });
+        (resp, signer_cached)
+    }
+
+    /// Verify a batch of txs like `verfiy_tx`, recovering their signatures in parallel.
+    /// The responses are in the order of `reqs`.
+    pub fn verify_batch(&self, reqs: &[(VerifyTxReq, VerifyType)]) -> Vec<VerifyTxResp> {
+        self.verify_batch_with_hits(reqs)
+            .into_iter()
+            .map(|(resp, _)| resp)
+            .collect()
+    }
+
+    /// Like `verify_batch`, also telling whether the signer of each tx was cached
+    pub fn verify_batch_with_hits(&self, reqs: &[(VerifyTxReq, VerifyType)]) -> Vec<(VerifyTxResp, bool)> {
+        self.check_batch(reqs).recover(reqs)
+    }
+
+    /// Do the checks of a batch which need the verifier, leaving its signatures to
+    /// recover with `PendingBatch::recover` once the verifier lock is released
+    pub fn check_batch(&self, reqs: &[(VerifyTxReq, VerifyType)]) -> PendingBatch {
+        let resps = reqs.iter()
+            .map(|&(ref req, verify_type)| {
+                self.check_before_sig(req, verify_type).map(|resp| (resp, false)).or_else(|| {
+                    self.cached_signer(&H256::from_slice(req.get_tx_hash()))
+                        .map(|signer| (Verifier::resp_of_signer(req, Ok(signer)), true))
+                })
+            })
+            .collect();
+        PendingBatch {
+            resps: resps,
+            multisig: self.multisig.clone(),
+            sig_pool: self.sig_pool.clone(),
+            signer_cache: self.signer_cache.clone(),
+        }
+    }
+
+    fn cached_signer(&self, tx_hash: &H256) -> Option<PubKey> {
+        let mut cache = self.signer_cache.as_ref()?.lock();
+        cache.get_mut(tx_hash).cloned()
+    }
+
+    fn cache_signer(&self, tx_hash: H256, signer: &Result<PubKey, ()>) {
+        cache_signer(self.signer_cache.as_ref(), tx_hash, signer);
+    }
+
+    fn forget_signer(&self, tx_hash: &H256) {
+        if let Some(ref cache) = self.signer_cache {
+            cache.lock().remove(tx_hash);
+        }
+    }
+
+    /// The response of a tx failing a check done before recovering its signature
+    fn check_before_sig(&self, req: &VerifyTxReq, verify_type: VerifyType) -> Option<VerifyTxResp> {
         let mut resp = VerifyTxResp::new();
         resp.set_tx_hash(req.get_tx_hash().to_vec());
 
-        if req.get_nonce().len() > 128 {
+        let nonce_len = req.get_nonce().len();
+        if nonce_len > self.max_nonce_len || (nonce_len == 0 && self.require_nonce) {
             resp.set_ret(Ret::InvalidNonce);
-            return resp;
+            return Some(resp);
+        }
+
+        if let Some(max_data_size) = self.max_data_size {
+            if req.get_data_len() > max_data_size {
+                resp.set_ret(Ret::InvalidDataSize);
+                return Some(resp);
+            }
+        }
+
+        // A tx of a format this node doesn't know would be misread, so don't try
+        if let Some(ref versions) = self.accepted_versions {
+            if !versions.contains(&req.get_version()) {
+                resp.set_ret(Ret::UnsupportedVersion);
+                return Some(resp);
+            }
+        }
+
+        // A contract creation has no recipient to block. Only new txs are blocked,
+        // a block agreed on by consensus is verified regardless of the local blocklist.
+        if let (Some(blocklist), VerifyType::SingleVerify) = (self.recipient_blocklist.as_ref(), verify_type) {
+            if parse_address(req.get_to()).map_or(false, |to| blocklist.contains(&to)) {
+                resp.set_ret(Ret::BlockedRecipient);
+                return Some(resp);
+            }
         }
 
         let tx_hash = H256::from_slice(req.get_tx_hash());
@@ -211,20 +789,29 @@ impl Verifier {
             } else {
                 resp.set_ret(Ret::NotReady);
             }
-            return resp;
-        }
-        let ret = self.verify_sig(req);
-        if ret.is_err() {
-            resp.set_ret(Ret::BadSig);
-            return resp;
+            return Some(resp);
         }
+        None
+    }
+
+    /// The response of a tx whose signature recovered `signer`
+    fn resp_of_signer(req: &VerifyTxReq, signer: Result<PubKey, ()>) -> VerifyTxResp {
+        let mut resp = VerifyTxResp::new();
+        resp.set_tx_hash(req.get_tx_hash().to_vec());
+        let signer = match signer {
+            Ok(signer) => signer,
+            Err(_) => {
+                resp.set_ret(Ret::BadSig);
+                return resp;
+            }
+        };
         //check signer if req have
         let req_signer = req.get_signer();
-        if !req_signer.is_empty() && req_signer != ret.unwrap().to_vec().as_slice() {
+        if !req_signer.is_empty() && req_signer != signer.to_vec().as_slice() {
             resp.set_ret(Ret::BadSig);
             return resp;
         }
-        resp.set_signer(ret.unwrap().to_vec());
+        resp.set_signer(signer.to_vec());
         resp.set_ret(Ret::OK);
         trace!(
             "verfiy_tx's result:tx_hash={:?}, ret={:?}, signer={:?}",
@@ -235,26 +822,114 @@ impl Verifier {
         resp
     }
 
-    pub fn verify_valid_until_block(&self, valid_until_block: u64) -> bool {
-        let mut result = false;
-        if let Some(height) = self.height_latest {
-            result = valid_until_block > height && valid_until_block <= (height + BLOCKLIMIT);
-            if !result {
-                warn!(
-                    "The new tx is out of time valid_until_block: {:?}, height: {:?}, BLOCKLIMIT: {:?}",
-                    valid_until_block, height, BLOCKLIMIT
-                );
+    /// A tx is valid from the next block until `height + BLOCKLIMIT`, it can't be told before inited
+    pub fn verify_valid_until_block(&self, valid_until_block: u64) -> Result<(), Ret> {
+        let height = match self.height_latest {
+            Some(height) if self.inited => height,
+            _ => return Err(Ret::NotReady),
+        };
+        if valid_until_block == 0 && self.zero_valid_until_never_expires {
+            return Ok(());
+        }
+        if valid_until_block <= height {
+            warn!(
+                "The new tx has expired, valid_until_block: {:?}, height: {:?}",
+                valid_until_block, height
+            );
+            return Err(Ret::InvalidUntilBlock);
+        }
+        if valid_until_block > height + BLOCKLIMIT {
+            warn!(
+                "The new tx is valid too far ahead, valid_until_block: {:?}, height: {:?}, BLOCKLIMIT: {:?}",
+                valid_until_block, height, BLOCKLIMIT
+            );
+            return Err(Ret::InvalidUntilBlock);
+        }
+        Ok(())
+    }
+}
+
+/// Only signers recovered successfully are cached
+fn cache_signer(cache: Option<&Arc<Mutex<LruCache<H256, PubKey>>>>, tx_hash: H256, signer: &Result<PubKey, ()>) {
+    if let (Some(cache), &Ok(signer)) = (cache, signer) {
+        cache.lock().insert(tx_hash, signer);
+    }
+}
+
+/// A batch checked by `Verifier::check_batch`, with what recovering the rest of
+/// its signatures takes from the verifier
+pub struct PendingBatch {
+    /// Responses known without recovering a signature, with whether the signer was cached
+    resps: Vec<Option<(VerifyTxResp, bool)>>,
+    multisig: Option<Arc<MultisigAccounts>>,
+    sig_pool: Option<Arc<Mutex<ThreadPool>>>,
+    signer_cache: Option<Arc<Mutex<LruCache<H256, PubKey>>>>,
+}
+
+impl PendingBatch {
+    /// Recover the signatures left, in parallel if the verifier has a pool for it.
+    /// `reqs` are the ones checked, the responses are in their order.
+    pub fn recover(self, reqs: &[(VerifyTxReq, VerifyType)]) -> Vec<(VerifyTxResp, bool)> {
+        let PendingBatch {
+            mut resps,
+            multisig,
+            sig_pool,
+            signer_cache,
+        } = self;
+        let mut signers: Vec<(usize, Result<PubKey, ()>)> = Vec::new();
+        match sig_pool {
+            Some(ref pool) => {
+                let (signer_sender, signer_receiver) = channel();
+                let pool = pool.lock();
+                for (index, &(ref req, _)) in reqs.iter().enumerate() {
+                    if resps[index].is_some() {
+                        continue;
+                    }
+                    let req = req.clone();
+                    let multisig = multisig.clone();
+                    let signer_sender = signer_sender.clone();
+                    pool.execute(move || {
+                        let _ = signer_sender.send((index, Verifier::recover_signer(&req, multisig.as_ref())));
+                    });
+                }
+                drop(pool);
+                drop(signer_sender);
+                signers.extend(signer_receiver);
             }
+            None => for (index, &(ref req, _)) in reqs.iter().enumerate() {
+                if resps[index].is_none() {
+                    signers.push((index, Verifier::recover_signer(req, multisig.as_ref())));
+                }
+            },
+        }
+        for (index, signer) in signers {
+            let req = &reqs[index].0;
+            cache_signer(signer_cache.as_ref(), H256::from_slice(req.get_tx_hash()), &signer);
+            resps[index] = Some((Verifier::resp_of_signer(req, signer), false));
         }
-        result
+        resps
+            .into_iter()
+            .zip(reqs)
+            .map(|(resp, &(ref req, _))| {
+                // Only if the recovering thread panicked
+                resp.unwrap_or_else(|| (Verifier::resp_of_signer(req, Err(())), false))
+            })
+            .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Verifier;
-    use std::collections::HashSet;
+    use super::{MultisigPolicy, Verifier, VerifyType, BLOCKLIMIT, SECP_SIGNATURE_BYTES_LEN,
+                SM2_SIGNATURE_BYTES_LEN};
+    use crypto::{CreateKey, KeyPair, PubKey, Sign, Signature};
+    use libproto::{Crypto, Message, MsgClass, Ret, Transaction, VerifyTxReq, VerifyTxResp};
+    use protobuf::RepeatedField;
+    use std::collections::{HashMap, HashSet};
+    use std::convert::TryFrom;
     use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use util::{clean_0x, H256, U256};
 
     #[test]
     fn verify_init() {
@@ -286,4 +961,527 @@ mod tests {
         assert_eq!(v.get_height_latest(), Some(101));
         assert_eq!(v.get_height_low(), Some(2));
     }
+
+    #[test]
+    fn verify_far_future_height() {
+        let mut v = Verifier::new();
+        v.set_max_future_height(10);
+        let (tx_pub, rx_pub) = channel();
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+        while rx_pub.try_recv().is_ok() {}
+
+        v.update_hashes(u64::max_value(), HashSet::new(), &tx_pub);
+        assert!(rx_pub.try_recv().is_err());
+        assert_eq!(v.get_height_latest(), Some(0));
+
+        v.update_hashes(10, HashSet::new(), &tx_pub);
+        assert_eq!(rx_pub.try_iter().count(), 10);
+        assert_eq!(v.get_height_latest(), Some(0));
+    }
+
+    #[test]
+    fn verify_zero_valid_until_block() {
+        let mut v = Verifier::new();
+        let (tx_pub, _rx_pub) = channel();
+        for h in 0..11 {
+            v.update_hashes(h, HashSet::new(), &tx_pub);
+        }
+        assert_eq!(v.verify_valid_until_block(0), Err(Ret::InvalidUntilBlock));
+        assert_eq!(v.verify_valid_until_block(11), Ok(()));
+
+        v.set_zero_valid_until_never_expires(true);
+        assert_eq!(v.verify_valid_until_block(0), Ok(()));
+        assert_eq!(v.verify_valid_until_block(11), Ok(()));
+        assert_eq!(v.verify_valid_until_block(10), Err(Ret::InvalidUntilBlock));
+    }
+
+    #[test]
+    fn verify_valid_until_block_bounds() {
+        let mut v = Verifier::new();
+        assert_eq!(v.verify_valid_until_block(1), Err(Ret::NotReady));
+
+        let (tx_pub, _rx_pub) = channel();
+        v.update_hashes(BLOCKLIMIT + 10, HashSet::new(), &tx_pub);
+        let height = BLOCKLIMIT + 10;
+        // Height known but the tx hashes of the latest blocks aren't
+        assert_eq!(v.verify_valid_until_block(height + 1), Err(Ret::NotReady));
+        for h in 11..(height + 1) {
+            v.update_hashes(h, HashSet::new(), &tx_pub);
+        }
+        assert!(v.is_inited());
+
+        assert_eq!(v.verify_valid_until_block(height - 1), Err(Ret::InvalidUntilBlock));
+        assert_eq!(v.verify_valid_until_block(height), Err(Ret::InvalidUntilBlock));
+        assert_eq!(v.verify_valid_until_block(height + 1), Ok(()));
+        assert_eq!(v.verify_valid_until_block(height + BLOCKLIMIT), Ok(()));
+        assert_eq!(v.verify_valid_until_block(height + BLOCKLIMIT + 1), Err(Ret::InvalidUntilBlock));
+    }
+
+    #[test]
+    fn verify_nonce_len() {
+        let mut v = Verifier::new();
+        let (tx_pub, _rx_pub) = channel();
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+        let verify = |v: &Verifier, nonce_len: usize| {
+            let mut req = VerifyTxReq::new();
+            req.set_tx_hash(H256::from(U256::from(1)).to_vec());
+            req.set_nonce(String::from_utf8(vec![b'n'; nonce_len]).unwrap());
+            v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret()
+        };
+
+        assert_eq!(verify(&v, 129), Ret::InvalidNonce);
+        assert!(v.set_max_nonce_len(0).is_err());
+        v.set_max_nonce_len(256).unwrap();
+        assert_eq!(verify(&v, 257), Ret::InvalidNonce);
+        // Past the nonce check, the empty signature fails
+        assert_eq!(verify(&v, 256), Ret::BadSig);
+        assert_eq!(verify(&v, 0), Ret::BadSig);
+
+        v.set_require_nonce(true);
+        assert_eq!(verify(&v, 0), Ret::InvalidNonce);
+        assert_eq!(verify(&v, 1), Ret::BadSig);
+    }
+
+    #[test]
+    fn verify_data_size() {
+        let mut v = Verifier::new();
+        let mut req = VerifyTxReq::new();
+        req.set_tx_hash(H256::from(U256::from(0x1234)).to_vec());
+        req.set_data_len(1024);
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::InvalidDataSize);
+
+        v.set_max_data_size(Some(512));
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::InvalidDataSize);
+
+        req.set_data_len(512);
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::InvalidDataSize);
+    }
+
+    #[test]
+    fn verify_tx_version() {
+        let mut v = Verifier::new();
+        let mut req = VerifyTxReq::new();
+        req.set_tx_hash(H256::from(U256::from(0x1234)).to_vec());
+        req.set_version(1);
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::UnsupportedVersion);
+
+        v.set_accepted_versions(Some(vec![0, 1]));
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::UnsupportedVersion);
+
+        req.set_version(2);
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::UnsupportedVersion);
+    }
+
+    #[test]
+    fn verify_recipient_blocklist() {
+        let blocked = "0xffffffffffffffffffffffffffffffffff020004";
+        let allowed = "ffffffffffffffffffffffffffffffffff020005";
+        let mut v = Verifier::new();
+        let mut req = VerifyTxReq::new();
+        req.set_tx_hash(H256::from(U256::from(0x1234)).to_vec());
+        req.set_to(blocked.to_owned());
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+
+        v.set_recipient_blocklist(Some(&[blocked.to_owned()])).unwrap();
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+        req.set_to(clean_0x(blocked).to_owned());
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+        req.set_to(allowed.to_owned());
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+        req.set_to(String::new());
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+
+        // A bad list leaves the current one in place
+        assert!(v.set_recipient_blocklist(Some(&["0x1234".to_owned()])).is_err());
+        req.set_to(blocked.to_owned());
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+
+        assert_ne!(v.verfiy_tx(&req, VerifyType::BlockVerify).get_ret(), Ret::BlockedRecipient);
+
+        v.set_recipient_blocklist(None).unwrap();
+        assert_ne!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::BlockedRecipient);
+    }
+
+    #[test]
+    fn verify_batched_txhashs_req() {
+        let mut v = Verifier::new();
+        v.set_txhashs_req_batch(10);
+        let (tx_pub, rx_pub) = channel();
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+        v.update_hashes(25, HashSet::new(), &tx_pub);
+
+        let mut heights = Vec::new();
+        let mut msg_count = 0;
+        for (topic, body) in rx_pub.try_iter() {
+            assert_eq!(topic, "auth.blk_tx_hashs_req");
+            let mut msg = Message::try_from(&body[..]).unwrap();
+            match msg.take_content() {
+                MsgClass::BlockTxHashesReq(req) => heights.extend_from_slice(req.get_heights()),
+                _ => panic!("expect BlockTxHashesReq"),
+            }
+            msg_count += 1;
+        }
+        assert_eq!(msg_count, 3);
+        assert_eq!(heights, (1..26).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn verify_multisig_threshold() {
+        let keypairs: Vec<KeyPair> = (0..3).map(|_| KeyPair::gen_keypair()).collect();
+        let outsider = KeyPair::gen_keypair();
+        let account = *KeyPair::gen_keypair().pubkey();
+        let other_account = *KeyPair::gen_keypair().pubkey();
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            account,
+            MultisigPolicy {
+                account: account,
+                threshold: 2,
+                keys: keypairs.iter().map(|keypair| *keypair.pubkey()).collect(),
+            },
+        );
+        accounts.insert(
+            other_account,
+            MultisigPolicy {
+                account: other_account,
+                threshold: 1,
+                keys: vec![*outsider.pubkey()],
+            },
+        );
+        let mut v = Verifier::new();
+        v.set_multisig(Some(accounts));
+
+        let hash = H256::from(U256::from(0x1234));
+        let sign = |keypair: &KeyPair| Signature::sign(keypair.privkey(), &hash).unwrap().0.to_vec();
+        let verify = |account: Option<PubKey>, signatures: Vec<Vec<u8>>| {
+            let mut req = VerifyTxReq::new();
+            req.set_hash(hash.to_vec());
+            req.set_crypto(Crypto::SECP);
+            if let Some(account) = account {
+                req.set_signer(account.to_vec());
+            }
+            req.set_signatures(RepeatedField::from_vec(signatures));
+            v.verify_sig(&req)
+        };
+
+        assert!(verify(Some(account), vec![sign(&keypairs[0])]).is_err());
+        assert!(verify(Some(account), vec![sign(&keypairs[0]), sign(&keypairs[0])]).is_err());
+        assert!(verify(Some(account), vec![sign(&keypairs[0]), sign(&outsider)]).is_err());
+        // The sender is the account, whichever of its keys signed and in whatever order
+        assert_eq!(
+            verify(Some(account), vec![sign(&keypairs[1]), sign(&keypairs[2])]),
+            Ok(account)
+        );
+        assert_eq!(
+            verify(Some(account), vec![sign(&outsider), sign(&keypairs[2]), sign(&keypairs[0])]),
+            Ok(account)
+        );
+        // Keys of one account don't sign for another, nor for an account without a policy
+        assert!(verify(Some(other_account), vec![sign(&keypairs[1]), sign(&keypairs[2])]).is_err());
+        assert_eq!(verify(Some(other_account), vec![sign(&outsider)]), Ok(other_account));
+        assert!(verify(Some(*outsider.pubkey()), vec![sign(&outsider)]).is_err());
+        assert!(verify(None, vec![sign(&keypairs[1]), sign(&keypairs[2])]).is_err());
+    }
+
+    #[test]
+    fn verify_batch_keeps_order() {
+        let keypair = KeyPair::gen_keypair();
+        let dup_hash = H256::from(U256::from(3));
+        let mut v = Verifier::new();
+        v.set_sig_threads(2);
+        let (tx_pub, _rx_pub) = channel();
+        v.update_hashes(0, vec![dup_hash].into_iter().collect(), &tx_pub);
+
+        let build = |tx_hash: H256, signed: bool| {
+            let hash = H256::from(U256::from(0x1234));
+            let signer = if signed { keypair.privkey().clone() } else { KeyPair::gen_keypair().privkey().clone() };
+            let mut req = VerifyTxReq::new();
+            req.set_tx_hash(tx_hash.to_vec());
+            req.set_hash(hash.to_vec());
+            req.set_crypto(Crypto::SECP);
+            req.set_signature(Signature::sign(&signer, &hash).unwrap().0.to_vec());
+            req.set_signer(keypair.pubkey().to_vec());
+            req
+        };
+        let reqs = vec![
+            build(H256::from(U256::from(1)), true),
+            build(H256::from(U256::from(2)), false),
+            build(dup_hash, true),
+            build(H256::from(U256::from(4)), true),
+        ];
+
+        let reqs: Vec<(VerifyTxReq, VerifyType)> =
+            reqs.into_iter().map(|req| (req, VerifyType::SingleVerify)).collect();
+        let resps = v.verify_batch(&reqs);
+        let rets: Vec<Ret> = resps.iter().map(|resp| resp.get_ret()).collect();
+        assert_eq!(rets, vec![Ret::OK, Ret::BadSig, Ret::Dup, Ret::OK]);
+        for (&(ref req, _), resp) in reqs.iter().zip(resps.iter()) {
+            assert_eq!(req.get_tx_hash(), resp.get_tx_hash());
+        }
+        v.set_sig_threads(0);
+        assert_eq!(v.verify_batch(&reqs), resps);
+
+        // Recovered once the verifier is free to change, with what it was checked with
+        v.set_sig_threads(2);
+        let pending = v.check_batch(&reqs);
+        v.update_hashes(1, vec![H256::from(U256::from(4))].into_iter().collect(), &tx_pub);
+        let rets: Vec<Ret> = pending.recover(&reqs).iter().map(|&(ref resp, _)| resp.get_ret()).collect();
+        assert_eq!(rets, vec![Ret::OK, Ret::BadSig, Ret::Dup, Ret::OK]);
+    }
+
+    #[test]
+    fn verify_outcome_stats() {
+        let v = Verifier::new();
+        v.count_outcome(VerifyType::SingleVerify, Ret::OK);
+        v.count_outcome(VerifyType::SingleVerify, Ret::BadSig);
+        v.count_outcome(VerifyType::SingleVerify, Ret::Dup);
+        v.count_outcome(VerifyType::BlockVerify, Ret::OK);
+        v.count_outcome(VerifyType::BlockVerify, Ret::BadSig);
+
+        let stats = v.outcome_stats();
+        assert_eq!(stats.of_ret(Ret::OK), 2);
+        assert_eq!(stats.of_ret(Ret::BadSig), 2);
+        assert_eq!(stats.of_ret(Ret::Dup), 1);
+        assert_eq!(stats.of_ret(Ret::InvalidNonce), 0);
+        assert_eq!(stats.of_type(VerifyType::SingleVerify), 3);
+        assert_eq!(stats.of_type(VerifyType::BlockVerify), 2);
+    }
+
+    #[test]
+    fn verify_rejection_rate() {
+        let mut v = Verifier::new();
+        v.set_rejection_window(4);
+        assert_eq!(v.rejection_rate(), 0.0);
+
+        v.count_outcome(VerifyType::SingleVerify, Ret::BadSig);
+        v.count_outcome(VerifyType::SingleVerify, Ret::OK);
+        assert_eq!(v.recent_rejections(), (1, 2));
+        assert_eq!(v.rejection_rate(), 0.5);
+
+        v.count_outcome(VerifyType::SingleVerify, Ret::Dup);
+        v.count_outcome(VerifyType::BlockVerify, Ret::OK);
+        v.count_outcome(VerifyType::BlockVerify, Ret::BadSig);
+        // The first rejection slid out of the window, the dup and the bad signature are in
+        assert_eq!(v.recent_rejections(), (2, 4));
+        assert_eq!(v.rejection_rate(), 0.5);
+        assert_eq!(v.outcome_stats().of_ret(Ret::OK), 2);
+    }
+
+    #[test]
+    fn verify_with_cached_signer() {
+        let keypair = KeyPair::gen_keypair();
+        let tx_hash = H256::from(U256::from(1));
+        let mut v = Verifier::new();
+        let (tx_pub, _rx_pub) = channel();
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+
+        let hash = H256::from(U256::from(0x1234));
+        let mut req = VerifyTxReq::new();
+        req.set_tx_hash(tx_hash.to_vec());
+        req.set_hash(hash.to_vec());
+        req.set_crypto(Crypto::SECP);
+        req.set_signature(Signature::sign(keypair.privkey(), &hash).unwrap().0.to_vec());
+        let reqs = vec![(req.clone(), VerifyType::SingleVerify)];
+
+        let resps = v.verify_batch_with_hits(&reqs);
+        assert_eq!(resps[0].0.get_ret(), Ret::OK);
+        assert!(!resps[0].1);
+        assert_eq!(v.cached_signer(&tx_hash), Some(*keypair.pubkey()));
+        let resps = v.verify_batch_with_hits(&reqs);
+        assert_eq!(resps[0].0.get_signer(), keypair.pubkey().to_vec().as_slice());
+        assert!(resps[0].1);
+
+        // A tx in a block is a Dup, its signer isn't needed any more
+        v.update_hashes(1, vec![tx_hash].into_iter().collect(), &tx_pub);
+        assert_eq!(v.cached_signer(&tx_hash), None);
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::Dup);
+
+        v.set_signer_cache_size(0);
+        req.set_tx_hash(H256::from(U256::from(2)).to_vec());
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_ret(), Ret::OK);
+        assert_eq!(v.cached_signer(&H256::from(U256::from(2))), None);
+    }
+
+    #[test]
+    fn reverify_replaces_cached_signer() {
+        let keypair = KeyPair::gen_keypair();
+        let stale = KeyPair::gen_keypair();
+        let tx_hash = H256::from(U256::from(1));
+        let mut v = Verifier::new();
+        let (tx_pub, _rx_pub) = channel();
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+
+        let hash = H256::from(U256::from(0x1234));
+        let mut req = VerifyTxReq::new();
+        req.set_tx_hash(tx_hash.to_vec());
+        req.set_hash(hash.to_vec());
+        req.set_crypto(Crypto::SECP);
+        req.set_signature(Signature::sign(keypair.privkey(), &hash).unwrap().0.to_vec());
+
+        // verfiy_tx trusts the cached signer, reverify_tx recovers it again
+        v.cache_signer(tx_hash, &Ok(*stale.pubkey()));
+        assert_eq!(v.verfiy_tx(&req, VerifyType::SingleVerify).get_signer(), stale.pubkey().to_vec().as_slice());
+        let (resp, signer_cached) = v.reverify_tx(&req, VerifyType::SingleVerify);
+        assert!(signer_cached);
+        assert_eq!(resp.get_ret(), Ret::OK);
+        assert_eq!(resp.get_signer(), keypair.pubkey().to_vec().as_slice());
+        assert_eq!(v.cached_signer(&tx_hash), Some(*keypair.pubkey()));
+
+        // A signature which can't be recovered drops the cached signer
+        req.set_signature(vec![0; SECP_SIGNATURE_BYTES_LEN]);
+        let (resp, signer_cached) = v.reverify_tx(&req, VerifyType::SingleVerify);
+        assert!(signer_cached);
+        assert_eq!(resp.get_ret(), Ret::BadSig);
+        assert_eq!(v.cached_signer(&tx_hash), None);
+        let (_, signer_cached) = v.reverify_tx(&req, VerifyType::SingleVerify);
+        assert!(!signer_cached);
+    }
+
+    #[test]
+    fn verify_sig_checks_length_by_crypto() {
+        let v = Verifier::new();
+        let hash = H256::from(U256::from(0x1234));
+        let mut req = VerifyTxReq::new();
+        req.set_hash(hash.to_vec());
+        req.set_crypto(Crypto::SECP);
+        req.set_signature(vec![0; SM2_SIGNATURE_BYTES_LEN]);
+        assert!(v.verify_sig(&req).is_err());
+        req.set_crypto(Crypto::SM2);
+        req.set_signature(vec![0; SECP_SIGNATURE_BYTES_LEN]);
+        assert!(v.verify_sig(&req).is_err());
+    }
+
+    #[test]
+    fn signature_length_follows_crypto() {
+        // A tx signed by the crypto this node is built with has the length expected of its scheme
+        let keypair = KeyPair::gen_keypair();
+        let mut tx = Transaction::new();
+        tx.set_to("1234567".to_string());
+        tx.set_nonce("0".to_string());
+        tx.set_valid_until_block(99);
+        let signed_tx = tx.sign(*keypair.privkey());
+        let req = signed_tx.get_transaction_with_sig().tx_verify_req_msg();
+        assert_eq!(Verifier::signature_bytes_len(req.get_crypto()), req.get_signature().len());
+        assert_eq!(Verifier::new().verify_sig(&req), Ok(*keypair.pubkey()));
+    }
+
+    #[cfg(feature = "secp256k1")]
+    #[test]
+    fn verify_secp_sig() {
+        let keypair = KeyPair::gen_keypair();
+        let hash = H256::from(U256::from(0x1234));
+        let sig = Signature::sign(keypair.privkey(), &hash).unwrap();
+        let v = Verifier::new();
+
+        let mut req = VerifyTxReq::new();
+        req.set_hash(hash.to_vec());
+        req.set_crypto(Crypto::SECP);
+        req.set_signature(sig.0.to_vec());
+        assert_eq!(sig.0.len(), SECP_SIGNATURE_BYTES_LEN);
+        assert_eq!(v.verify_sig(&req), Ok(*keypair.pubkey()));
+
+        // The same bytes are refused as a signature of the other scheme
+        req.set_crypto(Crypto::SM2);
+        assert!(v.verify_sig(&req).is_err());
+    }
+
+    #[cfg(feature = "sm2")]
+    #[test]
+    fn verify_sm2_sig() {
+        let privkey = H256::from(U256::from(0x5eed));
+        let keypair = KeyPair::from_privkey(privkey).unwrap();
+        let hash = H256::from(U256::from(0x1234));
+        let sig = Signature::sign(keypair.privkey(), &hash).unwrap();
+        let v = Verifier::new();
+
+        let mut req = VerifyTxReq::new();
+        req.set_hash(hash.to_vec());
+        req.set_crypto(Crypto::SM2);
+        req.set_signature(sig.0.to_vec());
+        assert_eq!(v.verify_sig(&req), Ok(*keypair.pubkey()));
+
+        req.set_hash(H256::from(U256::from(0x4321)).to_vec());
+        assert!(v.verify_sig(&req).is_err());
+    }
+
+    #[test]
+    fn multisig_policy_from_hex_keys() {
+        let keypair = KeyPair::gen_keypair();
+        let account = KeyPair::gen_keypair();
+        let key = format!("0x{}", keypair.pubkey().hex());
+        let account_key = format!("0x{}", account.pubkey().hex());
+        let policy = MultisigPolicy::from_hex_keys(&account_key, 1, &[key.clone()]).unwrap();
+        assert_eq!(policy.account, *account.pubkey());
+        assert_eq!(policy.keys, vec![*keypair.pubkey()]);
+        assert!(MultisigPolicy::from_hex_keys(&account_key, 2, &[key.clone()]).is_err());
+        assert!(MultisigPolicy::from_hex_keys(&account_key, 1, &["0x1234".to_owned()]).is_err());
+        assert!(MultisigPolicy::from_hex_keys("0x1234", 1, &[key]).is_err());
+    }
+
+    #[test]
+    fn verify_init_eta() {
+        let mut v = Verifier::new();
+        let (tx_pub, _rx_pub) = channel();
+        assert_eq!(v.init_eta(), None);
+
+        v.update_hashes(100, HashSet::new(), &tx_pub);
+        assert_eq!(v.init_eta(), None);
+        let (started, _) = v.backfill_started.unwrap();
+
+        // 10 heights backfilled in 10 seconds, 89 left
+        for i in 1..11 {
+            v.update_hashes(i, HashSet::new(), &tx_pub);
+        }
+        assert_eq!(
+            v.init_eta_at(started + Duration::from_secs(10)),
+            Some(Duration::from_secs(89))
+        );
+
+        for i in 11..100 {
+            v.update_hashes(i, HashSet::new(), &tx_pub);
+        }
+        assert_eq!(v.init_eta(), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn verify_reorg_window() {
+        let mut v = Verifier::new();
+        v.set_reject_during_reorg(true);
+        let (tx_pub, _) = channel();
+        let mut hashes = HashSet::new();
+        hashes.insert(H256::from(U256::from(0x1234)));
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+        v.update_hashes(1, hashes.clone(), &tx_pub);
+        // the same block again is not a reorg
+        v.update_hashes(1, hashes, &tx_pub);
+        assert_eq!(v.is_rejecting_new_tx(), false);
+
+        let mut new_hashes = HashSet::new();
+        new_hashes.insert(H256::from(U256::from(0x5678)));
+        v.update_hashes(1, new_hashes, &tx_pub);
+        assert_eq!(v.is_rejecting_new_tx(), true);
+
+        v.update_hashes(2, HashSet::new(), &tx_pub);
+        assert_eq!(v.is_rejecting_new_tx(), false);
+    }
+
+    #[test]
+    fn verify_tx_height() {
+        let mut v = Verifier::new();
+        let (tx_pub, _) = channel();
+        let hash = H256::from(U256::from(0x1234));
+        let mut hashes = HashSet::new();
+        hashes.insert(hash);
+        v.update_hashes(0, HashSet::new(), &tx_pub);
+        v.update_hashes(1, hashes, &tx_pub);
+        assert_eq!(v.get_tx_height(&hash), Some(1));
+        assert_eq!(v.get_tx_height(&H256::from(U256::from(0x5678))), None);
+
+        let mut resp = VerifyTxResp::new();
+        resp.set_tx_hash(hash.to_vec());
+        resp.set_ret(Ret::Dup);
+        assert_eq!(v.get_dup_height(&resp), None);
+        v.set_report_dup_height(true);
+        assert_eq!(v.get_dup_height(&resp), Some(1));
+    }
 }
@@ -16,18 +16,26 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::{limit_logs, PollFilter, PollId};
-use jsonrpc_types::rpctypes::{Filter, FilterChanges, Index, Log};
+use jsonrpc_types::rpctypes::{Filter, FilterChanges, HashesWithGap, Index, Log};
 use libchain::chain::Chain;
 use types::filter::Filter as EthcoreFilter;
 use types::ids::BlockId;
 use util::H256;
 
+/// Error for a filter id which was never installed, was uninstalled, or expired unpolled
+pub fn filter_not_found(id: PollId) -> String {
+    format!("filter {} not found, it may have expired unpolled", id)
+}
+
 pub trait EthFilter {
     fn new_filter(&self, filter: Filter) -> PollId;
+    fn subscribe_filter(&self, filter: Filter) -> PollId;
+    fn subscribe_reverted_transactions(&self) -> PollId;
     fn new_block_filter(&self) -> PollId;
-    fn filter_changes(&self, index: Index) -> Option<FilterChanges>;
-    fn filter_logs(&self, index: Index) -> Option<Vec<Log>>;
+    fn filter_changes(&self, index: Index) -> Result<FilterChanges, String>;
+    fn filter_logs(&self, index: Index) -> Result<Option<Vec<Log>>, String>;
     fn uninstall_filter(&self, index: Index) -> bool;
+    fn take_filter_changes(&self) -> Vec<(PollId, FilterChanges)>;
 }
 
 impl EthFilter for Chain {
@@ -41,6 +49,35 @@ impl EthFilter for Chain {
         id
     }
 
+    /// Install a log filter whose changes are pushed through the filter notifier,
+    /// kept alive until it's uninstalled
+    fn subscribe_filter(&self, filter: Filter) -> PollId {
+        let block_number = self.get_current_height();
+        let id = {
+            let polls = self.poll_filter();
+            let mut polls = polls.lock();
+            let id = polls.create_poll(PollFilter::Logs(block_number, Default::default(), filter));
+            polls.keep_alive(&id);
+            id
+        };
+        self.subscribed_filters().lock().insert(id);
+        id
+    }
+
+    /// Install a filter collecting the hashes of reverted txs, pushed through the filter notifier,
+    /// kept alive until it's uninstalled
+    fn subscribe_reverted_transactions(&self) -> PollId {
+        let id = {
+            let polls = self.poll_filter();
+            let mut polls = polls.lock();
+            let id = polls.create_poll(PollFilter::RevertedTransactions(Vec::new()));
+            polls.keep_alive(&id);
+            id
+        };
+        self.subscribed_filters().lock().insert(id);
+        id
+    }
+
     fn new_block_filter(&self) -> PollId {
         let polls = self.poll_filter();
         let id = polls
@@ -50,38 +87,72 @@ impl EthFilter for Chain {
         id
     }
 
-    fn filter_changes(&self, index: Index) -> Option<FilterChanges> {
+    /// Changes of a filter since its last poll, an error if there is no such filter or the logs
+    /// can't be served, in which case the filter stays where it was
+    fn filter_changes(&self, index: Index) -> Result<FilterChanges, String> {
         let polls = self.poll_filter();
         let log = match polls.lock().poll_mut(&index.value()) {
-            None => Some(FilterChanges::Empty),
+            // Never installed, uninstalled, or unused for longer than its lifetime
+            None => Err(filter_not_found(index.value())),
             Some(filter) => match *filter {
                 PollFilter::Block(ref mut block_number) => {
                     // + 1, cause we want to return hashes including current block hash.
                     let current_number = self.get_current_height() + 1;
-                    let hashes = (*block_number..current_number)
+                    // only keep the latest blocks of a filter which fell too far behind
+                    let mut from_number = *block_number;
+                    let mut gap = 0;
+                    if let Some(limit) = self.block_filter_limit() {
+                        let pending = current_number.saturating_sub(from_number);
+                        if limit > 0 && pending > limit as u64 {
+                            gap = pending - limit as u64;
+                            from_number = current_number - limit as u64;
+                        }
+                    }
+                    let hashes = (from_number..current_number)
                         .into_iter()
                         .filter_map(|_id| self.block_hash_by_height(_id))
                         .collect::<Vec<H256>>();
 
                     *block_number = current_number;
-                    Some(FilterChanges::Hashes(hashes))
+                    if gap > 0 {
+                        warn!("block filter {:?} skipped {} blocks", index, gap);
+                        Ok(FilterChanges::HashesWithGap(HashesWithGap {
+                            gap: gap,
+                            hashes: hashes,
+                        }))
+                    } else {
+                        Ok(FilterChanges::Hashes(hashes))
+                    }
                 }
                 PollFilter::Logs(ref mut block_number, ref mut _previous_logs, ref filter) => {
-                    // retrive the current block number
+                    // retrive the current block number, a filter which fell too far behind
+                    // catches up over several polls instead of one query over too many blocks
+                    let max_range = self.max_log_block_range();
                     let current_number = self.get_current_height();
+                    let to_number = if max_range > 0 && current_number >= *block_number + max_range {
+                        *block_number + max_range - 1
+                    } else {
+                        current_number
+                    };
+                    // no block since the last poll
+                    if to_number < *block_number {
+                        return Ok(FilterChanges::Logs(Vec::new()));
+                    }
                     // build appropriate filter
                     let mut filter: EthcoreFilter = filter.clone().into();
                     filter.from_block = BlockId::Number(*block_number);
-                    filter.to_block = BlockId::Latest;
-                    // save the number of the next block as a first block from which
-                    // we want to get logs
-                    *block_number = current_number + 1;
+                    filter.to_block = BlockId::Number(to_number);
                     // retrieve logs in range from_block..min(BlockId::Latest..to_block)
                     let limit = filter.limit;
-                    Some(FilterChanges::Logs(limit_logs(
-                        self.get_logs(filter).into_iter().map(Into::into).collect(),
-                        limit,
-                    )))
+                    self.get_logs(filter).map(|logs| {
+                        // save the number of the next block as a first block from which
+                        // we want to get logs, only once the logs up to it are served
+                        *block_number = to_number + 1;
+                        FilterChanges::Logs(limit_logs(logs.into_iter().map(Into::into).collect(), limit))
+                    })
+                }
+                PollFilter::RevertedTransactions(ref mut hashes) => {
+                    Ok(FilterChanges::Hashes(hashes.drain(..).collect()))
                 }
             },
         };
@@ -89,18 +160,20 @@ impl EthFilter for Chain {
         log
     }
 
-    fn filter_logs(&self, index: Index) -> Option<Vec<Log>> {
+    /// All logs of a log filter, None for another kind of filter, an error if there is no such
+    /// filter or the query is too wide to serve
+    fn filter_logs(&self, index: Index) -> Result<Option<Vec<Log>>, String> {
         let polls = self.poll_filter();
-        let log = match polls.lock().poll(&index.value()) {
-            Some(&PollFilter::Logs(ref _block_number, ref _previous_log, ref filter)) => {
-                let filter: EthcoreFilter = filter.clone().into();
-                Some(self.get_logs(filter).into_iter().map(Into::into).collect())
-            }
+        let filter = match polls.lock().poll(&index.value()) {
+            Some(&PollFilter::Logs(ref _block_number, ref _previous_log, ref filter)) => filter.clone(),
             // just empty array
-            _ => None,
+            Some(_) => return Ok(None),
+            None => return Err(filter_not_found(index.value())),
         };
         drop(polls);
-        log
+        let filter: EthcoreFilter = filter.into();
+        self.get_logs(filter)
+            .map(|logs| Some(logs.into_iter().map(Into::into).collect()))
     }
 
     fn uninstall_filter(&self, index: Index) -> bool {
@@ -114,6 +187,220 @@ impl EthFilter for Chain {
             None => false,
         };
         drop(polls);
+        self.subscribed_filters().lock().remove(&index.value());
         is_uninstall
     }
+
+    /// Advance every subscribed filter to the current block, as a poll would,
+    /// returning the filters which have changes. Filters installed for polling are left alone.
+    fn take_filter_changes(&self) -> Vec<(PollId, FilterChanges)> {
+        let live = self.poll_filter().lock().poll_ids();
+        let ids: Vec<PollId> = {
+            let mut subscribed = self.subscribed_filters().lock();
+            subscribed.retain(|id| live.contains(id));
+            subscribed.iter().cloned().collect()
+        };
+        ids.into_iter()
+            .filter_map(|id| match self.filter_changes(Index(id)) {
+                Ok(changes) => Some((id, changes)),
+                Err(err) => {
+                    warn!("subscribed filter {} is retried on the next block: {}", id, err);
+                    None
+                }
+            })
+            .filter(|&(_, ref changes)| match *changes {
+                FilterChanges::Logs(ref logs) => !logs.is_empty(),
+                FilterChanges::Hashes(ref hashes) => !hashes.is_empty(),
+                FilterChanges::HashesWithGap(_) => true,
+                FilterChanges::Empty => false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_crypto::KeyPair;
+    use db;
+    use libchain::block::{Block, BlockBody};
+    use libchain::chain::{BlockInQueue, Config};
+    use libproto::blockchain::{Proof, Transaction};
+    use libproto::executor::{ExecutedResult, ReceiptWithOption};
+    use receipt::Receipt;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use types::log_entry::LogEntry;
+    use types::transaction::SignedTransaction;
+    use util::Address;
+    use util::crypto::CreateKey;
+    use util::kvdb::in_memory;
+
+    /// Enqueue a block with one tx at height 1, as consensus_block_enqueue does
+    fn enqueue_block_with_tx(chain: &Chain) {
+        let keypair = KeyPair::gen_keypair();
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1]);
+        tx.set_to("1234567".to_string());
+        tx.set_nonce("0".to_string());
+        tx.set_valid_until_block(99);
+        let mut body = BlockBody::new();
+        body.set_transactions(vec![SignedTransaction::new(&tx.sign(*keypair.privkey())).unwrap()]);
+        let mut block = Block::new();
+        block.set_number(1);
+        block.set_parent_hash(chain.get_current_hash());
+        block.set_body(body);
+        chain
+            .block_map
+            .write()
+            .insert(1, BlockInQueue::ConsensusBlock(block, Proof::new()));
+    }
+
+    /// Execute the enqueued block, its tx leaving one log
+    fn execute_block_with_log(chain: &Chain) {
+        let log = LogEntry {
+            address: Address::from(1),
+            topics: vec![H256::from(2)],
+            data: vec![3],
+        };
+        let receipt = Receipt::new(None, 0.into(), vec![log], None, 0.into());
+        let mut receipt_with_option = ReceiptWithOption::new();
+        receipt_with_option.set_receipt(receipt.protobuf());
+        let mut ret = ExecutedResult::new();
+        {
+            let info = ret.mut_executed_info();
+            info.mut_header().set_height(1);
+            info.mut_header().set_prevhash(chain.get_current_hash().to_vec());
+            info.mut_header().set_log_bloom(receipt.log_bloom.to_vec());
+            info.mut_receipts().push(receipt_with_option);
+        }
+        let (ctx_pub, _crx_pub) = channel();
+        chain.set_excuted_result(&ret, &ctx_pub);
+    }
+
+    #[test]
+    fn test_block_filter_gap() {
+        let mut config = Config::default();
+        config.block_filter_limit = Some(2);
+        let chain = Chain::init_chain(Arc::new(in_memory(db::NUM_COLUMNS.unwrap_or(0))), config);
+        let id = chain.new_block_filter();
+
+        chain.current_header.write().set_number(1);
+        match chain.filter_changes(Index(id)) {
+            Ok(FilterChanges::Hashes(_)) => {}
+            other => panic!("unexpected filter changes {:?}", other),
+        }
+
+        chain.current_header.write().set_number(10);
+        match chain.filter_changes(Index(id)) {
+            Ok(FilterChanges::HashesWithGap(changes)) => {
+                assert_eq!(changes.gap, 7);
+                assert!(changes.hashes.len() <= 2);
+            }
+            other => panic!("unexpected filter changes {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_filter_changes_pushed_for_enqueued_block() {
+        let chain = Chain::init_chain(Arc::new(in_memory(db::NUM_COLUMNS.unwrap_or(0))), Config::default());
+        let (notifier, notifications) = channel();
+        chain.set_filter_notifier(notifier);
+        let filter = Filter {
+            from_block: None,
+            to_block: None,
+            address: None,
+            topics: None,
+            limit: None,
+        };
+        let id = chain.subscribe_filter(filter.clone());
+        let polled = chain.new_filter(filter);
+
+        enqueue_block_with_tx(&chain);
+        assert!(notifications.try_recv().is_err());
+        execute_block_with_log(&chain);
+
+        match notifications.try_recv() {
+            Ok((notified, FilterChanges::Logs(logs))) => {
+                assert_eq!(notified, id);
+                assert_eq!(logs.len(), 1);
+            }
+            other => panic!("unexpected filter notification {:?}", other),
+        }
+        assert!(notifications.try_recv().is_err());
+        // Already pushed, so a poll has nothing new
+        match chain.filter_changes(Index(id)) {
+            Ok(FilterChanges::Logs(logs)) => assert!(logs.is_empty()),
+            other => panic!("unexpected filter changes {:?}", other),
+        }
+        // Not subscribed, so the changes are still there for the poll
+        match chain.filter_changes(Index(polled)) {
+            Ok(FilterChanges::Logs(logs)) => assert_eq!(logs.len(), 1),
+            other => panic!("unexpected filter changes {:?}", other),
+        }
+
+        assert!(chain.uninstall_filter(Index(id)));
+        assert!(chain.subscribed_filters().lock().is_empty());
+    }
+
+    #[test]
+    fn test_wide_log_queries_are_refused() {
+        let mut config = Config::default();
+        config.max_log_results = Some(0);
+        config.max_log_block_range = Some(1);
+        let chain = Chain::init_chain(Arc::new(in_memory(db::NUM_COLUMNS.unwrap_or(0))), config);
+        enqueue_block_with_tx(&chain);
+        execute_block_with_log(&chain);
+        let filter = |from_block: u64, limit: Option<usize>| EthcoreFilter {
+            from_block: BlockId::Number(from_block),
+            to_block: BlockId::Latest,
+            address: None,
+            topics: vec![None, None, None, None],
+            limit: limit,
+        };
+
+        // Blocks 0 to 1 are one more than allowed
+        assert!(chain.get_logs(filter(0, Some(0))).is_err());
+        // The only log is one more than allowed, unless the filter asks for no more than that
+        assert!(chain.get_logs(filter(1, None)).is_err());
+        assert!(chain.get_logs(filter(1, Some(0))).unwrap().is_empty());
+
+        let id = chain.new_filter(Filter {
+            from_block: None,
+            to_block: None,
+            address: None,
+            topics: None,
+            limit: None,
+        });
+        assert!(chain.filter_logs(Index(id)).is_err());
+        assert_eq!(chain.filter_logs(Index(id + 1)), Err(filter_not_found(id + 1)));
+    }
+
+    #[test]
+    fn test_filter_changes_kept_when_logs_are_refused() {
+        let mut config = Config::default();
+        config.max_log_results = Some(0);
+        config.max_log_block_range = Some(1);
+        let chain = Chain::init_chain(Arc::new(in_memory(db::NUM_COLUMNS.unwrap_or(0))), config);
+        let id = chain.new_filter(Filter {
+            from_block: None,
+            to_block: None,
+            address: None,
+            topics: None,
+            limit: None,
+        });
+        enqueue_block_with_tx(&chain);
+        execute_block_with_log(&chain);
+        let cursor = |chain: &Chain| match chain.poll_filter().lock().poll(&id) {
+            Some(&PollFilter::Logs(block_number, _, _)) => block_number,
+            _ => panic!("filter {} isn't a log filter", id),
+        };
+
+        // Block 0 has no logs, the only log of block 1 is one more than allowed
+        assert_eq!(chain.filter_changes(Index(id)), Ok(FilterChanges::Logs(Vec::new())));
+        assert_eq!(cursor(&chain), 1);
+        assert!(chain.filter_changes(Index(id)).is_err());
+        assert_eq!(cursor(&chain), 1);
+        assert!(chain.filter_changes(Index(id)).is_err());
+    }
 }
@@ -19,4 +19,4 @@ pub mod poll_filter;
 pub mod eth_filter;
 
 pub use self::poll_filter::{limit_logs, PollFilter};
-pub use self::poll_manager::{PollId, PollManager};
+pub use self::poll_manager::{PollId, PollManager, DEFAULT_POLL_LIFETIME};
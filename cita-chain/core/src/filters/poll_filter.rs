@@ -19,6 +19,7 @@
 
 use jsonrpc_types::rpctypes::{Log, Filter};
 use std::collections::HashSet;
+use util::H256;
 pub type BlockNumber = u64;
 
 /// Filter state.
@@ -28,6 +29,8 @@ pub enum PollFilter {
     Block(BlockNumber),
     /// Number of From block number, pending logs and log filter itself.
     Logs(BlockNumber, HashSet<Log>, Filter),
+    /// Hashes of reverted txs which client wasn't notified about yet.
+    RevertedTransactions(Vec<H256>),
 }
 
 /// Returns only last `n` logs
@@ -17,10 +17,11 @@
 //! Indexes all rpc poll requests.
 #![rustfmt_skip]
 
+use std::collections::HashSet;
 use transient_hashmap::{TransientHashMap, Timer, StandardTimer};
 
-/// Lifetime of poll (in seconds).
-const POLL_LIFETIME: u32 = 60;
+/// Lifetime of poll (in seconds), if not configured.
+pub const DEFAULT_POLL_LIFETIME: u32 = 60;
 
 pub type PollId = usize;
 
@@ -32,13 +33,20 @@ where
     T: Timer,
 {
     polls: TransientHashMap<PollId, F, T>,
+    /// Polls which never expire, until they're removed
+    kept_alive: HashSet<PollId>,
     next_available_id: PollId,
 }
 
 impl<F> PollManager<F, StandardTimer> {
     /// Creates new instance of indexer.
     pub fn default() -> Self {
-        PollManager::new_with_timer(Default::default())
+        PollManager::with_lifetime(DEFAULT_POLL_LIFETIME)
+    }
+
+    /// Creates new instance of indexer whose polls live `lifetime` seconds since last used.
+    pub fn with_lifetime(lifetime: u32) -> Self {
+        PollManager::new_with_timer(lifetime, Default::default())
     }
 }
 
@@ -46,9 +54,10 @@ impl<F, T> PollManager<F, T>
 where
     T: Timer,
 {
-    pub fn new_with_timer(timer: T) -> Self {
+    pub fn new_with_timer(lifetime: u32, timer: T) -> Self {
         PollManager {
-            polls: TransientHashMap::new_with_timer(POLL_LIFETIME, timer),
+            polls: TransientHashMap::new_with_timer(lifetime, timer),
+            kept_alive: HashSet::new(),
             next_available_id: 0,
         }
     }
@@ -57,7 +66,7 @@ where
     ///
     /// Stores information when last poll happend.
     pub fn create_poll(&mut self, filter: F) -> PollId {
-        self.polls.prune();
+        self.prune();
 
         let id = self.next_available_id;
         self.polls.insert(id, filter);
@@ -69,19 +78,41 @@ where
     // Implementation is always using `poll_mut`
     /// Get a reference to stored poll filter
     pub fn poll(&mut self, id: &PollId) -> Option<&F> {
-        self.polls.prune();
+        self.prune();
         self.polls.get(id)
     }
 
     /// Get a mutable reference to stored poll filter
     pub fn poll_mut(&mut self, id: &PollId) -> Option<&mut F> {
-        self.polls.prune();
+        self.prune();
         self.polls.get_mut(id)
     }
 
+    /// Returns ids of all live polls.
+    pub fn poll_ids(&mut self) -> Vec<PollId> {
+        self.prune();
+        (0..self.next_available_id).filter(|id| self.polls.contains_key(id)).collect()
+    }
+
+    /// Keeps a poll alive however long it's unused, until it's removed.
+    pub fn keep_alive(&mut self, id: &PollId) {
+        if self.polls.contains_key(id) {
+            self.kept_alive.insert(*id);
+        }
+    }
+
+    /// Drops the polls unused for longer than their lifetime, returning how many there were.
+    pub fn prune(&mut self) -> usize {
+        for id in &self.kept_alive {
+            self.polls.get_mut(id);
+        }
+        self.polls.prune().len()
+    }
+
     /// Removes poll info.
     pub fn remove_poll(&mut self, id: &PollId) {
         self.polls.remove(id);
+        self.kept_alive.remove(id);
     }
 }
 
@@ -106,7 +137,7 @@ mod tests {
         let time = Cell::new(0);
         let timer = TestTimer { time: &time };
 
-        let mut indexer = PollManager::new_with_timer(timer);
+        let mut indexer = PollManager::new_with_timer(DEFAULT_POLL_LIFETIME, timer);
         assert_eq!(indexer.create_poll(20), 0);
         assert_eq!(indexer.create_poll(20), 1);
 
@@ -123,8 +154,55 @@ mod tests {
         assert!(indexer.poll(&0).is_none());
         assert_eq!(*indexer.poll(&1).unwrap(), 23);
 
+        assert_eq!(indexer.poll_ids(), vec![1]);
+
+        indexer.remove_poll(&1);
+        assert!(indexer.poll(&1).is_none());
+        assert!(indexer.poll_ids().is_empty());
+    }
+
+    #[test]
+    fn test_prune_with_lifetime() {
+        let time = Cell::new(0);
+        let timer = TestTimer { time: &time };
+
+        let mut indexer = PollManager::new_with_timer(5, timer);
+        indexer.create_poll(1);
+        indexer.create_poll(2);
+
+        time.set(4);
+        assert!(indexer.poll_mut(&1).is_some());
+        assert_eq!(indexer.prune(), 0);
+
+        time.set(7);
+        assert_eq!(indexer.prune(), 1);
+        assert_eq!(indexer.poll_ids(), vec![1]);
+    }
+
+    #[test]
+    fn test_kept_alive_poll() {
+        let time = Cell::new(0);
+        let timer = TestTimer { time: &time };
+
+        let mut indexer = PollManager::new_with_timer(5, timer);
+        indexer.create_poll(1);
+        indexer.create_poll(2);
+        indexer.keep_alive(&1);
+
+        // Every lookup prunes, none of them drops the kept poll
+        time.set(7);
+        assert!(indexer.poll(&0).is_none());
+        assert_eq!(indexer.create_poll(3), 2);
+        time.set(14);
+        assert_eq!(*indexer.poll(&1).unwrap(), 2);
+        assert!(indexer.poll_mut(&1).is_some());
+        assert_eq!(indexer.poll_ids(), vec![1]);
+
+        // Once removed, it's not kept alive again under a reused id
         indexer.remove_poll(&1);
         assert!(indexer.poll(&1).is_none());
+        indexer.keep_alive(&1);
+        assert!(indexer.poll_ids().is_empty());
     }
 
 }
@@ -132,6 +132,16 @@ impl Block {
         block
     }
 
+    /// Hash of the block as proposed, the one its Tendermint proof commits
+    pub fn crypt_hash(&self) -> H256 {
+        self.protobuf().crypt_hash()
+    }
+
+    /// Recompute the transactions root from the body and compare it with the one in the header
+    pub fn check_transactions_root(&self) -> bool {
+        self.protobuf().check_hash()
+    }
+
     pub fn transaction_addresses(&self, hash: H256) -> HashMap<H256, TransactionAddress> {
         let tx_hashs = self.body().transaction_hashes();
         // Create TransactionAddress
@@ -199,3 +209,43 @@ impl BlockBody {
         self.transactions().iter().map(|ts| ts.hash()).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_crypto::KeyPair;
+    use libproto::blockchain::Transaction;
+    use util::crypto::CreateKey;
+
+    fn generate_block() -> Block {
+        let keypair = KeyPair::gen_keypair();
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1]);
+        tx.set_to("1234567".to_string());
+        tx.set_nonce("0".to_string());
+        tx.set_valid_until_block(99);
+        let signed_tx = tx.sign(*keypair.privkey());
+
+        let mut body = BlockBody::new();
+        body.set_transactions(vec![SignedTransaction::new(&signed_tx).unwrap()]);
+        let transactions_root = body.protobuf().transactions_root();
+
+        let mut block = Block::new();
+        block.set_transactions_root(H256::from_slice(&transactions_root));
+        block.set_body(body);
+        block
+    }
+
+    #[test]
+    fn test_check_transactions_root() {
+        let block = generate_block();
+        assert!(block.check_transactions_root());
+    }
+
+    #[test]
+    fn test_check_tampered_transactions_root() {
+        let mut block = generate_block();
+        block.body.transactions.push(generate_block().body.transactions[0].clone());
+        assert!(!block.check_transactions_root());
+    }
+}
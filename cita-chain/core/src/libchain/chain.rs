@@ -22,12 +22,15 @@ use cache_manager::CacheManager;
 use db;
 use db::*;
 
-use filters::{PollFilter, PollManager};
+use filters::{PollFilter, PollId, PollManager, DEFAULT_POLL_LIFETIME};
+use filters::eth_filter::EthFilter;
 use header::*;
+use jsonrpc_types::rpctypes::{FilterChanges, Genesis as RpcGenesis};
 pub use libchain::block::*;
 use libchain::cache::CacheSize;
 
 use libchain::extras::*;
+use libchain::merkle::MerkleProof;
 use libchain::status::Status;
 pub use libchain::transaction::*;
 
@@ -62,6 +65,12 @@ use util::kvdb::*;
 pub const VERSION: u32 = 0;
 const LOG_BLOOMS_LEVELS: usize = 3;
 const LOG_BLOOMS_ELEMENTS_PER_INDEX: usize = 16;
+/// Latest blocks whose interval is averaged for the block time
+const BLOCK_TIME_WINDOW: u64 = 100;
+/// Most logs one query may return
+pub const DEFAULT_MAX_LOG_RESULTS: usize = 10_000;
+/// Most blocks one log query may span
+pub const DEFAULT_MAX_LOG_BLOCK_RANGE: u64 = 100_000;
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum BlockSource {
@@ -84,6 +93,53 @@ pub struct Config {
     pub check_permission: bool,
     pub check_quota: bool,
     pub check_prooftype: u8,
+    /// Let identical concurrent queries share one lookup
+    pub dedup_queries: Option<bool>,
+    /// Number of threads serving read-only queries off the dispatch thread
+    pub query_threads: Option<usize>,
+    /// Origin id of this node, the same as `id_card` in the network config
+    pub id_card: Option<u32>,
+    /// Serve queries as a read replica without adding any block
+    pub read_only: Option<bool>,
+    /// Most block hashes returned by one poll of a block filter
+    pub block_filter_limit: Option<usize>,
+    /// Panic on messages chain doesn't handle instead of logging them, to catch protocol mismatches in testing
+    pub strict_msg_class: Option<bool>,
+    /// Sync blocks remembered as validated, so copies from other peers aren't validated again
+    pub sync_cache_size: Option<usize>,
+    /// Hold consensus blocks and executed results while this file exists, for coordinated upgrades
+    pub maintenance_file: Option<String>,
+    /// Most messages held in maintenance, later consensus blocks are dropped and synced once it's lifted
+    pub max_maintenance_msgs: Option<usize>,
+    /// Seconds a sync block waits for its proof before it's dropped and requested again, 0 waits forever
+    pub sync_proof_timeout: Option<u64>,
+    /// Most logs one query may return, wider queries are refused
+    pub max_log_results: Option<usize>,
+    /// Most blocks one log query may span, wider queries are refused
+    pub max_log_block_range: Option<u64>,
+    /// Threads serializing large query results in chunks, 0 serializes them on the query thread
+    pub serialize_threads: Option<usize>,
+    /// Fewest items of a query result serialized in chunks
+    pub parallel_serialize_threshold: Option<usize>,
+    /// Seconds an installed filter lives since it was last polled
+    pub filter_lifetime: Option<u32>,
+    /// Most executed results queued for the block processor, further ones wait for room; unbounded if not set
+    pub write_queue_size: Option<usize>,
+    /// Most blocks in one reply to a sync request by heights, larger requests are replied in several
+    pub sync_chunk_blocks: Option<usize>,
+    /// Most encoded bytes of blocks in one reply to a sync request by heights
+    pub sync_chunk_bytes: Option<usize>,
+    /// Seconds a height served to a peer keeps requests for the same heights ignored
+    pub sync_throttle_secs: Option<u64>,
+    /// Most heights served to one peer within `sync_throttle_secs`
+    pub max_sync_heights: Option<usize>,
+    /// Most tx hashes one receipts query may ask for, longer lists are refused
+    pub max_receipts_per_call: Option<usize>,
+    /// Error code answered for a packed tx whose receipt can't be read yet, 0 answers `null` as for an unknown tx
+    pub receipt_not_ready_code: Option<i64>,
+    /// Id of the chain told to clients in its metadata, the same as the network's `chain_id`;
+    /// txs don't carry it
+    pub chain_id: Option<u32>,
 }
 
 impl Config {
@@ -92,6 +148,29 @@ impl Config {
             check_permission: false,
             check_quota: false,
             check_prooftype: 2,
+            dedup_queries: None,
+            query_threads: None,
+            id_card: None,
+            read_only: None,
+            block_filter_limit: None,
+            strict_msg_class: None,
+            sync_cache_size: None,
+            maintenance_file: None,
+            max_maintenance_msgs: None,
+            sync_proof_timeout: None,
+            max_log_results: None,
+            max_log_block_range: None,
+            serialize_threads: None,
+            parallel_serialize_threshold: None,
+            filter_lifetime: None,
+            write_queue_size: None,
+            sync_chunk_blocks: None,
+            sync_chunk_bytes: None,
+            sync_throttle_secs: None,
+            max_sync_heights: None,
+            max_receipts_per_call: None,
+            receipt_not_ready_code: None,
+            chain_id: None,
         }
     }
 
@@ -151,12 +230,26 @@ pub struct Chain {
 
     pub block_gas_limit: AtomicUsize,
     pub account_gas_limit: RwLock<ProtoAccountGasLimit>,
+    /// Gas limits keyed by the height from which they apply
+    gas_limits: RwLock<BTreeMap<BlockNumber, (u64, ProtoAccountGasLimit)>>,
+    /// Validators keyed by the height from which they apply
+    validators: RwLock<BTreeMap<BlockNumber, Vec<Address>>>,
 
     cache_man: Mutex<CacheManager<CacheId>>,
     polls_filter: Arc<Mutex<PollManager<PollFilter>>>,
 
     /// Switch, check proof type for add_sync_block
     pub check_prooftype: u8,
+    block_filter_limit: Option<usize>,
+    max_log_results: usize,
+    max_log_block_range: u64,
+    /// Where filter changes are pushed as blocks are committed
+    filter_notifier: Mutex<Option<Sender<(PollId, FilterChanges)>>>,
+    /// Filters whose changes are pushed instead of polled
+    subscribed_filters: Mutex<HashSet<PollId>>,
+    /// Height up to which txs have been counted and their number, kept in the db
+    /// with each block
+    counted_txs: Mutex<(BlockNumber, u64)>,
 }
 
 /// Get latest status
@@ -233,18 +326,45 @@ impl Chain {
             cache_man: Mutex::new(cache_man),
             db: db,
             state_db: state_db,
-            polls_filter: Arc::new(Mutex::new(PollManager::default())),
+            polls_filter: Arc::new(Mutex::new(PollManager::with_lifetime(
+                chain_config.filter_lifetime.unwrap_or(DEFAULT_POLL_LIFETIME),
+            ))),
             nodes: RwLock::new(Vec::new()),
             block_gas_limit: AtomicUsize::new(18_446_744_073_709_551_615),
             account_gas_limit: RwLock::new(ProtoAccountGasLimit::new()),
+            gas_limits: RwLock::new(BTreeMap::new()),
+            validators: RwLock::new(BTreeMap::new()),
             check_prooftype: chain_config.check_prooftype,
+            block_filter_limit: chain_config.block_filter_limit,
+            max_log_results: chain_config
+                .max_log_results
+                .unwrap_or(DEFAULT_MAX_LOG_RESULTS),
+            max_log_block_range: chain_config
+                .max_log_block_range
+                .unwrap_or(DEFAULT_MAX_LOG_BLOCK_RANGE),
+            filter_notifier: Mutex::new(None),
+            subscribed_filters: Mutex::new(HashSet::new()),
+            counted_txs: Mutex::new((0, 0)),
         };
 
+        // A chain recorded before the count was kept has its txs counted once
+        let counted: Option<TxTotal> = chain.db.read(db::COL_EXTRA, &TotalTransactions);
+        if let Some(counted) = counted {
+            *chain.counted_txs.lock() = (counted.height, counted.total);
+        }
+        let current_height = chain.get_current_height();
+        if chain.counted_txs.lock().0 < current_height {
+            info!("counting txs of the blocks up to {}", current_height);
+            let mut batch = DBTransaction::new();
+            chain.count_block_txs(current_height, None, &mut batch);
+            chain.db.write(batch).expect("DB write failed.");
+        }
+
         chain
     }
 
     /// Get block number by BlockId
-    fn block_number(&self, id: BlockId) -> Option<BlockNumber> {
+    pub fn block_number(&self, id: BlockId) -> Option<BlockNumber> {
         match id {
             BlockId::Number(number) => Some(number),
             BlockId::Hash(hash) => self.block_height_by_hash(hash),
@@ -263,6 +383,13 @@ impl Chain {
     pub fn set_excuted_result_genesis(&self, ret: &ExecutedResult) {
         let blk = Block::default();
         self.set_db_result(ret, &blk);
+
+        let nodes = NodeList {
+            nodes: self.validators(),
+        };
+        let mut batch = DBTransaction::new();
+        batch.write(db::COL_EXTRA, &GenesisNodes, &nodes);
+        self.db.write(batch).expect("genesis nodes DB write failed.");
     }
 
     pub fn set_db_config(&self, ret: &ExecutedResult) {
@@ -273,6 +400,7 @@ impl Chain {
             .map(|vecaddr| Address::from_slice(&vecaddr[..]))
             .collect();
         info!("consensus nodes {:?}", nodes);
+        self.record_validators(ret.get_executed_info().get_header().get_height(), &nodes);
         self.set_excuted_config(
             conf.get_block_gas_limit(),
             conf.get_account_gas_limit(),
@@ -286,6 +414,8 @@ impl Chain {
 
         let info = ret.get_executed_info();
         let number = info.get_header().get_height();
+        let conf = ret.get_config();
+        self.record_gas_limits(number, conf.get_block_gas_limit(), conf.get_account_gas_limit());
         let mut hdr = Header::new();
         let log_bloom = H2048::from(info.get_header().get_log_bloom());
         hdr.set_gas_limit(U256::from(info.get_header().get_gas_limit()));
@@ -354,6 +484,9 @@ impl Chain {
             }
         }
 
+        // Before the bodies are locked, the blocks missing from the count are read
+        self.count_block_txs(number, Some(block.body().transactions().len() as u64), &mut batch);
+
         let mut write_headers = self.block_headers.write();
         let mut write_bodies = self.block_bodies.write();
         let mut write_blooms = self.blocks_blooms.write();
@@ -467,6 +600,7 @@ impl Chain {
                 if self.validate_height(block.number()) && self.validate_hash(block.parent_hash()) {
                     self.set_db_result(&ret, &block);
                     self.broadcast_current_status(&ctx_pub);
+                    self.notify_filters();
                     debug!("set consensus block-{}", number);
                 }
             }
@@ -481,6 +615,7 @@ impl Chain {
                     self.is_sync.store(true, Ordering::SeqCst);
                     self.broadcast_current_status(&ctx_pub);
                     self.is_sync.store(false, Ordering::SeqCst);
+                    self.notify_filters();
                     debug!("finish sync blocks to {}", number);
                 };
             }
@@ -501,6 +636,76 @@ impl Chain {
         *self.nodes.write() = nodes.clone();
     }
 
+    /// Remember the gas limits of the block, only when they differ from the previous ones
+    fn record_gas_limits(&self, number: BlockNumber, bgas_limit: u64, agas_limit: &ProtoAccountGasLimit) {
+        let mut gas_limits = self.gas_limits.write();
+        let changed = gas_limits
+            .range(..number)
+            .next_back()
+            .map_or(true, |(_, limits)| limits.0 != bgas_limit || limits.1 != *agas_limit);
+        if changed {
+            gas_limits.insert(number, (bgas_limit, agas_limit.clone()));
+        }
+    }
+
+    /// Get the block and account gas limits which applied at the block.
+    /// Only the heights processed since this node started are known.
+    pub fn gas_limits(&self, id: BlockId) -> Option<(u64, ProtoAccountGasLimit)> {
+        let number = self.block_number(id)?;
+        if number > self.get_current_height() {
+            return None;
+        }
+        self.gas_limits
+            .read()
+            .range(..number + 1)
+            .next_back()
+            .map(|(_, limits)| limits.clone())
+    }
+
+    /// Remember the validators set by the block, only when they differ from the previous ones
+    fn record_validators(&self, number: BlockNumber, nodes: &[Address]) {
+        let mut validators = self.validators.write();
+        let changed = validators
+            .range(..number)
+            .next_back()
+            .map_or(true, |(_, previous)| previous.as_slice() != nodes);
+        if changed {
+            validators.insert(number, nodes.to_vec());
+        }
+    }
+
+    /// Get the validators set by the executed block of `number`, they sign the proof of the next one.
+    /// Only the heights processed since this node started are known.
+    pub fn validators_at(&self, number: BlockNumber) -> Option<Vec<Address>> {
+        if number > self.get_current_height() {
+            return None;
+        }
+        self.validators
+            .read()
+            .range(..number + 1)
+            .next_back()
+            .map(|(_, nodes)| nodes.clone())
+    }
+
+    /// Get the current consensus authorities
+    pub fn validators(&self) -> Vec<Address> {
+        self.nodes.read().clone()
+    }
+
+    /// Get the genesis block and the validators the chain started with
+    pub fn genesis(&self) -> Option<RpcGenesis> {
+        let header = self.block_header_by_height(0)?;
+        let validators: Option<NodeList> = self.db.read(db::COL_EXTRA, &GenesisNodes);
+        Some(RpcGenesis {
+            hash: header.hash(),
+            timestamp: header.timestamp().into(),
+            prevhash: *header.parent_hash(),
+            state_root: *header.state_root(),
+            consensus: self.get_chain_prooftype().map(|prooftype| format!("{:?}", prooftype)),
+            validators: validators.map(|list| list.nodes),
+        })
+    }
+
     /// Get block by BlockId
     pub fn block(&self, id: BlockId) -> Option<Block> {
         match id {
@@ -528,6 +733,65 @@ impl Chain {
         }
     }
 
+    /// Check the transactions root of the block, None if the block doesn't exist
+    pub fn check_transactions_root(&self, id: BlockId) -> Option<bool> {
+        self.block(id).map(|block| block.check_transactions_root())
+    }
+
+    /// Number of txs in all blocks up to the current height
+    pub fn total_transactions(&self) -> u64 {
+        self.counted_txs.lock().1
+    }
+
+    /// Count the txs of the blocks up to `height` into the batch, `txs` being those of the
+    /// block at `height` when it isn't stored yet. A block counted already is skipped.
+    fn count_block_txs(&self, height: BlockNumber, txs: Option<u64>, batch: &mut DBTransaction) {
+        let mut counted = self.counted_txs.lock();
+        if counted.0 >= height {
+            return;
+        }
+        while counted.0 < height - 1 {
+            let next = counted.0 + 1;
+            counted.1 += self.block_body_by_height(next)
+                .map_or(0, |body| body.transactions().len() as u64);
+            counted.0 = next;
+        }
+        counted.1 += txs.unwrap_or_else(|| {
+            self.block_body_by_height(height)
+                .map_or(0, |body| body.transactions().len() as u64)
+        });
+        counted.0 = height;
+        batch.write(
+            db::COL_EXTRA,
+            &TotalTransactions,
+            &TxTotal {
+                height: counted.0,
+                total: counted.1,
+            },
+        );
+    }
+
+    /// Average interval of the latest blocks in milliseconds, None until there are two blocks
+    /// after genesis, whose timestamp is set by hand
+    pub fn average_block_time(&self) -> Option<u64> {
+        let current_height = self.get_current_height();
+        if current_height < 2 {
+            return None;
+        }
+        let from = ::std::cmp::max(current_height.saturating_sub(BLOCK_TIME_WINDOW), 1);
+        let first = self.block_header_by_height(from)?.timestamp();
+        let last = self.block_header_by_height(current_height)?.timestamp();
+        Some(last.saturating_sub(first) / (current_height - from))
+    }
+
+    /// Get the state roots of two blocks, so clients can check the state progressed
+    /// between them without fetching the blocks
+    pub fn state_roots(&self, from: BlockId, to: BlockId) -> Option<(H256, H256)> {
+        let from = self.block_header(from)?;
+        let to = self.block_header(to)?;
+        Some((*from.state_root(), *to.state_root()))
+    }
+
     /// Get block header by BlockId
     pub fn block_header(&self, id: BlockId) -> Option<Header> {
         match id {
@@ -608,6 +872,12 @@ impl Chain {
         })
     }
 
+    /// Whether the transaction has been packed into a block of this chain,
+    /// regardless of whether its receipt can be read yet.
+    pub fn has_transaction(&self, hash: TransactionId) -> bool {
+        self.transaction_address(hash).is_some()
+    }
+
     /// Get address of transaction by hash.
     fn transaction_address(&self, hash: TransactionId) -> Option<TransactionAddress> {
         let result = self.db
@@ -621,7 +891,7 @@ impl Chain {
     /// Get transaction by address
     fn transaction_by_address(&self, hash: H256, index: usize) -> Option<SignedTransaction> {
         self.block_body_by_hash(hash)
-            .map(|body| body.transactions()[index].clone())
+            .and_then(|body| body.transactions().get(index).cloned())
     }
 
     /// Get transaction hashes by block hash
@@ -629,24 +899,89 @@ impl Chain {
         self.block_body(id).map(|body| body.transaction_hashes())
     }
 
-    /// Get full transaction by hash
-    pub fn full_transaction(&self, hash: TransactionId) -> Option<FullTransaction> {
-        self.transaction_address(hash).map_or(None, |addr| {
-            let index = addr.index;
-            let hash = addr.block_hash;
-            self.block_by_hash(hash).map(|block| {
-                let transactions = block.body().transactions();
-                let tx = transactions[index].protobuf();
-                let mut full_ts = FullTransaction::new();
-                full_ts.set_transaction(tx);
-                full_ts.set_block_number(block.number());
-                full_ts.set_block_hash(hash.to_vec());
-                full_ts.set_index(index as u32);
-                full_ts
-            })
+    /// Get the merkle proof that the transaction is in its block, along with
+    /// the block header holding the transactions root it is checked against
+    pub fn transaction_proof(&self, hash: TransactionId) -> Option<(Header, MerkleProof)> {
+        let address = self.transaction_address(hash)?;
+        let block = self.block_by_hash(address.block_hash)?;
+        let proof = MerkleProof::new(&block.body().transaction_hashes(), address.index)?;
+        if !proof.verify(&hash, block.transactions_root()) {
+            warn!(
+                "proof of tx {:?} doesn't match the transactions root of block {}",
+                hash,
+                block.number()
+            );
+            return None;
+        }
+        Some((block.header().clone(), proof))
+    }
+
+    /// Number of txs in the block, read from its body only. None if the block doesn't exist,
+    /// a block stored but not executed yet doesn't either.
+    pub fn block_transaction_count(&self, id: BlockId) -> Option<usize> {
+        let height = match id {
+            BlockId::Hash(hash) => self.block_height_by_hash(hash)?,
+            BlockId::Number(number) => number,
+            BlockId::Earliest => 0,
+            BlockId::Latest => self.get_current_height(),
+        };
+        if height > self.get_current_height() {
+            return None;
+        }
+        self.block_body_by_height(height)
+            .map(|body| body.transaction_hashes().len())
+    }
+
+    /// Length of the encoded block, None if the block doesn't exist
+    pub fn block_size(&self, id: BlockId) -> Option<usize> {
+        self.block(id).map(|block| {
+            let bytes: Vec<u8> = block.protobuf().try_into().unwrap();
+            bytes.len()
         })
     }
 
+    /// Length of the signed transaction encoded as in its block, None if it isn't packed
+    pub fn transaction_size(&self, hash: TransactionId) -> Option<usize> {
+        let address = self.transaction_address(hash)?;
+        let block = self.block_by_hash(address.block_hash)?;
+        block.body().transactions().get(address.index).map(|tx| {
+            let bytes: Vec<u8> = tx.protobuf().try_into().unwrap();
+            bytes.len()
+        })
+    }
+
+    /// The tx along with the hash and number of its block and its index in it,
+    /// `None` if it isn't on chain
+    pub fn full_transaction(&self, hash: TransactionId) -> Option<FullTransaction> {
+        let address = self.transaction_address(hash)?;
+        let number = self.block_height_by_hash(address.block_hash)?;
+        let body = self.block_body_by_height(number)?;
+        // The address can be left pointing at a block which no longer holds the tx
+        let tx = match body.transactions().get(address.index) {
+            Some(tx) if tx.hash() == hash => tx.protobuf(),
+            _ => return None,
+        };
+        let mut full_ts = FullTransaction::new();
+        full_ts.set_transaction(tx);
+        full_ts.set_block_number(number);
+        full_ts.set_block_hash(address.block_hash.to_vec());
+        full_ts.set_index(address.index as u32);
+        Some(full_ts)
+    }
+
+    /// The tx at `index` in the block, the same as `full_transaction` gives it,
+    /// `None` if the block doesn't exist or holds fewer txs
+    pub fn transaction_at(&self, id: BlockId, index: usize) -> Option<FullTransaction> {
+        let block = self.block(id)?;
+        let tx = block.body().transactions().get(index)?.protobuf();
+        let mut full_ts = FullTransaction::new();
+        full_ts.set_transaction(tx);
+        full_ts.set_block_number(block.number());
+        full_ts.set_block_hash(block.hash().to_vec());
+        full_ts.set_index(index as u32);
+        Some(full_ts)
+    }
+
     pub fn localized_receipt(&self, id: TransactionId) -> Option<LocalizedReceipt> {
         trace!("Get receipt id: {:?}", id);
 
@@ -694,6 +1029,7 @@ impl Chain {
                 cumulative_gas_used: last_receipt.gas_used,
                 gas_used: last_receipt.gas_used - prior_gas_used,
                 contract_address: contract_address,
+                gas_price: stx.gas_price,
                 logs: last_receipt
                     .logs
                     .into_iter()
@@ -851,25 +1187,121 @@ impl Chain {
         }
     }
 
-    pub fn get_logs(&self, filter: Filter) -> Vec<LocalizedLogEntry> {
+    /// The logs blooms of all blocks from `from` to `to` combined, None if a block is missing
+    pub fn logs_bloom(&self, from: BlockNumber, to: BlockNumber) -> Option<H2048> {
+        let mut bloom = H2048::zero();
+        for number in from..(to + 1) {
+            bloom = bloom | *self.block_header_by_height(number)?.log_bloom();
+        }
+        Some(bloom)
+    }
+
+    /// Numbers of the first and last block of `filter`, with tags resolved, or why the
+    /// range is invalid: a block is unknown or after the latest one, or the range is inverted.
+    pub fn log_range(&self, filter: &Filter) -> Result<(BlockNumber, BlockNumber), String> {
+        let current = self.get_current_height();
+        let resolve = |id: BlockId| match self.block_number(id) {
+            Some(number) if number <= current => Ok(number),
+            Some(number) => Err(format!("block {} is after the latest block {}", number, current)),
+            None => Err(format!("unknown block {:?}", id)),
+        };
+        let from = resolve(filter.from_block)?;
+        let to = resolve(filter.to_block)?;
+        if from > to {
+            return Err(format!("fromBlock {} is after toBlock {}", from, to));
+        }
+        Ok((from, to))
+    }
+
+    /// Whether `address` logged anything from `from_block` to `to_block`. Only receipts of the blocks
+    /// whose blooms match are scanned, ruling out false positives, and the scan stops at the first log.
+    pub fn has_logs(&self, address: Address, from_block: BlockId, to_block: BlockId) -> Result<bool, String> {
+        let filter = Filter {
+            from_block: from_block,
+            to_block: to_block,
+            address: Some(vec![address]),
+            topics: vec![None, None, None, None],
+            limit: Some(1),
+        };
+        let (from, to) = self.log_range(&filter)?;
+        let blocks = filter
+            .bloom_possibilities()
+            .iter()
+            .flat_map(|bloom| self.blocks_with_bloom(bloom, from, to))
+            .collect::<Vec<u64>>();
+        Ok(!self.logs(blocks, |entry| filter.matches(entry), Some(1)).is_empty())
+    }
+
+    /// Logs matching `filter`, or why the query is refused: its range is invalid, it spans
+    /// more blocks than `max_log_block_range`, or more logs than `max_log_results` match.
+    pub fn get_logs(&self, filter: Filter) -> Result<Vec<LocalizedLogEntry>, String> {
+        let (from, to) = self.capped_log_range(&filter)?;
+        self.logs_in_range(&filter, from, to)
+    }
+
+    /// Logs matching `filter` like `get_logs`, with the logs blooms of the same blocks combined.
+    /// The range is resolved once, so the bloom covers exactly the blocks the logs are from.
+    pub fn get_logs_with_bloom(&self, filter: Filter) -> Result<(Vec<LocalizedLogEntry>, H2048), String> {
+        let (from, to) = self.capped_log_range(&filter)?;
+        let logs = self.logs_in_range(&filter, from, to)?;
+        let bloom = self.logs_bloom(from, to).ok_or_else(|| format!("no block in {} to {}", from, to))?;
+        Ok((logs, bloom))
+    }
+
+    /// `log_range` of `filter`, refused if it spans more blocks than `max_log_block_range`
+    fn capped_log_range(&self, filter: &Filter) -> Result<(BlockNumber, BlockNumber), String> {
+        let (from, to) = self.log_range(filter)?;
+        if self.max_log_block_range > 0 && to - from >= self.max_log_block_range {
+            return Err(format!(
+                "blocks {} to {} span more than {} blocks, query a narrower range",
+                from, to, self.max_log_block_range
+            ));
+        }
+        Ok((from, to))
+    }
+
+    fn logs_in_range(
+        &self,
+        filter: &Filter,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<LocalizedLogEntry>, String> {
         let blocks = filter.bloom_possibilities().iter()
-            .filter_map(|bloom| self.blocks_with_bloom_by_id(bloom, filter.from_block, filter.to_block))
-            .flat_map(|m| m)
+            .flat_map(|bloom| self.blocks_with_bloom(bloom, from, to))
             // remove duplicate elements
             .collect::<HashSet<u64>>()
             .into_iter()
             .collect::<Vec<u64>>();
 
-        self.logs(blocks, |entry| filter.matches(entry), filter.limit)
+        // Stop collecting once there are more logs than allowed, unless the filter asks for fewer
+        let max = self.max_log_results;
+        match filter.limit {
+            Some(limit) if limit <= max => Ok(self.logs(blocks, |entry| filter.matches(entry), Some(limit))),
+            _ => {
+                let logs = self.logs(blocks, |entry| filter.matches(entry), Some(max + 1));
+                if logs.len() > max {
+                    Err(format!(
+                        "more than {} logs match, query a narrower range or set a limit",
+                        max
+                    ))
+                } else {
+                    Ok(logs)
+                }
+            }
+        }
+    }
+
+    pub fn max_log_block_range(&self) -> u64 {
+        self.max_log_block_range
     }
 
-    /// Delivery block tx hashes to auth
+    /// Delivery block tx hashes to auth, return false if it can't be sent
     pub fn delivery_block_tx_hashes(
         &self,
         block_height: u64,
         tx_hashes: Vec<H256>,
         ctx_pub: &Sender<(String, Vec<u8>)>,
-    ) {
+    ) -> bool {
         let ctx_pub_clone = ctx_pub.clone();
         let mut block_tx_hashes = BlockTxHashes::new();
         block_tx_hashes.set_height(block_height);
@@ -885,11 +1317,21 @@ impl Chain {
         }
         block_tx_hashes.set_tx_hashes(RepeatedField::from_slice(&tx_hashes_in_u8[..]));
         let msg: Message = block_tx_hashes.into();
-
-        ctx_pub_clone
-            .send(("chain.txhashes".to_string(), msg.try_into().unwrap()))
-            .unwrap();
-        trace!("delivery block's tx hashes for height: {}", block_height);
+        // A closed channel shouldn't take the chain down, auth asks again for missing heights.
+        // Its receiver never comes back, so the send isn't retried.
+        match ctx_pub_clone.send(("chain.txhashes".to_string(), msg.try_into().unwrap())) {
+            Ok(_) => {
+                trace!("delivery block's tx hashes for height: {}", block_height);
+                true
+            }
+            Err(_) => {
+                error!(
+                    "failed to delivery block's tx hashes for height: {}, chain.txhashes is disconnected",
+                    block_height
+                );
+                false
+            }
+        }
     }
 
     /// Delivery current rich status
@@ -991,6 +1433,17 @@ impl Chain {
         }
     }
 
+    /// Whether a Tendermint `proof` commits `block`: it's of the block's height and hash and signed
+    /// by enough of the validators set by the parent block. None while those validators aren't known.
+    pub fn check_tendermint_proof(&self, proof: &ProtoProof, block: &Block) -> Option<bool> {
+        let proof = TendermintProof::from(proof.clone());
+        if proof.height as u64 != block.number() || proof.proposal != block.crypt_hash() {
+            return Some(false);
+        }
+        let validators = self.validators_at(block.number().checked_sub(1)?)?;
+        Some(proof.check(proof.height, &validators))
+    }
+
     /// Broadcast new status
     pub fn broadcast_status(&self, ctx_pub: &Sender<(String, Vec<u8>)>) {
         if self.max_store_height.load(Ordering::SeqCst) == 0 {
@@ -1099,12 +1552,72 @@ impl Chain {
     pub fn poll_filter(&self) -> Arc<Mutex<PollManager<PollFilter>>> {
         Arc::clone(&self.polls_filter)
     }
+
+    pub fn block_filter_limit(&self) -> Option<usize> {
+        self.block_filter_limit
+    }
+
+    pub fn subscribed_filters(&self) -> &Mutex<HashSet<PollId>> {
+        &self.subscribed_filters
+    }
+
+    /// Push the changes of subscribed filters to `notifier` once an enqueued block is committed,
+    /// instead of leaving them for the next poll.
+    pub fn set_filter_notifier(&self, notifier: Sender<(PollId, FilterChanges)>) {
+        *self.filter_notifier.lock() = Some(notifier);
+    }
+
+    /// Uninstall the filters nobody polled within their lifetime, returning how many there were.
+    /// Subscribed filters are uninstalled when their connection closes, so they're kept alive.
+    pub fn prune_filters(&self) -> usize {
+        self.polls_filter.lock().prune()
+    }
+
+    /// Hand the hashes of txs dropped from the chain to the reverted tx subscriptions,
+    /// pushing them right away since no block commit is coming for them.
+    pub fn revert_transactions(&self, hashes: &[H256]) {
+        {
+            let mut polls = self.polls_filter.lock();
+            for id in polls.poll_ids() {
+                if let Some(&mut PollFilter::RevertedTransactions(ref mut reverted)) = polls.poll_mut(&id) {
+                    reverted.extend_from_slice(hashes);
+                }
+            }
+        }
+        self.notify_filters();
+    }
+
+    fn notify_filters(&self) {
+        let mut notifier = self.filter_notifier.lock();
+        let closed = match *notifier {
+            Some(ref sender) => self.take_filter_changes()
+                .into_iter()
+                .any(|changes| sender.send(changes).is_err()),
+            None => false,
+        };
+        if closed {
+            warn!("filter notification channel closed, falling back to polling");
+            *notifier = None;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cita_crypto::KeyPair;
+    use libproto::blockchain::Transaction;
+    use log;
+    use std::sync::atomic::ATOMIC_USIZE_INIT;
+    use std::sync::mpsc::channel;
+    use util::crypto::CreateKey;
     use util::H256;
+    use util::kvdb::in_memory;
+
+    fn init_chain() -> Chain {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        Chain::init_chain(Arc::new(db), Config::default())
+    }
 
     #[test]
     fn test_heapsizeof() {
@@ -1127,4 +1640,449 @@ mod tests {
         );
         assert_eq!(block_receipts.heap_size_of_children(), 1856);
     }
+
+    #[test]
+    fn test_gas_limits() {
+        let chain = init_chain();
+        chain.current_header.write().set_number(10);
+        assert_eq!(chain.gas_limits(BlockId::Number(3)), None);
+
+        let mut agas_limit = ProtoAccountGasLimit::new();
+        agas_limit.set_common_gas_limit(1000);
+        chain.record_gas_limits(3, 10_000, &agas_limit);
+        chain.record_gas_limits(4, 10_000, &agas_limit);
+        let mut new_agas_limit = ProtoAccountGasLimit::new();
+        new_agas_limit.set_common_gas_limit(2000);
+        chain.record_gas_limits(6, 20_000, &new_agas_limit);
+        assert_eq!(chain.gas_limits.read().len(), 2);
+
+        assert_eq!(chain.gas_limits(BlockId::Number(2)), None);
+        assert_eq!(
+            chain.gas_limits(BlockId::Number(5)),
+            Some((10_000, agas_limit))
+        );
+        assert_eq!(
+            chain.gas_limits(BlockId::Number(6)),
+            Some((20_000, new_agas_limit.clone()))
+        );
+        assert_eq!(
+            chain.gas_limits(BlockId::Latest),
+            Some((20_000, new_agas_limit))
+        );
+        assert_eq!(chain.gas_limits(BlockId::Number(11)), None);
+    }
+
+    static DISCONNECTED_ERRORS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    /// Counts the errors logged about a disconnected `chain.txhashes`
+    struct DisconnectedErrors;
+
+    impl log::Log for DisconnectedErrors {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Error
+        }
+
+        fn log(&self, record: &log::Record) {
+            let message = format!("{}", record.args());
+            if self.enabled(record.metadata()) && message.contains("chain.txhashes is disconnected") {
+                DISCONNECTED_ERRORS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static DISCONNECTED_ERRORS_LOGGER: DisconnectedErrors = DisconnectedErrors;
+
+    #[test]
+    fn test_delivery_block_tx_hashes_to_closed_channel() {
+        log::set_logger(&DISCONNECTED_ERRORS_LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Error);
+        let chain = init_chain();
+        let (ctx_pub, crx_pub) = channel();
+        assert!(chain.delivery_block_tx_hashes(1, vec![H256::from(U256::from(1))], &ctx_pub));
+        assert_eq!(crx_pub.recv().unwrap().0, "chain.txhashes");
+        assert_eq!(DISCONNECTED_ERRORS.load(Ordering::SeqCst), 0);
+
+        drop(crx_pub);
+        assert!(!chain.delivery_block_tx_hashes(2, vec![H256::from(U256::from(2))], &ctx_pub));
+        assert_eq!(DISCONNECTED_ERRORS.load(Ordering::SeqCst), 1);
+    }
+
+    fn signed_txs(n: usize) -> Vec<SignedTransaction> {
+        let keypair = KeyPair::gen_keypair();
+        (0..n)
+            .map(|nonce| {
+                let mut tx = Transaction::new();
+                tx.set_data(vec![1]);
+                tx.set_to("1234567".to_string());
+                tx.set_nonce(nonce.to_string());
+                tx.set_valid_until_block(99);
+                SignedTransaction::new(&tx.sign(*keypair.privkey())).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chain_stats() {
+        let db = Arc::new(in_memory(db::NUM_COLUMNS.unwrap_or(0)));
+        let chain = Chain::init_chain(db.clone(), Config::default());
+        assert_eq!(chain.total_transactions(), 0);
+        assert_eq!(chain.average_block_time(), None);
+
+        let mut header = Header::new();
+        for (height, txs, timestamp) in vec![(1, 2, 10_000), (2, 1, 13_000), (3, 3, 16_000)] {
+            let mut body = BlockBody::new();
+            body.set_transactions(signed_txs(txs));
+            header.set_number(height);
+            header.set_timestamp(timestamp);
+            chain.block_headers.write().insert(height, header.clone());
+            chain.block_bodies.write().insert(height, body);
+            if height == 2 {
+                *chain.current_header.write() = header.clone();
+                let mut batch = DBTransaction::new();
+                chain.count_block_txs(1, None, &mut batch);
+                db.write(batch).unwrap();
+                assert_eq!(chain.total_transactions(), 2);
+                assert_eq!(chain.average_block_time(), Some(3_000));
+            }
+        }
+        *chain.current_header.write() = header;
+        // The block skipped is counted with the next one
+        let mut batch = DBTransaction::new();
+        chain.count_block_txs(3, Some(3), &mut batch);
+        db.write(batch).unwrap();
+        assert_eq!(chain.total_transactions(), 6);
+        assert_eq!(chain.average_block_time(), Some(3_000));
+
+        // A block counted already isn't counted again
+        let mut batch = DBTransaction::new();
+        chain.count_block_txs(3, Some(3), &mut batch);
+        db.write(batch).unwrap();
+        assert_eq!(chain.total_transactions(), 6);
+
+        // The count is kept in the db
+        let chain = Chain::init_chain(db, Config::default());
+        assert_eq!(chain.total_transactions(), 6);
+    }
+
+    #[test]
+    fn test_log_range() {
+        let chain = init_chain();
+        chain.current_header.write().set_number(5);
+        let filter = |from_block: BlockId, to_block: BlockId| Filter {
+            from_block: from_block,
+            to_block: to_block,
+            address: None,
+            topics: vec![None, None, None, None],
+            limit: None,
+        };
+
+        assert_eq!(
+            chain.log_range(&filter(BlockId::Earliest, BlockId::Latest)),
+            Ok((0, 5))
+        );
+        assert_eq!(
+            chain.log_range(&filter(BlockId::Number(5), BlockId::Latest)),
+            Ok((5, 5))
+        );
+        // Tags are resolved before the range is compared
+        assert!(
+            chain
+                .log_range(&filter(BlockId::Latest, BlockId::Number(3)))
+                .is_err()
+        );
+        assert!(
+            chain
+                .log_range(&filter(BlockId::Number(4), BlockId::Number(6)))
+                .is_err()
+        );
+        assert!(
+            chain
+                .log_range(&filter(BlockId::Hash(H256::from(1)), BlockId::Latest))
+                .is_err()
+        );
+        assert!(chain.get_logs(filter(BlockId::Number(3), BlockId::Number(2))).is_err());
+    }
+
+    #[test]
+    fn test_logs_bloom() {
+        let chain = init_chain();
+        let entries: Vec<LogEntry> = (1..4)
+            .map(|i| LogEntry {
+                address: Address::from(i),
+                topics: vec![H256::from(i * 10)],
+                data: vec![],
+            })
+            .collect();
+        let mut header = Header::new();
+        for (number, entry) in (1..4).zip(entries.iter()) {
+            header.set_number(number);
+            header.set_log_bloom(entry.bloom());
+            chain.block_headers.write().insert(number, header.clone());
+        }
+        *chain.current_header.write() = header;
+
+        let bloom = chain.logs_bloom(1, 3).unwrap();
+        for entry in &entries {
+            assert!(bloom.contains_bloomed(&entry.address.crypt_hash()));
+            assert!(bloom.contains_bloomed(&entry.topics[0].crypt_hash()));
+        }
+        let bloom = chain.logs_bloom(2, 2).unwrap();
+        assert!(!bloom.contains_bloomed(&entries[0].address.crypt_hash()));
+        assert!(bloom.contains_bloomed(&entries[1].address.crypt_hash()));
+        assert!(chain.logs_bloom(2, 4).is_none());
+
+        let filter = |from_block: BlockId, to_block: BlockId| Filter {
+            from_block: from_block,
+            to_block: to_block,
+            address: None,
+            topics: vec![None, None, None, None],
+            limit: None,
+        };
+        // Tags are resolved to the same blocks for the logs and the bloom
+        let (logs, bloom) = chain
+            .get_logs_with_bloom(filter(BlockId::Number(1), BlockId::Latest))
+            .unwrap();
+        assert!(logs.is_empty());
+        assert_eq!(bloom, chain.logs_bloom(1, 3).unwrap());
+        assert!(
+            chain
+                .get_logs_with_bloom(filter(BlockId::Number(2), BlockId::Number(4)))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_has_logs() {
+        let chain = init_chain();
+        let receipt = Receipt::new(
+            None,
+            U256::zero(),
+            vec![
+                LogEntry {
+                    address: Address::from(5),
+                    topics: vec![H256::from(50)],
+                    data: vec![],
+                },
+            ],
+            None,
+            U256::zero(),
+        );
+        let mut body = BlockBody::new();
+        body.set_transactions(signed_txs(1));
+        let mut header = Header::new();
+        for number in 1..4 {
+            header.set_number(number);
+            if number == 2 {
+                header.set_log_bloom(receipt.log_bloom);
+                let hash = header.hash();
+                let bgroup = bc::group::BloomGroupChain::new(chain.blooms_config, &chain);
+                let blooms: HashMap<LogGroupPosition, BloomGroup> = bgroup
+                    .insert(number as bc::Number, Bloom::from(receipt.log_bloom).into())
+                    .into_iter()
+                    .map(|p| (From::from(p.0), From::from(p.1)))
+                    .collect();
+                chain.blocks_blooms.write().extend(blooms);
+                chain
+                    .block_receipts
+                    .write()
+                    .insert(hash, BlockReceipts::new(vec![Some(receipt.clone())]));
+                chain.block_bodies.write().insert(number, body.clone());
+                chain.block_hashes.write().insert(hash, number);
+            } else {
+                header.set_log_bloom(H2048::zero());
+            }
+            chain.block_headers.write().insert(number, header.clone());
+        }
+        *chain.current_header.write() = header;
+
+        let has_logs = |address: u64, from: u64, to: u64| {
+            chain.has_logs(Address::from(address), BlockId::Number(from), BlockId::Number(to))
+        };
+        assert_eq!(has_logs(5, 1, 3), Ok(true));
+        assert_eq!(has_logs(5, 2, 2), Ok(true));
+        assert_eq!(has_logs(5, 3, 3), Ok(false));
+        assert_eq!(has_logs(6, 1, 3), Ok(false));
+        assert!(has_logs(5, 1, 4).is_err());
+    }
+
+    #[test]
+    fn test_genesis() {
+        let chain = init_chain();
+        assert!(chain.genesis().is_none());
+
+        let mut ret = ExecutedResult::new();
+        {
+            let header = ret.mut_executed_info().mut_header();
+            header.set_height(0);
+            header.set_timestamp(1_524_000_000_000);
+            header.set_prevhash(H256::from(1).to_vec());
+            header.set_state_root(H256::from(2).to_vec());
+        }
+        ret.mut_config().mut_nodes().push(Address::from(3).to_vec());
+        chain.set_excuted_result_genesis(&ret);
+
+        let genesis = chain.genesis().unwrap();
+        assert_eq!(genesis.hash, chain.block_hash_by_height(0).unwrap());
+        assert_eq!(genesis.timestamp, U256::from(1_524_000_000_000u64));
+        assert_eq!(genesis.prevhash, H256::from(1));
+        assert_eq!(genesis.state_root, H256::from(2));
+        assert_eq!(genesis.consensus, Some("Tendermint".to_owned()));
+        assert_eq!(genesis.validators, Some(vec![Address::from(3)]));
+
+        // Later validators don't change the ones the chain started with
+        chain.set_excuted_config(0, &ProtoAccountGasLimit::new(), &vec![Address::from(4)]);
+        assert_eq!(chain.genesis().unwrap().validators, Some(vec![Address::from(3)]));
+
+        // A chain started before the validators were recorded doesn't know them
+        let mut batch = DBTransaction::new();
+        batch.delete(db::COL_EXTRA, &GenesisNodes);
+        chain.db.write(batch).unwrap();
+        assert_eq!(chain.genesis().unwrap().validators, None);
+    }
+
+    #[test]
+    fn test_transaction_proof() {
+        let chain = init_chain();
+        let mut body = BlockBody::new();
+        body.set_transactions(signed_txs(5));
+        let transactions_root = H256::from_slice(&body.protobuf().transactions_root());
+
+        let mut header = Header::new();
+        header.set_number(1);
+        header.set_transactions_root(transactions_root);
+        let block = Block {
+            header: header.clone(),
+            body: body.clone(),
+        };
+        chain.block_headers.write().insert(1, header.clone());
+        chain.block_bodies.write().insert(1, body.clone());
+        chain.block_hashes.write().insert(header.hash(), 1);
+        chain
+            .transaction_addresses
+            .write()
+            .extend(block.transaction_addresses(header.hash()));
+
+        for tx_hash in body.transaction_hashes() {
+            let (proof_header, proof) = chain.transaction_proof(tx_hash).unwrap();
+            assert_eq!(proof_header.number(), 1);
+            assert!(proof.verify(&tx_hash, &transactions_root));
+        }
+        assert!(chain.transaction_proof(H256::from(1)).is_none());
+    }
+
+    #[test]
+    fn test_block_transaction_count() {
+        let chain = init_chain();
+        let mut body = BlockBody::new();
+        body.set_transactions(signed_txs(4));
+        let mut header = Header::new();
+        header.set_number(1);
+        chain.block_headers.write().insert(1, header.clone());
+        chain.block_bodies.write().insert(1, body.clone());
+        chain.block_hashes.write().insert(header.hash(), 1);
+        chain.current_header.write().set_number(1);
+
+        assert_eq!(chain.block_transaction_count(BlockId::Number(1)), Some(4));
+        assert_eq!(chain.block_transaction_count(BlockId::Hash(header.hash())), Some(4));
+        assert_eq!(chain.block_transaction_count(BlockId::Latest), Some(4));
+        assert_eq!(chain.block_transaction_count(BlockId::Hash(H256::from(1))), None);
+
+        // Stored but not executed
+        chain.block_bodies.write().insert(2, body);
+        assert_eq!(chain.block_transaction_count(BlockId::Number(2)), None);
+    }
+
+    #[test]
+    fn test_encoded_size() {
+        let chain = init_chain();
+        let mut body = BlockBody::new();
+        body.set_transactions(signed_txs(3));
+        let mut header = Header::new();
+        header.set_number(1);
+        let block = Block {
+            header: header.clone(),
+            body: body.clone(),
+        };
+        chain.block_headers.write().insert(1, header.clone());
+        chain.block_bodies.write().insert(1, body.clone());
+        chain.block_hashes.write().insert(header.hash(), 1);
+        chain
+            .transaction_addresses
+            .write()
+            .extend(block.transaction_addresses(header.hash()));
+
+        let block_len = ::protobuf::Message::write_to_bytes(&block.protobuf())
+            .unwrap()
+            .len();
+        assert_eq!(chain.block_size(BlockId::Number(1)), Some(block_len));
+        assert_eq!(chain.block_size(BlockId::Hash(header.hash())), Some(block_len));
+        assert_eq!(chain.block_size(BlockId::Number(2)), None);
+
+        for tx in body.transactions() {
+            let tx_len = ::protobuf::Message::write_to_bytes(&tx.protobuf())
+                .unwrap()
+                .len();
+            assert_eq!(chain.transaction_size(tx.hash()), Some(tx_len));
+        }
+        assert_eq!(chain.transaction_size(H256::from(1)), None);
+    }
+
+    #[test]
+    fn test_state_roots() {
+        let chain = init_chain();
+        let mut header = Header::new();
+        for (number, state_root) in vec![(1, 1), (2, 1), (3, 2)] {
+            header.set_number(number);
+            header.set_state_root(H256::from(state_root));
+            chain.block_headers.write().insert(number, header.clone());
+        }
+        *chain.current_header.write() = header;
+
+        assert_eq!(
+            chain.state_roots(BlockId::Number(1), BlockId::Number(2)),
+            Some((H256::from(1), H256::from(1)))
+        );
+        assert_eq!(
+            chain.state_roots(BlockId::Number(2), BlockId::Latest),
+            Some((H256::from(1), H256::from(2)))
+        );
+        assert_eq!(chain.state_roots(BlockId::Number(2), BlockId::Number(4)), None);
+    }
+
+    #[test]
+    fn test_validators() {
+        let chain = init_chain();
+        assert!(chain.validators().is_empty());
+
+        let nodes = vec![
+            Address::from("0x1111111111111111111111111111111111111111"),
+            Address::from("0x2222222222222222222222222222222222222222"),
+        ];
+        chain.set_excuted_config(0, &ProtoAccountGasLimit::new(), &nodes);
+        assert_eq!(chain.validators(), nodes);
+    }
+
+    #[test]
+    fn test_validators_at() {
+        let chain = init_chain();
+        let set_validators = |height: u64, validators: &[Address]| {
+            let mut ret = ExecutedResult::new();
+            ret.mut_executed_info().mut_header().set_height(height);
+            for validator in validators {
+                ret.mut_config().mut_nodes().push(validator.to_vec());
+            }
+            chain.set_db_config(&ret);
+        };
+        let (first, second) = (Address::from(1), Address::from(2));
+        let height = chain.get_current_height();
+        set_validators(height, &[first]);
+        assert_eq!(chain.validators_at(height), Some(vec![first]));
+        assert_eq!(chain.validators_at(height + 1), None);
+
+        set_validators(height, &[first, second]);
+        assert_eq!(chain.validators_at(height), Some(vec![first, second]));
+        assert_eq!(chain.validators.read().len(), 1);
+    }
 }
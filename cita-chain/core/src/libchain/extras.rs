@@ -75,6 +75,70 @@ impl Key<BlockNumber> for CurrentHeight {
     }
 }
 
+/// Validators the chain started with, recorded from the genesis result
+pub struct GenesisNodes;
+
+impl Key<NodeList> for GenesisNodes {
+    type Target = H256;
+
+    fn key(&self) -> H256 {
+        H256::from("7cabfb7709b29c16d9e876e876c9988d03f9c3414e1d3ff77ec1de2d0ee59f69")
+    }
+}
+
+/// List of consensus nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeList {
+    pub nodes: Vec<Address>,
+}
+
+impl Decodable for NodeList {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(NodeList { nodes: rlp.as_list()? })
+    }
+}
+
+impl Encodable for NodeList {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append_list(&self.nodes);
+    }
+}
+
+/// Number of txs in all blocks up to a height
+pub struct TotalTransactions;
+
+impl Key<TxTotal> for TotalTransactions {
+    type Target = H256;
+
+    fn key(&self) -> H256 {
+        H256::from("7cabfb7709b29c16d9e876e876c9988d03f9c3414e1d3ff77ec1de2d0ee59f6a")
+    }
+}
+
+/// Txs counted up to a height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxTotal {
+    pub height: BlockNumber,
+    pub total: u64,
+}
+
+impl Decodable for TxTotal {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(TxTotal {
+            height: rlp.val_at(0)?,
+            total: rlp.val_at(1)?,
+        })
+    }
+}
+
+impl Encodable for TxTotal {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.height);
+        s.append(&self.total);
+    }
+}
+
 impl Key<Header> for H256 {
     type Target = H256;
 
@@ -0,0 +1,117 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Inclusion proofs over the complete merkle tree of a block's tx hashes.
+//!
+//! The leaves sit in order at the end of a complete binary tree stored as an array,
+//! and each inner node is the hash of its two children concatenated.
+
+use util::{H256, Hashable};
+
+fn merge(left: &H256, right: &H256) -> H256 {
+    let mut data = left.to_vec();
+    data.extend_from_slice(right);
+    data.crypt_hash()
+}
+
+fn build_tree(hashes: &[H256]) -> Vec<H256> {
+    let leaves = hashes.len();
+    let mut nodes = vec![H256::default(); leaves * 2 - 1];
+    nodes[leaves - 1..].clone_from_slice(hashes);
+    for i in (0..leaves - 1).rev() {
+        nodes[i] = merge(&nodes[i * 2 + 1], &nodes[i * 2 + 2]);
+    }
+    nodes
+}
+
+/// Siblings on the path from a leaf to the root, each with whether it is the left child
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub siblings: Vec<(H256, bool)>,
+}
+
+impl MerkleProof {
+    /// Build the proof of the leaf at `index`, None if it is out of range
+    pub fn new(hashes: &[H256], index: usize) -> Option<Self> {
+        if index >= hashes.len() {
+            return None;
+        }
+        let nodes = build_tree(hashes);
+        let mut pos = hashes.len() - 1 + index;
+        let mut siblings = Vec::new();
+        while pos > 0 {
+            // Left children have odd positions, so an even position has its sibling on the left
+            let sibling_is_left = pos % 2 == 0;
+            let sibling = if sibling_is_left { pos - 1 } else { pos + 1 };
+            siblings.push((nodes[sibling], sibling_is_left));
+            pos = (pos - 1) / 2;
+        }
+        Some(MerkleProof {
+            index: index,
+            siblings: siblings,
+        })
+    }
+
+    /// The root reached by folding `leaf` with the siblings
+    pub fn root(&self, leaf: &H256) -> H256 {
+        self.siblings
+            .iter()
+            .fold(*leaf, |node, &(ref sibling, is_left)| {
+                if is_left {
+                    merge(sibling, &node)
+                } else {
+                    merge(&node, sibling)
+                }
+            })
+    }
+
+    pub fn verify(&self, leaf: &H256, root: &H256) -> bool {
+        self.root(leaf) == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::U256;
+
+    fn hashes(n: u64) -> Vec<H256> {
+        (0..n).map(|i| H256::from(U256::from(i + 1))).collect()
+    }
+
+    #[test]
+    fn test_proof_of_every_leaf() {
+        for n in 1..9 {
+            let hashes = hashes(n);
+            let root = build_tree(&hashes)[0];
+            for (index, leaf) in hashes.iter().enumerate() {
+                let proof = MerkleProof::new(&hashes, index).unwrap();
+                assert!(proof.verify(leaf, &root));
+                assert!(!proof.verify(&H256::from(U256::from(100)), &root));
+            }
+            assert_eq!(MerkleProof::new(&hashes, n as usize), None);
+        }
+    }
+
+    #[test]
+    fn test_single_leaf_is_root() {
+        let hashes = hashes(1);
+        let proof = MerkleProof::new(&hashes, 0).unwrap();
+        assert!(proof.siblings.is_empty());
+        assert_eq!(proof.root(&hashes[0]), hashes[0]);
+    }
+}
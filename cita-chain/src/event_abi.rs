@@ -0,0 +1,148 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Receipt logs decoded against the events of a contract ABI.
+
+use ethabi::{Contract, RawLog};
+use jsonrpc_types::rpctypes::{DecodedLog, DecodedParam, Log, Receipt};
+
+/// Decode the logs of the receipt against the events of `abi`, a JSON ABI which may hold
+/// only the events. The raw logs are kept.
+pub fn decode_receipt_logs(receipt: &mut Receipt, abi: &str) -> Result<(), String> {
+    let contract = Contract::load(abi.as_bytes()).map_err(|err| format!("invalid ABI: {:?}", err))?;
+    let decoded = receipt
+        .logs
+        .iter()
+        .map(|log| decode_log(&contract, log))
+        .collect();
+    receipt.decoded_logs = Some(decoded);
+    Ok(())
+}
+
+/// The log as the first event of the contract it parses as, if any
+fn decode_log(contract: &Contract, log: &Log) -> Option<DecodedLog> {
+    contract
+        .events
+        .values()
+        .filter_map(|event| {
+            let raw = RawLog {
+                topics: log.topics.iter().map(|topic| topic.0).collect(),
+                data: log.data.0.clone(),
+            };
+            event.parse_log(raw).ok().map(|parsed| DecodedLog {
+                event: event.name.clone(),
+                params: parsed
+                    .params
+                    .into_iter()
+                    .map(|param| DecodedParam {
+                        name: param.name,
+                        value: format!("{}", param.value),
+                    })
+                    .collect(),
+            })
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::Token;
+    use jsonrpc_types::bytes::Bytes;
+    use util::{Bloom, H160, H256, U256};
+
+    const ABI: &str = r#"[{
+        "type": "event",
+        "name": "Transfer",
+        "anonymous": false,
+        "inputs": [
+            {"name": "from", "type": "address", "indexed": true},
+            {"name": "value", "type": "uint256", "indexed": false}
+        ]
+    }]"#;
+
+    fn receipt(logs: Vec<Log>) -> Receipt {
+        Receipt {
+            transaction_hash: Some(H256::from(1)),
+            transaction_index: Some(U256::zero()),
+            block_hash: Some(H256::from(2)),
+            block_number: Some(U256::one()),
+            cumulative_gas_used: U256::zero(),
+            gas_used: Some(U256::zero()),
+            contract_address: None,
+            logs: logs,
+            state_root: None,
+            logs_bloom: Bloom::default(),
+            error_message: None,
+            effective_gas_price: None,
+            status: None,
+            decoded_logs: None,
+        }
+    }
+
+    fn log(topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address: H160::from(3),
+            topics: topics,
+            data: Bytes::new(data),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+        }
+    }
+
+    #[test]
+    fn decode_known_event() {
+        let signature = H256::from(Contract::load(ABI.as_bytes()).unwrap().events["Transfer"].signature());
+        let from = H256::from(H160::from(4));
+        let transfer = log(vec![signature, from], H256::from(100).to_vec());
+        let unknown = log(vec![H256::from(5)], vec![]);
+        let mut receipt = receipt(vec![transfer.clone(), unknown.clone()]);
+
+        decode_receipt_logs(&mut receipt, ABI).unwrap();
+
+        assert_eq!(receipt.logs, vec![transfer, unknown]);
+        let decoded = receipt.decoded_logs.unwrap();
+        assert_eq!(decoded.len(), 2);
+        let transfer = decoded[0].clone().unwrap();
+        assert_eq!(transfer.event, "Transfer");
+        assert_eq!(
+            transfer.params,
+            vec![
+                DecodedParam {
+                    name: "from".to_owned(),
+                    value: format!("{}", Token::Address(H160::from(4).0)),
+                },
+                DecodedParam {
+                    name: "value".to_owned(),
+                    value: format!("{}", Token::Uint(H256::from(100).0)),
+                },
+            ]
+        );
+        assert!(decoded[1].is_none());
+    }
+
+    #[test]
+    fn invalid_abi_is_an_error() {
+        let mut receipt = receipt(vec![]);
+        assert!(decode_receipt_logs(&mut receipt, "{").is_err());
+        assert!(receipt.decoded_logs.is_none());
+    }
+}
@@ -17,62 +17,823 @@
 
 #![allow(unused_must_use)]
 
+use core::filters::PollId;
 use core::filters::eth_filter::EthFilter;
+use core::header::Header;
 use core::libchain::block::Block;
 use core::libchain::chain::{BlockInQueue, Chain};
 use error::ErrorCode;
+use event_abi::decode_receipt_logs;
 //CountOrCode
+use json_chunks::to_json_array;
+use jsonrpc_types::ErrorCode as RpcErrorCode;
 use jsonrpc_types::rpctypes::{self as rpctypes, BlockParamsByHash, BlockParamsByNumber, Filter as RpcFilter,
-                              Log as RpcLog, Receipt as RpcReceipt, RpcBlock};
-use libproto::{request, response, Block as ProtobufBlock, BlockTxHashes, BlockTxHashesReq, BlockWithProof,
-               ExecutedResult, Message, MsgClass, OperateType, ProofType, Request_oneof_req as Request, SyncRequest,
-               SyncResponse};
+                              FilterChanges, Log as RpcLog, Receipt as RpcReceipt, RpcBlock};
+use libproto::blockchain::Proof as ProtoProof;
+use libproto::{request, response, Block as ProtobufBlock, BlockTxHashesReq, BlockWithProof, ExecutedResult, Message,
+               MsgClass, OperateType, ProofType, Request_oneof_req as Request, SyncRequest, SyncResponse};
 use proof::TendermintProof;
 use protobuf::RepeatedField;
+use recent_hashes::RecentHashes;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use serde_json;
+use sibling_blocks::SiblingBlocks;
+use single_flight::SingleFlight;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::{Into, TryFrom, TryInto};
 use std::mem;
+use std::ops::Range;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+use sync_throttle::{SyncThrottle, Throttled};
+use threadpool::ThreadPool;
 use types::filter::Filter;
 use types::ids::BlockId;
-use util::H256;
+use util::{Mutex, RwLock, H256, U256};
 
 #[derive(Clone)]
 pub struct Forward {
-    write_sender: Sender<ExecutedResult>,
+    write_sender: ResultSender,
     chain: Arc<Chain>,
     ctx_pub: Sender<(String, Vec<u8>)>,
+    query_flight: Option<Arc<SingleFlight<Vec<u8>, response::Response>>>,
+    query_pool: Option<ThreadPool>,
+    id_card: Option<u32>,
+    /// When each peer origin was last heard from
+    seen_origins: Arc<RwLock<HashMap<u32, Instant>>>,
+    /// Serve queries only, ignoring blocks from consensus, sync and executor
+    read_only: bool,
+    /// Sync responses dropped because consensus already passed all their blocks
+    stale_sync_responses: Arc<AtomicUsize>,
+    /// Messages and requests chain doesn't handle, counted by kind
+    unhandled_msgs: Arc<RwLock<HashMap<String, usize>>>,
+    /// Panic on unhandled messages instead of logging them
+    strict_msg_class: bool,
+    /// Hashes of sync blocks already validated and queued, whichever peer sent them
+    validated_sync_blocks: Arc<Mutex<RecentHashes>>,
+    /// Sync blocks skipped because an identical block was validated already
+    sync_cache_hits: Arc<AtomicUsize>,
+    /// Queued blocks replaced by a different block at the same height
+    sibling_blocks: Arc<Mutex<SiblingBlocks>>,
+    /// Consensus blocks and executed results held in maintenance, None out of maintenance
+    maintenance: Arc<Mutex<Option<Held>>>,
+    /// Most messages held in maintenance
+    max_maintenance_msgs: usize,
+    /// When each queued sync block without its proof was first found waiting, by block hash
+    proofless_sync_blocks: Arc<Mutex<HashMap<H256, Instant>>>,
+    /// How long a sync block may wait for its proof, forever if None
+    sync_proof_timeout: Option<Duration>,
+    /// Most blocks in one reply to a sync request by heights
+    sync_chunk_blocks: usize,
+    /// Most encoded bytes of blocks in one reply to a sync request by heights
+    sync_chunk_bytes: usize,
+    /// Height ranges recently served to each peer
+    sync_throttle: Arc<Mutex<SyncThrottle>>,
+    /// Where large query results are serialized in chunks, on the query thread if None
+    serialize_pool: Option<ThreadPool>,
+    /// Fewest items of a query result serialized in chunks
+    parallel_serialize_threshold: usize,
+    /// Executed results dropped because the block processor was gone
+    lost_executed_results: Arc<AtomicUsize>,
+    /// Most tx hashes one receipts query may ask for
+    max_receipts_per_call: usize,
+    /// Error code answered for a packed tx whose receipt can't be read yet, 0 answers `null`
+    receipt_not_ready_code: i64,
+    /// Id of the chain told to clients
+    chain_id: Option<u32>,
+    /// Serialized metadata of the chain, known once the genesis block is written
+    meta_data: Arc<RwLock<Option<String>>>,
+}
+
+/// Messages held in maintenance
+#[derive(Default)]
+struct Held {
+    msgs: Vec<MsgClass>,
+    /// Consensus blocks dropped because too many messages were held
+    dropped_blocks: usize,
+}
+
+/// Where executed results are sent to the block processor
+#[derive(Clone)]
+pub enum ResultSender {
+    Unbounded(Sender<ExecutedResult>),
+    /// Sending waits while the queue is full, holding back the messages behind it
+    Bounded(SyncSender<ExecutedResult>),
+}
+
+impl ResultSender {
+    /// Gives the result back if the block processor is gone
+    fn send(&self, info: ExecutedResult) -> Result<(), ExecutedResult> {
+        match *self {
+            ResultSender::Unbounded(ref sender) => sender.send(info).map_err(|err| err.0),
+            ResultSender::Bounded(ref sender) => match sender.try_send(info) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(info)) => {
+                    warn!("block processor is behind, waiting to queue an executed result");
+                    sender.send(info).map_err(|err| err.0)
+                }
+                Err(TrySendError::Disconnected(info)) => Err(info),
+            },
+        }
+    }
+}
+
+/// The queue of executed results to the block processor, holding at most `bound` of them if set
+pub fn result_channel(bound: Option<usize>) -> (ResultSender, Receiver<ExecutedResult>) {
+    match bound {
+        Some(bound) => {
+            let (sender, receiver) = sync_channel(bound);
+            (ResultSender::Bounded(sender), receiver)
+        }
+        None => {
+            let (sender, receiver) = channel();
+            (ResultSender::Unbounded(sender), receiver)
+        }
+    }
+}
+
+/// Peers heard from within this many seconds are reported by `cita_getPeers`.
+const RECENT_PEER_SECS: u64 = 300;
+
+/// Most blocks replied in one pull sync window.
+const MAX_SYNC_WINDOW: u64 = 64;
+
+/// Sync blocks remembered as validated if `sync_cache_size` isn't configured.
+pub const DEFAULT_SYNC_CACHE_SIZE: usize = 256;
+
+/// Seconds a sync block waits for its proof if `sync_proof_timeout` isn't configured.
+pub const DEFAULT_SYNC_PROOF_TIMEOUT: u64 = 60;
+
+/// Most blocks in one reply to a sync request by heights if `sync_chunk_blocks` isn't configured.
+pub const DEFAULT_SYNC_CHUNK_BLOCKS: usize = 64;
+
+/// Most encoded bytes of blocks in one reply to a sync request by heights if `sync_chunk_bytes`
+/// isn't configured.
+pub const DEFAULT_SYNC_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Seconds a height served to a peer stays in flight if `sync_throttle_secs` isn't configured,
+/// well below the time a peer waits before asking again.
+pub const DEFAULT_SYNC_THROTTLE_SECS: u64 = 3;
+
+/// Most heights in flight to one peer if `max_sync_heights` isn't configured.
+pub const DEFAULT_MAX_SYNC_HEIGHTS: usize = 1024;
+
+/// Threads serializing large query results if `serialize_threads` isn't configured.
+pub const DEFAULT_SERIALIZE_THREADS: usize = 4;
+
+/// Fewest items of a query result serialized in chunks if `parallel_serialize_threshold` isn't configured.
+pub const DEFAULT_PARALLEL_SERIALIZE_THRESHOLD: usize = 1000;
+
+/// Most tx hashes one receipts query may ask for if `max_receipts_per_call` isn't configured.
+pub const DEFAULT_MAX_RECEIPTS_PER_CALL: usize = 1000;
+
+/// Most messages held in maintenance if `max_maintenance_msgs` isn't configured.
+pub const DEFAULT_MAX_MAINTENANCE_MSGS: usize = 1000;
+
+/// Replaced blocks are remembered for this many of the highest heights.
+const SIBLING_BLOCK_HEIGHTS: usize = 64;
+
+/// Error code returned for a packed tx whose receipt can't be read yet if
+/// `receipt_not_ready_code` isn't configured; unlike a `null` result, clients
+/// should retry the query.
+pub const DEFAULT_RECEIPT_NOT_READY_CODE: i64 = -32_004;
+
+/// Params of a query, or None with `response` answering they are invalid.
+/// Why they are is only traced, clients get a stable message.
+fn parse_params<T: DeserializeOwned>(response: &mut response::Response, params: &str) -> Option<T> {
+    match serde_json::from_str(params) {
+        Ok(params) => Some(params),
+        Err(err) => {
+            trace!("invalid params {}: {:?}", params, err);
+            response.set_code(RpcErrorCode::InvalidParams.code());
+            response.set_error_msg("invalid params".to_owned());
+            None
+        }
+    }
+}
+
+/// Answer a result which can't be serialized as a query error, tracing why.
+fn serialization_failed(response: &mut response::Response, err: serde_json::Error) {
+    trace!("can't serialize the result: {:?}", err);
+    response.set_code(ErrorCode::query_error());
+    response.set_error_msg("can't serialize the result".to_owned());
+}
+
+/// Groups sync blocks, in order, into responses of at most `max_blocks` blocks and `max_bytes`
+/// encoded bytes; a block larger than that is sent alone.
+struct SyncChunks {
+    max_blocks: usize,
+    max_bytes: usize,
+    chunk: Vec<ProtobufBlock>,
+    chunk_bytes: usize,
+}
+
+impl SyncChunks {
+    fn new(max_blocks: usize, max_bytes: usize) -> Self {
+        SyncChunks {
+            max_blocks: max_blocks,
+            max_bytes: max_bytes,
+            chunk: Vec::new(),
+            chunk_bytes: 0,
+        }
+    }
+
+    /// Add the next block, returning the chunk before it once that one is full
+    fn push(&mut self, block: ProtobufBlock) -> Option<Vec<ProtobufBlock>> {
+        let size = ::protobuf::Message::compute_size(&block) as usize;
+        let full = if !self.chunk.is_empty()
+            && (self.chunk.len() >= self.max_blocks || self.chunk_bytes + size > self.max_bytes)
+        {
+            self.chunk_bytes = 0;
+            Some(mem::replace(&mut self.chunk, Vec::new()))
+        } else {
+            None
+        };
+        self.chunk_bytes += size;
+        self.chunk.push(block);
+        full
+    }
+
+    /// The last chunk, if any block is left
+    fn finish(self) -> Option<Vec<ProtobufBlock>> {
+        if self.chunk.is_empty() {
+            None
+        } else {
+            Some(self.chunk)
+        }
+    }
+}
+
+/// Heights of the pull sync window starting at `cursor`, none past `current_height`
+fn sync_window_heights(cursor: u64, window: u64, current_height: u64) -> Range<u64> {
+    if cursor > current_height {
+        return cursor..cursor;
+    }
+    cursor..::std::cmp::min(cursor.saturating_add(window), current_height + 1)
+}
+
+/// Blocks of the pull sync window starting at `cursor`, and the cursor of the
+/// next window, None once `current_height` is reached.
+/// Err with the height of a block of the window which can't be read.
+fn sync_window<F>(
+    cursor: u64,
+    window: u64,
+    current_height: u64,
+    block: F,
+) -> Result<(Vec<ProtobufBlock>, Option<u64>), u64>
+where
+    F: Fn(u64) -> Option<ProtobufBlock>,
+{
+    let heights = sync_window_heights(cursor, window, current_height);
+    if heights.start == heights.end {
+        return Ok((Vec::new(), None));
+    }
+    let end = heights.end;
+    let mut blocks = Vec::new();
+    for height in heights {
+        blocks.push(block(height).ok_or(height)?);
+    }
+    let next_cursor = if end <= current_height { Some(end) } else { None };
+    Ok((blocks, next_cursor))
+}
+
+/// Publish the changes of subscribed filters as they are pushed by the chain,
+/// tagged with the filter id so jsonrpc can stream them to the subscriber.
+pub fn publish_filter_changes(filter_changes: Receiver<(PollId, FilterChanges)>, ctx_pub: Sender<(String, Vec<u8>)>) {
+    for (id, changes) in filter_changes {
+        let mut response = response::Response::new();
+        response.set_subscription(id as u64);
+        response.set_filter_changes(serde_json::to_string(&changes).unwrap());
+        let msg: Message = response.into();
+        if ctx_pub
+            .send(("chain.subscription".to_string(), msg.try_into().unwrap()))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Hash of a sync block's header, the same as the hash of the block once queued.
+fn sync_block_hash(block: &ProtobufBlock) -> H256 {
+    let mut header = Header::from(block.get_header().clone());
+    header.set_version(block.get_version());
+    header.hash()
+}
+
+fn queued_block(block_in_queue: &BlockInQueue) -> &Block {
+    match *block_in_queue {
+        BlockInQueue::Proposal(ref block)
+        | BlockInQueue::ConsensusBlock(ref block, _)
+        | BlockInQueue::SyncBlock((ref block, _)) => block,
+    }
+}
+
+/// Name of the message class, e.g. `Response` for `MsgClass::Response(..)`.
+fn msg_class_name(msg: &MsgClass) -> &'static str {
+    match *msg {
+        MsgClass::Request(_) => "Request",
+        MsgClass::Response(_) => "Response",
+        MsgClass::Status(_) => "Status",
+        MsgClass::RichStatus(_) => "RichStatus",
+        MsgClass::SignedProposal(_) => "SignedProposal",
+        MsgClass::BlockWithProof(_) => "BlockWithProof",
+        MsgClass::BlockTxHashes(_) => "BlockTxHashes",
+        MsgClass::BlockTxHashesReq(_) => "BlockTxHashesReq",
+        MsgClass::VerifyBlockReq(_) => "VerifyBlockReq",
+        MsgClass::VerifyBlockResp(_) => "VerifyBlockResp",
+        MsgClass::SyncRequest(_) => "SyncRequest",
+        MsgClass::SyncResponse(_) => "SyncResponse",
+        MsgClass::ExecutedResult(_) => "ExecutedResult",
+        MsgClass::RawBytes(_) => "RawBytes",
+        _ => "Unknown",
+    }
+}
+
+/// Name of the request, e.g. `peers` for `Request::peers(..)`.
+fn request_name(req: &Request) -> &'static str {
+    match *req {
+        Request::block_by_hash(_) => "block_by_hash",
+        Request::block_by_height(_) => "block_by_height",
+        Request::block_head(_) => "block_head",
+        Request::block_number(_) => "block_number",
+        Request::block_size(_) => "block_size",
+        Request::block_tx_count_by_hash(_) => "block_tx_count_by_hash",
+        Request::block_tx_count_by_height(_) => "block_tx_count_by_height",
+        Request::blocks_at_height(_) => "blocks_at_height",
+        Request::call(_) => "call",
+        Request::chain_stats(_) => "chain_stats",
+        Request::check_transactions_root(_) => "check_transactions_root",
+        Request::code(_) => "code",
+        Request::compare_state_roots(_) => "compare_state_roots",
+        Request::decoded_receipt(_) => "decoded_receipt",
+        Request::filter(_) => "filter",
+        Request::filter_changes(_) => "filter_changes",
+        Request::filter_logs(_) => "filter_logs",
+        Request::gas_limits(_) => "gas_limits",
+        Request::genesis(_) => "genesis",
+        Request::has_logs(_) => "has_logs",
+        Request::heights(_) => "heights",
+        Request::meta_data(_) => "meta_data",
+        Request::new_block_filter(_) => "new_block_filter",
+        Request::new_filter(_) => "new_filter",
+        Request::peers(_) => "peers",
+        Request::simulate(_) => "simulate",
+        Request::subscribe_filter(_) => "subscribe_filter",
+        Request::subscribe_reverted_txs(_) => "subscribe_reverted_txs",
+        Request::transaction(_) => "transaction",
+        Request::transaction_by_position(_) => "transaction_by_position",
+        Request::transaction_count(_) => "transaction_count",
+        Request::transaction_proof(_) => "transaction_proof",
+        Request::transaction_receipt(_) => "transaction_receipt",
+        Request::transaction_receipts(_) => "transaction_receipts",
+        Request::transaction_size(_) => "transaction_size",
+        Request::uninstall_filter(_) => "uninstall_filter",
+        Request::validators(_) => "validators",
+        _ => "unknown",
+    }
+}
+
+/// Read-only queries whose identical concurrent copies can share one lookup.
+fn is_coalescable(req: &Request) -> bool {
+    match *req {
+        Request::block_by_hash(_)
+        | Request::block_by_height(_)
+        | Request::transaction(_)
+        | Request::transaction_receipt(_) => true,
+        _ => false,
+    }
+}
+
+/// Queries which only read the chain and can be answered out of order.
+fn is_read_only(req: &Request) -> bool {
+    match *req {
+        Request::block_number(_)
+        | Request::block_by_hash(_)
+        | Request::block_by_height(_)
+        | Request::transaction(_)
+        | Request::transaction_receipt(_)
+        | Request::decoded_receipt(_)
+        | Request::transaction_receipts(_)
+        | Request::filter(_)
+        | Request::validators(_)
+        | Request::check_transactions_root(_)
+        | Request::gas_limits(_)
+        | Request::peers(_)
+        | Request::compare_state_roots(_)
+        | Request::transaction_proof(_)
+        | Request::chain_stats(_)
+        | Request::blocks_at_height(_)
+        | Request::heights(_)
+        | Request::block_head(_)
+        | Request::block_size(_)
+        | Request::transaction_size(_)
+        | Request::block_tx_count_by_hash(_)
+        | Request::block_tx_count_by_height(_)
+        | Request::transaction_by_position(_)
+        | Request::has_logs(_)
+        | Request::genesis(_)
+        | Request::meta_data(_) => true,
+        _ => false,
+    }
 }
 
 // TODO: Add future client to support forward
 impl Forward {
-    pub fn new(chain: Arc<Chain>, ctx_pub: Sender<(String, Vec<u8>)>, write_sender: Sender<ExecutedResult>) -> Forward {
+    pub fn new(
+        chain: Arc<Chain>,
+        ctx_pub: Sender<(String, Vec<u8>)>,
+        write_sender: ResultSender,
+        dedup_queries: bool,
+        query_threads: usize,
+        id_card: Option<u32>,
+        read_only: bool,
+    ) -> Forward {
         Forward {
             chain: chain,
             ctx_pub: ctx_pub,
             write_sender: write_sender,
+            query_flight: if dedup_queries {
+                Some(Arc::new(SingleFlight::new()))
+            } else {
+                None
+            },
+            query_pool: if query_threads > 0 {
+                Some(ThreadPool::new(query_threads))
+            } else {
+                None
+            },
+            id_card: id_card,
+            seen_origins: Arc::new(RwLock::new(HashMap::new())),
+            read_only: read_only,
+            stale_sync_responses: Arc::new(AtomicUsize::new(0)),
+            lost_executed_results: Arc::new(AtomicUsize::new(0)),
+            unhandled_msgs: Arc::new(RwLock::new(HashMap::new())),
+            strict_msg_class: false,
+            validated_sync_blocks: Arc::new(Mutex::new(RecentHashes::new(DEFAULT_SYNC_CACHE_SIZE))),
+            sync_cache_hits: Arc::new(AtomicUsize::new(0)),
+            sibling_blocks: Arc::new(Mutex::new(SiblingBlocks::new(SIBLING_BLOCK_HEIGHTS))),
+            maintenance: Arc::new(Mutex::new(None)),
+            max_maintenance_msgs: DEFAULT_MAX_MAINTENANCE_MSGS,
+            proofless_sync_blocks: Arc::new(Mutex::new(HashMap::new())),
+            sync_proof_timeout: Some(Duration::from_secs(DEFAULT_SYNC_PROOF_TIMEOUT)),
+            sync_chunk_blocks: DEFAULT_SYNC_CHUNK_BLOCKS,
+            sync_chunk_bytes: DEFAULT_SYNC_CHUNK_BYTES,
+            sync_throttle: Arc::new(Mutex::new(SyncThrottle::new(
+                Duration::from_secs(DEFAULT_SYNC_THROTTLE_SECS),
+                DEFAULT_MAX_SYNC_HEIGHTS,
+            ))),
+            serialize_pool: None,
+            parallel_serialize_threshold: DEFAULT_PARALLEL_SERIALIZE_THRESHOLD,
+            max_receipts_per_call: DEFAULT_MAX_RECEIPTS_PER_CALL,
+            receipt_not_ready_code: DEFAULT_RECEIPT_NOT_READY_CODE,
+            chain_id: None,
+            meta_data: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn set_strict_msg_class(&mut self, strict: bool) {
+        self.strict_msg_class = strict;
+    }
+
+    /// Remember up to `size` validated sync blocks, 0 validates every copy received
+    pub fn set_sync_cache_size(&mut self, size: usize) {
+        self.validated_sync_blocks = Arc::new(Mutex::new(RecentHashes::new(size)));
+    }
+
+    /// Drop sync blocks waiting longer than `secs` for their proof, 0 waits forever
+    pub fn set_sync_proof_timeout(&mut self, secs: u64) {
+        self.sync_proof_timeout = if secs > 0 {
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        };
+    }
+
+    /// Cap each reply to a sync request by heights at `blocks` blocks and `bytes` encoded bytes,
+    /// replying as many as it takes
+    pub fn set_sync_chunk(&mut self, blocks: usize, bytes: usize) {
+        self.sync_chunk_blocks = blocks;
+        self.sync_chunk_bytes = bytes;
+    }
+
+    /// Ignore sync requests for heights served to the same peer in the last `secs` seconds, and
+    /// those beyond `max_heights` heights served to it meanwhile, 0 seconds serves every request
+    pub fn set_sync_throttle(&mut self, secs: u64, max_heights: usize) {
+        self.sync_throttle = Arc::new(Mutex::new(SyncThrottle::new(Duration::from_secs(secs), max_heights)));
+    }
+
+    /// Serialize query results of at least `threshold` items in chunks on `threads` threads, 0 threads
+    /// serializes every result on the query thread
+    pub fn set_parallel_serialize(&mut self, threads: usize, threshold: usize) {
+        self.serialize_pool = if threads > 0 {
+            Some(ThreadPool::with_name("serialize_pool".to_string(), threads))
+        } else {
+            None
+        };
+        self.parallel_serialize_threshold = threshold;
+    }
+
+    /// Refuse receipts queries asking for more than `max` tx hashes
+    pub fn set_max_receipts_per_call(&mut self, max: usize) {
+        self.max_receipts_per_call = max;
+    }
+
+    /// Answer `code` for a packed tx whose receipt can't be read yet, or `null` if it's 0
+    pub fn set_receipt_not_ready_code(&mut self, code: i64) {
+        self.receipt_not_ready_code = code;
+    }
+
+    /// Hold at most `max` messages in maintenance
+    pub fn set_max_maintenance_msgs(&mut self, max: usize) {
+        self.max_maintenance_msgs = max;
+    }
+
+    pub fn set_chain_id(&mut self, chain_id: Option<u32>) {
+        self.chain_id = chain_id;
+        self.meta_data = Arc::new(RwLock::new(None));
+    }
+
+    /// Metadata of the chain, serialized once as none of it changes after the genesis block
+    fn meta_data(&self) -> Option<String> {
+        if let Some(ref meta_data) = *self.meta_data.read() {
+            return Some(meta_data.clone());
+        }
+        let genesis = self.chain.genesis()?;
+        let meta_data = rpctypes::MetaData {
+            chain_id: self.chain_id.map(U256::from),
+            genesis_hash: genesis.hash,
+            consensus: genesis.consensus,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        };
+        let serialized = serde_json::to_string(&meta_data).unwrap();
+        *self.meta_data.write() = Some(serialized.clone());
+        Some(serialized)
+    }
+
+    fn to_json_array<T: Serialize + Send + 'static>(&self, items: Vec<T>) -> Result<String, serde_json::Error> {
+        to_json_array(items, self.serialize_pool.as_ref(), self.parallel_serialize_threshold)
+    }
+
+    /// Drop the sync blocks which waited too long for their proof and return their heights.
+    /// The status is announced again, so network requests them anew.
+    pub fn expire_proofless_sync_blocks(&self) -> Vec<u64> {
+        self.expire_proofless_sync_blocks_at(Instant::now())
+    }
+
+    fn expire_proofless_sync_blocks_at(&self, now: Instant) -> Vec<u64> {
+        let timeout = match self.sync_proof_timeout {
+            Some(timeout) => timeout,
+            None => return Vec::new(),
+        };
+        let current_height = self.chain.get_current_height();
+        let mut expired = Vec::new();
+        {
+            let mut blocks = self.chain.block_map.write();
+            let mut waiting = self.proofless_sync_blocks.lock();
+            let proofless: Vec<(u64, H256)> = blocks
+                .iter()
+                .filter_map(|(height, block_in_queue)| match *block_in_queue {
+                    BlockInQueue::SyncBlock((ref block, None)) if *height > current_height => {
+                        Some((*height, block.hash()))
+                    }
+                    _ => None,
+                })
+                .collect();
+            waiting.retain(|hash, _| proofless.iter().any(|&(_, waiting_hash)| waiting_hash == *hash));
+            for (height, hash) in proofless {
+                let since = *waiting.entry(hash).or_insert(now);
+                if now.duration_since(since) < timeout {
+                    continue;
+                }
+                warn!(
+                    "sync: block-{} {:?} got no proof in {:?}, drop it and request it again",
+                    height, hash, timeout
+                );
+                blocks.remove(&height);
+                waiting.remove(&hash);
+                self.validated_sync_blocks.lock().remove(&hash);
+                expired.push(height);
+            }
+        }
+        if !expired.is_empty() {
+            self.chain.broadcast_status(&self.ctx_pub);
+        }
+        expired
+    }
+
+    /// In maintenance consensus blocks and executed results are held instead of added,
+    /// they are added in the order received once maintenance is lifted. Queries are still served.
+    /// Consensus blocks beyond `max_maintenance_msgs` are dropped, the status told once
+    /// maintenance is lifted has them synced again.
+    pub fn set_maintenance(&self, on: bool) {
+        let mut maintenance = self.maintenance.lock();
+        if on {
+            if maintenance.is_none() {
+                info!("enter maintenance, hold consensus blocks and executed results");
+                *maintenance = Some(Held::default());
+            }
+        } else if let Some(held) = maintenance.take() {
+            info!(
+                "lift maintenance, add {} held messages, {} consensus blocks were dropped",
+                held.msgs.len(),
+                held.dropped_blocks
+            );
+            for msg in held.msgs {
+                self.add_consensus_msg(msg);
+            }
+            if held.dropped_blocks > 0 {
+                self.chain.broadcast_status(&self.ctx_pub);
+            }
+        }
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.lock().is_some()
+    }
+
+    fn hold_or_add_consensus_msg(&self, msg: MsgClass) {
+        if let Some(ref mut held) = *self.maintenance.lock() {
+            // Executed results can't be had again, so only consensus blocks are dropped
+            let is_block = match msg {
+                MsgClass::BlockWithProof(_) => true,
+                _ => false,
+            };
+            if is_block && held.msgs.len() >= self.max_maintenance_msgs {
+                held.dropped_blocks += 1;
+                warn!(
+                    "{} messages held in maintenance, drop consensus block, {} dropped so far",
+                    held.msgs.len(),
+                    held.dropped_blocks
+                );
+            } else {
+                held.msgs.push(msg);
+            }
+            return;
+        }
+        self.add_consensus_msg(msg);
+    }
+
+    fn add_consensus_msg(&self, msg: MsgClass) {
+        match msg {
+            //send to block_processor to operate
+            MsgClass::ExecutedResult(info) => {
+                if let Err(info) = self.write_sender.send(info) {
+                    let lost = self.lost_executed_results.fetch_add(1, Ordering::Relaxed) + 1;
+                    error!(
+                        "block processor is gone, drop executed result of height {}, {} dropped so far",
+                        info.get_executed_info().get_header().get_height(),
+                        lost
+                    );
+                }
+            }
+            MsgClass::BlockWithProof(proof_blk) => {
+                self.consensus_block_enqueue(proof_blk);
+            }
+            msg => warn!("not a consensus message {}", msg_class_name(&msg)),
+        }
+    }
+
+    /// How many messages of each kind chain didn't handle
+    pub fn unhandled_msgs(&self) -> HashMap<String, usize> {
+        self.unhandled_msgs.read().clone()
+    }
+
+    fn note_unhandled(&self, kind: String) {
+        let count = {
+            let mut unhandled = self.unhandled_msgs.write();
+            let count = unhandled.entry(kind.clone()).or_insert(0);
+            *count += 1;
+            *count
+        };
+        if self.strict_msg_class {
+            panic!("unexpected {} message", kind);
+        }
+        error!("unexpected {} message, {} so far", kind, count);
+    }
+
+    /// Origins of the peers heard from within `RECENT_PEER_SECS`
+    pub fn recent_peers(&self) -> Vec<u32> {
+        let window = Duration::from_secs(RECENT_PEER_SECS);
+        let mut peers: Vec<u32> = self.seen_origins
+            .read()
+            .iter()
+            .filter(|&(_, seen)| seen.elapsed() <= window)
+            .map(|(origin, _)| *origin)
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// Stored, executed and current heights, the max store height is MAX before any block is stored
+    fn heights(&self) -> rpctypes::Heights {
+        let max_store_height = self.chain.get_max_store_height();
+        rpctypes::Heights {
+            max_store_height: if max_store_height == ::std::u64::MAX {
+                None
+            } else {
+                Some(U256::from(max_store_height))
+            },
+            max_height: U256::from(self.chain.get_max_height()),
+            current_height: U256::from(self.chain.get_current_height()),
+        }
+    }
+
+    /// Height reported by `blockNumber`, the stored height if any block is stored
+    fn head_height(&self) -> u64 {
+        let heights = self.heights();
+        heights.max_store_height.unwrap_or(heights.max_height).low_u64()
+    }
+
+    /// The block at `height` with its header, still queued if it isn't executed yet
+    fn block_head(&self, height: u64) -> Option<rpctypes::BlockHead> {
+        let queued = self.chain
+            .block_map
+            .read()
+            .get(&height)
+            .map(|block_in_queue| queued_block(block_in_queue).clone());
+        queued
+            .or_else(|| self.chain.block(BlockId::Number(height)))
+            .map(|block| rpctypes::BlockHead {
+                number: U256::from(height),
+                hash: block.hash(),
+                header: block.protobuf().take_header().into(),
+            })
+    }
+
+    /// Canonical, queued and replaced blocks known at `height`, each once
+    fn blocks_at_height(&self, height: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = self.chain.block(BlockId::Number(height)).into_iter().collect();
+        if let Some(block_in_queue) = self.chain.block_map.read().get(&height) {
+            blocks.push(queued_block(block_in_queue).clone());
+        }
+        blocks.extend(self.sibling_blocks.lock().at(height));
+
+        let mut known = Vec::new();
+        blocks.retain(|block| {
+            let hash = block.hash();
+            if known.contains(&hash) {
+                false
+            } else {
+                known.push(hash);
+                true
+            }
+        });
+        blocks
+    }
+
+    /// Queue a block at its height, remembering the block it replaces if they differ
+    fn queue_block(&self, blocks: &mut BTreeMap<u64, BlockInQueue>, height: u64, block_in_queue: BlockInQueue) {
+        let hash = queued_block(&block_in_queue).hash();
+        if let Some(replaced) = blocks.insert(height, block_in_queue) {
+            let replaced = queued_block(&replaced);
+            if replaced.hash() != hash {
+                info!("block-{} {:?} in queue is replaced by {:?}", height, replaced.hash(), hash);
+                self.sibling_blocks.lock().insert(replaced.clone());
+                let kept = blocks
+                    .get(&height)
+                    .map(|block_in_queue| queued_block(block_in_queue).body().transaction_hashes())
+                    .unwrap_or_default();
+                let reverted: Vec<H256> = replaced
+                    .body()
+                    .transaction_hashes()
+                    .into_iter()
+                    .filter(|hash| !kept.contains(hash))
+                    .collect();
+                if !reverted.is_empty() {
+                    info!("{} txs of block-{} {:?} are reverted", reverted.len(), height, replaced.hash());
+                    self.chain.revert_transactions(&reverted);
+                }
+            }
         }
     }
 
     // 注意: 划分函数处理流程
-    pub fn dispatch_msg(&self, _key: &str, msg_bytes: &[u8]) {
+    pub fn dispatch_msg(&self, key: &str, msg_bytes: &[u8]) {
         let mut msg = Message::try_from(msg_bytes).unwrap();
         let origin = msg.get_origin();
+        // Only messages relayed by network come from a peer
+        if key.starts_with("net.") {
+            self.seen_origins.write().insert(origin, Instant::now());
+        }
         let content_ext = msg.take_content();
         match content_ext {
             MsgClass::Request(req) => {
                 self.reply_request(req, msg_bytes.to_vec());
             }
 
-            //send to block_processor to operate
-            MsgClass::ExecutedResult(info) => {
-                self.write_sender.send(info).unwrap();
+            MsgClass::ExecutedResult(_) | MsgClass::BlockWithProof(_) | MsgClass::SyncResponse(_) if self.read_only => {
+                warn!(
+                    "read-only replica, refuse to add blocks from {} message of origin {}",
+                    key, origin
+                );
             }
 
-            MsgClass::BlockWithProof(proof_blk) => {
-                self.consensus_block_enqueue(proof_blk);
+            consensus_msg @ MsgClass::ExecutedResult(_) | consensus_msg @ MsgClass::BlockWithProof(_) => {
+                self.hold_or_add_consensus_msg(consensus_msg);
             }
 
             MsgClass::SyncRequest(sync_req) => {
@@ -85,56 +846,140 @@ impl Forward {
                 self.deal_block_tx_req(&block_tx_hashes_req);
             }
 
-            _ => {
-                error!("error MsgClass!!!!");
+            unexpected => {
+                self.note_unhandled(msg_class_name(&unexpected).to_owned());
             }
         }
     }
 
     fn reply_request(&self, mut req: request::Request, imsg: Vec<u8>) {
-        let mut response = response::Response::new();
-        response.set_request_id(req.take_request_id());
-        let topic = "chain.rpc".to_string();
+        let request_id = req.take_request_id();
         let retrans_topic = "executor.rpc".to_string();
-        match req.req.unwrap() {
+        let req = req.req.unwrap();
+        match req {
+            Request::call(ref call) => {
+                trace!("Chainvm Call {:?}", call);
+                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
+                return;
+            }
+
+            Request::simulate(ref call) => {
+                trace!("Chainvm Simulate {:?}", call);
+                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
+                return;
+            }
+
+            Request::transaction_count(ref tx_count) => {
+                trace!("transaction count request from jsonrpc {:?}", tx_count);
+                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
+                return;
+            }
+
+            Request::code(ref code_content) => {
+                trace!("code request from josnrpc  {:?}", code_content);
+                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
+                return;
+            }
+            _ => {}
+        }
+
+        // Keep slow queries from holding up consensus and sync messages
+        match self.query_pool {
+            Some(ref pool) if is_read_only(&req) => {
+                let forward = self.clone();
+                pool.execute(move || forward.reply_query(request_id, req));
+            }
+            _ => self.reply_query(request_id, req),
+        }
+    }
+
+    fn reply_query(&self, request_id: Vec<u8>, req: Request) {
+        let topic = "chain.rpc".to_string();
+        let mut response = match self.query_flight {
+            Some(ref flight) if is_coalescable(&req) => {
+                // The query encoded without its request id, the same bytes for the same query
+                let mut query = request::Request::new();
+                query.req = Some(req.clone());
+                let key = ::protobuf::Message::write_to_bytes(&query).unwrap();
+                flight.work(key, || self.query(req))
+            }
+            _ => self.query(req),
+        };
+        response.set_request_id(request_id);
+        let msg: Message = response.into();
+        self.ctx_pub.send((topic, msg.try_into().unwrap())).unwrap();
+    }
+
+    /// Answer the receipt of the tx, with its logs decoded against the events of `abi` if given
+    fn set_receipt(&self, response: &mut response::Response, tx_hash: H256, abi: Option<&str>) {
+        let receipt = self.chain.localized_receipt(tx_hash);
+        if let Some(receipt) = receipt {
+            let mut rpc_receipt: RpcReceipt = receipt.into();
+            if let Some(abi) = abi {
+                if let Err(err) = decode_receipt_logs(&mut rpc_receipt, abi) {
+                    response.set_code(ErrorCode::query_error());
+                    response.set_error_msg(err);
+                    return;
+                }
+            }
+            let serialized = serde_json::to_string(&rpc_receipt).unwrap();
+            response.set_receipt(serialized);
+        } else if self.receipt_not_ready_code != 0 && self.chain.has_transaction(tx_hash) {
+            // The tx is on chain but its receipt can't be read yet (e.g. mid-reorg),
+            // so tell the client to retry rather than reporting an unknown tx.
+            response.set_code(self.receipt_not_ready_code);
+            response.set_error_msg(format!("receipt of {:?} is not available yet", tx_hash));
+        } else {
+            response.set_none(true);
+        }
+    }
+
+    fn query(&self, req: Request) -> response::Response {
+        let mut response = response::Response::new();
+        match req {
             // TODO: should check the result, parse it first!
             Request::block_number(_) => {
-                // let sys_time = SystemTime::now();
-                let mut height = self.chain.get_max_store_height();
-                if height == ::std::u64::MAX {
-                    height = self.chain.get_max_height();
+                response.set_block_number(self.head_height());
+            }
+            Request::block_head(_) => {
+                let height = self.head_height();
+                match self.block_head(height) {
+                    Some(head) => response.set_block_head(serde_json::to_string(&head).unwrap()),
+                    None => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(format!("block-{} is not found", height));
+                    }
                 }
-                response.set_block_number(height);
+            }
+            Request::heights(_) => {
+                response.set_heights(serde_json::to_string(&self.heights()).unwrap());
             }
 
             Request::block_by_hash(rpc) => {
-                //let rpc: BlockParamsByHash = serde_json::from_str(&rpc);
-                match serde_json::from_str::<BlockParamsByHash>(&rpc) {
-                    Ok(param) => {
-                        let hash = param.hash;
-                        let include_txs = param.include_txs;
-                        match self.chain.block_by_hash(H256::from(hash.as_slice())) {
-                            Some(block) => {
-                                let rpc_block = RpcBlock::new(hash, include_txs, block.protobuf().try_into().unwrap());
-                                serde_json::to_string(&rpc_block)
-                                    .map(|data| response.set_block(data))
-                                    .map_err(|err| {
-                                        response.set_code(ErrorCode::query_error());
-                                        response.set_error_msg(format!("{:?}", err));
-                                    });
-                            }
-                            None => response.set_none(true),
+                let param: BlockParamsByHash = match parse_params(&mut response, &rpc) {
+                    Some(param) => param,
+                    None => return response,
+                };
+                let hash = param.hash;
+                let include_txs = param.include_txs;
+                match self.chain.block_by_hash(H256::from(hash.as_slice())) {
+                    Some(block) => {
+                        let rpc_block = RpcBlock::new(hash, include_txs, block.protobuf().try_into().unwrap())
+                            .set_fields(param.fields);
+                        match serde_json::to_string(&rpc_block) {
+                            Ok(data) => response.set_block(data),
+                            Err(err) => serialization_failed(&mut response, err),
                         }
                     }
-                    Err(err) => {
-                        response.set_block(format!("{:?}", err));
-                        response.set_code(ErrorCode::query_error());
-                    }
-                };
+                    None => response.set_none(true),
+                }
             }
 
             Request::block_by_height(block_height) => {
-                let block_height: BlockParamsByNumber = serde_json::from_str(&block_height).expect("Invalid param");
+                let block_height: BlockParamsByNumber = match parse_params(&mut response, &block_height) {
+                    Some(block_height) => block_height,
+                    None => return response,
+                };
                 let include_txs = block_height.include_txs;
                 match self.chain.block(block_height.block_id.into()) {
                     Some(block) => {
@@ -142,13 +987,11 @@ impl Forward {
                             block.hash().to_vec(),
                             include_txs,
                             block.protobuf().try_into().unwrap(),
-                        );
-                        serde_json::to_string(&rpc_block)
-                            .map(|data| response.set_block(data))
-                            .map_err(|err| {
-                                response.set_code(ErrorCode::query_error());
-                                response.set_error_msg(format!("{:?}", err));
-                            });
+                        ).set_fields(block_height.fields);
+                        match serde_json::to_string(&rpc_block) {
+                            Ok(data) => response.set_block(data),
+                            Err(err) => serialization_failed(&mut response, err),
+                        }
                     }
                     None => {
                         response.set_none(true);
@@ -166,55 +1009,108 @@ impl Forward {
             },
 
             Request::transaction_receipt(hash) => {
-                let tx_hash = H256::from_slice(&hash);
-                let receipt = self.chain.localized_receipt(tx_hash);
-                if let Some(receipt) = receipt {
-                    let rpc_receipt: RpcReceipt = receipt.into();
-                    let serialized = serde_json::to_string(&rpc_receipt).unwrap();
-                    response.set_receipt(serialized);
-                } else {
-                    response.set_none(true);
+                self.set_receipt(&mut response, H256::from_slice(&hash), None);
+            }
+
+            Request::decoded_receipt(params) => {
+                let params: rpctypes::ReceiptParams = match parse_params(&mut response, &params) {
+                    Some(params) => params,
+                    None => return response,
+                };
+                self.set_receipt(&mut response, params.hash, Some(&params.abi));
+            }
+
+            Request::transaction_receipts(hashes) => {
+                let hashes: Vec<H256> = match parse_params(&mut response, &hashes) {
+                    Some(hashes) => hashes,
+                    None => return response,
+                };
+                if hashes.len() > self.max_receipts_per_call {
+                    response.set_code(ErrorCode::query_error());
+                    response.set_error_msg(format!(
+                        "{} tx hashes are asked, at most {} are allowed",
+                        hashes.len(),
+                        self.max_receipts_per_call
+                    ));
+                    return response;
+                }
+                // A receipt which can't be read yet is null as well as an unknown one
+                let receipts: Vec<Option<RpcReceipt>> = hashes
+                    .into_iter()
+                    .map(|hash| self.chain.localized_receipt(hash).map(RpcReceipt::from))
+                    .collect();
+                match self.to_json_array(receipts) {
+                    Ok(receipts) => response.set_receipts(receipts),
+                    Err(err) => serialization_failed(&mut response, err),
                 }
             }
 
             Request::filter(encoded) => {
                 trace!("filter: {:?}", encoded);
-                serde_json::from_str::<RpcFilter>(&encoded)
-                    .map_err(|err| {
-                        response.set_code(ErrorCode::query_error());
-                        response.set_error_msg(format!("{:?}", err));
-                    })
-                    .map(|rpc_filter| {
+                parse_params::<RpcFilter>(&mut response, &encoded)
+                    .ok_or(())
+                    .and_then(|rpc_filter| {
+                        let include_bloom = rpc_filter.include_bloom.unwrap_or(false);
                         let filter: Filter = rpc_filter.into();
-                        let logs = self.chain.get_logs(filter);
-                        let rpc_logs: Vec<RpcLog> = logs.into_iter().map(|x| x.into()).collect();
-                        response.set_logs(serde_json::to_string(&rpc_logs).unwrap());
+                        if include_bloom {
+                            let (logs, logs_bloom) = self.chain
+                                .get_logs_with_bloom(filter)
+                                .map_err(|err| {
+                                    response.set_code(ErrorCode::query_error());
+                                    response.set_error_msg(err);
+                                })?;
+                            let logs_with_bloom = rpctypes::LogsWithBloom {
+                                logs: logs.into_iter().map(|x| x.into()).collect(),
+                                logs_bloom: logs_bloom,
+                            };
+                            response.set_logs(serde_json::to_string(&logs_with_bloom).unwrap());
+                        } else {
+                            let logs = self.chain.get_logs(filter).map_err(|err| {
+                                response.set_code(ErrorCode::query_error());
+                                response.set_error_msg(err);
+                            })?;
+                            let rpc_logs: Vec<RpcLog> = logs.into_iter().map(|x| x.into()).collect();
+                            match self.to_json_array(rpc_logs) {
+                                Ok(rpc_logs) => response.set_logs(rpc_logs),
+                                Err(err) => serialization_failed(&mut response, err),
+                            }
+                        }
+                        Ok(())
                     });
             }
 
-            Request::call(call) => {
-                trace!("Chainvm Call {:?}", call);
-                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
-                return;
-            }
-
-            Request::transaction_count(tx_count) => {
-                trace!("transaction count request from jsonrpc {:?}", tx_count);
-                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
-                return;
+            Request::new_filter(new_filter) => {
+                trace!("new_filter {:?}", new_filter);
+                let new_filter: RpcFilter = match parse_params(&mut response, &new_filter) {
+                    Some(new_filter) => new_filter,
+                    None => return response,
+                };
+                let checked = self.chain
+                    .log_range(&new_filter.clone().into())
+                    .map(|_| new_filter);
+                match checked {
+                    Ok(new_filter) => {
+                        trace!("new_filter {:?}", new_filter);
+                        response.set_filter_id(self.chain.new_filter(new_filter) as u64);
+                    }
+                    Err(err) => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(err);
+                    }
+                }
             }
 
-            Request::code(code_content) => {
-                trace!("code request from josnrpc  {:?}", code_content);
-                self.ctx_pub.send((retrans_topic, imsg)).unwrap();
-                return;
+            Request::subscribe_filter(filter) => {
+                trace!("subscribe_filter {:?}", filter);
+                let filter: RpcFilter = match parse_params(&mut response, &filter) {
+                    Some(filter) => filter,
+                    None => return response,
+                };
+                response.set_filter_id(self.chain.subscribe_filter(filter) as u64);
             }
 
-            Request::new_filter(new_filter) => {
-                trace!("new_filter {:?}", new_filter);
-                let new_filter: RpcFilter = serde_json::from_str(&new_filter).expect("Invalid param");
-                trace!("new_filter {:?}", new_filter);
-                response.set_filter_id(self.chain.new_filter(new_filter) as u64);
+            Request::subscribe_reverted_txs(_) => {
+                response.set_filter_id(self.chain.subscribe_reverted_transactions() as u64);
             }
 
             Request::new_block_filter(_) => {
@@ -232,24 +1128,244 @@ impl Forward {
             Request::filter_changes(filter_id) => {
                 trace!("filter_changes's id is {:?}", filter_id);
                 let index = rpctypes::Index(filter_id as usize);
-                let log = self.chain.filter_changes(index).unwrap();
-                trace!("Log is: {:?}", log);
-                response.set_filter_changes(serde_json::to_string(&log).unwrap());
+                match self.chain.filter_changes(index) {
+                    Ok(log) => {
+                        trace!("Log is: {:?}", log);
+                        response.set_filter_changes(serde_json::to_string(&log).unwrap());
+                    }
+                    Err(err) => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(err);
+                    }
+                }
             }
 
             Request::filter_logs(filter_id) => {
                 trace!("filter_log's id is {:?}", filter_id);
                 let index = rpctypes::Index(filter_id as usize);
-                let log = self.chain.filter_logs(index).unwrap_or_default();
-                trace!("Log is: {:?}", log);
-                response.set_filter_logs(serde_json::to_string(&log).unwrap());
+                match self.chain.filter_logs(index) {
+                    Ok(log) => {
+                        let log = log.unwrap_or_default();
+                        trace!("Log is: {:?}", log);
+                        match self.to_json_array(log) {
+                            Ok(log) => response.set_filter_logs(log),
+                            Err(err) => serialization_failed(&mut response, err),
+                        }
+                    }
+                    Err(err) => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(err);
+                    }
+                }
+            }
+
+            Request::validators(_) => {
+                let validators = self.chain.validators();
+                trace!("validators: {:?}", validators);
+                response.set_validators(serde_json::to_string(&validators).unwrap());
+            }
+
+            Request::genesis(_) => match self.chain.genesis() {
+                Some(genesis) => response.set_genesis(serde_json::to_string(&genesis).unwrap()),
+                None => {
+                    response.set_code(ErrorCode::query_error());
+                    response.set_error_msg("genesis block isn't written yet".to_owned());
+                }
+            },
+
+            Request::meta_data(_) => match self.meta_data() {
+                Some(meta_data) => response.set_meta_data(meta_data),
+                None => {
+                    response.set_code(ErrorCode::query_error());
+                    response.set_error_msg("genesis block isn't written yet".to_owned());
+                }
+            },
+
+            Request::peers(_) => {
+                let peers = rpctypes::Peers {
+                    origin: self.id_card,
+                    peers: self.recent_peers(),
+                };
+                response.set_peers(serde_json::to_string(&peers).unwrap());
+            }
+
+            Request::check_transactions_root(number) => {
+                let number: rpctypes::BlockNumber = match parse_params(&mut response, &number) {
+                    Some(number) => number,
+                    None => return response,
+                };
+                match self.chain.check_transactions_root(number.clone().into()) {
+                    Some(matched) => {
+                        if !matched {
+                            warn!("transactions root isn't correct for block {:?}", number);
+                        }
+                        response.set_transactions_root_matched(matched);
+                    }
+                    None => {
+                        response.set_none(true);
+                    }
+                }
+            }
+            Request::gas_limits(number) => {
+                let number: rpctypes::BlockNumber = match parse_params(&mut response, &number) {
+                    Some(number) => number,
+                    None => return response,
+                };
+                match self.chain.gas_limits(number.into()) {
+                    Some((block_gas_limit, account_gas_limit)) => {
+                        let gas_limits = rpctypes::GasLimits {
+                            block_gas_limit: U256::from(block_gas_limit),
+                            account_gas_limit: U256::from(account_gas_limit.get_common_gas_limit()),
+                            specific_gas_limit: account_gas_limit
+                                .get_specific_gas_limit()
+                                .iter()
+                                .map(|(address, limit)| (address.clone(), U256::from(*limit)))
+                                .collect(),
+                        };
+                        response.set_gas_limits(serde_json::to_string(&gas_limits).unwrap());
+                    }
+                    None => {
+                        response.set_none(true);
+                    }
+                }
+            }
+            Request::block_tx_count_by_hash(hash) => {
+                let block_id = BlockId::Hash(H256::from_slice(&hash));
+                match self.chain.block_transaction_count(block_id) {
+                    Some(count) => response.set_block_tx_count(count as u64),
+                    None => response.set_none(true),
+                }
+            }
+            Request::block_tx_count_by_height(number) => {
+                let number: rpctypes::BlockNumber = match parse_params(&mut response, &number) {
+                    Some(number) => number,
+                    None => return response,
+                };
+                match self.chain.block_transaction_count(number.into()) {
+                    Some(count) => response.set_block_tx_count(count as u64),
+                    None => response.set_none(true),
+                }
+            }
+            Request::transaction_by_position(position) => {
+                let position: rpctypes::TransactionPosition = match parse_params(&mut response, &position) {
+                    Some(position) => position,
+                    None => return response,
+                };
+                let block_id = match position.block {
+                    rpctypes::PositionBlock::Hash(hash) => BlockId::Hash(hash),
+                    rpctypes::PositionBlock::Number(number) => number.into(),
+                };
+                match self.chain.transaction_at(block_id, position.index as usize) {
+                    Some(ts) => response.set_ts(ts),
+                    None => response.set_none(true),
+                }
+            }
+            Request::has_logs(params) => {
+                let params: rpctypes::HasLogsParams = match parse_params(&mut response, &params) {
+                    Some(params) => params,
+                    None => return response,
+                };
+                match self.chain
+                    .has_logs(params.address, params.from_block.into(), params.to_block.into())
+                {
+                    Ok(has_logs) => response.set_has_logs(has_logs),
+                    Err(err) => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(err);
+                    }
+                }
             }
-            _ => {
-                error!("mtach error Request_oneof_req msg!!!!");
+            Request::block_size(number) => {
+                let number: rpctypes::BlockNumber = match parse_params(&mut response, &number) {
+                    Some(number) => number,
+                    None => return response,
+                };
+                match self.chain.block_size(number.into()) {
+                    Some(size) => response.set_encoded_size(size as u64),
+                    None => response.set_none(true),
+                }
+            }
+            Request::transaction_size(hash) => match self.chain.transaction_size(H256::from_slice(&hash)) {
+                Some(size) => response.set_encoded_size(size as u64),
+                None => response.set_none(true),
+            },
+            Request::compare_state_roots(heights) => {
+                let heights: rpctypes::StateRootsParams = match parse_params(&mut response, &heights) {
+                    Some(heights) => heights,
+                    None => return response,
+                };
+                match self.chain.state_roots(heights.from.into(), heights.to.into()) {
+                    Some((from_state_root, to_state_root)) => {
+                        let state_roots = rpctypes::StateRoots::new(from_state_root, to_state_root);
+                        response.set_state_roots(serde_json::to_string(&state_roots).unwrap());
+                    }
+                    None => {
+                        response.set_none(true);
+                    }
+                }
+            }
+            Request::transaction_proof(hash) => {
+                let tx_hash = H256::from_slice(&hash);
+                match self.chain.transaction_proof(tx_hash) {
+                    Some((header, proof)) => {
+                        let proof = rpctypes::TransactionProof {
+                            block_number: U256::from(header.number()),
+                            block_hash: header.hash(),
+                            transactions_root: *header.transactions_root(),
+                            index: U256::from(proof.index as u64),
+                            proof: proof
+                                .siblings
+                                .into_iter()
+                                .map(|(hash, is_left)| rpctypes::MerkleNode {
+                                    hash: hash,
+                                    is_left: is_left,
+                                })
+                                .collect(),
+                        };
+                        response.set_transaction_proof(serde_json::to_string(&proof).unwrap());
+                    }
+                    None => {
+                        response.set_code(ErrorCode::query_error());
+                        response.set_error_msg(format!("no proof of transaction {:?}", tx_hash));
+                    }
+                }
+            }
+            Request::chain_stats(_) => {
+                // The new txs buffered by jsonrpc are filled in there
+                let stats = rpctypes::ChainStats {
+                    height: U256::from(self.chain.get_current_height()),
+                    total_transactions: U256::from(self.chain.total_transactions()),
+                    average_block_time: self.chain.average_block_time().map(U256::from),
+                    pending_transactions: U256::zero(),
+                };
+                response.set_chain_stats(serde_json::to_string(&stats).unwrap());
+            }
+            Request::blocks_at_height(params) => {
+                let params: BlockParamsByNumber = match parse_params(&mut response, &params) {
+                    Some(params) => params,
+                    None => return response,
+                };
+                let include_txs = params.include_txs;
+                let blocks = self.chain
+                    .block_number(params.block_id.into())
+                    .map_or_else(Vec::new, |height| self.blocks_at_height(height));
+                let rpc_blocks: Vec<RpcBlock> = blocks
+                    .into_iter()
+                    .map(|block| {
+                        let hash = block.hash().to_vec();
+                        RpcBlock::new(hash, include_txs, block.protobuf().try_into().unwrap())
+                    })
+                    .collect();
+                match self.to_json_array(rpc_blocks) {
+                    Ok(rpc_blocks) => response.set_blocks(rpc_blocks),
+                    Err(err) => serialization_failed(&mut response, err),
+                }
+            }
+            unexpected => {
+                self.note_unhandled(format!("Request::{}", request_name(&unexpected)));
             }
         };
-        let msg: Message = response.into();
-        self.ctx_pub.send((topic, msg.try_into().unwrap())).unwrap();
+        response
     }
 
     // Consensus block enqueue
@@ -266,12 +1382,11 @@ impl Forward {
         );
         let rblock = Block::from(block);
         if blk_height == (current_height + 1) {
-            {
-                self.chain.block_map.write().insert(
-                    blk_height as u64,
-                    BlockInQueue::ConsensusBlock(rblock.clone(), proof.clone()),
-                );
-            };
+            self.queue_block(
+                &mut self.chain.block_map.write(),
+                blk_height as u64,
+                BlockInQueue::ConsensusBlock(rblock.clone(), proof.clone()),
+            );
             self.chain.save_current_block_poof(proof);
             self.chain.set_block_body(blk_height as u64, &rblock);
             self.chain
@@ -285,34 +1400,102 @@ impl Forward {
 
     fn reply_syn_req(&self, sync_req: SyncRequest, origin: u32) {
         let mut sync_req = sync_req;
-        let heights = sync_req.take_heights();
-        debug!(
-            "sync: receive sync from node {:?}, height lists = {:?}",
-            origin, heights
-        );
+        if sync_req.get_window() > 0 {
+            // Pull sync: reply one bounded window and tell the peer where to continue
+            let mut res_vec = SyncResponse::new();
+            let window = ::std::cmp::min(sync_req.get_window(), MAX_SYNC_WINDOW);
+            let current_height = self.chain.get_current_height();
+            debug!(
+                "sync: receive pull sync from node {:?}, cursor = {}, window = {}",
+                origin,
+                sync_req.get_cursor(),
+                window
+            );
+            let cursor = sync_req.get_cursor();
+            // Only the heights which can be served count against the peer's quota
+            let window_heights: Vec<u64> = sync_window_heights(cursor, window, current_height).collect();
+            if !self.admit_sync_heights(origin, &window_heights) {
+                return;
+            }
+            let window_blocks = sync_window(cursor, window, current_height, |height| {
+                self.chain.block(BlockId::Number(height)).map(|block| block.protobuf())
+            });
+            let (blocks, next_cursor) = match window_blocks {
+                Ok(window_blocks) => window_blocks,
+                Err(height) => {
+                    warn!(
+                        "sync: refuse pull sync from node {} at cursor {}, block {} can't be read",
+                        origin, cursor, height
+                    );
+                    return;
+                }
+            };
+            let reach_current = blocks
+                .last()
+                .map_or(false, |block| block.get_header().get_height() == current_height);
+            res_vec.set_blocks(RepeatedField::from_vec(blocks));
+            if reach_current {
+                if let Some(proof_block) = self.current_proof_block() {
+                    res_vec.mut_blocks().push(proof_block);
+                }
+            }
+            if let Some(next_cursor) = next_cursor {
+                res_vec.set_has_next(true);
+                res_vec.set_next_cursor(next_cursor);
+            }
+            self.send_sync_response(origin, res_vec);
+        } else {
+            let current_height = self.chain.get_current_height();
+            let heights = if sync_req.get_range_end() > 0 {
+                // A long range is served as far as the peer has room for, it asks again for the rest
+                let end = ::std::cmp::min(sync_req.get_range_end(), current_height);
+                match self.admit_sync_range(origin, sync_req.get_range_start(), end) {
+                    Some(heights) => heights,
+                    None => return,
+                }
+            } else {
+                let heights = sync_req.take_heights();
+                if !self.admit_sync_heights(origin, &heights) {
+                    return;
+                }
+                heights
+            };
+            debug!(
+                "sync: receive sync from node {:?}, height lists = {:?}",
+                origin, heights
+            );
 
-        let mut res_vec = SyncResponse::new();
-        for height in heights {
-            if let Some(block) = self.chain.block(BlockId::Number(height)) {
-                res_vec.mut_blocks().push(block.protobuf());
-                //push double
-                if height == self.chain.get_current_height() {
-                    let mut proof_block = ProtobufBlock::new();
-                    //get current block proof
-                    if let Some(proof) = self.chain.current_block_poof() {
-                        proof_block.mut_header().set_proof(proof);
-                        proof_block.mut_header().set_height(::std::u64::MAX);
-                        res_vec.mut_blocks().push(proof_block);
-                        trace!(
-                            "sync: max height {:?}, chain.blk: OperateType {:?}",
-                            height,
-                            OperateType::SINGLE
-                        );
+            // Each chunk is sent once full, so only one is held at a time
+            let send_chunk = |blocks: Vec<ProtobufBlock>| {
+                let mut res_vec = SyncResponse::new();
+                res_vec.set_blocks(RepeatedField::from_vec(blocks));
+                self.send_sync_response(origin, res_vec);
+            };
+            let mut chunks = SyncChunks::new(self.sync_chunk_blocks, self.sync_chunk_bytes);
+            let mut reach_current = false;
+            for height in heights {
+                if let Some(block) = self.chain.block(BlockId::Number(height)) {
+                    reach_current = reach_current || height == current_height;
+                    if let Some(chunk) = chunks.push(block.protobuf()) {
+                        send_chunk(chunk);
+                    }
+                }
+            }
+            // push double, once, so the proof of the current block ends the last reply
+            if reach_current {
+                if let Some(proof_block) = self.current_proof_block() {
+                    if let Some(chunk) = chunks.push(proof_block) {
+                        send_chunk(chunk);
                     }
                 }
             }
+            if let Some(chunk) = chunks.finish() {
+                send_chunk(chunk);
+            }
         }
+    }
 
+    fn send_sync_response(&self, origin: u32, mut res_vec: SyncResponse) {
         debug!(
             "sync: reply node = {}, response blocks len = {}",
             origin,
@@ -331,8 +1514,89 @@ impl Forward {
         }
     }
 
+    /// Whether `heights` should be served to `origin` now
+    fn admit_sync_heights(&self, origin: u32, heights: &[u64]) -> bool {
+        match self.sync_throttle.lock().admit(origin, heights) {
+            Ok(()) => true,
+            Err(Throttled::Redundant) => {
+                info!(
+                    "sync: ignore sync request from node {} for {} heights, served already",
+                    origin,
+                    heights.len()
+                );
+                false
+            }
+            Err(Throttled::TooMany(in_flight)) => {
+                warn!(
+                    "sync: throttle sync request from node {} for {} heights, {} heights in flight",
+                    origin,
+                    heights.len(),
+                    in_flight
+                );
+                false
+            }
+        }
+    }
+
+    /// Heights from `start` to `end` which should be served to `origin` now
+    fn admit_sync_range(&self, origin: u32, start: u64, end: u64) -> Option<Vec<u64>> {
+        match self.sync_throttle.lock().admit_range(origin, start, end) {
+            Ok(heights) => Some(heights),
+            Err(Throttled::Redundant) => {
+                info!(
+                    "sync: ignore sync request from node {} for heights {}..{}, served already",
+                    origin, start, end
+                );
+                None
+            }
+            Err(Throttled::TooMany(in_flight)) => {
+                warn!(
+                    "sync: throttle sync request from node {} for heights {}..{}, {} heights in flight",
+                    origin, start, end, in_flight
+                );
+                None
+            }
+        }
+    }
+
+    /// A placeholder block carrying the proof of the current block
+    fn current_proof_block(&self) -> Option<ProtobufBlock> {
+        self.chain.current_block_poof().map(|proof| {
+            let mut proof_block = ProtobufBlock::new();
+            proof_block.mut_header().set_proof(proof);
+            proof_block.mut_header().set_height(::std::u64::MAX);
+            trace!(
+                "sync: max height {:?}, chain.blk: OperateType {:?}",
+                self.chain.get_current_height(),
+                OperateType::SINGLE
+            );
+            proof_block
+        })
+    }
+
     fn deal_sync_blocks(&self, mut sync_res: SyncResponse) {
         debug!("sync: current height = {}", self.chain.get_current_height());
+        // A late response for heights already reached is useless, skip it before checking any block
+        let max_height = self.chain.get_max_height();
+        let newest_height = sync_res
+            .get_blocks()
+            .iter()
+            .map(|block| block.get_header().get_height())
+            .filter(|height| *height != ::std::u64::MAX)
+            .max();
+        if let Some(newest_height) = newest_height {
+            if newest_height < max_height {
+                let dropped = self.stale_sync_responses.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!(
+                    "sync: drop stale response, newest block {} is {} behind height {}, {} dropped so far",
+                    newest_height,
+                    max_height - newest_height,
+                    max_height,
+                    dropped
+                );
+                return;
+            }
+        }
         for block in sync_res.take_blocks().into_iter() {
             let blk_height = block.get_header().get_height();
 
@@ -341,21 +1605,47 @@ impl Forward {
                 continue;
             };
 
+            // The proof placeholder has no transactions to check
+            if blk_height == ::std::u64::MAX {
+                self.add_sync_block(Block::from(block));
+                continue;
+            }
+
+            // Overlapping responses from several peers carry the same blocks. A block validated
+            // already is queued, so a copy is dropped unchecked; it can't replace the queued body.
+            let hash = sync_block_hash(&block);
+            if self.validated_sync_blocks.lock().contains(&hash) {
+                let hits = self.sync_cache_hits.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!(
+                    "sync: block-{} {:?} is validated already, {} copies skipped so far",
+                    blk_height, hash, hits
+                );
+                continue;
+            }
+
             // Check transaction root
-            if blk_height != ::std::u64::MAX && !block.check_hash() {
+            if !block.check_hash() {
                 warn!(
                     "sync: transactions root isn't correct, height is {}",
                     blk_height
                 );
                 break;
             }
-            self.add_sync_block(Block::from(block));
+            if self.add_sync_block(Block::from(block)) {
+                self.validated_sync_blocks.lock().insert(hash);
+            }
         }
     }
 
-    // Check block group from remote and enqueue
-    #[cfg_attr(feature = "clippy", allow(single_match))]
-    fn add_sync_block(&self, block: Block) {
+    // Check block group from remote and enqueue, returns whether the block is queued.
+    // A queued block is only stored once its proof is verified, see `commit_sync_blocks`.
+    fn add_sync_block(&self, block: Block) -> bool {
+        let queued = self.queue_sync_block(block);
+        self.commit_sync_blocks();
+        queued
+    }
+
+    fn queue_sync_block(&self, block: Block) -> bool {
         let block_proof_type = block.proof_type();
         let chain_proof_type = self.chain.get_chain_prooftype();
         let blk_height = block.number() as usize;
@@ -367,89 +1657,1426 @@ impl Forward {
                 "sync: block_proof_type {:?} mismatch with chain_proof_type {:?}",
                 block_proof_type, chain_proof_type
             );
-            return;
+            return false;
         }
-        match block_proof_type {
-            Some(ProofType::Tendermint) => {
-                let proof = TendermintProof::from(block.proof().clone());
-                let proof_height = if proof.height == ::std::usize::MAX {
-                    0
-                } else {
-                    proof.height as u64
-                };
+        // A Tendermint block carries the proof of its parent, the proof of the last block
+        // comes in a placeholder block of height MAX. Raft and POA blocks carry their own.
+        let carries_own_proof = match block_proof_type {
+            Some(ProofType::Tendermint) => false,
+            Some(ProofType::Raft) | Some(ProofType::AuthorityRound) => true,
+            None => {
+                error!("sync: block-{} has no proof type", block.number());
+                return false;
+            }
+        };
+        let proof_height = Chain::get_block_proof_height(&block) as u64;
+        let parent_height = if carries_own_proof {
+            proof_height.saturating_sub(1)
+        } else {
+            proof_height
+        };
 
-                debug!(
-                    "sync: add_sync_block: proof_height = {}, block height = {} max_height = {}",
-                    proof_height, blk_height, chain_max_height
+        debug!(
+            "sync: add_sync_block: proof_height = {}, block height = {} max_height = {}",
+            proof_height, blk_height, chain_max_height
+        );
+
+        let height = block.number();
+        let mut blocks = self.chain.block_map.write();
+        if blk_height != ::std::usize::MAX {
+            let parent_queued = match blocks.get(&parent_height) {
+                Some(&BlockInQueue::SyncBlock(_)) => true,
+                _ => false,
+            };
+            if parent_height != chain_max_height && parent_height != chain_max_store_height && !parent_queued {
+                info!(
+                    "sync: insert block-{} is not continious proof height {}",
+                    block.number(),
+                    proof_height
                 );
+                return false;
+            }
+            let own_proof = if carries_own_proof {
+                Some(block.proof().clone())
+            } else {
+                // Set proof of prev sync block, a block which isn't chained onto it isn't queued
+                if !self.set_sync_block_proof(&mut blocks, proof_height, block.proof()) {
+                    return false;
+                }
+                None
+            };
+            debug!("sync: insert block-{} in map", block.number());
+            self.queue_block(&mut blocks, height, BlockInQueue::SyncBlock((block, own_proof)));
+            return true;
+        } else if !carries_own_proof && proof_height > self.chain.get_current_height() {
+            self.set_sync_block_proof(&mut blocks, proof_height, block.proof());
+        }
+        false
+    }
 
-                let height = block.number();
-                let mut blocks = self.chain.block_map.write();
-                if blk_height != ::std::usize::MAX {
-                    if proof_height == chain_max_height || proof_height == chain_max_store_height {
-                        // Set proof of prev sync block
-                        if let Some(prev_block_in_queue) = blocks.get_mut(&proof_height) {
-                            if let &mut BlockInQueue::SyncBlock(ref mut value) = prev_block_in_queue {
-                                if value.1.is_none() {
-                                    debug!("sync: set prev sync block proof {}", value.0.number());
-                                    mem::swap(&mut value.1, &mut Some(block.proof().clone()));
-                                }
-                            }
-                        }
-                        self.chain.set_block_body(height, &block);
-                        self.chain
-                            .max_store_height
-                            .store(height as usize, Ordering::SeqCst);
-                        let tx_hashes = block.body().transaction_hashes();
-                        self.chain
-                            .delivery_block_tx_hashes(height, tx_hashes, &self.ctx_pub);
-                        debug!("sync: insert block-{} in map", block.number());
-                        blocks.insert(height, BlockInQueue::SyncBlock((block, None)));
+    /// Set the Tendermint `proof` of the sync block queued at `height` if it commits that block,
+    /// returns false if it commits another one. The proof of a block not queued is of no use.
+    fn set_sync_block_proof(&self, blocks: &mut BTreeMap<u64, BlockInQueue>, height: u64, proof: &ProtoProof) -> bool {
+        if let Some(&mut BlockInQueue::SyncBlock(ref mut value)) = blocks.get_mut(&height) {
+            let proposal = TendermintProof::from(proof.clone()).proposal;
+            let queued = value.0.crypt_hash();
+            if proposal != queued {
+                warn!(
+                    "sync: proof of block-{} is for {:?}, not the queued {:?}",
+                    height, proposal, queued
+                );
+                return false;
+            }
+            if value.1.is_none() {
+                debug!("sync: set sync block proof {}", height);
+                value.1 = Some(proof.clone());
+            }
+        }
+        true
+    }
+
+    /// Store the queued sync blocks, in height order, whose proof is verified. A Tendermint proof
+    /// is verified once the parent block is executed, which sets the validators signing it.
+    /// A block whose proof isn't signed by them is dropped with the blocks queued after it.
+    pub fn commit_sync_blocks(&self) {
+        loop {
+            let height = ::std::cmp::max(self.chain.get_max_height(), self.chain.get_max_store_height()) + 1;
+            let mut blocks = self.chain.block_map.write();
+            let verified = match blocks.get(&height) {
+                Some(&BlockInQueue::SyncBlock((ref block, Some(ref proof)))) => {
+                    if block.proof_type() == Some(ProofType::Tendermint) {
+                        self.chain.check_tendermint_proof(proof, block)
                     } else {
-                        info!(
-                            "sync: insert block-{} is not continious proof height {}",
-                            block.number(),
-                            proof_height
-                        );
+                        Some(true)
                     }
-                } else if proof_height > self.chain.get_current_height() {
-                    if let Some(block_in_queue) = blocks.get_mut(&proof_height) {
-                        if let &mut BlockInQueue::SyncBlock(ref mut value) = block_in_queue {
-                            if value.1.is_none() {
-                                debug!("sync: insert block proof {} in map", proof_height);
-                                mem::swap(&mut value.1, &mut Some(block.proof().clone()));
-                            }
-                        }
+                }
+                _ => None,
+            };
+            match verified {
+                Some(true) => {
+                    let block = queued_block(&blocks[&height]).clone();
+                    drop(blocks);
+                    self.chain.set_block_body(height, &block);
+                    self.chain
+                        .max_store_height
+                        .store(height as usize, Ordering::SeqCst);
+                    let tx_hashes = block.body().transaction_hashes();
+                    self.chain
+                        .delivery_block_tx_hashes(height, tx_hashes, &self.ctx_pub);
+                    debug!("sync: store block-{}", height);
+                }
+                Some(false) => {
+                    warn!(
+                        "sync: proof of block-{} isn't signed by the validators, drop it and the blocks after it",
+                        height
+                    );
+                    let dropped = blocks.split_off(&height);
+                    drop(blocks);
+                    let mut validated = self.validated_sync_blocks.lock();
+                    for block_in_queue in dropped.values() {
+                        validated.remove(&queued_block(block_in_queue).hash());
                     }
+                    drop(validated);
+                    self.chain.broadcast_status(&self.ctx_pub);
+                    return;
                 }
-            }
-            // TODO: Handle Raft and POA
-            _ => {
-                unimplemented!();
+                None => return,
             }
         }
     }
 
     fn deal_block_tx_req(&self, block_tx_hashes_req: &BlockTxHashesReq) {
-        let block_height = block_tx_hashes_req.get_height();
+        let heights = block_tx_hashes_req.get_heights();
+        if heights.is_empty() {
+            self.reply_block_tx_hashes(block_tx_hashes_req.get_height());
+        } else {
+            for height in heights {
+                self.reply_block_tx_hashes(*height);
+            }
+        }
+    }
+
+    fn reply_block_tx_hashes(&self, block_height: u64) {
         if let Some(tx_hashes) = self.chain.transaction_hashes(BlockId::Number(block_height)) {
             //prepare and send the block tx hashes to auth
-            let mut block_tx_hashes = BlockTxHashes::new();
-            block_tx_hashes.set_height(block_height);
-            let mut tx_hashes_in_u8 = Vec::new();
-            for tx_hash_in_h256 in &tx_hashes {
-                tx_hashes_in_u8.push(tx_hash_in_h256.to_vec());
-            }
-            block_tx_hashes.set_tx_hashes(RepeatedField::from_slice(&tx_hashes_in_u8[..]));
-            block_tx_hashes.set_block_gas_limit(self.chain.block_gas_limit.load(Ordering::SeqCst) as u64);
-            block_tx_hashes.set_account_gas_limit(self.chain.account_gas_limit.read().clone().into());
-            let msg: Message = block_tx_hashes.into();
-            self.ctx_pub
-                .send(("chain.txhashes".to_string(), msg.try_into().unwrap()))
-                .unwrap();
-            trace!("response block's tx hashes for height:{}", block_height);
+            if self.chain
+                .delivery_block_tx_hashes(block_height, tx_hashes, &self.ctx_pub)
+            {
+                trace!("response block's tx hashes for height:{}", block_height);
+            }
         } else {
             warn!("get block's tx hashes for height:{} error", block_height);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::{serialize, Infinite};
+    use core::db;
+    use core::libchain::block::BlockBody;
+    use core::libchain::chain::Config;
+    use core::libchain::extras::{BlockReceipts, TransactionAddress};
+    use crypto::{KeyPair, Sign, Signature};
+    use jsonrpc_types::rpctypes::{BlockNumber, BlockTag, PositionBlock, TransactionPosition};
+    use libproto::blockchain::AccountGasLimit as ProtoAccountGasLimit;
+    use libproto::request::Request as ProtoRequest;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use types::receipt::Receipt;
+    use types::transaction::SignedTransaction;
+    use util::Hashable;
+    use util::crypto::CreateKey;
+    use util::kvdb::in_memory;
+
+    /// Tendermint steps up to the one precommits are signed at, they're serialized by index
+    #[allow(dead_code)]
+    #[derive(Serialize)]
+    enum Step {
+        Propose,
+        Prevote,
+        Precommit,
+    }
+
+    fn signed_proof(height: usize, proposal: H256, keypair: &KeyPair) -> TendermintProof {
+        let mut proof = TendermintProof::default();
+        proof.height = height;
+        proof.proposal = proposal;
+        let sender = keypair.address().clone();
+        let msg = serialize(
+            &(
+                proof.height,
+                proof.round,
+                Step::Precommit,
+                sender.clone(),
+                Some(proof.proposal.clone()),
+            ),
+            Infinite,
+        ).unwrap();
+        let signature = Signature::sign(keypair.privkey(), &msg.crypt_hash().into()).unwrap();
+        proof.commits.insert(sender, signature.into());
+        proof
+    }
+
+    /// Sync blocks from `first_height` on, each carrying the proof of its parent signed by `signer`
+    fn sync_blocks(first_height: u64, count: u64, signer: &KeyPair) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in first_height..first_height + count {
+            let parent_hash = blocks.last().map_or_else(H256::default, |parent| parent.crypt_hash());
+            let mut block = Block::new();
+            block.set_number(height);
+            block.set_proof(signed_proof((height - 1) as usize, parent_hash, signer).into());
+            let transactions_root = block.body().protobuf().transactions_root();
+            block.set_transactions_root(H256::from_slice(&transactions_root));
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn sync_response(blocks: &[Block]) -> Vec<u8> {
+        let mut res = SyncResponse::new();
+        for block in blocks {
+            res.mut_blocks().push(block.protobuf());
+        }
+        let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncResponse(res));
+        msg.try_into().unwrap()
+    }
+
+    /// Set the validators as those set by the executed block of `height`
+    fn set_validators(chain: &Chain, height: u64, validators: &[&KeyPair]) {
+        let mut ret = ExecutedResult::new();
+        ret.mut_executed_info().mut_header().set_height(height);
+        for validator in validators {
+            ret.mut_config().mut_nodes().push(validator.address().to_vec());
+        }
+        chain.set_db_config(&ret);
+    }
+
+    #[test]
+    fn test_pull_sync_by_cursor() {
+        let current_height = 10;
+        let block = |height| {
+            let mut blk = ProtobufBlock::new();
+            blk.mut_header().set_height(height);
+            Some(blk)
+        };
+
+        let mut synced = Vec::new();
+        let mut cursor = 1;
+        let mut windows = 0;
+        loop {
+            let (blocks, next_cursor) = sync_window(cursor, 4, current_height, &block).unwrap();
+            assert!(blocks.len() <= 4);
+            synced.extend(blocks.iter().map(|blk| blk.get_header().get_height()));
+            windows += 1;
+            match next_cursor {
+                Some(next_cursor) => cursor = next_cursor,
+                None => break,
+            }
+        }
+        assert_eq!(windows, 3);
+        assert_eq!(synced, (1..current_height + 1).collect::<Vec<u64>>());
+
+        // Pulling from genesis goes on to height 1
+        let (_, next_cursor) = sync_window(0, 1, current_height, &block).unwrap();
+        assert_eq!(next_cursor, Some(1));
+
+        // A window stops at the current height
+        assert_eq!(sync_window_heights(8, 64, current_height), 8..11);
+        assert_eq!(sync_window_heights(11, 4, current_height), 11..11);
+
+        // A missing block ends the window with an error rather than a cursor pointing back at it
+        let missing = |height| if height == 6 { None } else { block(height) };
+        assert_eq!(sync_window(5, 4, current_height, &missing).map(|_| ()), Err(6));
+    }
+
+    #[test]
+    fn test_sync_chunks() {
+        let block = |height: u64, data: usize| {
+            let mut blk = ProtobufBlock::new();
+            blk.mut_header().set_height(height);
+            blk.mut_body().mut_transactions().push(Default::default());
+            blk.mut_body().mut_transactions()[0].set_tx_hash(vec![0; data]);
+            blk
+        };
+        let sync_chunks = |blocks: Vec<ProtobufBlock>, max_blocks, max_bytes| {
+            let mut chunks = SyncChunks::new(max_blocks, max_bytes);
+            let mut full: Vec<Vec<ProtobufBlock>> = blocks.into_iter().filter_map(|blk| chunks.push(blk)).collect();
+            full.extend(chunks.finish());
+            full
+        };
+        let heights = |chunks: &Vec<Vec<ProtobufBlock>>| {
+            chunks
+                .iter()
+                .map(|chunk| chunk.iter().map(|blk| blk.get_header().get_height()).collect())
+                .collect::<Vec<Vec<u64>>>()
+        };
+
+        let blocks: Vec<ProtobufBlock> = (1..8).map(|height| block(height, 10)).collect();
+        let chunks = sync_chunks(blocks.clone(), 3, usize::max_value());
+        assert_eq!(heights(&chunks), vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+
+        let size = ::protobuf::Message::compute_size(&blocks[0]) as usize;
+        let chunks = sync_chunks(blocks, 100, 2 * size);
+        assert_eq!(heights(&chunks), vec![vec![1, 2], vec![3, 4], vec![5, 6], vec![7]]);
+
+        // A block over the byte cap still goes, alone
+        let blocks = vec![block(1, 10), block(2, 1000), block(3, 10)];
+        let chunks = sync_chunks(blocks, 100, 3 * size);
+        assert_eq!(heights(&chunks), vec![vec![1], vec![2], vec![3]]);
+        assert!(sync_chunks(Vec::new(), 3, 100).is_empty());
+    }
+
+    fn test_forward() -> (Arc<Chain>, Receiver<(String, Vec<u8>)>, Receiver<ExecutedResult>, Forward) {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, crx_pub) = channel();
+        let (write_sender, write_receiver) = result_channel(None);
+        let forward = Forward::new(Arc::clone(&chain), ctx_pub, write_sender, false, 0, None, false);
+        (chain, crx_pub, write_receiver, forward)
+    }
+
+    /// Headers, with empty bodies, up to `current_height`, which becomes the chain's current header
+    fn insert_headers(chain: &Chain, current_height: u64) {
+        for height in 1..current_height + 1 {
+            let mut header = Header::new();
+            header.set_number(height);
+            chain.block_headers.write().insert(height, header.clone());
+            chain.block_bodies.write().insert(height, BlockBody::new());
+            if height == current_height {
+                *chain.current_header.write() = header;
+            }
+        }
+    }
+
+    /// Packs transactions with the hashes of `ns` into the genesis block, returns its hash
+    fn insert_body(chain: &Chain, ns: Vec<u64>) -> H256 {
+        let txs = ns.into_iter()
+            .map(|n| {
+                let mut stx = SignedTransaction::default().protobuf();
+                stx.set_tx_hash(H256::from(n).to_vec());
+                SignedTransaction::new(&stx).unwrap()
+            })
+            .collect();
+        let mut body = BlockBody::new();
+        body.set_transactions(txs);
+        let mut block = Block::new();
+        block.set_body(body);
+        chain.set_block_body(0, &block);
+        chain.get_current_hash()
+    }
+
+    #[test]
+    fn test_sync_by_range() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_chunk(4, DEFAULT_SYNC_CHUNK_BYTES);
+
+        let current_height = 10;
+        insert_headers(&chain, current_height);
+
+        // Heights after the current one are left out
+        let mut sync_req = SyncRequest::new();
+        sync_req.set_range_start(3);
+        sync_req.set_range_end(20);
+        let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncRequest(sync_req));
+        let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("net.sync", &msg_bytes);
+
+        let mut synced = Vec::new();
+        while let Ok((topic, res_bytes)) = crx_pub.try_recv() {
+            assert_eq!(topic, "chain.blk");
+            match Message::try_from(&res_bytes[..]).unwrap().take_content() {
+                MsgClass::SyncResponse(res) => {
+                    synced.extend(res.get_blocks().iter().map(|blk| blk.get_header().get_height()))
+                }
+                _ => panic!("expected a sync response"),
+            }
+        }
+        let synced: Vec<u64> = synced
+            .into_iter()
+            .filter(|height| *height != ::std::u64::MAX)
+            .collect();
+        assert_eq!(synced, (3..current_height + 1).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_long_sync_range_served_as_throttled() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_throttle(60, 4);
+
+        let current_height = 10;
+        insert_headers(&chain, current_height);
+
+        let synced = |start: u64, end: u64| {
+            let mut sync_req = SyncRequest::new();
+            sync_req.set_range_start(start);
+            sync_req.set_range_end(end);
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncRequest(sync_req));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.sync", &msg_bytes);
+            let mut synced = Vec::new();
+            while let Ok((_, res_bytes)) = crx_pub.try_recv() {
+                match Message::try_from(&res_bytes[..]).unwrap().take_content() {
+                    MsgClass::SyncResponse(res) => {
+                        synced.extend(res.get_blocks().iter().map(|blk| blk.get_header().get_height()))
+                    }
+                    _ => panic!("expected a sync response"),
+                }
+            }
+            synced
+        };
+        // Far longer than the heights node 2 may have in flight, served up to that
+        assert_eq!(synced(1, 10_000), vec![1, 2, 3, 4]);
+        assert_eq!(synced(5, 10_000), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_sync_proof_sent_once_in_last_chunk() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_throttle(0, 0);
+
+        let current_height = 10;
+        insert_headers(&chain, current_height);
+        chain.save_current_block_poof(ProtoProof::new());
+
+        // Chunks ending short of the cap, or full so the proof goes alone
+        for &(chunk_blocks, replies) in &[(4, 3), (5, 3)] {
+            forward.set_sync_chunk(chunk_blocks, DEFAULT_SYNC_CHUNK_BYTES);
+            let mut sync_req = SyncRequest::new();
+            sync_req.set_heights((1..current_height + 1).collect());
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncRequest(sync_req));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.sync", &msg_bytes);
+
+            let mut chunks = Vec::new();
+            while let Ok((_, res_bytes)) = crx_pub.try_recv() {
+                match Message::try_from(&res_bytes[..]).unwrap().take_content() {
+                    MsgClass::SyncResponse(res) => chunks.push(
+                        res.get_blocks()
+                            .iter()
+                            .map(|blk| blk.get_header().get_height())
+                            .collect::<Vec<u64>>(),
+                    ),
+                    _ => panic!("expected a sync response"),
+                }
+            }
+            assert_eq!(chunks.len(), replies);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= chunk_blocks));
+            let proofs = chunks
+                .iter()
+                .flat_map(|chunk| chunk.iter())
+                .filter(|height| **height == ::std::u64::MAX)
+                .count();
+            assert_eq!(proofs, 1);
+            assert_eq!(chunks.last().and_then(|chunk| chunk.last()), Some(&::std::u64::MAX));
+        }
+    }
+
+    #[test]
+    fn test_redundant_sync_request() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_throttle(60, 4);
+
+        insert_headers(&chain, 5);
+
+        let replies = |heights: Vec<u64>, origin: u32| {
+            let mut sync_req = SyncRequest::new();
+            sync_req.set_heights(heights);
+            let msg = Message::init(OperateType::SINGLE, origin, MsgClass::SyncRequest(sync_req));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.sync", &msg_bytes);
+            let mut count = 0;
+            while let Ok(_) = crx_pub.try_recv() {
+                count += 1;
+            }
+            count
+        };
+        assert_eq!(replies(vec![1, 2, 3], 2), 1);
+        // Heights served to node 2 already
+        assert_eq!(replies(vec![2, 3], 2), 0);
+        assert_eq!(replies(vec![2, 3], 3), 1);
+        // Node 2 reaches its cap of heights in flight
+        assert_eq!(replies(vec![3, 4], 2), 1);
+        assert_eq!(replies(vec![5], 2), 0);
+    }
+
+    #[test]
+    fn test_pull_sync_counts_served_heights() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_throttle(60, 4);
+
+        let current_height = 5;
+        insert_headers(&chain, current_height);
+
+        let pull = |cursor: u64, window: u64| {
+            let mut sync_req = SyncRequest::new();
+            sync_req.set_cursor(cursor);
+            sync_req.set_window(window);
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncRequest(sync_req));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.sync", &msg_bytes);
+            crx_pub.try_recv().ok().map(|(_, res_bytes)| {
+                match Message::try_from(&res_bytes[..]).unwrap().take_content() {
+                    MsgClass::SyncResponse(res) => res,
+                    _ => panic!("expected a sync response"),
+                }
+            })
+        };
+
+        // A window reaching past the current height only counts the heights served
+        let res = pull(4, 64).unwrap();
+        assert_eq!(
+            res.get_blocks()
+                .iter()
+                .map(|blk| blk.get_header().get_height())
+                .collect::<Vec<u64>>(),
+            vec![4, 5]
+        );
+        assert!(!res.get_has_next());
+
+        let res = pull(1, 2).unwrap();
+        assert_eq!(res.get_blocks().len(), 2);
+        assert!(res.get_has_next());
+        assert_eq!(res.get_next_cursor(), 3);
+
+        // Node 2 has 4 heights in flight now
+        assert!(pull(3, 1).is_none());
+    }
+
+    #[test]
+    fn test_recent_peers() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, _crx_pub) = channel();
+        let (write_sender, _write_receiver) = result_channel(None);
+        let forward = Forward::new(chain, ctx_pub, write_sender, false, 0, Some(1), false);
+
+        for origin in vec![3, 2, 3] {
+            let msg = Message::init(OperateType::SINGLE, origin, MsgClass::SyncRequest(SyncRequest::new()));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.sync", &msg_bytes);
+        }
+        // Local services aren't peers
+        let mut req = ProtoRequest::new();
+        req.set_block_number(true);
+        let msg = Message::init(OperateType::SINGLE, 4, MsgClass::Request(req));
+        let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("jsonrpc.request", &msg_bytes);
+
+        assert_eq!(forward.recent_peers(), vec![2, 3]);
+        assert_eq!(forward.id_card, Some(1));
+    }
+
+    #[test]
+    fn test_executed_result_without_block_processor() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, _crx_pub) = channel();
+        let (write_sender, write_receiver) = result_channel(Some(1));
+        let forward = Forward::new(chain, ctx_pub, write_sender, false, 0, None, false);
+
+        let msg: Message = ExecutedResult::new().into();
+        let result_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("executor.result", &result_bytes);
+        assert!(write_receiver.try_recv().is_ok());
+
+        drop(write_receiver);
+        forward.dispatch_msg("executor.result", &result_bytes);
+        forward.dispatch_msg("executor.result", &result_bytes);
+        assert_eq!(forward.lost_executed_results.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_read_only_replica() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, crx_pub) = channel();
+        let (write_sender, write_receiver) = result_channel(None);
+        let forward = Forward::new(Arc::clone(&chain), ctx_pub, write_sender, false, 0, None, true);
+
+        let mut blk = ProtobufBlock::new();
+        blk.mut_header().set_height(1);
+        let mut proof_blk = BlockWithProof::new();
+        proof_blk.set_blk(blk);
+        let msg: Message = proof_blk.into();
+        let blk_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("consensus.blk", &blk_bytes);
+        assert!(!chain.block_map.read().contains_key(&1));
+
+        let msg: Message = ExecutedResult::new().into();
+        let result_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("executor.result", &result_bytes);
+        assert!(write_receiver.try_recv().is_err());
+
+        let mut req = ProtoRequest::new();
+        req.set_request_id(vec![1]);
+        req.set_block_number(true);
+        let msg: Message = req.into();
+        let req_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("jsonrpc.request", &req_bytes);
+        let (topic, _) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.rpc");
+    }
+
+    #[test]
+    fn test_receipt_of_unknown_tx_and_unavailable_receipt() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, crx_pub) = channel();
+        let (write_sender, _write_receiver) = result_channel(None);
+        let forward = Forward::new(Arc::clone(&chain), ctx_pub.clone(), write_sender.clone(), false, 0, None, false);
+
+        let query_receipt = |forward: &Forward, hash: H256| {
+            let mut req = ProtoRequest::new();
+            req.set_request_id(vec![1]);
+            req.set_transaction_receipt(hash.to_vec());
+            let msg: Message = req.into();
+            let req_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("jsonrpc.request", &req_bytes);
+            let (topic, resp_bytes) = crx_pub.try_recv().unwrap();
+            assert_eq!(topic, "chain.rpc");
+            match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+                MsgClass::Response(resp) => resp,
+                _ => panic!("expected a response"),
+            }
+        };
+
+        let unknown = query_receipt(&forward, H256::from(1));
+        assert_eq!(unknown.get_code(), 0);
+        assert!(unknown.get_none());
+
+        // Packed into a block, but the block's receipts haven't been written
+        let known = H256::from(2);
+        chain.transaction_addresses.write().insert(
+            known,
+            TransactionAddress {
+                block_hash: H256::from(3),
+                index: 0,
+            },
+        );
+        let pending = query_receipt(&forward, known);
+        assert_eq!(pending.get_code(), DEFAULT_RECEIPT_NOT_READY_CODE);
+        assert!(!pending.get_none());
+        assert!(!pending.get_error_msg().is_empty());
+
+        let mut custom = Forward::new(Arc::clone(&chain), ctx_pub.clone(), write_sender.clone(), false, 0, None, false);
+        custom.set_receipt_not_ready_code(-32_099);
+        assert_eq!(query_receipt(&custom, known).get_code(), -32_099);
+
+        // 0 falls back to answering null, as for an unknown tx
+        let mut fallback = Forward::new(Arc::clone(&chain), ctx_pub, write_sender, false, 0, None, false);
+        fallback.set_receipt_not_ready_code(0);
+        let pending = query_receipt(&fallback, known);
+        assert_eq!(pending.get_code(), 0);
+        assert!(pending.get_none());
+    }
+
+    #[test]
+    fn test_transaction_receipts() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_max_receipts_per_call(2);
+
+        let query_receipts = |hashes: Vec<H256>| {
+            let mut req = ProtoRequest::new();
+            req.set_request_id(vec![1]);
+            req.set_transaction_receipts(serde_json::to_string(&hashes).unwrap());
+            let msg: Message = req.into();
+            let req_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("jsonrpc.request", &req_bytes);
+            let (_, resp_bytes) = crx_pub.try_recv().unwrap();
+            match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+                MsgClass::Response(resp) => resp,
+                _ => panic!("expected a response"),
+            }
+        };
+
+        let unknown = query_receipts(vec![H256::from(1), H256::from(2)]);
+        assert_eq!(unknown.get_code(), 0);
+        let receipts: Vec<Option<RpcReceipt>> = serde_json::from_str(unknown.get_receipts()).unwrap();
+        assert_eq!(receipts, vec![None, None]);
+
+        let too_many = query_receipts(vec![H256::from(1), H256::from(2), H256::from(3)]);
+        assert_eq!(too_many.get_code(), ErrorCode::query_error());
+        assert!(too_many.get_receipts().is_empty());
+
+        // Both packed into the genesis block, only the first one has a receipt
+        let block_hash = insert_body(&chain, vec![1, 2]);
+        for index in 0..2 {
+            chain.transaction_addresses.write().insert(
+                H256::from(index as u64 + 1),
+                TransactionAddress {
+                    block_hash: block_hash,
+                    index: index,
+                },
+            );
+        }
+        let receipt = Receipt::new(None, U256::from(21_000), vec![], None, U256::zero());
+        chain
+            .block_receipts
+            .write()
+            .insert(block_hash, BlockReceipts::new(vec![Some(receipt), None]));
+
+        let found = query_receipts(vec![H256::from(1), H256::from(3)]);
+        assert_eq!(found.get_code(), 0);
+        let receipts: Vec<Option<RpcReceipt>> = serde_json::from_str(found.get_receipts()).unwrap();
+        assert_eq!(receipts.len(), 2);
+        let first = receipts[0].clone().unwrap();
+        assert_eq!(first.transaction_hash, Some(H256::from(1)));
+        assert_eq!(first.transaction_index, Some(U256::from(0)));
+        assert_eq!(first.block_hash, Some(block_hash));
+        assert_eq!(first.block_number, Some(U256::from(0)));
+        assert_eq!(first.gas_used, Some(U256::from(21_000)));
+        assert_eq!(receipts[1], None);
+        // Packed, but its receipt is missing
+        let receipts: Vec<Option<RpcReceipt>> =
+            serde_json::from_str(query_receipts(vec![H256::from(2)]).get_receipts()).unwrap();
+        assert_eq!(receipts, vec![None]);
+    }
+
+    #[test]
+    fn test_meta_data() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_chain_id(Some(7));
+
+        let query_meta_data = || {
+            let mut req = ProtoRequest::new();
+            req.set_request_id(vec![1]);
+            req.set_meta_data(true);
+            let msg: Message = req.into();
+            let req_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("jsonrpc.request", &req_bytes);
+            let (_, resp_bytes) = crx_pub.try_recv().unwrap();
+            match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+                MsgClass::Response(resp) => resp,
+                _ => panic!("expected a response"),
+            }
+        };
+
+        assert_eq!(query_meta_data().get_code(), ErrorCode::query_error());
+
+        chain.set_excuted_result_genesis(&ExecutedResult::new());
+        for _ in 0..2 {
+            let resp = query_meta_data();
+            assert_eq!(resp.get_code(), 0);
+            let meta_data: rpctypes::MetaData = serde_json::from_str(resp.get_meta_data()).unwrap();
+            assert_eq!(meta_data.chain_id, Some(U256::from(7)));
+            assert_eq!(meta_data.genesis_hash, chain.block_hash_by_height(0).unwrap());
+            assert_eq!(meta_data.consensus, Some("Tendermint".to_owned()));
+            assert_eq!(meta_data.version, env!("CARGO_PKG_VERSION"));
+        }
+    }
+
+    #[test]
+    fn test_transaction_position() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let block_hash = insert_body(&chain, vec![1, 2]);
+        for (n, index) in vec![(2, 1), (3, 0), (4, 5)] {
+            chain.transaction_addresses.write().insert(
+                H256::from(n),
+                TransactionAddress {
+                    block_hash: block_hash,
+                    index: index,
+                },
+            );
+        }
+
+        let mut response = forward.query(Request::transaction(H256::from(2).to_vec()));
+        assert!(response.has_ts());
+        let ts = response.take_ts();
+        assert_eq!(ts.get_transaction().get_tx_hash(), &H256::from(2).to_vec()[..]);
+        assert_eq!(ts.get_block_hash(), &block_hash.to_vec()[..]);
+        assert_eq!(ts.get_block_number(), 0);
+        assert_eq!(ts.get_index(), 1);
+
+        // Unknown, pointing at another tx of the block, and past the end of it
+        for n in vec![1, 3, 4, 5] {
+            let response = forward.query(Request::transaction(H256::from(n).to_vec()));
+            assert!(response.get_none(), "tx {}", n);
+        }
+    }
+
+    #[test]
+    fn test_transaction_by_position() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let block_hash = insert_body(&chain, vec![1, 2]);
+
+        let query = |block: PositionBlock, index: u64| {
+            let position = TransactionPosition {
+                block: block,
+                index: index,
+            };
+            forward.query(Request::transaction_by_position(serde_json::to_string(&position).unwrap()))
+        };
+
+        let mut response = query(PositionBlock::Hash(block_hash), 1);
+        let ts = response.take_ts();
+        assert_eq!(ts.get_transaction().get_tx_hash(), &H256::from(2).to_vec()[..]);
+        assert_eq!(ts.get_block_hash(), &block_hash.to_vec()[..]);
+        assert_eq!(ts.get_index(), 1);
+        let mut response = query(PositionBlock::Number(BlockNumber::Tag(BlockTag::Earliest)), 0);
+        assert_eq!(response.take_ts().get_transaction().get_tx_hash(), &H256::from(1).to_vec()[..]);
+
+        assert!(query(PositionBlock::Hash(block_hash), 2).get_none());
+        assert!(query(PositionBlock::Hash(H256::from(9)), 0).get_none());
+        assert!(query(PositionBlock::Number(BlockNumber::Height(5)), 0).get_none());
+    }
+
+    #[test]
+    fn test_proof_of_unknown_tx() {
+        let (chain, crx_pub, _write_receiver, forward) = test_forward();
+
+        let mut req = ProtoRequest::new();
+        req.set_request_id(vec![1]);
+        req.set_transaction_proof(H256::from(1).to_vec());
+        let msg: Message = req.into();
+        let req_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("jsonrpc.request", &req_bytes);
+
+        let (topic, resp_bytes) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.rpc");
+        match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_code(), ErrorCode::query_error());
+                assert!(!resp.get_error_msg().is_empty());
+            }
+            _ => panic!("expected a response"),
+        }
+    }
+
+    #[test]
+    fn test_publish_filter_changes() {
+        let (notifier, filter_changes) = channel();
+        let (ctx_pub, crx_pub) = channel();
+        let publisher = thread::spawn(move || publish_filter_changes(filter_changes, ctx_pub));
+        notifier.send((3, FilterChanges::Logs(Vec::new()))).unwrap();
+        drop(notifier);
+        publisher.join().unwrap();
+
+        let (topic, resp_bytes) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.subscription");
+        match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+            MsgClass::Response(resp) => {
+                assert_eq!(resp.get_subscription(), 3);
+                assert_eq!(resp.get_filter_changes(), "[]");
+            }
+            _ => panic!("expected a response"),
+        }
+        assert!(crx_pub.try_recv().is_err());
+    }
+
+    fn unhandled_response() -> Vec<u8> {
+        let msg: Message = response::Response::new().into();
+        msg.try_into().unwrap()
+    }
+
+    #[test]
+    fn test_count_unhandled_msg_class() {
+        let (_, crx_pub, _write_receiver, forward) = test_forward();
+
+        forward.dispatch_msg("auth.rpc", &unhandled_response());
+        forward.dispatch_msg("auth.rpc", &unhandled_response());
+        assert_eq!(forward.unhandled_msgs().get("Response"), Some(&2));
+        assert!(crx_pub.try_recv().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected Response message")]
+    fn test_strict_msg_class() {
+        let (_, _crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_strict_msg_class(true);
+
+        forward.dispatch_msg("auth.rpc", &unhandled_response());
+    }
+
+    #[test]
+    fn test_drop_stale_sync_response() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        chain.max_height.store(10, Ordering::SeqCst);
+
+        let sync_response = |heights: Vec<u64>| {
+            let mut res = SyncResponse::new();
+            for height in heights {
+                let mut blk = ProtobufBlock::new();
+                blk.mut_header().set_height(height);
+                res.mut_blocks().push(blk);
+            }
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncResponse(res));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            msg_bytes
+        };
+
+        // Consensus already passed every block, even the proof placeholder is ignored
+        forward.dispatch_msg("net.blk", &sync_response(vec![3, 4, ::std::u64::MAX]));
+        assert_eq!(forward.stale_sync_responses.load(Ordering::SeqCst), 1);
+        assert!(chain.block_map.read().is_empty());
+
+        forward.dispatch_msg("net.blk", &sync_response(vec![10, 11]));
+        assert_eq!(forward.stale_sync_responses.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_skip_sync_block_validated_already() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let mut proof = TendermintProof::default();
+        proof.height = chain.get_max_height() as usize;
+        let mut block = Block::new();
+        block.set_number(chain.get_max_height() + 1);
+        block.set_proof(proof.into());
+        let transactions_root = block.body().protobuf().transactions_root();
+        block.set_transactions_root(H256::from_slice(&transactions_root));
+        let sync_response = |origin: u32| {
+            let mut res = SyncResponse::new();
+            res.mut_blocks().push(block.protobuf());
+            let msg = Message::init(OperateType::SINGLE, origin, MsgClass::SyncResponse(res));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            msg_bytes
+        };
+
+        forward.dispatch_msg("net.blk", &sync_response(2));
+        assert!(chain.block_map.read().contains_key(&block.number()));
+        assert!(forward
+            .validated_sync_blocks
+            .lock()
+            .contains(&sync_block_hash(&block.protobuf())));
+        assert_eq!(forward.sync_cache_hits.load(Ordering::SeqCst), 0);
+
+        // The same block from another peer isn't validated again
+        forward.dispatch_msg("net.blk", &sync_response(3));
+        assert_eq!(forward.sync_cache_hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_expire_proofless_sync_block() {
+        let (chain, crx_pub, _write_receiver, mut forward) = test_forward();
+        forward.set_sync_proof_timeout(10);
+
+        let validator = KeyPair::gen_keypair();
+        set_validators(&chain, chain.get_current_height(), &[&validator]);
+
+        // A Tendermint block carries the proof of its parent, so the last one waits for its own
+        let first_height = chain.get_max_height() + 1;
+        let blocks = sync_blocks(first_height, 2, &validator);
+        let last_hash = sync_block_hash(&blocks[1].protobuf());
+        let msg_bytes = sync_response(&blocks);
+        forward.dispatch_msg("net.blk", &msg_bytes);
+        assert_eq!(chain.get_max_store_height(), first_height);
+        assert!(chain.block_map.read().contains_key(&(first_height + 1)));
+        while crx_pub.try_recv().is_ok() {}
+
+        let now = Instant::now();
+        assert!(forward.expire_proofless_sync_blocks_at(now).is_empty());
+        assert!(forward
+            .expire_proofless_sync_blocks_at(now + Duration::from_secs(9))
+            .is_empty());
+        assert!(chain.block_map.read().contains_key(&(first_height + 1)));
+
+        assert_eq!(
+            forward.expire_proofless_sync_blocks_at(now + Duration::from_secs(10)),
+            vec![first_height + 1]
+        );
+        assert!(chain.block_map.read().contains_key(&first_height));
+        assert!(!chain.block_map.read().contains_key(&(first_height + 1)));
+        assert_eq!(chain.get_max_store_height(), first_height);
+        assert!(!forward.validated_sync_blocks.lock().contains(&last_hash));
+        assert!(forward.proofless_sync_blocks.lock().is_empty());
+        // The status announced again makes network request the block anew
+        let (topic, _) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.status");
+
+        // It's queued again when synced again
+        forward.dispatch_msg("net.blk", &msg_bytes);
+        assert!(chain.block_map.read().contains_key(&(first_height + 1)));
+    }
+
+    #[test]
+    fn test_reject_sync_block_with_forged_proof() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        let validator = KeyPair::gen_keypair();
+        let outsider = KeyPair::gen_keypair();
+        set_validators(&chain, chain.get_current_height(), &[&validator]);
+
+        let first_height = chain.get_max_height() + 1;
+        let blocks = sync_blocks(first_height, 2, &validator);
+        forward.dispatch_msg("net.blk", &sync_response(&blocks[..1]));
+        assert!(chain.block_map.read().contains_key(&first_height));
+        // Queued but not stored until its proof comes
+        assert_eq!(chain.get_max_store_height(), first_height - 1);
+
+        let child = |proposal: H256, signer: &KeyPair| {
+            let mut block = blocks[1].clone();
+            block.set_proof(signed_proof(first_height as usize, proposal, signer).into());
+            block
+        };
+
+        // The proof of another block at the height neither is set nor queues the block carrying it
+        let mut sibling = blocks[0].clone();
+        sibling.set_timestamp(10);
+        forward.dispatch_msg("net.blk", &sync_response(&[child(sibling.crypt_hash(), &validator)]));
+        assert!(!chain.block_map.read().contains_key(&(first_height + 1)));
+        assert_eq!(chain.get_max_store_height(), first_height - 1);
+        match chain.block_map.read()[&first_height] {
+            BlockInQueue::SyncBlock((_, ref proof)) => assert!(proof.is_none()),
+            _ => panic!("block-{} isn't a sync block", first_height),
+        }
+
+        // A forged proof drops the block it's for and the block carrying it
+        forward.dispatch_msg("net.blk", &sync_response(&[child(blocks[0].crypt_hash(), &outsider)]));
+        assert!(!chain.block_map.read().contains_key(&first_height));
+        assert!(!chain.block_map.read().contains_key(&(first_height + 1)));
+        assert!(!forward
+            .validated_sync_blocks
+            .lock()
+            .contains(&sync_block_hash(&blocks[0].protobuf())));
+        assert_eq!(chain.get_max_store_height(), first_height - 1);
+
+        forward.dispatch_msg("net.blk", &sync_response(&blocks));
+        assert!(chain.block_map.read().contains_key(&(first_height + 1)));
+        assert_eq!(chain.get_max_store_height(), first_height);
+    }
+
+    #[test]
+    fn test_sync_proof_checked_against_validators_of_parent() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        let validator = KeyPair::gen_keypair();
+        let successor = KeyPair::gen_keypair();
+        set_validators(&chain, chain.get_current_height(), &[&validator]);
+        // The latest executed config has other validators, the proof is checked against the old ones
+        chain.set_excuted_config(0, &ProtoAccountGasLimit::new(), &vec![*successor.address()]);
+
+        let first_height = chain.get_max_height() + 1;
+        forward.dispatch_msg("net.blk", &sync_response(&sync_blocks(first_height, 3, &validator)));
+        assert_eq!(chain.get_max_store_height(), first_height);
+
+        // The validators of the proof of the second block are only known once the first is executed
+        match chain.block_map.read()[&(first_height + 1)] {
+            BlockInQueue::SyncBlock((_, ref proof)) => assert!(proof.is_some()),
+            _ => panic!("block-{} isn't a sync block", first_height + 1),
+        }
+        assert_eq!(chain.validators_at(first_height), None);
+    }
+
+    #[test]
+    fn test_check_tendermint_proof() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Chain::init_chain(Arc::new(db), Config::default());
+        let validator = KeyPair::gen_keypair();
+        let outsider = KeyPair::gen_keypair();
+        set_validators(&chain, chain.get_current_height(), &[&validator]);
+
+        let mut block = Block::new();
+        block.set_number(chain.get_current_height() + 1);
+        let height = block.number() as usize;
+        let hash = block.crypt_hash();
+        let check = |proof: TendermintProof, block: &Block| chain.check_tendermint_proof(&proof.into(), block);
+        assert_eq!(check(signed_proof(height, hash, &validator), &block), Some(true));
+        assert_eq!(check(signed_proof(height, hash, &outsider), &block), Some(false));
+        let mut unsigned = TendermintProof::default();
+        unsigned.height = height;
+        unsigned.proposal = hash;
+        assert_eq!(check(unsigned, &block), Some(false));
+
+        // The proof must be of the block
+        assert_eq!(check(signed_proof(height, H256::from(1), &validator), &block), Some(false));
+        assert_eq!(check(signed_proof(height + 1, hash, &validator), &block), Some(false));
+
+        // Validators of a parent not executed yet aren't known
+        let mut next = Block::new();
+        next.set_number(block.number() + 1);
+        assert_eq!(check(signed_proof(height + 1, next.crypt_hash(), &validator), &next), None);
+    }
+
+    #[test]
+    fn test_sync_raft_blocks() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let mut config = Config::default();
+        config.check_prooftype = 1;
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), config));
+        let (ctx_pub, _crx_pub) = channel();
+        let (write_sender, _write_receiver) = result_channel(None);
+        let forward = Forward::new(Arc::clone(&chain), ctx_pub, write_sender, false, 0, None, false);
+
+        let mut proof = ProtoProof::new();
+        proof.set_field_type(ProofType::Raft);
+        let mut res = SyncResponse::new();
+        let first_height = chain.get_max_height() + 1;
+        for height in first_height..first_height + 2 {
+            let mut block = Block::new();
+            block.set_number(height);
+            block.set_proof(proof.clone());
+            let transactions_root = block.body().protobuf().transactions_root();
+            block.set_transactions_root(H256::from_slice(&transactions_root));
+            res.mut_blocks().push(block.protobuf());
+        }
+        let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncResponse(res));
+        let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("net.blk", &msg_bytes);
+
+        // Each block is queued with its own proof
+        for height in first_height..first_height + 2 {
+            match chain.block_map.read().get(&height) {
+                Some(&BlockInQueue::SyncBlock((ref block, Some(ref block_proof)))) => {
+                    assert_eq!(block.number(), height);
+                    assert_eq!(block_proof.get_field_type(), ProofType::Raft);
+                }
+                other => panic!("block-{} isn't queued with its proof: {:?}", height, other),
+            }
+        }
+        assert_eq!(chain.get_max_store_height(), first_height + 1);
+    }
+
+    #[test]
+    fn test_maintenance_holds_consensus_blocks() {
+        let (chain, crx_pub, write_receiver, forward) = test_forward();
+        let height = chain.get_max_store_height();
+
+        forward.set_maintenance(true);
+        assert!(forward.in_maintenance());
+        let consensus_blk = |height: u64| {
+            let mut blk = ProtobufBlock::new();
+            blk.mut_header().set_height(height);
+            let mut proof_blk = BlockWithProof::new();
+            proof_blk.set_blk(blk);
+            let msg: Message = proof_blk.into();
+            let blk_bytes: Vec<u8> = msg.try_into().unwrap();
+            blk_bytes
+        };
+        forward.dispatch_msg("consensus.blk", &consensus_blk(height + 1));
+        forward.dispatch_msg("consensus.blk", &consensus_blk(height + 2));
+        let msg: Message = ExecutedResult::new().into();
+        let result_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("executor.result", &result_bytes);
+        assert!(!chain.block_map.read().contains_key(&(height + 1)));
+        assert!(write_receiver.try_recv().is_err());
+
+        // Queries are still answered
+        let mut req = ProtoRequest::new();
+        req.set_request_id(vec![1]);
+        req.set_block_number(true);
+        let msg: Message = req.into();
+        let req_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("jsonrpc.request", &req_bytes);
+        let (topic, _) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.rpc");
+
+        // Block height+2 is only queued after height+1, which moves max_store_height
+        forward.set_maintenance(false);
+        assert!(!forward.in_maintenance());
+        assert!(chain.block_map.read().contains_key(&(height + 1)));
+        assert!(chain.block_map.read().contains_key(&(height + 2)));
+        assert!(write_receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_maintenance_drops_blocks_beyond_limit() {
+        let (chain, crx_pub, write_receiver, mut forward) = test_forward();
+        forward.set_max_maintenance_msgs(2);
+        let height = chain.get_max_store_height();
+
+        forward.set_maintenance(true);
+        let consensus_blk = |height: u64| {
+            let mut blk = ProtobufBlock::new();
+            blk.mut_header().set_height(height);
+            let mut proof_blk = BlockWithProof::new();
+            proof_blk.set_blk(blk);
+            let msg: Message = proof_blk.into();
+            let blk_bytes: Vec<u8> = msg.try_into().unwrap();
+            blk_bytes
+        };
+        forward.dispatch_msg("consensus.blk", &consensus_blk(height + 1));
+        forward.dispatch_msg("consensus.blk", &consensus_blk(height + 2));
+        forward.dispatch_msg("consensus.blk", &consensus_blk(height + 3));
+        // Executed results are held past the limit
+        let msg: Message = ExecutedResult::new().into();
+        let result_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("executor.result", &result_bytes);
+
+        forward.set_maintenance(false);
+        assert!(chain.block_map.read().contains_key(&(height + 2)));
+        assert!(!chain.block_map.read().contains_key(&(height + 3)));
+        assert!(write_receiver.try_recv().is_ok());
+        // The status is told so the dropped block is synced
+        assert!(crx_pub.try_iter().any(|(topic, _)| topic == "chain.status"));
+    }
+
+    #[test]
+    fn test_heights() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        let max_height = chain.get_max_height();
+
+        chain.max_store_height.store(max_height as usize + 2, Ordering::SeqCst);
+        let heights: rpctypes::Heights =
+            serde_json::from_str(forward.query(Request::heights(true)).get_heights()).unwrap();
+        assert_eq!(heights.max_store_height, Some(U256::from(max_height + 2)));
+        assert_eq!(heights.max_height, U256::from(max_height));
+        assert_eq!(heights.current_height, U256::from(chain.get_current_height()));
+        assert_eq!(forward.query(Request::block_number(true)).get_block_number(), max_height + 2);
+
+        chain.max_store_height.store(::std::usize::MAX, Ordering::SeqCst);
+        let heights: rpctypes::Heights =
+            serde_json::from_str(forward.query(Request::heights(true)).get_heights()).unwrap();
+        assert_eq!(heights.max_store_height, None);
+        assert_eq!(heights.max_height, U256::from(max_height));
+        assert_eq!(forward.query(Request::block_number(true)).get_block_number(), max_height);
+    }
+
+    #[test]
+    fn test_block_by_height_params() {
+        let (_, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let query = |params: &str| forward.query(Request::block_by_height(params.to_owned()));
+        let latest = query(r#"{"block_id":"latest","include_txs":false,"fields":null}"#);
+        let pending = query(r#"{"block_id":"pending","include_txs":false,"fields":null}"#);
+        assert_eq!(latest.get_code(), 0);
+        assert_eq!(pending, latest);
+        assert_eq!(query(r#"{"block_id":"earliest","include_txs":false,"fields":null}"#).get_code(), 0);
+
+        // Bad params are answered instead of panicking, why they are bad is only traced
+        for params in vec!["", "{}", r#"{"block_id":"safe","include_txs":false,"fields":null}"#] {
+            let response = query(params);
+            assert_eq!(response.get_code(), RpcErrorCode::InvalidParams.code());
+            assert_eq!(response.get_error_msg(), "invalid params");
+        }
+    }
+
+    #[test]
+    fn test_block_by_hash_bad_params() {
+        let (_, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let response = forward.query(Request::block_by_hash(r#"{"hash":"0x12"}"#.to_owned()));
+        assert_eq!(response.get_code(), RpcErrorCode::InvalidParams.code());
+        assert_eq!(response.get_error_msg(), "invalid params");
+        assert_eq!(response.get_block(), "");
+    }
+
+    #[test]
+    fn test_unknown_filter_is_an_error() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+
+        let id = chain.new_block_filter() as u64;
+        assert_eq!(forward.query(Request::filter_changes(id)).get_code(), 0);
+        assert!(forward.query(Request::uninstall_filter(id)).get_uninstall_filter());
+
+        for request in vec![
+            Request::filter_changes(id),
+            Request::filter_logs(id),
+            Request::filter_changes(id + 1),
+            Request::filter_logs(id + 1),
+        ] {
+            let response = forward.query(request);
+            assert_eq!(response.get_code(), ErrorCode::query_error());
+            assert!(response.get_error_msg().contains("not found"));
+        }
+    }
+
+    #[test]
+    fn test_block_head() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        chain.set_excuted_result_genesis(&ExecutedResult::new());
+
+        let height = chain.get_max_height() + 1;
+        let mut block = Block::new();
+        block.set_number(height);
+        block.set_timestamp(1516000000000);
+        chain
+            .block_map
+            .write()
+            .insert(height, BlockInQueue::SyncBlock((block.clone(), None)));
+        chain.max_store_height.store(height as usize, Ordering::SeqCst);
+
+        let head: rpctypes::BlockHead =
+            serde_json::from_str(forward.query(Request::block_head(true)).get_block_head()).unwrap();
+        assert_eq!(head.number, U256::from(height));
+        assert_eq!(head.hash, block.hash());
+        assert_eq!(head.header.timestamp, 1516000000000);
+        assert_eq!(forward.query(Request::block_number(true)).get_block_number(), height);
+
+        // Falls back to the executed height like blockNumber
+        chain.block_map.write().clear();
+        chain.max_store_height.store(::std::usize::MAX, Ordering::SeqCst);
+        let response = forward.query(Request::block_head(true));
+        assert_eq!(response.get_code(), 0);
+        let head: rpctypes::BlockHead = serde_json::from_str(response.get_block_head()).unwrap();
+        assert_eq!(head.number, U256::from(height - 1));
+        assert_eq!(head.hash, chain.block_hash_by_height(height - 1).unwrap());
+    }
+
+    #[test]
+    fn test_blocks_at_height_include_replaced_block() {
+        let (chain, crx_pub, _write_receiver, forward) = test_forward();
+
+        let height = chain.get_max_height() + 1;
+        let sync_block = |timestamp: u64| {
+            let mut proof = TendermintProof::default();
+            proof.height = chain.get_max_height() as usize;
+            let mut block = Block::new();
+            block.set_number(height);
+            block.set_timestamp(timestamp);
+            block.set_proof(proof.into());
+            let transactions_root = block.body().protobuf().transactions_root();
+            block.set_transactions_root(H256::from_slice(&transactions_root));
+            block
+        };
+        let first = sync_block(10);
+        let second = sync_block(20);
+        for block in vec![&first, &second] {
+            let mut res = SyncResponse::new();
+            res.mut_blocks().push(block.protobuf());
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncResponse(res));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.blk", &msg_bytes);
+        }
+        while crx_pub.try_recv().is_ok() {}
+
+        let mut req = ProtoRequest::new();
+        req.set_request_id(vec![1]);
+        let params = BlockParamsByNumber::new(rpctypes::BlockNumber::Height(height), false);
+        req.set_blocks_at_height(serde_json::to_string(&params).unwrap());
+        let msg: Message = req.into();
+        let req_bytes: Vec<u8> = msg.try_into().unwrap();
+        forward.dispatch_msg("jsonrpc.request", &req_bytes);
+
+        let (topic, resp_bytes) = crx_pub.try_recv().unwrap();
+        assert_eq!(topic, "chain.rpc");
+        let resp = match Message::try_from(&resp_bytes[..]).unwrap().take_content() {
+            MsgClass::Response(resp) => resp,
+            _ => panic!("expected a response"),
+        };
+        let blocks: Vec<RpcBlock> = serde_json::from_str(resp.get_blocks()).unwrap();
+        let hashes: Vec<Vec<u8>> = blocks.into_iter().map(|block| block.hash).collect();
+        assert_eq!(hashes, vec![second.hash().to_vec(), first.hash().to_vec()]);
+    }
+
+    #[test]
+    fn test_replaced_block_reverts_its_transactions() {
+        let (chain, _crx_pub, _write_receiver, forward) = test_forward();
+        let (notifier, notifications) = channel();
+        chain.set_filter_notifier(notifier);
+        let id = chain.subscribe_reverted_transactions();
+
+        let tx = |n: u64| {
+            let mut stx = SignedTransaction::default().protobuf();
+            stx.set_tx_hash(H256::from(n).to_vec());
+            SignedTransaction::new(&stx).unwrap()
+        };
+        let height = chain.get_max_height() + 1;
+        let sync_block = |timestamp: u64, txs: Vec<SignedTransaction>| {
+            let mut proof = TendermintProof::default();
+            proof.height = chain.get_max_height() as usize;
+            let mut body = BlockBody::new();
+            body.set_transactions(txs);
+            let mut block = Block::new();
+            block.set_number(height);
+            block.set_timestamp(timestamp);
+            block.set_proof(proof.into());
+            block.set_body(body);
+            let transactions_root = block.body().protobuf().transactions_root();
+            block.set_transactions_root(H256::from_slice(&transactions_root));
+            block
+        };
+        // The block at the height is replaced by a sibling which only kept one of its txs
+        let first = sync_block(10, vec![tx(1), tx(2)]);
+        let second = sync_block(20, vec![tx(2)]);
+        for block in vec![&first, &second] {
+            let mut res = SyncResponse::new();
+            res.mut_blocks().push(block.protobuf());
+            let msg = Message::init(OperateType::SINGLE, 2, MsgClass::SyncResponse(res));
+            let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+            forward.dispatch_msg("net.blk", &msg_bytes);
+        }
+
+        match notifications.try_recv().unwrap() {
+            (notified, FilterChanges::Hashes(hashes)) => {
+                assert_eq!(notified, id);
+                assert_eq!(hashes, vec![H256::from(1)]);
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+        assert!(notifications.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_slow_query_does_not_delay_consensus_block() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap_or(0));
+        let chain = Arc::new(Chain::init_chain(Arc::new(db), Config::default()));
+        let (ctx_pub, crx_pub) = channel();
+        let (write_sender, _write_receiver) = result_channel(None);
+        let forward = Forward::new(Arc::clone(&chain), ctx_pub, write_sender, false, 1, None, false);
+
+        // Keep the only query worker busy, as a wide getLogs would
+        forward
+            .query_pool
+            .as_ref()
+            .unwrap()
+            .execute(|| thread::sleep(Duration::from_millis(500)));
+
+        let mut req = ProtoRequest::new();
+        req.set_request_id(vec![1]);
+        req.set_block_number(true);
+        let msg: Message = req.into();
+        let req_bytes: Vec<u8> = msg.try_into().unwrap();
+
+        let mut blk = ProtobufBlock::new();
+        blk.mut_header().set_height(1);
+        let mut proof_blk = BlockWithProof::new();
+        proof_blk.set_blk(blk);
+        let msg: Message = proof_blk.into();
+        let blk_bytes: Vec<u8> = msg.try_into().unwrap();
+
+        let now = Instant::now();
+        forward.dispatch_msg("jsonrpc.request", &req_bytes);
+        forward.dispatch_msg("consensus.blk", &blk_bytes);
+        assert!(now.elapsed() < Duration::from_millis(500));
+        assert!(chain.block_map.read().contains_key(&1));
+
+        // The block's tx hashes go out before the queued query is answered
+        let (topic, _) = crx_pub.recv().unwrap();
+        assert_eq!(topic, "chain.txhashes");
+        let (topic, _) = crx_pub.recv().unwrap();
+        assert_eq!(topic, "chain.rpc");
+    }
+}
@@ -0,0 +1,141 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! JSON arrays serialized in chunks on a thread pool, for large query results.
+
+use serde::Serialize;
+use serde::ser::Error;
+use serde_json;
+use std::sync::mpsc::channel;
+use threadpool::ThreadPool;
+
+/// Serialize `items` as a JSON array, the same as `serde_json::to_string` would.
+/// With a pool and at least `threshold` items, chunks of them are serialized on
+/// the pool and concatenated. An error if any chunk fails, even by a panic on the pool.
+pub fn to_json_array<T>(
+    mut items: Vec<T>,
+    pool: Option<&ThreadPool>,
+    threshold: usize,
+) -> Result<String, serde_json::Error>
+where
+    T: Serialize + Send + 'static,
+{
+    let pool = match pool {
+        Some(pool) if items.len() >= threshold && items.len() > 1 => pool,
+        _ => return serde_json::to_string(&items),
+    };
+    let workers = pool.max_count();
+    let chunk_size = (items.len() + workers - 1) / workers;
+
+    let (sender, receiver) = channel();
+    let mut chunks = 0;
+    // Split from the back, so no item is moved more than once
+    while !items.is_empty() {
+        let index = (items.len() - 1) / chunk_size;
+        let chunk = items.split_off(index * chunk_size);
+        let sender = sender.clone();
+        pool.execute(move || {
+            let _ = sender.send((index, serde_json::to_string(&chunk)));
+        });
+        chunks += 1;
+    }
+    drop(sender);
+
+    let mut parts = Vec::with_capacity(chunks);
+    for (index, part) in receiver.iter() {
+        parts.push((index, part?));
+    }
+    if parts.len() < chunks {
+        return Err(serde_json::Error::custom("a chunk was lost on the serialize pool"));
+    }
+    parts.sort_by_key(|&(index, _)| index);
+
+    let mut json = String::with_capacity(parts.iter().map(|&(_, ref part)| part.len()).sum());
+    json.push('[');
+    for (i, (_, part)) in parts.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        // Each part is an array of its own
+        json.push_str(&part[1..part.len() - 1]);
+    }
+    json.push(']');
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_types::bytes::Bytes;
+    use jsonrpc_types::rpctypes::Log;
+    use serde::Serializer;
+    use util::{H160, H256, U256};
+
+    /// Fails to serialize when it's true, panics when it's None
+    struct Faulty(Option<bool>);
+
+    impl Serialize for Faulty {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 {
+                Some(false) => serializer.serialize_u8(0),
+                Some(true) => Err(S::Error::custom("faulty")),
+                None => panic!("faulty"),
+            }
+        }
+    }
+
+    fn logs(n: usize) -> Vec<Log> {
+        (0..n)
+            .map(|i| Log {
+                address: H160::from(i as u64),
+                topics: vec![H256::from(i as u64), H256::from(2)],
+                data: Bytes::new(vec![i as u8; i % 7]),
+                block_hash: Some(H256::from(i as u64 / 10)),
+                block_number: Some(U256::from(i / 10)),
+                transaction_hash: Some(H256::from(i as u64 + 1)),
+                transaction_index: Some(U256::from(i % 10)),
+                log_index: Some(U256::from(i)),
+                transaction_log_index: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_is_identical_to_serial() {
+        let pool = ThreadPool::new(4);
+        for n in vec![0, 1, 2, 3, 5, 1001, 10_000] {
+            let serial = serde_json::to_string(&logs(n)).unwrap();
+            assert_eq!(to_json_array(logs(n), Some(&pool), 0).unwrap(), serial);
+            assert_eq!(to_json_array(logs(n), None, 0).unwrap(), serial);
+        }
+    }
+
+    #[test]
+    fn failed_chunk_is_an_error() {
+        let pool = ThreadPool::new(4);
+        let faulty = |fault: Option<bool>| {
+            let mut items: Vec<Faulty> = (0..100).map(|_| Faulty(Some(false))).collect();
+            items[42] = Faulty(fault);
+            items
+        };
+        assert!(to_json_array(faulty(Some(true)), Some(&pool), 0).is_err());
+        assert!(to_json_array(faulty(Some(true)), None, 0).is_err());
+        assert!(to_json_array(faulty(None), Some(&pool), 0).is_err());
+        // The pool is still there for the next array
+        assert_eq!(to_json_array(faulty(Some(false)), Some(&pool), 0).unwrap().len(), 201);
+    }
+}
@@ -21,12 +21,17 @@
 #![feature(custom_attribute)]
 #![feature(refcell_replace_swap)]
 #![feature(try_from)]
+#[cfg(test)]
+extern crate bincode;
 extern crate byteorder;
+#[cfg(test)]
+extern crate cita_crypto as crypto;
 extern crate clap;
 extern crate common_types as types;
 extern crate core;
 extern crate dotenv;
 extern crate error;
+extern crate ethabi;
 extern crate jsonrpc_types;
 extern crate libproto;
 #[macro_use]
@@ -35,12 +40,23 @@ extern crate logger;
 extern crate proof;
 extern crate protobuf;
 extern crate pubsub;
+extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
+extern crate threadpool;
 #[macro_use]
 extern crate util;
 
 mod forward;
 mod block_processor;
+mod single_flight;
+mod recent_hashes;
+mod sibling_blocks;
+mod json_chunks;
+mod event_abi;
+mod sync_throttle;
 
 use block_processor::BlockProcessor;
 use clap::App;
@@ -48,11 +64,12 @@ use core::db;
 use core::libchain;
 use forward::Forward;
 use pubsub::start_pubsub;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use util::datapath::DataPath;
 use util::kvdb::{Database, DatabaseConfig};
 use util::set_panic_handler;
@@ -94,6 +111,50 @@ fn main() {
     let db = Database::open(&config, &nosql_path).unwrap();
 
     let chain_config = libchain::chain::Config::new(config_path);
+    let dedup_queries = chain_config.dedup_queries.unwrap_or(false);
+    let query_threads = chain_config.query_threads.unwrap_or(0);
+    let id_card = chain_config.id_card;
+    let read_only = chain_config.read_only.unwrap_or(false);
+    let strict_msg_class = chain_config.strict_msg_class.unwrap_or(false);
+    let sync_cache_size = chain_config
+        .sync_cache_size
+        .unwrap_or(forward::DEFAULT_SYNC_CACHE_SIZE);
+    let maintenance_file = chain_config.maintenance_file.clone();
+    let max_maintenance_msgs = chain_config
+        .max_maintenance_msgs
+        .unwrap_or(forward::DEFAULT_MAX_MAINTENANCE_MSGS);
+    let sync_proof_timeout = chain_config
+        .sync_proof_timeout
+        .unwrap_or(forward::DEFAULT_SYNC_PROOF_TIMEOUT);
+    let serialize_threads = chain_config
+        .serialize_threads
+        .unwrap_or(forward::DEFAULT_SERIALIZE_THREADS);
+    let parallel_serialize_threshold = chain_config
+        .parallel_serialize_threshold
+        .unwrap_or(forward::DEFAULT_PARALLEL_SERIALIZE_THRESHOLD);
+    let write_queue_size = chain_config.write_queue_size;
+    let sync_chunk_blocks = chain_config
+        .sync_chunk_blocks
+        .unwrap_or(forward::DEFAULT_SYNC_CHUNK_BLOCKS);
+    let sync_chunk_bytes = chain_config
+        .sync_chunk_bytes
+        .unwrap_or(forward::DEFAULT_SYNC_CHUNK_BYTES);
+    let sync_throttle_secs = chain_config
+        .sync_throttle_secs
+        .unwrap_or(forward::DEFAULT_SYNC_THROTTLE_SECS);
+    let max_sync_heights = chain_config
+        .max_sync_heights
+        .unwrap_or(forward::DEFAULT_MAX_SYNC_HEIGHTS);
+    let max_receipts_per_call = chain_config
+        .max_receipts_per_call
+        .unwrap_or(forward::DEFAULT_MAX_RECEIPTS_PER_CALL);
+    let receipt_not_ready_code = chain_config
+        .receipt_not_ready_code
+        .unwrap_or(forward::DEFAULT_RECEIPT_NOT_READY_CODE);
+    let chain_id = chain_config.chain_id;
+    if chain_id.is_none() {
+        warn!("chain_id isn't configured, clients are told it's null");
+    }
     let chain = Arc::new(libchain::chain::Chain::init_chain(
         Arc::new(db),
         chain_config,
@@ -103,17 +164,55 @@ fn main() {
         chain.delivery_block_tx_hashes(chain.get_current_height(), block_tx_hashes, &ctx_pub);
     }
 
-    let (write_sender, write_receiver) = channel();
-    let forward = Forward::new(Arc::clone(&chain), ctx_pub.clone(), write_sender);
+    // Changes of filters subscribed over websocket are pushed to jsonrpc as blocks are committed
+    let (filter_notifier, filter_changes) = channel();
+    chain.set_filter_notifier(filter_notifier);
+    let subscription_pub = ctx_pub.clone();
+    thread::spawn(move || forward::publish_filter_changes(filter_changes, subscription_pub));
+
+    let (write_sender, write_receiver) = forward::result_channel(write_queue_size);
+    let mut forward = Forward::new(
+        Arc::clone(&chain),
+        ctx_pub.clone(),
+        write_sender,
+        dedup_queries,
+        query_threads,
+        id_card,
+        read_only,
+    );
+    forward.set_strict_msg_class(strict_msg_class);
+    forward.set_sync_cache_size(sync_cache_size);
+    forward.set_sync_proof_timeout(sync_proof_timeout);
+    forward.set_parallel_serialize(serialize_threads, parallel_serialize_threshold);
+    forward.set_sync_chunk(sync_chunk_blocks, sync_chunk_bytes);
+    forward.set_sync_throttle(sync_throttle_secs, max_sync_heights);
+    forward.set_max_receipts_per_call(max_receipts_per_call);
+    forward.set_receipt_not_ready_code(receipt_not_ready_code);
+    forward.set_max_maintenance_msgs(max_maintenance_msgs);
+    forward.set_chain_id(chain_id);
 
     let block_processor = BlockProcessor::new(Arc::clone(&chain), ctx_pub);
     block_processor.broadcast_current_status();
 
     //chain 读写分离
     //chain 读数据 => 查询数据
-    thread::spawn(move || loop {
-        if let Ok((key, msg)) = rx.recv() {
-            forward.dispatch_msg(&key, &msg);
+    //维护模式在分发线程切换，暂存的消息不会被新消息插队
+    //等不到证明的同步块也在分发线程定时丢弃
+    thread::spawn(move || {
+        let check_interval = Duration::from_secs(1);
+        let mut checked: Option<Instant> = None;
+        loop {
+            if checked.map_or(true, |checked| checked.elapsed() >= check_interval) {
+                if let Some(ref path) = maintenance_file {
+                    forward.set_maintenance(Path::new(path).exists());
+                }
+                forward.expire_proofless_sync_blocks();
+                forward.commit_sync_blocks();
+                checked = Some(Instant::now());
+            }
+            if let Ok((key, msg)) = rx.recv_timeout(check_interval) {
+                forward.dispatch_msg(&key, &msg);
+            }
         }
     });
 
@@ -133,6 +232,10 @@ fn main() {
     let mut i: u32 = 0;
     loop {
         thread::sleep(time::Duration::from_millis(10_000));
+        let pruned = chain.prune_filters();
+        if pruned > 0 {
+            info!("uninstalled {} filters unpolled for too long", pruned);
+        }
         if i > 100 {
             chain.collect_garbage();
             i = 0;
@@ -0,0 +1,95 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded set of recently seen hashes, the oldest is forgotten first.
+
+use std::collections::{HashSet, VecDeque};
+use util::H256;
+
+pub struct RecentHashes {
+    capacity: usize,
+    order: VecDeque<H256>,
+    hashes: HashSet<H256>,
+}
+
+impl RecentHashes {
+    pub fn new(capacity: usize) -> Self {
+        RecentHashes {
+            capacity: capacity,
+            order: VecDeque::with_capacity(capacity),
+            hashes: HashSet::with_capacity(capacity),
+        }
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    pub fn insert(&mut self, hash: H256) {
+        if self.capacity == 0 || !self.hashes.insert(hash) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, hash: &H256) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|known| known != hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forget_oldest() {
+        let mut recent = RecentHashes::new(2);
+        recent.insert(H256::from(1));
+        recent.insert(H256::from(2));
+        recent.insert(H256::from(1));
+        recent.insert(H256::from(3));
+        assert!(!recent.contains(&H256::from(1)));
+        assert!(recent.contains(&H256::from(2)));
+        assert!(recent.contains(&H256::from(3)));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut recent = RecentHashes::new(2);
+        recent.insert(H256::from(1));
+        recent.insert(H256::from(2));
+        recent.remove(&H256::from(1));
+        recent.insert(H256::from(3));
+        assert!(!recent.contains(&H256::from(1)));
+        assert!(recent.contains(&H256::from(2)));
+        assert!(recent.contains(&H256::from(3)));
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let mut recent = RecentHashes::new(0);
+        recent.insert(H256::from(1));
+        assert!(!recent.contains(&H256::from(1)));
+    }
+}
@@ -0,0 +1,94 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Blocks which were queued at a height and then replaced by a different
+//! block, kept for reorg analysis. Only the highest heights are remembered.
+
+use core::libchain::block::Block;
+use std::collections::BTreeMap;
+
+/// Most replaced blocks remembered at one height.
+const MAX_SIBLINGS_PER_HEIGHT: usize = 8;
+
+pub struct SiblingBlocks {
+    max_heights: usize,
+    blocks: BTreeMap<u64, Vec<Block>>,
+}
+
+impl SiblingBlocks {
+    pub fn new(max_heights: usize) -> Self {
+        SiblingBlocks {
+            max_heights: max_heights,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, block: Block) {
+        if self.max_heights == 0 {
+            return;
+        }
+        {
+            let siblings = self.blocks.entry(block.number()).or_insert_with(Vec::new);
+            let hash = block.hash();
+            if siblings.len() >= MAX_SIBLINGS_PER_HEIGHT || siblings.iter().any(|sibling| sibling.hash() == hash) {
+                return;
+            }
+            siblings.push(block);
+        }
+        while self.blocks.len() > self.max_heights {
+            let lowest = *self.blocks.keys().next().unwrap();
+            self.blocks.remove(&lowest);
+        }
+    }
+
+    /// Replaced blocks at `height`, in the order they were replaced
+    pub fn at(&self, height: u64) -> Vec<Block> {
+        self.blocks.get(&height).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, timestamp: u64) -> Block {
+        let mut block = Block::new();
+        block.set_number(number);
+        block.set_timestamp(timestamp);
+        block
+    }
+
+    #[test]
+    fn test_keep_distinct_blocks() {
+        let mut siblings = SiblingBlocks::new(2);
+        siblings.insert(block(1, 10));
+        siblings.insert(block(1, 10));
+        siblings.insert(block(1, 20));
+        assert_eq!(siblings.at(1), vec![block(1, 10), block(1, 20)]);
+        assert!(siblings.at(2).is_empty());
+    }
+
+    #[test]
+    fn test_forget_lowest_height() {
+        let mut siblings = SiblingBlocks::new(2);
+        siblings.insert(block(3, 10));
+        siblings.insert(block(1, 10));
+        siblings.insert(block(2, 10));
+        assert!(siblings.at(1).is_empty());
+        assert_eq!(siblings.at(2).len(), 1);
+        assert_eq!(siblings.at(3).len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Coalesce identical in-flight queries so that they share one computation.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex};
+
+enum State<V> {
+    Pending,
+    Done(V),
+    /// The leader panicked, a follower computes the value anew
+    Abandoned,
+}
+
+struct Call<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+/// Ends the leader's call when dropped, also when the computation panics,
+/// so the key isn't left in flight and the followers are woken.
+struct Finish<'a, K: 'a + Eq + Hash, V: 'a> {
+    calls: &'a Mutex<HashMap<K, Arc<Call<V>>>>,
+    key: &'a K,
+    call: &'a Call<V>,
+}
+
+impl<'a, K: Eq + Hash, V> Drop for Finish<'a, K, V> {
+    fn drop(&mut self) {
+        self.calls.lock().unwrap().remove(self.key);
+        let mut state = self.call.state.lock().unwrap();
+        if let State::Pending = *state {
+            *state = State::Abandoned;
+        }
+        self.call.done.notify_all();
+    }
+}
+
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        SingleFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `f` for `key`, unless the same key is already being computed,
+    /// in which case wait for that computation and return its result.
+    /// If that computation panics, `f` is run instead.
+    pub fn work<F>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        loop {
+            let (call, leader) = {
+                let mut calls = self.calls.lock().unwrap();
+                if let Some(call) = calls.get(&key) {
+                    (Arc::clone(call), false)
+                } else {
+                    let call = Arc::new(Call {
+                        state: Mutex::new(State::Pending),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key.clone(), Arc::clone(&call));
+                    (call, true)
+                }
+            };
+
+            if leader {
+                let _finish = Finish {
+                    calls: &self.calls,
+                    key: &key,
+                    call: &call,
+                };
+                let value = f();
+                *call.state.lock().unwrap() = State::Done(value.clone());
+                return value;
+            }
+
+            let mut state = call.state.lock().unwrap();
+            loop {
+                match *state {
+                    State::Pending => {}
+                    State::Done(ref value) => return value.clone(),
+                    State::Abandoned => break,
+                }
+                state = call.done.wait(state).unwrap();
+            }
+            drop(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_concurrent_identical_work_runs_once() {
+        let flight = Arc::new(SingleFlight::new());
+        let counter = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let flight = Arc::clone(&flight);
+                let counter = Arc::clone(&counter);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    flight.work("block_by_height 1".to_string(), || {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        42u64
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_panicking_work_wakes_waiters() {
+        let flight: Arc<SingleFlight<u64, u64>> = Arc::new(SingleFlight::new());
+        let started = Arc::new(Barrier::new(2));
+
+        let leader = {
+            let flight = Arc::clone(&flight);
+            let started = Arc::clone(&started);
+            thread::spawn(move || {
+                flight.work(1, || {
+                    started.wait();
+                    thread::sleep(Duration::from_millis(200));
+                    panic!("query failed");
+                })
+            })
+        };
+
+        // The waiter computes the value itself once the leader panics
+        started.wait();
+        assert_eq!(flight.work(1, || 7), 7);
+        assert!(leader.join().is_err());
+        assert!(flight.calls.lock().unwrap().is_empty());
+        assert_eq!(flight.work(1, || 8), 8);
+    }
+
+    #[test]
+    fn test_sequential_work_is_not_cached() {
+        let flight = SingleFlight::new();
+        assert_eq!(flight.work(1, || "a"), "a");
+        assert_eq!(flight.work(1, || "b"), "b");
+    }
+}
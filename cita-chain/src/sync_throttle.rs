@@ -0,0 +1,188 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Heights recently served to each peer, to drop redundant sync requests.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+pub enum Throttled {
+    /// Every requested height was served to the peer within the window
+    Redundant,
+    /// The peer already has this many heights served within the window
+    TooMany(usize),
+}
+
+pub struct SyncThrottle {
+    window: Duration,
+    max_heights: usize,
+    served: HashMap<u32, HashMap<u64, Instant>>,
+}
+
+impl SyncThrottle {
+    /// A zero window admits every request.
+    pub fn new(window: Duration, max_heights: usize) -> Self {
+        SyncThrottle {
+            window: window,
+            max_heights: max_heights,
+            served: HashMap::new(),
+        }
+    }
+
+    /// Records `heights` as served to `origin`, unless throttled.
+    pub fn admit(&mut self, origin: u32, heights: &[u64]) -> Result<(), Throttled> {
+        self.admit_at(origin, heights, Instant::now())
+    }
+
+    /// Records as served to `origin` the heights from `start` to `end` it wasn't served yet,
+    /// as many of them, in order, as it has room for. Returns the heights recorded, to serve
+    /// now; the peer asks for the rest again.
+    pub fn admit_range(&mut self, origin: u32, start: u64, end: u64) -> Result<Vec<u64>, Throttled> {
+        self.admit_range_at(origin, start, end, Instant::now())
+    }
+
+    fn admit_at(&mut self, origin: u32, heights: &[u64], now: Instant) -> Result<(), Throttled> {
+        if self.window == Duration::from_secs(0) || heights.is_empty() {
+            return Ok(());
+        }
+        self.expire(now);
+        let served = self.served.entry(origin).or_insert_with(HashMap::new);
+        let mut fresh: Vec<u64> = heights
+            .iter()
+            .filter(|height| !served.contains_key(height))
+            .cloned()
+            .collect();
+        if fresh.is_empty() {
+            return Err(Throttled::Redundant);
+        }
+        fresh.sort();
+        fresh.dedup();
+        if served.len() + fresh.len() > self.max_heights {
+            return Err(Throttled::TooMany(served.len()));
+        }
+        for height in heights {
+            served.insert(*height, now);
+        }
+        Ok(())
+    }
+
+    fn admit_range_at(&mut self, origin: u32, start: u64, end: u64, now: Instant) -> Result<Vec<u64>, Throttled> {
+        if start > end {
+            return Ok(Vec::new());
+        }
+        if self.window == Duration::from_secs(0) {
+            return Ok((start..end + 1).collect());
+        }
+        self.expire(now);
+        let max_heights = self.max_heights;
+        let served = self.served.entry(origin).or_insert_with(HashMap::new);
+        let fresh: Vec<u64> = {
+            let mut fresh = (start..end + 1)
+                .filter(|height| !served.contains_key(height))
+                .peekable();
+            if fresh.peek().is_none() {
+                return Err(Throttled::Redundant);
+            }
+            let room = max_heights.saturating_sub(served.len());
+            if room == 0 {
+                return Err(Throttled::TooMany(served.len()));
+            }
+            fresh.take(room).collect()
+        };
+        for height in &fresh {
+            served.insert(*height, now);
+        }
+        Ok(fresh)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let window = self.window;
+        self.served.retain(|_, served| {
+            served.retain(|_, at| now.duration_since(*at) < window);
+            !served.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(start: u64, end: u64) -> Vec<u64> {
+        (start..end + 1).collect()
+    }
+
+    #[test]
+    fn test_redundant_heights() {
+        let mut throttle = SyncThrottle::new(Duration::from_secs(5), 100);
+        let now = Instant::now();
+        assert_eq!(throttle.admit_at(1, &heights(10, 29), now), Ok(()));
+        assert_eq!(throttle.admit_at(1, &heights(12, 20), now), Err(Throttled::Redundant));
+        // Only partly served, or served to another peer
+        assert_eq!(throttle.admit_at(1, &heights(20, 39), now), Ok(()));
+        assert_eq!(throttle.admit_at(2, &heights(10, 29), now), Ok(()));
+        // Heights far apart only cover themselves
+        assert_eq!(throttle.admit_at(3, &[1, 1000], now), Ok(()));
+        assert_eq!(throttle.admit_at(3, &[500], now), Ok(()));
+        // Served again once the window passed
+        let later = now + Duration::from_secs(5);
+        assert_eq!(throttle.admit_at(1, &heights(10, 29), later), Ok(()));
+    }
+
+    #[test]
+    fn test_too_many_heights() {
+        let mut throttle = SyncThrottle::new(Duration::from_secs(5), 40);
+        let now = Instant::now();
+        assert_eq!(throttle.admit_at(1, &heights(1, 20), now), Ok(()));
+        assert_eq!(throttle.admit_at(1, &heights(21, 40), now), Ok(()));
+        assert_eq!(throttle.admit_at(1, &[41], now), Err(Throttled::TooMany(40)));
+        // Counted by heights, not by the span they are in
+        assert_eq!(throttle.admit_at(2, &[1, 1_000_000], now), Ok(()));
+        assert_eq!(throttle.admit_at(2, &heights(41, 78), now), Ok(()));
+        assert_eq!(throttle.admit_at(2, &[79], now), Err(Throttled::TooMany(40)));
+        let later = now + Duration::from_secs(6);
+        assert_eq!(throttle.admit_at(1, &[41], later), Ok(()));
+    }
+
+    #[test]
+    fn test_range_admitted_in_chunks() {
+        let mut throttle = SyncThrottle::new(Duration::from_secs(5), 40);
+        let now = Instant::now();
+        // A long range is served as far as there is room, the rest when asked again
+        assert_eq!(throttle.admit_range_at(1, 1, 10_000, now), Ok(heights(1, 40)));
+        assert_eq!(throttle.admit_range_at(1, 1, 10_000, now), Err(Throttled::TooMany(40)));
+        assert_eq!(throttle.admit_range_at(1, 1, 40, now), Err(Throttled::Redundant));
+        let later = now + Duration::from_secs(5);
+        assert_eq!(throttle.admit_range_at(1, 41, 10_000, later), Ok(heights(41, 80)));
+        // Heights served already are skipped rather than taking room
+        assert_eq!(throttle.admit_range_at(2, 11, 20, now), Ok(heights(11, 20)));
+        assert_eq!(
+            throttle.admit_range_at(2, 1, 100, now),
+            Ok(heights(1, 10).into_iter().chain(heights(21, 40)).collect())
+        );
+        assert_eq!(throttle.admit_range_at(3, 5, 4, now), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_zero_window_admits_all() {
+        let mut throttle = SyncThrottle::new(Duration::from_secs(0), 0);
+        assert_eq!(throttle.admit(1, &heights(1, 20)), Ok(()));
+        assert_eq!(throttle.admit(1, &heights(1, 20)), Ok(()));
+        assert_eq!(throttle.admit_range(1, 1, 100), Ok(heights(1, 100)));
+    }
+}
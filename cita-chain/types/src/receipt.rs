@@ -297,6 +297,8 @@ pub struct LocalizedReceipt {
     pub gas_used: U256,
     /// Contract address.
     pub contract_address: Option<Address>,
+    /// Gas price of the transaction.
+    pub gas_price: U256,
     /// Logs
     pub logs: Vec<LocalizedLogEntry>,
     /// Logs bloom
@@ -28,6 +28,7 @@ use evm::Factory as EvmFactory;
 use executive::{Executed, Executive, TransactOptions};
 use factory::*;
 use header::*;
+use jsonrpc_types::rpctypes::{Simulation, StorageChange};
 pub use libexecutor::block::*;
 use libexecutor::call_request::CallRequest;
 use libexecutor::extras::*;
@@ -498,6 +499,45 @@ impl Executor {
             .or_else(|e| Err(format!("Call Error {}", e)))
     }
 
+    /// Run the call on a copy of the state at `id` and report the storage it would write,
+    /// nothing is committed. Only storage is reported: the call runs with no value and a zero
+    /// gas price so no balance moves, and the bump of the sender's nonce is left out as it's
+    /// an artifact of executing the call rather than something the call does.
+    pub fn simulate(&self, request: CallRequest, id: BlockId) -> Result<Simulation, String> {
+        self.simulate_call(request, id).map_err(|e| format!("Call Error {}", e))
+    }
+
+    fn simulate_call(&self, request: CallRequest, id: BlockId) -> Result<Simulation, CallError> {
+        let mut signed = self.sign_call(request);
+        let (executed, state) = self.transact_on_copy(&mut signed, id, Default::default())?;
+        let original = self.state_at(id).ok_or(CallError::StatePruned)?;
+
+        let mut storage_changes = Vec::new();
+        for (address, changes) in state.dirty_storage() {
+            for (key, after) in changes {
+                let before = original.storage_at(&address, &key).map_err(|e| {
+                    warn!("simulate: reading storage {} of {} failed: {}", key, address, e);
+                    CallError::StateCorrupt
+                })?;
+                if before != after {
+                    storage_changes.push(StorageChange {
+                        address: address,
+                        key: key,
+                        before: before,
+                        after: after,
+                    });
+                }
+            }
+        }
+        storage_changes.sort_by(|a, b| (a.address, a.key).cmp(&(b.address, b.key)));
+
+        Ok(Simulation {
+            output: executed.output.into(),
+            gas_used: executed.gas_used,
+            storage_changes: storage_changes,
+        })
+    }
+
     fn sign_call(&self, request: CallRequest) -> SignedTransaction {
         let from = request.from.unwrap_or_else(Address::zero);
         Transaction {
@@ -517,6 +557,17 @@ impl Executor {
         block_id: BlockId,
         analytics: CallAnalytics,
     ) -> Result<Executed, CallError> {
+        self.transact_on_copy(t, block_id, analytics).map(|(executed, _)| executed)
+    }
+
+    /// Execute on a copy of the state at `block_id`, which is returned with the changes
+    /// left uncommitted.
+    fn transact_on_copy(
+        &self,
+        t: &mut SignedTransaction,
+        block_id: BlockId,
+        analytics: CallAnalytics,
+    ) -> Result<(Executed, State<StateDB>), CallError> {
         let header = self.block_header(block_id).ok_or(CallError::StatePruned)?;
         let last_hashes = self.build_last_hashes(None, header.number());
         let env_info = EnvInfo {
@@ -553,7 +604,7 @@ impl Executor {
             &self.factories.native,
         ).transact(t, options)?;
 
-        Ok(ret)
+        Ok((ret, state))
     }
 
     pub fn set_gas_and_nodes(&self) {
@@ -848,6 +899,59 @@ mod tests {
             receipt2.contract_address.unwrap()
         );
     }
+    #[test]
+    fn test_simulate_returns_deltas_without_committing() {
+        let executor = init_executor();
+        let chain = init_chain();
+
+        let block = create_block(&executor, Address::from(0), &generate_contract(), (0, 1));
+        let hash = block.body().transactions()[0].hash();
+        let h = executor.get_current_height() + 1;
+        let (send, recv) = channel::<(String, Vec<u8>)>();
+        executor.execute_block(block.clone(), &send);
+        if let Ok((_, msg_vec)) = recv.recv() {
+            let mut msg = Message::try_from(&msg_vec).unwrap();
+            if let MsgClass::ExecutedResult(info) = msg.take_content() {
+                let chain_block = ChainBlock::from(block.protobuf());
+                chain.set_block_body(h, &chain_block);
+                chain.set_db_result(&info, &chain_block);
+            }
+        }
+        let contract = chain.localized_receipt(hash).unwrap().contract_address.unwrap();
+
+        // set(5)
+        let mut data = vec![0x60, 0xfe, 0x47, 0xb1];
+        data.extend_from_slice(&H256::from(5).to_vec());
+        let request = CallRequest {
+            from: None,
+            to: contract,
+            data: Some(data),
+        };
+        let simulation = executor.simulate(request, BlockId::Latest).unwrap();
+
+        assert_eq!(
+            simulation.storage_changes,
+            vec![
+                StorageChange {
+                    address: contract,
+                    key: H256::from(0),
+                    before: H256::from(0),
+                    after: H256::from(5),
+                },
+            ]
+        );
+        assert!(simulation.gas_used > U256::zero());
+        assert_eq!(executor.state().storage_at(&contract, &H256::from(0)).unwrap(), H256::from(0));
+
+        let request = CallRequest {
+            from: None,
+            to: contract,
+            data: None,
+        };
+        let err = executor.simulate(request, BlockId::Number(h + 100)).unwrap_err();
+        assert_eq!(err, format!("Call Error {}", CallError::StatePruned));
+    }
+
     #[test]
     fn test_global_sys_config_equal() {
         let mut lhs = GlobalSysConfig::new();
@@ -634,6 +634,22 @@ impl<B: Backend> State<B> {
         )
     }
 
+    /// Storage written to the cache and not committed yet, by account.
+    pub fn dirty_storage(&self) -> HashMap<Address, HashMap<H256, H256>> {
+        self.cache
+            .borrow()
+            .iter()
+            .filter(|&(_, entry)| entry.is_dirty())
+            .filter_map(|(address, entry)| {
+                entry
+                    .account
+                    .as_ref()
+                    .map(|account| (*address, account.storage_changes().clone()))
+            })
+            .filter(|&(_, ref changes)| !changes.is_empty())
+            .collect()
+    }
+
     /// Clear state cache
     pub fn clear(&mut self) {
         self.cache.borrow_mut().clear();
@@ -4,6 +4,7 @@ use core::libexecutor::block::{Block, ClosedBlock};
 use core::libexecutor::call_request::CallRequest;
 use core::libexecutor::executor::{BlockInQueue, Config, Executor, Stage};
 use error::ErrorCode;
+use jsonrpc_types::ErrorCode as RpcErrorCode;
 use jsonrpc_types::rpctypes::{BlockNumber, CountOrCode};
 use libproto::{request, response, Message, MsgClass, SyncResponse};
 use libproto::blockchain::{BlockWithProof, Proof, ProofType};
@@ -21,6 +22,13 @@ use util::Address;
 use util::datapath::DataPath;
 use util::kvdb::{Database, DatabaseConfig};
 
+/// Answer malformed query params as JSON-RPC's invalid params, the parse error only traced
+fn invalid_params(response: &mut response::Response, params: &str, err: serde_json::Error) {
+    trace!("invalid params {}: {:?}", params, err);
+    response.set_code(RpcErrorCode::InvalidParams.code());
+    response.set_error_msg("invalid params".to_owned());
+}
+
 #[derive(Clone)]
 pub struct ExecutorInstance {
     ctx_pub: Sender<(String, Vec<u8>)>,
@@ -244,32 +252,44 @@ impl ExecutorInstance {
         match req.req.unwrap() {
             Request::call(call) => {
                 trace!("Chainvm Call {:?}", call);
-                serde_json::from_str::<BlockNumber>(&call.height)
-                    .map(|block_id| {
+                let block_id = serde_json::from_str::<BlockNumber>(&call.height);
+                match block_id {
+                    Ok(block_id) => {
                         let call_request = CallRequest::from(call);
-                        self.ext
-                            .eth_call(call_request, block_id.into())
-                            .map(|ok| {
-                                response.set_call_result(ok);
-                            })
-                            .map_err(|err| {
+                        match self.ext.eth_call(call_request, block_id.into()) {
+                            Ok(ok) => response.set_call_result(ok),
+                            Err(err) => {
                                 response.set_code(ErrorCode::query_error());
                                 response.set_error_msg(err);
-                            })
-                    })
-                    .map_err(|err| {
-                        response.set_code(ErrorCode::query_error());
-                        response.set_error_msg(format!("{:?}", err));
-                    });
+                            }
+                        }
+                    }
+                    Err(err) => invalid_params(&mut response, &call.height, err),
+                }
+            }
+
+            Request::simulate(call) => {
+                trace!("Chainvm Simulate {:?}", call);
+                let block_id = serde_json::from_str::<BlockNumber>(&call.height);
+                match block_id {
+                    Ok(block_id) => {
+                        let call_request = CallRequest::from(call);
+                        match self.ext.simulate(call_request, block_id.into()) {
+                            Ok(simulation) => response.set_simulation(serde_json::to_string(&simulation).unwrap()),
+                            Err(err) => {
+                                response.set_code(ErrorCode::query_error());
+                                response.set_error_msg(err);
+                            }
+                        }
+                    }
+                    Err(err) => invalid_params(&mut response, &call.height, err),
+                }
             }
 
             Request::transaction_count(tx_count) => {
                 trace!("transaction count request from jsonrpc {:?}", tx_count);
                 serde_json::from_str::<CountOrCode>(&tx_count)
-                    .map_err(|err| {
-                        response.set_code(ErrorCode::query_error());
-                        response.set_error_msg(format!("{:?}", err));
-                    })
+                    .map_err(|err| invalid_params(&mut response, &tx_count, err))
                     .map(|tx_count| {
                         let address = Address::from_slice(tx_count.address.as_ref());
                         match self.ext.nonce(&address, tx_count.block_id.into()) {
@@ -286,10 +306,7 @@ impl ExecutorInstance {
             Request::code(code_content) => {
                 trace!("code request from josnrpc  {:?}", code_content);
                 serde_json::from_str::<CountOrCode>(&code_content)
-                    .map_err(|err| {
-                        response.set_code(ErrorCode::query_error());
-                        response.set_error_msg(format!("{:?}", err));
-                    })
+                    .map_err(|err| invalid_params(&mut response, &code_content, err))
                     .map(|code_content| {
                         let address = Address::from_slice(code_content.address.as_ref());
                         match self.ext.code_at(&address, code_content.block_id.into()) {
@@ -485,9 +502,13 @@ impl ExecutorInstance {
                     }
                 }
             }
-            // TODO: Handle Raft and POA
+            // Only Tendermint proofs can be checked by `set_sync_block`
             _ => {
-                unimplemented!();
+                error!(
+                    "sync: drop block-{}, syncing {:?} blocks in executor is not supported",
+                    block.number(),
+                    block_proof_type
+                );
             }
         }
     }
@@ -0,0 +1,94 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use jsonrpc_types::bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use util::H256;
+
+/// Where a new tx was forwarded to auth: the request id of the
+/// `BatchRequest` carrying it and its index inside that batch.
+/// Served by `GET /batch_position?hash=<tx hash>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPosition {
+    pub batch_id: Bytes,
+    pub index: usize,
+}
+
+/// Remembers the batch position of the most recent forwarded txs,
+/// dropping the oldest once `capacity` txs are tracked.
+#[derive(Debug, Default)]
+pub struct BatchPositions {
+    positions: HashMap<H256, BatchPosition>,
+    order: VecDeque<H256>,
+    capacity: usize,
+}
+
+impl BatchPositions {
+    pub fn new(capacity: usize) -> Self {
+        BatchPositions {
+            positions: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity,
+        }
+    }
+
+    pub fn record(&mut self, tx_hash: H256, batch_id: &[u8], index: usize) {
+        if self.capacity == 0 {
+            return;
+        }
+        let position = BatchPosition {
+            batch_id: Bytes::from(batch_id.to_vec()),
+            index: index,
+        };
+        if self.positions.insert(tx_hash, position).is_none() {
+            self.order.push_back(tx_hash);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.positions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn position(&self, tx_hash: &H256) -> Option<&BatchPosition> {
+        self.positions.get(tx_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::U256;
+
+    #[test]
+    fn oldest_position_is_dropped() {
+        let mut positions = BatchPositions::new(2);
+        let batch_id = vec![1, 2, 3];
+        for i in 0..3 {
+            positions.record(H256::from(U256::from(i)), &batch_id, i);
+        }
+        assert_eq!(positions.position(&H256::from(U256::from(0))), None);
+        assert_eq!(
+            positions.position(&H256::from(U256::from(2))),
+            Some(&BatchPosition {
+                batch_id: Bytes::from(batch_id.clone()),
+                index: 2,
+            })
+        );
+    }
+}
@@ -15,36 +15,85 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use helper::DuplicateRequestId;
 use std::convert::Into;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use toml;
 use ws::Settings;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub backlog_capacity: usize,
+    /// How a request is relayed while one with the same request id is in flight, rejected if not set
+    pub duplicate_request_id: Option<DuplicateRequestId>,
+    /// Requests queued for relaying before more are answered as server busy, defaults to 10000
+    pub relay_queue_capacity: Option<usize>,
     pub profile_config: ProfileConfig,
     pub http_config: HttpConfig,
     pub ws_config: WsConfig,
     pub new_tx_flow_config: NewTxFlowConfig,
 }
 
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file can't be opened or read
+    Unreadable(String, io::Error),
+    /// The file has nothing but whitespace in it
+    Empty(String),
+    /// The file isn't valid TOML or misses required options
+    Invalid(String, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Unreadable(ref path, ref err) => write!(f, "can't read config file {}: {}", path, err),
+            ConfigError::Empty(ref path) => write!(f, "config file {} is empty", path),
+            ConfigError::Invalid(ref path, ref err) => write!(f, "config file {} is invalid: {}", path, err),
+        }
+    }
+}
+
 impl Config {
-    pub fn new(path: &str) -> Self {
-        let mut config_file = File::open(path).unwrap();
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
         let mut buffer = String::new();
-        config_file
-            .read_to_string(&mut buffer)
-            .expect("Failed to load jsonrpc config.");
-        toml::from_str(&buffer).unwrap()
+        File::open(path)
+            .and_then(|mut config_file| config_file.read_to_string(&mut buffer))
+            .map_err(|err| ConfigError::Unreadable(path.to_owned(), err))?;
+        if buffer.trim().is_empty() {
+            return Err(ConfigError::Empty(path.to_owned()));
+        }
+        toml::from_str(&buffer).map_err(|err| ConfigError::Invalid(path.to_owned(), err))
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
 pub struct NewTxFlowConfig {
     pub count_per_batch: usize,
     pub buffer_duration: u32, //in unit of ns
+    /// New txs a client IP may send per window, unlimited if not set or 0.
+    /// Clients on the Unix socket aren't limited.
+    pub per_ip_rate: Option<u32>,
+    /// New txs a client IP may send at once, defaults to `per_ip_rate`
+    pub per_ip_burst: Option<u32>,
+    /// In unit of seconds, defaults to 1
+    pub per_ip_window: Option<u64>,
+    /// How long to wait for buffered txs to be sent on shutdown, in unit of seconds
+    pub drain_timeout: Option<u64>,
+    /// In unit of seconds, how long a resubmission with the same idempotency key gets the first result
+    pub idempotency_window: Option<u64>,
+    /// Most idempotency keys remembered at once
+    pub idempotency_keys: Option<usize>,
+    /// Times the tx dispatch thread is restarted after a panic within a minute, before exiting
+    pub dispatch_max_restarts: Option<usize>,
+    /// Answer a new tx already buffered as Dup instead of buffering it again, enabled if not set
+    pub dedup_new_txs: Option<bool>,
+    /// Buffer new txs into batches, enabled if not set; otherwise each is forwarded as it comes
+    pub batch_enable: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -60,6 +109,13 @@ pub struct WsConfig {
     pub thread_number: usize,
     pub listen_ip: String,
     pub listen_port: String,
+    /// Notifications queued for a connection before its subscription is dropped as too slow,
+    /// defaults to 1024
+    pub subscription_queue: Option<usize>,
+    /// Subscriptions one connection may have, defaults to 16
+    pub max_subscriptions: Option<usize>,
+    /// Threads writing notifications out to the connections, defaults to 2
+    pub subscription_threads: Option<usize>,
 
     max_connections: usize,
     queue_size: usize,
@@ -124,6 +180,270 @@ pub struct HttpConfig {
     pub thread_number: Option<usize>,
     pub listen_ip: String,
     pub listen_port: String,
+    /// `unix:/path/to/socket` to listen on a Unix socket instead of `listen_ip` and `listen_port`
+    pub listen_socket: Option<String>,
+    /// Seconds to wait for the backend to answer a request before failing it with `backend timeout`
     pub timeout: u64,
-    pub allow_origin: Option<String>,
+    /// Origins allowed by CORS, `*`, exact origins or patterns like `*.example.com`
+    pub allow_origin: Option<AllowOrigin>,
+    pub tls_enable: Option<bool>,
+    /// PEM file of the certificate chain
+    pub tls_cert: Option<String>,
+    /// PEM file of the PKCS#8 or RSA private key
+    pub tls_key: Option<String>,
+    /// Whether an IPv6 `listen_ip` like `::` takes IPv4 clients as well, defaults to true
+    pub dual_stack: Option<bool>,
+    /// Largest request body accepted, in bytes, defaults to 1 MiB
+    pub max_body_size: Option<usize>,
+    /// Most calls in one batch request, defaults to 100
+    pub max_batch_size: Option<usize>,
+    /// Whether responses are compressed for clients accepting gzip or deflate, defaults to false
+    pub compression_enable: Option<bool>,
+    /// Smallest response compressed, in bytes, defaults to 1 KiB
+    pub compression_min_size: Option<usize>,
+    /// Threads compressing responses, shared by all http workers, defaults to 4
+    pub compression_threads: Option<usize>,
+}
+
+/// A single origin as older configs have it, or a list of them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AllowOrigin {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Where the HTTP server listens
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenTarget {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert: String,
+    pub key: String,
+}
+
+impl WsConfig {
+    pub fn listen_addr(&self) -> Result<SocketAddr, String> {
+        listen_addr(&self.listen_ip, &self.listen_port)
+    }
+}
+
+impl HttpConfig {
+    pub fn listen_addr(&self) -> Result<SocketAddr, String> {
+        listen_addr(&self.listen_ip, &self.listen_port)
+    }
+
+    /// The Unix socket if `listen_socket` is set, the TCP address otherwise
+    pub fn listen_target(&self) -> Result<ListenTarget, String> {
+        match self.listen_socket {
+            Some(ref socket) => {
+                if !socket.starts_with("unix:") || socket.len() == "unix:".len() {
+                    return Err(format!("listen_socket {} isn't like unix:/path/to/socket", socket));
+                }
+                if self.tls_enable.unwrap_or(false) {
+                    return Err("tls_enable isn't supported with listen_socket".to_owned());
+                }
+                Ok(ListenTarget::Unix(PathBuf::from(&socket["unix:".len()..])))
+            }
+            None => self.listen_addr().map(ListenTarget::Tcp),
+        }
+    }
+
+    pub fn allow_origins(&self) -> Vec<String> {
+        match self.allow_origin {
+            Some(AllowOrigin::One(ref origin)) => vec![origin.clone()],
+            Some(AllowOrigin::Many(ref origins)) => origins.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// TLS settings if it's enabled, an error if it's enabled without cert or key
+    pub fn tls_config(&self) -> Result<Option<TlsConfig>, String> {
+        if !self.tls_enable.unwrap_or(false) {
+            return Ok(None);
+        }
+        match (self.tls_cert.clone(), self.tls_key.clone()) {
+            (Some(cert), Some(key)) => Ok(Some(TlsConfig { cert: cert, key: key })),
+            _ => Err("tls_enable is set but tls_cert or tls_key is missing".to_owned()),
+        }
+    }
+}
+
+/// An IPv6 ip may be given with or without brackets, a host name like `localhost`
+/// is resolved to its first address
+fn listen_addr(ip: &str, port: &str) -> Result<SocketAddr, String> {
+    let ip = ip.trim();
+    let bare_ip = if ip.starts_with('[') && ip.ends_with(']') {
+        &ip[1..ip.len() - 1]
+    } else {
+        ip
+    };
+    let port: u16 = port.trim()
+        .parse()
+        .map_err(|_| format!("listen_port {} isn't a port", port))?;
+    if let Ok(bare_ip) = bare_ip.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(bare_ip, port));
+    }
+    (bare_ip, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("listen_ip {} isn't an IP address or a host name that resolves", ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    fn write_config(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("jsonrpc-config-{}.toml", name));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn http_allow_origins() {
+        let base = r#"
+            enable = true
+            listen_ip = "0.0.0.0"
+            listen_port = "1337"
+            timeout = 3
+            "#;
+        let http_config: HttpConfig = toml::from_str(base).unwrap();
+        assert!(http_config.allow_origins().is_empty());
+
+        let http_config: HttpConfig = toml::from_str(&format!("{}allow_origin = \"*\"", base)).unwrap();
+        assert_eq!(http_config.allow_origins(), vec!["*".to_owned()]);
+
+        let http_config: HttpConfig = toml::from_str(&format!(
+            "{}allow_origin = [\"https://a.org\", \"*.example.com\"]",
+            base
+        )).unwrap();
+        assert_eq!(
+            http_config.allow_origins(),
+            vec!["https://a.org".to_owned(), "*.example.com".to_owned()]
+        );
+    }
+
+    #[test]
+    fn http_listen_addr() {
+        let http_config = |ip: &str, port: &str| -> HttpConfig {
+            toml::from_str(&format!(
+                "enable = true\nlisten_ip = \"{}\"\nlisten_port = \"{}\"\ntimeout = 3",
+                ip, port
+            )).unwrap()
+        };
+        assert_eq!(
+            http_config("0.0.0.0", "1337").listen_addr(),
+            Ok("0.0.0.0:1337".parse().unwrap())
+        );
+        assert_eq!(http_config("::", "1337").listen_addr(), Ok("[::]:1337".parse().unwrap()));
+        assert_eq!(
+            http_config("[fe80::1]", "1337").listen_addr(),
+            Ok("[fe80::1]:1337".parse().unwrap())
+        );
+        let local = http_config("localhost", "1337").listen_addr().unwrap();
+        assert!(local.ip().is_loopback());
+        assert_eq!(local.port(), 1337);
+        assert!(http_config("no such host", "1337").listen_addr().is_err());
+        assert!(http_config("::1", "http").listen_addr().is_err());
+        assert_eq!(http_config("::", "1337").dual_stack, None);
+    }
+
+    #[test]
+    fn http_listen_target() {
+        let mut http_config: HttpConfig = toml::from_str(
+            r#"
+            enable = true
+            listen_ip = "0.0.0.0"
+            listen_port = "1337"
+            timeout = 3
+            "#,
+        ).unwrap();
+        assert_eq!(
+            http_config.listen_target(),
+            Ok(ListenTarget::Tcp("0.0.0.0:1337".parse().unwrap()))
+        );
+
+        http_config.listen_socket = Some("unix:/run/cita/jsonrpc.sock".to_owned());
+        assert_eq!(
+            http_config.listen_target(),
+            Ok(ListenTarget::Unix(PathBuf::from("/run/cita/jsonrpc.sock")))
+        );
+
+        http_config.tls_enable = Some(true);
+        assert!(http_config.listen_target().is_err());
+
+        http_config.tls_enable = None;
+        for socket in vec!["unix:", "/run/cita/jsonrpc.sock"] {
+            http_config.listen_socket = Some(socket.to_owned());
+            assert!(http_config.listen_target().is_err());
+        }
+    }
+
+    #[test]
+    fn http_tls_config() {
+        let mut http_config: HttpConfig = toml::from_str(
+            r#"
+            enable = true
+            listen_ip = "0.0.0.0"
+            listen_port = "1337"
+            timeout = 3
+            "#,
+        ).unwrap();
+        assert_eq!(http_config.tls_config(), Ok(None));
+
+        http_config.tls_enable = Some(true);
+        http_config.tls_cert = Some("cert.pem".to_owned());
+        assert!(http_config.tls_config().is_err());
+
+        http_config.tls_key = Some("key.pem".to_owned());
+        assert_eq!(
+            http_config.tls_config(),
+            Ok(Some(TlsConfig {
+                cert: "cert.pem".to_owned(),
+                key: "key.pem".to_owned(),
+            }))
+        );
+    }
+
+    #[test]
+    fn load_missing_config() {
+        match Config::load("/nonexistent/jsonrpc.toml") {
+            Err(ConfigError::Unreadable(path, _)) => assert_eq!(path, "/nonexistent/jsonrpc.toml"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_empty_config() {
+        for (name, content) in vec![("empty", ""), ("blank", " \n\t\n")] {
+            let path = write_config(name, content);
+            let result = Config::load(&path);
+            fs::remove_file(&path).unwrap();
+            match result {
+                Err(ConfigError::Empty(_)) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn load_invalid_config() {
+        let path = write_config("invalid", "backlog_capacity = [");
+        let result = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(err @ ConfigError::Invalid(..)) => assert!(format!("{}", err).contains("is invalid")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
 }
@@ -1,34 +1,114 @@
 use futures::sync::oneshot;
+use idempotency::{Claim, IdempotencyKeys};
 use jsonrpc_types::{Call, Error, Id};
+use jsonrpc_types::method::method::{CITA_CHECK_TRANSACTIONS_ROOT, CITA_GET_CHAIN_STATS, CITA_GET_PEERS,
+                                    CITA_GET_REJECTED_TRANSACTIONS, CITA_GET_REJECTION_RATE, CITA_GET_VERIFY_STATS,
+                                    CITA_REVERIFY_TRANSACTION};
 use jsonrpc_types::request::Version;
-use jsonrpc_types::response::Output;
+use jsonrpc_types::response::{Output, RpcFailure};
 use libproto::request as reqlib;
+use libproto::response::Response;
 use serde_json;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{self, TrySendError};
+use std::time::{Duration, Instant};
 use util::Mutex;
+use uuid::Uuid;
 use ws;
 
 pub enum TransferType {
     HTTP((ReqInfo, oneshot::Sender<Output>)),
     WEBSOCKET((ReqInfo, ws::Sender)),
+    /// A websocket subscription waiting for chain to install its filter
+    SUBSCRIPTION((ReqInfo, ws::Sender)),
+}
+
+impl TransferType {
+    fn req_info(&self) -> &ReqInfo {
+        match *self {
+            TransferType::HTTP((ref req_info, _))
+            | TransferType::WEBSOCKET((ref req_info, _))
+            | TransferType::SUBSCRIPTION((ref req_info, _)) => req_info,
+        }
+    }
+
+    /// Answer the waiting client with `content`
+    pub fn answer(self, content: Response) {
+        match self {
+            TransferType::HTTP((req_info, sender)) => {
+                let _ = sender.send(Output::from(content, req_info.id, req_info.jsonrpc));
+            }
+            TransferType::WEBSOCKET((req_info, sender)) | TransferType::SUBSCRIPTION((req_info, sender)) => {
+                let output = Output::from(content, req_info.id, req_info.jsonrpc);
+                let _ = sender.send(serde_json::to_string(&output).unwrap());
+            }
+        }
+    }
+
+    /// Answer the waiting client with `err`
+    fn fail(self, err: Error) {
+        match self {
+            TransferType::HTTP((req_info, sender)) => {
+                let failure = RpcFailure::from_options(req_info.id, req_info.jsonrpc, err);
+                let _ = sender.send(Output::Failure(failure));
+            }
+            TransferType::WEBSOCKET((req_info, sender)) | TransferType::SUBSCRIPTION((req_info, sender)) => {
+                let failure = RpcFailure::from_options(req_info.id, req_info.jsonrpc, err);
+                let _ = sender.send(serde_json::to_string(&failure).unwrap());
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ReqInfo {
     pub jsonrpc: Option<Version>,
     pub id: Id,
+    /// When the request was relayed, to reap it if nobody ever answers
+    pub created_at: Instant,
+}
+
+/// How a request is relayed while another one with the same request id waits for its answer,
+/// as the answers of both would be told apart by the request id only
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateRequestId {
+    /// Fail the later request, the waiting one is answered as usual
+    #[serde(rename = "reject")]
+    Reject,
+    /// Relay the later request with a random suffix appended to its request id
+    #[serde(rename = "disambiguate")]
+    Disambiguate,
 }
 
+impl Default for DuplicateRequestId {
+    fn default() -> Self {
+        DuplicateRequestId::Reject
+    }
+}
+
+/// Error code a request shed for the relay queue being full is answered with
+pub const SERVER_BUSY_ERROR: i64 = -32_005;
+
+/// Error code an admin method called by a client on another host is answered with
+pub const ADMIN_ONLY_ERROR: i64 = -32_007;
+
+/// Whether requests are being shed, only the start and the end of it are logged
+static SHEDDING: AtomicBool = ATOMIC_BOOL_INIT;
+/// Requests shed since the last `take_shed_requests`
+static SHED_REQUESTS: AtomicUsize = ATOMIC_USIZE_INIT;
+
 pub type RpcMap = Arc<Mutex<HashMap<Vec<u8>, TransferType>>>;
-pub type ReqSender = Mutex<mpsc::Sender<(String, reqlib::Request)>>;
+pub type ReqSender = Mutex<mpsc::SyncSender<(String, reqlib::Request)>>;
 
 impl ReqInfo {
     pub fn new(jsonrpc: Option<Version>, id: Id) -> ReqInfo {
         ReqInfo {
             jsonrpc: jsonrpc,
             id: id,
+            created_at: Instant::now(),
         }
     }
 }
@@ -41,8 +121,180 @@ pub fn encode_request(body: &str) -> Result<Call, Error> {
     }
 }
 
+/// Relay `req`, whose answer goes to `transfer`. A new tx resubmitted with the idempotency
+/// `key` of an earlier one isn't relayed again, it gets the result of the earlier one.
+/// Another tx submitted with the key is failed.
+/// A request with the id of one still waiting is handled as `on_duplicate` says, one coming
+/// while the relay queue is full is failed as busy right away. Returns the request id the
+/// answer is awaited under, None if the request is rejected as a duplicate.
+pub fn relay_request(
+    responses: &RpcMap,
+    sender: &mpsc::SyncSender<(String, reqlib::Request)>,
+    topic: String,
+    mut req: reqlib::Request,
+    transfer: TransferType,
+    key: Option<(&Mutex<IdempotencyKeys>, String)>,
+    on_duplicate: DuplicateRequestId,
+) -> Option<Vec<u8>> {
+    let mut request_id = req.take_request_id();
+    // Registered before relaying, so a failed relay can still be answered
+    let rejected = {
+        let mut responses = responses.lock();
+        if responses.contains_key(&request_id) {
+            warn!("request id {:?} is in flight already, {:?} the later one", request_id, on_duplicate);
+            match on_duplicate {
+                DuplicateRequestId::Reject => Some(transfer),
+                DuplicateRequestId::Disambiguate => {
+                    request_id.extend_from_slice(Uuid::new_v4().as_bytes());
+                    responses.insert(request_id.clone(), transfer);
+                    None
+                }
+            }
+        } else {
+            responses.insert(request_id.clone(), transfer);
+            None
+        }
+    };
+    if let Some(transfer) = rejected {
+        transfer.fail(Error::invalid_request_with_message("request id is in flight already"));
+        return None;
+    }
+    req.set_request_id(request_id.clone());
+    let keys = key.as_ref().map(|&(keys, _)| keys);
+    let claim = match key {
+        Some((keys, key)) => {
+            let (claim, dropped) = keys.lock().claim(&key, req.get_un_tx().crypt_hash(), &request_id);
+            for request_id in dropped {
+                fail_request_with(responses, &request_id, Error::internal_error());
+            }
+            claim
+        }
+        None => Claim::New,
+    };
+    match claim {
+        Claim::New => match sender.try_send((topic, req)) {
+            Ok(()) => {
+                if SHEDDING.load(Ordering::Relaxed) && SHEDDING.swap(false, Ordering::Relaxed) {
+                    info!("relay queue has room again, stop shedding requests");
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if !SHEDDING.swap(true, Ordering::Relaxed) {
+                    warn!("relay queue is full, shedding requests as server busy");
+                }
+                SHED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+                trace!("shed request {:?}", request_id);
+                let followers = keys.map_or_else(Vec::new, |keys| keys.lock().abandon(&request_id));
+                for request_id in Some(request_id.clone()).into_iter().chain(followers) {
+                    fail_request_with(responses, &request_id, server_busy_error());
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("relay channel is disconnected");
+                fail_request(responses, &request_id);
+            }
+        },
+        Claim::Pending => trace!("resubmission {:?} waits for the first submission", request_id),
+        Claim::Done(content) => answer_request(responses, &request_id, content),
+        Claim::Conflict => fail_request_with(
+            responses,
+            &request_id,
+            Error::invalid_params("idempotency key is used by another transaction"),
+        ),
+    }
+    Some(request_id)
+}
+
+/// Requests shed as server busy since the last call
+pub fn take_shed_requests() -> usize {
+    SHED_REQUESTS.swap(0, Ordering::Relaxed)
+}
+
+pub fn server_busy_error() -> Error {
+    Error::server_error(SERVER_BUSY_ERROR, "server busy")
+}
+
+/// Answer a request with `content`, unless it was answered or timed out already.
+pub fn answer_request(responses: &RpcMap, request_id: &[u8], content: Response) {
+    let value = { responses.lock().remove(request_id) };
+    if let Some(transfer) = value {
+        transfer.answer(content);
+    }
+}
+
+/// Answer a request that can't be relayed with an internal error, instead of leaving it
+/// to time out. Does nothing if the request was answered or timed out already.
+pub fn fail_request(responses: &RpcMap, request_id: &[u8]) {
+    fail_request_with(responses, request_id, Error::internal_error());
+}
+
+pub fn fail_request_with(responses: &RpcMap, request_id: &[u8], err: Error) {
+    let value = { responses.lock().remove(request_id) };
+    if let Some(transfer) = value {
+        transfer.fail(err);
+    }
+}
+
+/// Evict the requests waiting longer than `timeout`, whose answer was most likely lost,
+/// and answer their clients with a time out error. The resubmissions waiting for an evicted
+/// new tx are evicted with it. Returns how many were evicted.
+pub fn reap_expired(responses: &RpcMap, keys: &Mutex<IdempotencyKeys>, timeout: Duration) -> usize {
+    let expired: Vec<TransferType> = {
+        let mut responses = responses.lock();
+        let mut request_ids: Vec<Vec<u8>> = responses
+            .iter()
+            .filter(|&(_, transfer)| transfer.req_info().created_at.elapsed() >= timeout)
+            .map(|(request_id, _)| request_id.clone())
+            .collect();
+        let mut keys = keys.lock();
+        let followers: Vec<Vec<u8>> = request_ids
+            .iter()
+            .flat_map(|request_id| keys.abandon(request_id))
+            .collect();
+        request_ids.extend(followers);
+        request_ids
+            .iter()
+            .filter_map(|request_id| responses.remove(request_id))
+            .collect()
+    };
+    let evicted = expired.len();
+    for transfer in expired {
+        let mut err = Error::internal_error();
+        err.message = "system time out, please resend".to_owned();
+        transfer.fail(err);
+    }
+    evicted
+}
+
+/// Operator methods, only served to clients on this host, over loopback or the Unix socket
+const ADMIN_METHODS: [&str; 5] = [
+    CITA_GET_REJECTED_TRANSACTIONS,
+    CITA_REVERIFY_TRANSACTION,
+    CITA_GET_PEERS,
+    CITA_GET_CHAIN_STATS,
+    CITA_CHECK_TRANSACTIONS_ROOT,
+];
+
+pub fn is_admin_method(method: &str) -> bool {
+    ADMIN_METHODS.contains(&method)
+}
+
+/// Whether a call of `method` from `ip` has to be refused for being an admin method.
+/// A client without a known address isn't trusted.
+pub fn admin_refused(ip: Option<IpAddr>, method: &str) -> bool {
+    is_admin_method(method) && !ip.map_or(false, |ip| ip.is_loopback())
+}
+
+pub fn admin_only_error() -> Error {
+    Error::server_error(ADMIN_ONLY_ERROR, "admin method, only served to local clients")
+}
+
 pub fn select_topic(method: &str) -> String {
-    if method.starts_with("cita_send") {
+    if method == CITA_GET_REJECTED_TRANSACTIONS || method == CITA_GET_REJECTION_RATE
+        || method == CITA_REVERIFY_TRANSACTION || method == CITA_GET_VERIFY_STATS
+    {
+        "jsonrpc.auth"
+    } else if method.starts_with("cita_send") {
         "jsonrpc.new_tx"
     } else if method.starts_with("cita") || method.starts_with("eth") {
         "jsonrpc.request"
@@ -56,13 +308,278 @@ pub fn select_topic(method: &str) -> String {
 #[cfg(test)]
 mod test {
     use super::*;
+    use libproto::UnverifiedTransaction;
+
+    fn keys() -> Mutex<IdempotencyKeys> {
+        Mutex::new(IdempotencyKeys::new(Duration::from_secs(600), Duration::from_secs(10), 16))
+    }
+
+    #[test]
+    fn test_fail_request() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, mut rx) = oneshot::channel();
+        let req_info = ReqInfo::new(Some(Version::V2), Id::Num(1));
+        responses
+            .lock()
+            .insert(vec![1], TransferType::HTTP((req_info, tx)));
+
+        fail_request(&responses, &[1]);
+        assert!(responses.lock().is_empty());
+        match rx.try_recv() {
+            Ok(Some(Output::Failure(failure))) => {
+                assert_eq!(failure.id, Id::Num(1));
+                assert_eq!(failure.error, Error::internal_error());
+            }
+            _ => panic!("expect an internal error"),
+        }
+
+        // Already answered, nothing left to fail
+        fail_request(&responses, &[1]);
+    }
+
+    #[test]
+    fn test_reap_expired() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (stale_tx, mut stale_rx) = oneshot::channel();
+        let mut stale_info = ReqInfo::new(None, Id::Num(1));
+        stale_info.created_at = Instant::now() - Duration::from_secs(10);
+        let (fresh_tx, mut fresh_rx) = oneshot::channel();
+        let fresh_info = ReqInfo::new(None, Id::Num(2));
+        {
+            let mut guard = responses.lock();
+            guard.insert(vec![1], TransferType::HTTP((stale_info, stale_tx)));
+            guard.insert(vec![2], TransferType::HTTP((fresh_info, fresh_tx)));
+        }
+
+        assert_eq!(reap_expired(&responses, &keys(), Duration::from_secs(5)), 1);
+        assert!(responses.lock().contains_key(&vec![2]));
+        match stale_rx.try_recv() {
+            Ok(Some(Output::Failure(failure))) => {
+                assert_eq!(failure.id, Id::Num(1));
+                assert_eq!(failure.error.code.code(), -32_603);
+            }
+            _ => panic!("expect a time out error"),
+        }
+        match fresh_rx.try_recv() {
+            Ok(None) => {}
+            _ => panic!("a fresh request is kept waiting"),
+        }
+    }
+
+    #[test]
+    fn test_reap_expired_with_resubmissions() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, _relayed) = mpsc::sync_channel(16);
+        let keys = keys();
+        let mut outputs: Vec<_> = (1..3)
+            .map(|id| {
+                let (tx, rx) = oneshot::channel();
+                let mut req = reqlib::Request::new();
+                req.set_request_id(vec![id as u8]);
+                let mut req_info = ReqInfo::new(Some(Version::V2), Id::Num(id));
+                if id == 1 {
+                    req_info.created_at = Instant::now() - Duration::from_secs(10);
+                }
+                relay_request(
+                    &responses,
+                    &sender,
+                    "jsonrpc.new_tx".to_owned(),
+                    req,
+                    TransferType::HTTP((req_info, tx)),
+                    Some((&keys, "retry-1".to_owned())),
+                    DuplicateRequestId::Reject,
+                );
+                rx
+            })
+            .collect();
+
+        // The resubmission waiting for the evicted submission is evicted with it
+        assert_eq!(reap_expired(&responses, &keys, Duration::from_secs(5)), 2);
+        assert!(responses.lock().is_empty());
+        for output in outputs.iter_mut() {
+            match output.try_recv() {
+                Ok(Some(Output::Failure(failure))) => assert_eq!(failure.error.code.code(), -32_603),
+                _ => panic!("expect a time out error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_relay_with_key_of_another_tx() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, relayed) = mpsc::sync_channel(16);
+        let keys = keys();
+        let mut outputs: Vec<_> = (1..3)
+            .map(|id| {
+                let (tx, rx) = oneshot::channel();
+                let mut req = reqlib::Request::new();
+                req.set_request_id(vec![id as u8]);
+                let mut un_tx = UnverifiedTransaction::new();
+                un_tx.mut_transaction().set_nonce(id.to_string());
+                req.set_un_tx(un_tx);
+                let req_info = ReqInfo::new(Some(Version::V2), Id::Num(id));
+                relay_request(
+                    &responses,
+                    &sender,
+                    "jsonrpc.new_tx".to_owned(),
+                    req,
+                    TransferType::HTTP((req_info, tx)),
+                    Some((&keys, "retry-1".to_owned())),
+                    DuplicateRequestId::Reject,
+                );
+                rx
+            })
+            .collect();
+
+        assert!(relayed.try_recv().is_ok());
+        assert!(relayed.try_recv().is_err());
+        match outputs[1].try_recv() {
+            Ok(Some(Output::Failure(failure))) => {
+                assert_eq!(failure.id, Id::Num(2));
+                assert_eq!(failure.error.code.code(), -32_602);
+            }
+            _ => panic!("expect the other tx to be refused"),
+        }
+    }
 
     #[test]
     fn test_get_topic() {
         assert_eq!(select_topic("net_work"), "jsonrpc.net".to_string());
         assert_eq!(select_topic("cita_send"), "jsonrpc.new_tx".to_string());
         assert_eq!(select_topic("cita"), "jsonrpc.request".to_string());
+        assert_eq!(
+            select_topic("cita_getRejectedTransactions"),
+            "jsonrpc.auth".to_string()
+        );
+        assert_eq!(select_topic("cita_getRejectionRate"), "jsonrpc.auth".to_string());
+        assert_eq!(select_topic("cita_reverifyTransaction"), "jsonrpc.auth".to_string());
+        assert_eq!(select_topic("cita_getVerifyStats"), "jsonrpc.auth".to_string());
         assert_eq!(select_topic("eth"), "jsonrpc.request".to_string());
         assert_eq!(select_topic("123"), "jsonrpc".to_string());
     }
+
+    #[test]
+    fn test_admin_refused() {
+        let local = Some("127.0.0.1".parse().unwrap());
+        let local_v6 = Some("::1".parse().unwrap());
+        let remote = Some("10.0.0.1".parse().unwrap());
+        assert!(!admin_refused(local, "cita_getRejectedTransactions"));
+        assert!(!admin_refused(local_v6, "cita_getRejectedTransactions"));
+        assert!(admin_refused(remote, "cita_getRejectedTransactions"));
+        assert!(admin_refused(None, "cita_getRejectedTransactions"));
+        assert!(!admin_refused(local, "cita_reverifyTransaction"));
+        assert!(admin_refused(remote, "cita_reverifyTransaction"));
+        for method in &["cita_getPeers", "cita_getChainStats", "cita_checkTransactionsRoot"] {
+            assert!(!admin_refused(local, method));
+            assert!(admin_refused(remote, method));
+        }
+        assert!(!admin_refused(remote, "cita_getRejectionRate"));
+        assert!(!admin_refused(None, "cita_blockNumber"));
+    }
+
+    #[test]
+    fn test_relay_duplicate_request_id() {
+        let relay = |responses: &RpcMap, sender: &mpsc::SyncSender<(String, reqlib::Request)>, id: u64, on_duplicate| {
+            let (tx, rx) = oneshot::channel();
+            let mut req = reqlib::Request::new();
+            req.set_request_id(vec![7]);
+            let req_info = ReqInfo::new(Some(Version::V2), Id::Num(id));
+            relay_request(
+                responses,
+                sender,
+                "jsonrpc.request".to_owned(),
+                req,
+                TransferType::HTTP((req_info, tx)),
+                None,
+                on_duplicate,
+            );
+            rx
+        };
+        let answer = |responses: &RpcMap, request_id: &[u8], height: u64| {
+            let mut content = Response::new();
+            content.set_request_id(request_id.to_vec());
+            content.set_block_number(height);
+            answer_request(responses, request_id, content);
+        };
+
+        // The later request fails, the answer goes to the waiting one
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, relayed) = mpsc::sync_channel(16);
+        let mut first = relay(&responses, &sender, 1, DuplicateRequestId::Reject);
+        let mut second = relay(&responses, &sender, 2, DuplicateRequestId::Reject);
+        match second.try_recv() {
+            Ok(Some(Output::Failure(failure))) => {
+                assert_eq!(failure.id, Id::Num(2));
+                assert_eq!(failure.error.code.code(), -32_600);
+            }
+            _ => panic!("expect the later request to be rejected"),
+        }
+        let (_, req) = relayed.try_recv().unwrap();
+        assert!(relayed.try_recv().is_err());
+        answer(&responses, req.get_request_id(), 1);
+        match first.try_recv() {
+            Ok(Some(Output::Success(success))) => assert_eq!(success.id, Id::Num(1)),
+            _ => panic!("expect the waiting request to be answered"),
+        }
+
+        // Both are relayed under distinct ids and each gets its own answer
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, relayed) = mpsc::sync_channel(16);
+        let mut first = relay(&responses, &sender, 1, DuplicateRequestId::Disambiguate);
+        let mut second = relay(&responses, &sender, 2, DuplicateRequestId::Disambiguate);
+        let (_, first_req) = relayed.try_recv().unwrap();
+        let (_, second_req) = relayed.try_recv().unwrap();
+        assert_eq!(first_req.get_request_id(), &[7][..]);
+        assert_ne!(first_req.get_request_id(), second_req.get_request_id());
+        assert!(second_req.get_request_id().starts_with(&[7]));
+        answer(&responses, second_req.get_request_id(), 2);
+        answer(&responses, first_req.get_request_id(), 1);
+        for (rx, id) in vec![(&mut first, 1), (&mut second, 2)] {
+            match rx.try_recv() {
+                Ok(Some(Output::Success(success))) => assert_eq!(success.id, Id::Num(id)),
+                _ => panic!("expect request {} to be answered", id),
+            }
+        }
+        assert!(responses.lock().is_empty());
+    }
+
+    #[test]
+    fn test_relay_to_full_queue() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, relayed) = mpsc::sync_channel(1);
+        let mut outputs: Vec<_> = (1..3)
+            .map(|id| {
+                let (tx, rx) = oneshot::channel();
+                let mut req = reqlib::Request::new();
+                req.set_request_id(vec![id as u8]);
+                let req_info = ReqInfo::new(Some(Version::V2), Id::Num(id));
+                relay_request(
+                    &responses,
+                    &sender,
+                    "jsonrpc.request".to_owned(),
+                    req,
+                    TransferType::HTTP((req_info, tx)),
+                    None,
+                    DuplicateRequestId::Reject,
+                );
+                rx
+            })
+            .collect();
+
+        // The first is queued, the second is shed at once instead of waiting
+        assert_eq!(relayed.try_recv().unwrap().1.get_request_id(), &[1][..]);
+        match outputs[0].try_recv() {
+            Ok(None) => {}
+            _ => panic!("expect the queued request to wait for its answer"),
+        }
+        match outputs[1].try_recv() {
+            Ok(Some(Output::Failure(failure))) => {
+                assert_eq!(failure.id, Id::Num(2));
+                assert_eq!(failure.error.code.code(), SERVER_BUSY_ERROR);
+                assert_eq!(failure.error.message, "server busy");
+            }
+            _ => panic!("expect the request to be shed"),
+        }
+        assert_eq!(responses.lock().len(), 1);
+    }
 }
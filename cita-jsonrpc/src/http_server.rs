@@ -1,31 +1,62 @@
-use error::ErrorCode;
+use batch_position::BatchPositions;
+use config::TlsConfig;
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
 use futures::{self, Stream};
 use futures::future::{Either, Future};
 use futures::stream::FuturesOrdered;
 use futures::sync::oneshot;
-use helper::{select_topic, ReqInfo, ReqSender, RpcMap, TransferType};
-use hyper::{self, Method, StatusCode};
+use helper::{admin_only_error, admin_refused, relay_request, select_topic, DuplicateRequestId, ReqInfo, ReqSender,
+             RpcMap, TransferType};
+use hyper::{self, Body, Method, StatusCode};
 use hyper::header::{AccessControlAllowHeaders, AccessControlAllowMethods, AccessControlAllowOrigin,
-                    AccessControlMaxAge, ContentType, Headers};
+                    AccessControlMaxAge, ContentEncoding, ContentLength, ContentType, Encoding, Headers, Vary};
 use hyper::server::{Http, NewService, Request, Response, Service};
-use jsonrpc_types::{Call, Error, RpcRequest};
+use idempotency::IdempotencyKeys;
+use jsonrpc_types::{Call, Error, Id, RpcRequest, Version};
 use jsonrpc_types::method::{self, MethodHandler};
-use jsonrpc_types::response::RpcFailure;
+use jsonrpc_types::response::{Output, RpcFailure};
 use libproto::request as reqlib;
+use metrics::{NewTxStats, MAX_THROUGHPUT_WINDOW};
+use native_tls::{Pkcs12, TlsAcceptor};
 use net2;
+use openssl::pkcs12::Pkcs12 as PemBundle;
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use rate_limit::{batch_over_limit, over_limit, rate_limited_error, RateLimiter};
 use response::{BatchFutureResponse, SingleFutureResponse};
 use serde_json;
-use std::io;
-use std::net::SocketAddr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream};
+use std::path::Path;
+use std::rc::Rc;
 use std::sync::{mpsc, Arc};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
 use tokio_core::net::TcpListener;
 use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_tls::TlsAcceptorExt;
+use tokio_uds::UnixListener;
 use unicase::Ascii;
-use util::Mutex;
+use util::{Mutex, H256};
 
 const TCP_BACKLOG: i32 = 1024;
 const CORS_CACHE: u32 = 86_400u32;
+/// Protects the in-memory PKCS#12 bundle of the TLS cert and key, it's never written out
+const TLS_BUNDLE_PASSWORD: &str = "cita-jsonrpc";
+/// Largest request body accepted if `max_body_size` isn't configured
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1_048_576;
+/// Most calls in one batch request if `max_batch_size` isn't configured
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+/// Smallest response compressed if `compression_min_size` isn't configured
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+/// Threads compressing responses if `compression_threads` isn't configured
+pub const DEFAULT_COMPRESSION_THREADS: usize = 4;
+/// Window of `GET /throughput` if no `seconds` is given
+const DEFAULT_THROUGHPUT_WINDOW: u64 = 60;
 
 struct Inner {
     pub tx: ReqSender,
@@ -34,12 +65,36 @@ struct Inner {
     pub reactor_handle: Handle,
     pub method_handler: method::MethodHandler,
     pub http_headers: Headers,
+    pub allow_origins: Vec<String>,
+    pub rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    pub new_tx_stats: Arc<NewTxStats>,
+    pub batch_positions: Arc<Mutex<BatchPositions>>,
+    pub max_body_size: usize,
+    pub max_batch_size: usize,
+    /// None if compression isn't enabled
+    pub compressor: Option<Compressor>,
+    pub idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    pub on_duplicate: DuplicateRequestId,
 }
 
 pub struct Server {
     inner: Arc<Inner>,
 }
 
+/// Where response bodies are compressed, so a large one doesn't hold up the reactor
+#[derive(Clone)]
+pub struct Compressor {
+    /// Smallest response compressed, smaller ones aren't worth the CPU
+    pub min_size: usize,
+    pub pool: ThreadPool,
+}
+
+/// Where connections are accepted
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
 pub struct NewServer {
     inner: Arc<Inner>,
 }
@@ -57,6 +112,37 @@ impl NewService for NewServer {
     }
 }
 
+/// A connection's service, counted in `live` until the connection is closed
+struct Tracked<S> {
+    service: S,
+    _live: Rc<()>,
+}
+
+impl<S: Service> Service for Tracked<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, req: S::Request) -> S::Future {
+        self.service.call(req)
+    }
+}
+
+/// Keeps the reactor turning until the connections counted in `live` are closed, for `timeout` at most,
+/// so requests in flight at shutdown are still answered
+fn finish_connections(core: &mut Core, live: &Rc<()>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Rc::strong_count(live) > 1 {
+        let now = Instant::now();
+        if now >= deadline {
+            warn!("{} connections still open at shutdown are dropped", Rc::strong_count(live) - 1);
+            return;
+        }
+        core.turn(Some(deadline - now));
+    }
+}
+
 impl Service for Server {
     type Request = Request;
     type Response = Response;
@@ -70,34 +156,93 @@ impl Service for Server {
         let method_handler = self.inner.method_handler;
         let timeout = self.inner.timeout;
         let reactor_handle = self.inner.reactor_handle.clone();
-        let http_headers = self.inner.http_headers.clone();
+        let allowed_origin = {
+            let origin = req.headers()
+                .get_raw("Origin")
+                .and_then(|raw| raw.one())
+                .and_then(|value| ::std::str::from_utf8(value).ok());
+            allowed_origin(&self.inner.allow_origins, origin)
+        };
+        let mut http_headers = self.inner.http_headers.clone();
+        // Responses differ by origin unless any is allowed, a disallowed one gets no CORS headers
+        if !self.inner.allow_origins.is_empty() && allowed_origin != Some(AccessControlAllowOrigin::Any) {
+            vary_on(&mut http_headers, "Origin");
+        }
+        if let Some(ref allowed_origin) = allowed_origin {
+            http_headers.set(allowed_origin.clone());
+        }
+        let compression = self.inner.compressor.as_ref().map(|compressor| {
+            vary_on(&mut http_headers, "Accept-Encoding");
+            let accept_encoding = req.headers()
+                .get_raw("Accept-Encoding")
+                .and_then(|raw| raw.one())
+                .and_then(|value| ::std::str::from_utf8(value).ok());
+            (accepted_encoding(accept_encoding), compressor.clone())
+        });
+        let rate_limiter = self.inner.rate_limiter.clone();
+        let remote_ip = req.remote_addr().map(|addr| addr.ip());
+        let max_batch_size = self.inner.max_batch_size;
+        let idempotency_keys = Arc::clone(&self.inner.idempotency_keys);
+        let on_duplicate = self.inner.on_duplicate;
 
         match (req.method(), req.path()) {
             (&Method::Post, "/") => {
-                let mapping = req.body().concat2().and_then(move |chunk| {
+                let max_body_size = self.inner.max_body_size;
+                let too_large = req.headers()
+                    .get::<ContentLength>()
+                    .map_or(false, |&ContentLength(len)| len > max_body_size as u64);
+                if too_large {
+                    return Box::new(futures::future::ok(payload_too_large(http_headers)));
+                }
+                let mapping = read_body(req.body(), max_body_size).and_then(move |chunk| {
+                    let chunk = match chunk {
+                        Some(chunk) => chunk,
+                        None => return Either::B(futures::future::ok(payload_too_large(http_headers))),
+                    };
                     if let Ok(rpc) = serde_json::from_slice::<RpcRequest>(&chunk) {
                         match rpc {
                             RpcRequest::Single(call) => match read_single(&call, method_handler, &http_headers) {
                                 Ok(req) => {
+                                    if admin_refused(remote_ip, &call.method) {
+                                        let failure = RpcFailure::from_options(
+                                            call.answer_id(),
+                                            call.jsonrpc.clone(),
+                                            admin_only_error(),
+                                        );
+                                        return Either::B(futures::future::ok(failure_response(&failure, http_headers)));
+                                    }
+                                    if over_limit(&rate_limiter, remote_ip, &call.method) {
+                                        let failure = RpcFailure::from_options(
+                                            call.answer_id(),
+                                            call.jsonrpc.clone(),
+                                            rate_limited_error(),
+                                        );
+                                        return Either::B(futures::future::ok(failure_response(&failure, http_headers)));
+                                    }
                                     if let Ok(timeout) = Timeout::new(timeout, &reactor_handle) {
-                                        let id = call.id.clone();
+                                        let id = call.answer_id();
                                         let jsonrpc_version = call.jsonrpc.clone();
-                                        let request_id = req.request_id.clone();
-                                        let mq_resp = handle_single(call, req, &responses, &sender, &http_headers);
+                                        let (request_id, mq_resp) = handle_single(
+                                            call,
+                                            req,
+                                            &responses,
+                                            &sender,
+                                            &idempotency_keys,
+                                            on_duplicate,
+                                            &http_headers,
+                                        );
 
                                         let resp = mq_resp.select2(timeout).then(move |res| match res {
                                             Ok(Either::A((got, _timeout))) => Ok(got),
                                             Ok(Either::B((_timeout_error, _get))) => {
-                                                {
+                                                warn!("backend timeout, request {:?} of call {:?}", request_id, id);
+                                                if let Some(request_id) = request_id {
                                                     timeout_responses.lock().remove(&request_id);
                                                 }
                                                 let failure = RpcFailure::from_options(
                                                     id,
                                                     jsonrpc_version,
-                                                    Error::server_error(
-                                                        ErrorCode::time_out_error(),
-                                                        "system time out, please resend",
-                                                    ),
+                                                    backend_timeout_error(),
                                                 );
                                                 let resp_body = serde_json::to_string(&failure)
                                                     .expect("should be serialize by serde_json");
@@ -120,33 +265,52 @@ impl Service for Server {
                                 }
                                 Err(resp) => Either::B(futures::future::ok(resp)),
                             },
-                            RpcRequest::Batch(calls) => match read_batch(calls, method_handler, &http_headers) {
-                                Ok(reqs) => {
-                                    let request_ids: Vec<Vec<u8>> = reqs.iter()
-                                        .map(|&(ref _call, ref req)| req.request_id.clone())
-                                        .collect();
-
-                                    let mq_resp = handle_batch(reqs, &responses, &sender, &http_headers);
+                            RpcRequest::Batch(calls) => match check_batch(&calls, max_batch_size) {
+                                Ok(()) => {
+                                    let answered = answered_calls(&calls);
+                                    let methods = calls.iter().map(|call| call.method.as_str());
+                                    if batch_over_limit(&rate_limiter, remote_ip, methods) {
+                                        let resp =
+                                            batch_failure_response(&answered, &rate_limited_error(), http_headers);
+                                        return Either::B(futures::future::ok(resp));
+                                    }
+                                    let reqs = read_batch(calls, method_handler, remote_ip);
+                                    let (request_ids, batch_resp) = handle_batch(
+                                        reqs,
+                                        &responses,
+                                        &sender,
+                                        &idempotency_keys,
+                                        on_duplicate,
+                                        &http_headers,
+                                    );
+                                    let mq_resp = match batch_resp {
+                                        Some(mq_resp) => mq_resp,
+                                        // Nothing but notifications, there is no response to wait for
+                                        None => {
+                                            return Either::B(futures::future::ok(
+                                                Response::new()
+                                                    .with_headers(http_headers)
+                                                    .with_status(StatusCode::NoContent),
+                                            ))
+                                        }
+                                    };
 
                                     if let Ok(timeout) = Timeout::new(timeout, &reactor_handle) {
                                         let resp = mq_resp.select2(timeout).then(move |res| match res {
                                             Ok(Either::A((got, _timeout))) => Ok(got),
                                             Ok(Either::B((_timeout_error, _get))) => {
+                                                warn!("backend timeout, requests {:?} of a batch", request_ids);
                                                 {
                                                     let mut guard = timeout_responses.lock();
                                                     for request_id in request_ids {
                                                         guard.remove(&request_id);
                                                     }
                                                 }
-                                                let failure = RpcFailure::from(Error::server_error(
-                                                    ErrorCode::time_out_error(),
-                                                    "system time out, please resend",
-                                                ));
-                                                let resp_body = serde_json::to_string(&failure)
-                                                    .expect("should be serialize by serde_json");
-                                                Ok(Response::new()
-                                                    .with_headers(http_headers)
-                                                    .with_body(resp_body))
+                                                Ok(batch_failure_response(
+                                                    &answered,
+                                                    &backend_timeout_error(),
+                                                    http_headers,
+                                                ))
                                             }
                                             Err(Either::A((get_error, _timeout))) => Err(get_error),
                                             Err(Either::B((timeout_error, _get))) => Err(From::from(timeout_error)),
@@ -161,7 +325,10 @@ impl Service for Server {
                                         ))
                                     }
                                 }
-                                Err(resp) => Either::B(futures::future::ok(resp)),
+                                Err(err) => {
+                                    let failure = RpcFailure::from(err);
+                                    Either::B(futures::future::ok(failure_response(&failure, http_headers)))
+                                }
                             },
                         }
                     } else {
@@ -172,10 +339,57 @@ impl Service for Server {
                         ))
                     }
                 });
-                let resp: Box<Future<Error = hyper::Error, Item = hyper::Response>> = Box::new(mapping);
+                let resp: Box<Future<Error = hyper::Error, Item = hyper::Response>> = match compression {
+                    Some((Some(encoding), compressor)) => {
+                        Box::new(mapping.and_then(move |resp| compress(resp, encoding, compressor)))
+                    }
+                    _ => Box::new(mapping),
+                };
                 resp
             }
-            (&Method::Options, "/") => handle_preflighted(http_headers),
+            (&Method::Options, "/") => handle_preflighted(http_headers, allowed_origin.is_some()),
+            (&Method::Get, "/metrics") => Box::new(futures::future::ok(
+                Response::new()
+                    .with_header(ContentType::plaintext())
+                    .with_body(self.inner.new_tx_stats.render()),
+            )),
+            (&Method::Get, "/batch_position") => {
+                let position = query_tx_hash(req.query()).map(|tx_hash| {
+                    self.inner
+                        .batch_positions
+                        .lock()
+                        .position(&tx_hash)
+                        .map(|position| serde_json::to_string(position).expect("should be serialize by serde_json"))
+                });
+                let resp = match position {
+                    Some(Some(position)) => Response::new()
+                        .with_header(ContentType::json())
+                        .with_body(position),
+                    // Forwarded too long ago or never
+                    Some(None) => Response::new()
+                        .with_headers(http_headers)
+                        .with_status(StatusCode::NotFound),
+                    None => Response::new()
+                        .with_headers(http_headers)
+                        .with_status(StatusCode::BadRequest),
+                };
+                Box::new(futures::future::ok(resp))
+            }
+            (&Method::Get, "/throughput") => match window_seconds(req.query()) {
+                Some(seconds) => {
+                    let throughput = self.inner.new_tx_stats.throughput(seconds);
+                    Box::new(futures::future::ok(
+                        Response::new()
+                            .with_header(ContentType::json())
+                            .with_body(serde_json::to_string(&throughput).expect("should be serialize by serde_json")),
+                    ))
+                }
+                None => Box::new(futures::future::ok(
+                    Response::new()
+                        .with_headers(http_headers)
+                        .with_status(StatusCode::BadRequest),
+                )),
+            },
             _ => Box::new(futures::future::ok(
                 Response::new()
                     .with_headers(http_headers)
@@ -185,8 +399,34 @@ impl Service for Server {
     }
 }
 
-fn handle_preflighted(mut headers: Headers) -> Box<Future<Item = Response, Error = hyper::Error>> {
+/// The window asked by `?seconds=N`, a minute if not given
+fn window_seconds(query: Option<&str>) -> Option<u64> {
+    let seconds = query
+        .and_then(|query| query.split('&').find(|pair| pair.starts_with("seconds=")))
+        .map(|pair| pair["seconds=".len()..].parse::<u64>().ok());
+    match seconds {
+        None => Some(DEFAULT_THROUGHPUT_WINDOW),
+        Some(Some(seconds)) if seconds > 0 && seconds <= MAX_THROUGHPUT_WINDOW => Some(seconds),
+        Some(_) => None,
+    }
+}
+
+/// The tx hash asked by `?hash=0x...`
+fn query_tx_hash(query: Option<&str>) -> Option<H256> {
+    let hash = query?
+        .split('&')
+        .find(|pair| pair.starts_with("hash="))
+        .map(|pair| &pair["hash=".len()..])?;
+    let hash = if hash.starts_with("0x") { &hash[2..] } else { hash };
+    hash.parse::<H256>().ok()
+}
+
+/// A disallowed origin gets no CORS headers, so the browser blocks the request
+fn handle_preflighted(mut headers: Headers, allowed: bool) -> Box<Future<Item = Response, Error = hyper::Error>> {
     headers.set(ContentType::plaintext());
+    if !allowed {
+        return Box::new(futures::future::ok(Response::new().with_headers(headers)));
+    }
     headers.set(AccessControlAllowMethods(vec![
         Method::Post,
         Method::Options,
@@ -202,12 +442,167 @@ fn handle_preflighted(mut headers: Headers) -> Box<Future<Item = Response, Error
     Box::new(futures::future::ok(Response::new().with_headers(headers)))
 }
 
+/// Add `header` to those responses vary by
+fn vary_on(headers: &mut Headers, header: &str) {
+    let mut items = match headers.get::<Vary>() {
+        Some(&Vary::Any) => return,
+        Some(&Vary::Items(ref items)) => items.clone(),
+        None => Vec::new(),
+    };
+    items.push(Ascii::new(header.to_owned()));
+    headers.set(Vary::Items(items));
+}
+
+/// The encoding a response is compressed with, gzip rather than deflate,
+/// as the `Accept-Encoding` header of the request allows
+fn accepted_encoding(accept_encoding: Option<&str>) -> Option<Encoding> {
+    // Codings listed, each with whether it's refused by `q=0`
+    let codings: Vec<(String, bool)> = accept_encoding
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let refused = parts.any(|param| {
+                let param = param.trim();
+                param.starts_with("q=") && param[2..].trim().parse::<f32>().ok() == Some(0.0)
+            });
+            if name.is_empty() {
+                None
+            } else {
+                Some((name, refused))
+            }
+        })
+        .collect();
+    let accepts = |name: &str| {
+        let refused = |wanted: &str| {
+            codings
+                .iter()
+                .find(|&&(ref coding, _)| coding == wanted)
+                .map(|&(_, refused)| refused)
+        };
+        !refused(name).or_else(|| refused("*")).unwrap_or(true)
+    };
+    if accepts("gzip") {
+        Some(Encoding::Gzip)
+    } else if accepts("deflate") {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Compress the body of `resp` with `encoding` on the pool of `compressor`,
+/// once it's at least `min_size` bytes
+fn compress(
+    resp: Response,
+    encoding: Encoding,
+    compressor: Compressor,
+) -> Box<Future<Item = Response, Error = hyper::Error>> {
+    let status = resp.status();
+    let mut headers = resp.headers().clone();
+    Box::new(resp.body().concat2().and_then(move |body| {
+        if body.len() < compressor.min_size {
+            return Either::A(futures::future::ok(
+                Response::new()
+                    .with_status(status)
+                    .with_headers(headers)
+                    .with_body(body),
+            ));
+        }
+        let (compressed_tx, compressed_rx) = oneshot::channel();
+        compressor.pool.execute(move || {
+            let compressed = match encoding {
+                Encoding::Gzip => {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(&body).and_then(|_| encoder.finish())
+                }
+                _ => {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(&body).and_then(|_| encoder.finish())
+                }
+            };
+            let _ = compressed_tx.send(compressed.map_err(|err| (err, body)));
+        });
+        Either::B(compressed_rx.then(move |compressed| -> Result<Response, hyper::Error> {
+            let resp = Response::new().with_status(status);
+            Ok(match compressed {
+                Ok(Ok(compressed)) => {
+                    headers.set(ContentEncoding(vec![encoding]));
+                    resp.with_headers(headers).with_body(compressed)
+                }
+                Ok(Err((err, body))) => {
+                    warn!("failed to compress a response with {}: {}", encoding, err);
+                    resp.with_headers(headers).with_body(body)
+                }
+                Err(_) => {
+                    error!("response compression was dropped, is the pool panicking?");
+                    Response::new().with_status(StatusCode::InternalServerError)
+                }
+            })
+        }))
+    }))
+}
+
+/// Collect the body, or None once it grows past `max_body_size`. Reading stops there,
+/// so a body sent without `Content-Length` isn't buffered in full either.
+fn read_body(body: Body, max_body_size: usize) -> Box<Future<Item = Option<Vec<u8>>, Error = hyper::Error>> {
+    let body = body.fold(Vec::new(), move |mut buf, chunk| {
+        if buf.len() + chunk.len() > max_body_size {
+            return Err(hyper::Error::TooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+        Ok(buf)
+    }).then(|res| match res {
+        Ok(buf) => Ok(Some(buf)),
+        Err(hyper::Error::TooLarge) => Ok(None),
+        Err(err) => Err(err),
+    });
+    Box::new(body)
+}
+
+fn payload_too_large(headers: Headers) -> Response {
+    Response::new()
+        .with_headers(headers)
+        .with_status(StatusCode::PayloadTooLarge)
+}
+
+fn failure_response(failure: &RpcFailure, headers: Headers) -> Response {
+    let resp_body = serde_json::to_vec(failure).expect("should be serialize by serde_json");
+    Response::new().with_headers(headers).with_body(resp_body)
+}
+
+/// Id and version of each call of a batch which isn't a notification, in order
+fn answered_calls(calls: &[Call]) -> Vec<(Id, Option<Version>)> {
+    calls
+        .iter()
+        .filter(|call| !call.is_notification())
+        .map(|call| (call.answer_id(), call.jsonrpc.clone()))
+        .collect()
+}
+
+/// Fail a whole batch with `err`, answering each of its `answered` calls in an array,
+/// or nothing if all of them are notifications.
+fn batch_failure_response(answered: &[(Id, Option<Version>)], err: &Error, headers: Headers) -> Response {
+    if answered.is_empty() {
+        return Response::new()
+            .with_headers(headers)
+            .with_status(StatusCode::NoContent);
+    }
+    let failures: Vec<RpcFailure> = answered
+        .iter()
+        .map(|&(ref id, ref jsonrpc)| RpcFailure::from_options(id.clone(), jsonrpc.clone(), err.clone()))
+        .collect();
+    let resp_body = serde_json::to_vec(&failures).expect("should be serialize by serde_json");
+    Response::new().with_headers(headers).with_body(resp_body)
+}
+
 fn read_single(call: &Call, method_handler: MethodHandler, headers: &Headers) -> Result<reqlib::Request, Response> {
     match method_handler.request(call) {
         Ok(req) => Ok(req),
         Err(e) => {
             let resp_body = serde_json::to_vec(&RpcFailure::from_options(
-                call.id.clone(),
+                call.answer_id(),
                 call.jsonrpc.clone(),
                 e,
             )).expect("should be serialize by serde_json");
@@ -218,88 +613,155 @@ fn read_single(call: &Call, method_handler: MethodHandler, headers: &Headers) ->
     }
 }
 
+/// Answered once a request isn't responded by the backend within the `timeout`
+fn backend_timeout_error() -> Error {
+    Error {
+        message: "backend timeout".to_owned(),
+        ..Error::internal_error()
+    }
+}
+
 fn handle_single(
     call: Call,
     req: reqlib::Request,
     responses: &RpcMap,
-    sender: &mpsc::Sender<(String, reqlib::Request)>,
+    sender: &mpsc::SyncSender<(String, reqlib::Request)>,
+    idempotency_keys: &Mutex<IdempotencyKeys>,
+    on_duplicate: DuplicateRequestId,
     headers: &Headers,
-) -> SingleFutureResponse {
-    let request_id = req.request_id.clone();
+) -> (Option<Vec<u8>>, SingleFutureResponse) {
     let (tx, rx) = oneshot::channel();
     let topic = select_topic(&call.method);
-    let req_info = (ReqInfo::new(call.jsonrpc, call.id), tx);
-    {
-        responses
-            .lock()
-            .insert(request_id, TransferType::HTTP(req_info));
-    }
-    let _ = sender.send((topic, req));
+    let key = MethodHandler.idempotency_key(&call);
+    let req_info = (ReqInfo::new(call.jsonrpc, call.id.unwrap_or_default()), tx);
+    let request_id = relay_request(
+        responses,
+        sender,
+        topic,
+        req,
+        TransferType::HTTP(req_info),
+        key.map(|key| (idempotency_keys, key)),
+        on_duplicate,
+    );
     let headers = headers.clone();
 
-    SingleFutureResponse::new(rx, headers)
+    (request_id, SingleFutureResponse::new(rx, headers))
+}
+
+/// A batch must have at least one call and at most `max_batch_size` calls.
+fn check_batch(calls: &[Call], max_batch_size: usize) -> Result<(), Error> {
+    if calls.is_empty() {
+        Err(Error::invalid_request_with_message("empty batch"))
+    } else if calls.len() > max_batch_size {
+        Err(Error::invalid_request_with_message(format!(
+            "batch of {} calls, at most {} are allowed",
+            calls.len(),
+            max_batch_size
+        )))
+    } else {
+        Ok(())
+    }
 }
 
+/// An invalid call doesn't fail the whole batch, it's answered with its own error,
+/// as is an admin method called from `remote_ip` on another host.
 fn read_batch(
     calls: Vec<Call>,
     method_handler: MethodHandler,
-    headers: &Headers,
-) -> Result<Vec<(Call, reqlib::Request)>, Response> {
-    let mut reqs = Vec::with_capacity(calls.len());
-    for call in calls {
-        match method_handler.request(&call) {
-            Ok(req) => {
-                reqs.push((call, req));
-            }
-            Err(_) => {
-                return Err(Response::new()
-                    .with_headers(headers.clone())
-                    .with_status(StatusCode::BadRequest))
-            }
-        }
-    }
-    Ok(reqs)
+    remote_ip: Option<IpAddr>,
+) -> Vec<(Call, Result<reqlib::Request, Error>)> {
+    calls
+        .into_iter()
+        .map(|call| {
+            let req = if admin_refused(remote_ip, &call.method) {
+                Err(admin_only_error())
+            } else {
+                method_handler.request(&call)
+            };
+            (call, req)
+        })
+        .collect()
 }
 
+/// Relay the calls and collect their outputs in order, None if all of them are notifications.
+/// Also returns the request ids the outputs are awaited under.
 fn handle_batch(
-    reqs: Vec<(Call, reqlib::Request)>,
+    reqs: Vec<(Call, Result<reqlib::Request, Error>)>,
     responses: &RpcMap,
-    sender: &mpsc::Sender<(String, reqlib::Request)>,
+    sender: &mpsc::SyncSender<(String, reqlib::Request)>,
+    idempotency_keys: &Mutex<IdempotencyKeys>,
+    on_duplicate: DuplicateRequestId,
     headers: &Headers,
-) -> BatchFutureResponse {
+) -> (Vec<Vec<u8>>, Option<BatchFutureResponse>) {
     use std::iter::FromIterator;
+    let mut request_ids = Vec::with_capacity(reqs.len());
     let mut rxs = Vec::with_capacity(reqs.len());
     for (call, req) in reqs {
-        let request_id = req.request_id.clone();
-        let topic = select_topic(&call.method);
+        let notification = call.is_notification();
         let (tx, rx) = oneshot::channel();
-        let req_info = (ReqInfo::new(call.jsonrpc, call.id), tx);
-        {
-            responses
-                .lock()
-                .insert(request_id, TransferType::HTTP(req_info));
+        match req {
+            Ok(req) => {
+                let topic = select_topic(&call.method);
+                let key = MethodHandler.idempotency_key(&call);
+                let req_info = (ReqInfo::new(call.jsonrpc, call.id.unwrap_or_default()), tx);
+                request_ids.extend(relay_request(
+                    responses,
+                    sender,
+                    topic,
+                    req,
+                    TransferType::HTTP(req_info),
+                    key.map(|key| (idempotency_keys, key)),
+                    on_duplicate,
+                ));
+            }
+            Err(err) => {
+                let failure = RpcFailure::from_options(call.id.unwrap_or_default(), call.jsonrpc, err);
+                let _ = tx.send(Output::Failure(failure));
+            }
+        }
+        // A notification is still relayed, but its output is dropped
+        if !notification {
+            rxs.push(rx);
         }
-        let _ = sender.send((topic, req));
-        rxs.push(rx);
+    }
+    if rxs.is_empty() {
+        return (request_ids, None);
     }
     let headers = headers.clone();
 
-    BatchFutureResponse::new(FuturesOrdered::from_iter(rxs).collect(), headers)
+    (
+        request_ids,
+        Some(BatchFutureResponse::new(FuturesOrdered::from_iter(rxs).collect(), headers)),
+    )
 }
 
 impl Server {
     pub fn start(
         core: Core,
-        listener: TcpListener,
-        tx: mpsc::Sender<(String, reqlib::Request)>,
+        listener: Listener,
+        tx: mpsc::SyncSender<(String, reqlib::Request)>,
         responses: RpcMap,
         timeout: Duration,
-        allow_origin: &Option<String>,
+        allow_origins: Vec<String>,
+        tls: Option<Arc<TlsAcceptor>>,
+        rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+        new_tx_stats: Arc<NewTxStats>,
+        batch_positions: Arc<Mutex<BatchPositions>>,
+        max_body_size: usize,
+        max_batch_size: usize,
+        compressor: Option<Compressor>,
+        idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+        on_duplicate: DuplicateRequestId,
+        shutdown: oneshot::Receiver<()>,
     ) {
         let mut headers = Headers::new();
-        let origin = parse_origin(allow_origin);
         headers.set(ContentType::json());
-        headers.set(origin);
+        // Local clients have no IP to tell them apart, so they aren't limited
+        // rather than all sharing one bucket
+        let rate_limiter = match listener {
+            Listener::Tcp(_) => rate_limiter,
+            Listener::Unix(_) => None,
+        };
 
         let new_service = NewServer {
             inner: Arc::new(Inner {
@@ -309,28 +771,190 @@ impl Server {
                 reactor_handle: core.handle(),
                 method_handler: method::MethodHandler,
                 http_headers: headers,
+                allow_origins: allow_origins,
+                rate_limiter: rate_limiter,
+                new_tx_stats: new_tx_stats,
+                batch_positions: batch_positions,
+                max_body_size: max_body_size,
+                max_batch_size: max_batch_size,
+                compressor: compressor,
+                idempotency_keys: idempotency_keys,
+                on_duplicate: on_duplicate,
             }),
         };
-        let server = Http::new()
-            .keep_alive(true)
-            .bind_listener(core, listener, new_service)
-            .unwrap();
-        server.run().unwrap();
+        let mut http = Http::new();
+        http.keep_alive(true);
+        // Stop accepting connections once shutdown is signaled (or its sender dropped),
+        // the open ones are given as long as a request may wait for its backend
+        let live = Rc::new(());
+        match (listener, tls) {
+            (Listener::Tcp(listener), None) => {
+                let mut server = http.bind_listener(core, listener, new_service).unwrap();
+                server.shutdown_timeout(timeout);
+                server.run_until(shutdown.then(|_| Ok(()))).unwrap();
+            }
+            (Listener::Tcp(listener), Some(tls)) => {
+                let mut core = core;
+                let handle = core.handle();
+                let conns = Rc::clone(&live);
+                let server = listener.incoming().for_each(move |(sock, remote_addr)| {
+                    let http = http.clone();
+                    let service = Tracked {
+                        service: new_service.new_service()?,
+                        _live: Rc::clone(&conns),
+                    };
+                    let conn_handle = handle.clone();
+                    let conn = tls.accept_async(sock).then(move |stream| -> Result<(), ()> {
+                        match stream {
+                            Ok(stream) => http.bind_connection(&conn_handle, stream, remote_addr, service),
+                            Err(err) => warn!("TLS handshake with {} failed: {}", remote_addr, err),
+                        }
+                        Ok(())
+                    });
+                    handle.spawn(conn);
+                    Ok(())
+                });
+                let shutdown = shutdown.then(|_| Ok(()));
+                core.run(server.select(shutdown).map_err(|(err, _)| err))
+                    .unwrap();
+                finish_connections(&mut core, &live, timeout);
+            }
+            // TLS over a Unix socket is refused when the config is read
+            (Listener::Unix(listener), _) => {
+                let mut core = core;
+                let handle = core.handle();
+                let conns = Rc::clone(&live);
+                // Local clients have no IP, they are trusted as loopback ones
+                let remote_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+                let server = listener.incoming().for_each(move |(sock, _)| {
+                    let service = Tracked {
+                        service: new_service.new_service()?,
+                        _live: Rc::clone(&conns),
+                    };
+                    http.bind_connection(&handle, sock, remote_addr, service);
+                    Ok(())
+                });
+                let shutdown = shutdown.then(|_| Ok(()));
+                core.run(server.select(shutdown).map_err(|(err, _)| err))
+                    .unwrap();
+                finish_connections(&mut core, &live, timeout);
+            }
+        }
+    }
+}
+
+/// Load the certificate chain and private key, so bad files are reported at startup
+pub fn load_tls_config(tls: &TlsConfig) -> Result<Arc<TlsAcceptor>, String> {
+    let cert_pem = read_file(&tls.cert).map_err(|err| format!("can't open TLS cert {}: {}", tls.cert, err))?;
+    let mut certs = X509::stack_from_pem(&cert_pem).map_err(|_| format!("can't parse TLS cert {}", tls.cert))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", tls.cert));
+    }
+    let key_pem = read_file(&tls.key).map_err(|err| format!("can't open TLS key {}: {}", tls.key, err))?;
+    let key = PKey::private_key_from_pem(&key_pem).map_err(|_| format!("no private key found in {}", tls.key))?;
+
+    // native-tls only takes a PKCS#12 identity, so the PEM files are bundled into one
+    let cert = certs.remove(0);
+    let bundle = Stack::new()
+        .and_then(|mut chain| {
+            for ca in certs {
+                chain.push(ca)?;
+            }
+            let mut builder = PemBundle::builder();
+            builder.ca(chain);
+            builder.build(TLS_BUNDLE_PASSWORD, "cita-jsonrpc", &key, &cert)
+        })
+        .and_then(|bundle| bundle.to_der())
+        .map_err(|err| format!("TLS key {} doesn't match cert {}: {}", tls.key, tls.cert, err))?;
+    Pkcs12::from_der(&bundle, TLS_BUNDLE_PASSWORD)
+        .and_then(TlsAcceptor::builder)
+        .and_then(|builder| builder.build())
+        .map(Arc::new)
+        .map_err(|err| format!("can't set up TLS with cert {}: {}", tls.cert, err))
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Origin to echo back in `Access-Control-Allow-Origin`, None if `origin` isn't allowed.
+fn allowed_origin(allow_origins: &[String], origin: Option<&str>) -> Option<AccessControlAllowOrigin> {
+    for allowed in allow_origins.iter().map(|allowed| allowed.trim()) {
+        if allowed == "*" {
+            return Some(AccessControlAllowOrigin::Any);
+        }
+        if let Some(origin) = origin {
+            if origin_matches(allowed, origin) {
+                return Some(AccessControlAllowOrigin::Value(origin.to_owned()));
+            }
+        }
+    }
+    None
+}
+
+/// Whether `origin`, as `scheme://host[:port]`, matches an allowed entry. The entry may leave
+/// out the scheme to allow any, and start with `*.` to allow any subdomain. An entry without
+/// a port allows the default port of its scheme, or any port if it has no scheme either.
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    let allowed = allowed.to_lowercase();
+    let origin = origin.to_lowercase();
+    let (allowed_scheme, allowed_host) = split_scheme(&allowed);
+    let (scheme, host) = split_scheme(&origin);
+    if allowed_scheme.is_some() && allowed_scheme != scheme {
+        return false;
+    }
+    let host = match port_at(host) {
+        _ if port_at(allowed_host).is_some() => host,
+        Some(at) if allowed_scheme.is_some() => if Some(&host[at + 1..]) == default_port(scheme) {
+            &host[..at]
+        } else {
+            return false;
+        },
+        Some(at) => &host[..at],
+        None => host,
+    };
+    if allowed_host.starts_with("*.") {
+        let suffix = &allowed_host[1..];
+        host.len() > suffix.len() && host.ends_with(suffix)
+    } else {
+        host == allowed_host
+    }
+}
+
+fn default_port(scheme: Option<&str>) -> Option<&'static str> {
+    match scheme {
+        Some("http") | Some("ws") => Some("80"),
+        Some("https") | Some("wss") => Some("443"),
+        _ => None,
     }
 }
 
-fn parse_origin(origin: &Option<String>) -> AccessControlAllowOrigin {
-    match origin.as_ref().map(|s| s.trim().as_ref()) {
-        Some("*") => AccessControlAllowOrigin::Any,
-        None | Some("") | Some("null") => AccessControlAllowOrigin::Null,
-        Some(origin) => AccessControlAllowOrigin::Value(origin.to_string()),
+fn split_scheme(origin: &str) -> (Option<&str>, &str) {
+    match origin.find("://") {
+        Some(at) => (Some(&origin[..at]), &origin[at + 3..]),
+        None => (None, origin),
     }
 }
 
-pub fn listener(addr: &SocketAddr, handle: &Handle) -> io::Result<TcpListener> {
+/// Where the `:port` of a host starts, an IPv6 host is bracketed
+fn port_at(host: &str) -> Option<usize> {
+    match host.rfind(':') {
+        Some(at) if !host[at..].contains(']') => Some(at),
+        _ => None,
+    }
+}
+
+/// An IPv6 listener takes IPv4 clients as well if `dual_stack` is set
+pub fn listener(addr: &SocketAddr, dual_stack: bool, handle: &Handle) -> io::Result<TcpListener> {
     let listener = match *addr {
         SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
-        SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
+        SocketAddr::V6(_) => {
+            let listener = net2::TcpBuilder::new_v6()?;
+            listener.only_v6(!dual_stack)?;
+            listener
+        }
     };
     configure_tcp(&listener)?;
     listener.reuse_address(true)?;
@@ -340,6 +964,14 @@ pub fn listener(addr: &SocketAddr, handle: &Handle) -> io::Result<TcpListener> {
         .and_then(|l| TcpListener::from_listener(l, addr, handle))
 }
 
+/// Binds the socket file, removing the one left behind by a server which didn't shut down cleanly
+pub fn unix_listener(path: &Path) -> io::Result<StdUnixListener> {
+    if path.exists() && StdUnixStream::connect(path).is_err() {
+        fs::remove_file(path)?;
+    }
+    StdUnixListener::bind(path)
+}
+
 fn configure_tcp(tcp: &net2::TcpBuilder) -> io::Result<()> {
     use net2::unix::*;
     tcp.reuse_port(true)?;
@@ -351,23 +983,107 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_parse_origin() {
-        vec![
-            (
-                Some("abc".to_owned()),
-                AccessControlAllowOrigin::Value("abc".to_owned()),
-            ),
-            (
-                Some(" xyz ".to_owned()),
-                AccessControlAllowOrigin::Value("xyz".to_owned()),
-            ),
-            (Some("*".to_owned()), AccessControlAllowOrigin::Any),
-            (Some(" * ".to_owned()), AccessControlAllowOrigin::Any),
-            (None, AccessControlAllowOrigin::Null),
-        ].into_iter()
-            .for_each(|(origin, result)| {
-                assert_eq!(parse_origin(&origin), result);
-            });
+    fn test_query_tx_hash() {
+        let hash = "0x000000000000000000000000000000000000000000000000000000000000000a";
+        assert_eq!(query_tx_hash(Some(&format!("hash={}", hash))), Some(H256::from(10)));
+        assert_eq!(query_tx_hash(Some(&format!("pretty=1&hash={}", &hash[2..]))), Some(H256::from(10)));
+        assert_eq!(query_tx_hash(Some("hash=0x12")), None);
+        assert_eq!(query_tx_hash(Some("pretty=1")), None);
+        assert_eq!(query_tx_hash(None), None);
+    }
+
+    #[test]
+    fn test_batch_failure_answers_each_call() {
+        let calls: Vec<Call> = serde_json::from_str(
+            r#"[
+                {"jsonrpc":"2.0","method":"cita_blockNumber","params":[],"id":1},
+                {"jsonrpc":"2.0","method":"cita_blockNumber","params":[],"id":null},
+                {"jsonrpc":"2.0","method":"cita_blockNumber","params":[]}
+            ]"#,
+        ).unwrap();
+        // Only the call without an id is a notification
+        let answered = answered_calls(&calls);
+        assert_eq!(answered, vec![(Id::Num(1), Some(Version::V2)), (Id::Null, Some(Version::V2))]);
+
+        let resp = batch_failure_response(&answered, &rate_limited_error(), Headers::new());
+        let body = resp.body().concat2().wait().unwrap();
+        let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let failures = rv.as_array().unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0]["id"], json!(1));
+        assert_eq!(failures[1]["id"], json!(null));
+        assert!(failures.iter().all(|failure| failure["error"]["code"] == json!(-32005)));
+
+        let resp = batch_failure_response(&[], &rate_limited_error(), Headers::new());
+        assert_eq!(resp.status(), StatusCode::NoContent);
+    }
+
+    #[test]
+    fn test_load_bad_tls_config() {
+        let tls = TlsConfig {
+            cert: "/nonexistent/cert.pem".to_owned(),
+            key: "/nonexistent/key.pem".to_owned(),
+        };
+        assert!(load_tls_config(&tls).unwrap_err().contains("can't open TLS cert"));
+
+        let path = ::std::env::temp_dir().join("jsonrpc-bad-cert.pem");
+        {
+            use std::io::Write;
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"not a certificate").unwrap();
+        }
+        let tls = TlsConfig {
+            cert: path.to_str().unwrap().to_owned(),
+            key: path.to_str().unwrap().to_owned(),
+        };
+        let result = load_tls_config(&tls);
+        ::std::fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("no certificate found"));
+    }
+
+    #[test]
+    fn test_unix_listener() {
+        let path = ::std::env::temp_dir().join(format!("jsonrpc-{}.sock", ::uuid::Uuid::new_v4()));
+        // Left behind as by a crash
+        drop(StdUnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = unix_listener(&path).unwrap();
+        assert!(StdUnixStream::connect(&path).is_ok());
+        // A live server keeps its socket
+        assert!(unix_listener(&path).is_err());
+        assert!(StdUnixStream::connect(&path).is_ok());
+
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let allow_origins = vec![
+            "https://wallet.io".to_owned(),
+            " *.example.com ".to_owned(),
+            "http://localhost:8080".to_owned(),
+        ];
+        let allowed = |origin: &str| allowed_origin(&allow_origins, Some(origin));
+        let echoed = |origin: &str| Some(AccessControlAllowOrigin::Value(origin.to_owned()));
+
+        assert_eq!(allowed("https://wallet.io"), echoed("https://wallet.io"));
+        assert_eq!(allowed("https://wallet.io:443"), echoed("https://wallet.io:443"));
+        assert_eq!(allowed("https://wallet.io:8443"), None);
+        assert_eq!(allowed("http://wallet.io"), None);
+        assert_eq!(allowed("https://app.example.com"), echoed("https://app.example.com"));
+        assert_eq!(allowed("http://a.b.EXAMPLE.com:3000"), echoed("http://a.b.EXAMPLE.com:3000"));
+        assert_eq!(allowed("https://example.com"), None);
+        assert_eq!(allowed("https://badexample.com"), None);
+        assert_eq!(allowed("http://localhost:8080"), echoed("http://localhost:8080"));
+        assert_eq!(allowed("http://localhost:9090"), None);
+        assert_eq!(allowed_origin(&allow_origins, None), None);
+
+        let any = vec!["*".to_owned()];
+        assert_eq!(allowed_origin(&any, Some("https://x.org")), Some(AccessControlAllowOrigin::Any));
+        assert_eq!(allowed_origin(&any, None), Some(AccessControlAllowOrigin::Any));
+        assert_eq!(allowed_origin(&[], Some("https://x.org")), None);
     }
 }
 
@@ -375,7 +1091,7 @@ mod test {
 mod integration_test {
     use std::collections::HashMap;
     use std::str::FromStr;
-    use std::sync::mpsc::channel;
+    use std::sync::mpsc::{channel, sync_channel};
     use std::thread;
 
     use uuid::Uuid;
@@ -400,31 +1116,28 @@ mod integration_test {
 
     fn start_server(
         responses: RpcMap,
-        tx: mpsc::Sender<(String, reqlib::Request)>,
+        tx: mpsc::SyncSender<(String, reqlib::Request)>,
         timeout: u64,
-        allow_origin: Option<&str>,
+        allow_origins: &[&str],
     ) -> Serve {
         let addr = "127.0.0.1:0".parse().unwrap();
         let tx = tx.clone();
 
         let timeout = Duration::from_secs(timeout);
-        let allow_origin = allow_origin.map(|s| s.to_owned());
+        let allow_origins: Vec<String> = allow_origins.iter().map(|s| (*s).to_owned()).collect();
         let (addr_tx, addr_rx) = ::std::sync::mpsc::channel();
         let thread_handle = thread::Builder::new()
             .name(format!("test-server-{}", Uuid::new_v4()))
             .spawn(move || {
                 let core = Core::new().unwrap();
                 let handle = core.handle();
-                let listener = listener(&addr, &handle).unwrap();
+                let listener = listener(&addr, true, &handle).unwrap();
                 let addr = listener.local_addr().unwrap().clone();
                 let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
                 addr_tx.send((addr, shutdown_tx)).unwrap();
 
-                // Server::start(core, listener, tx, responses, timeout, &allow_origin);
                 let mut headers = Headers::new();
-                let origin = parse_origin(&allow_origin);
                 headers.set(ContentType::json());
-                headers.set(origin);
                 let new_service = NewServer {
                     inner: Arc::new(Inner {
                         tx: Mutex::new(tx),
@@ -433,6 +1146,19 @@ mod integration_test {
                         reactor_handle: core.handle(),
                         method_handler: method::MethodHandler,
                         http_headers: headers,
+                        allow_origins: allow_origins,
+                        rate_limiter: None,
+                        new_tx_stats: Arc::new(NewTxStats::new()),
+                        batch_positions: Arc::new(Mutex::new(BatchPositions::new(16))),
+                        max_body_size: 1024,
+                        max_batch_size: 3,
+                        compressor: None,
+                        idempotency_keys: Arc::new(Mutex::new(IdempotencyKeys::new(
+                            Duration::from_secs(600),
+                            timeout,
+                            16,
+                        ))),
+                        on_duplicate: DuplicateRequestId::Reject,
                     }),
                 };
                 let server = Http::new()
@@ -455,10 +1181,10 @@ mod integration_test {
         use std::io::Write;
 
         // For message forwarding
-        let (tx_relay, rx_relay) = channel();
+        let (tx_relay, rx_relay) = sync_channel(256);
         let backlog_capacity = 256;
         let responses = Arc::new(Mutex::new(HashMap::with_capacity(backlog_capacity)));
-        let serve = start_server(responses.clone(), tx_relay, 3, Some("*"));
+        let serve = start_server(responses.clone(), tx_relay, 3, &["*"]);
 
         let http_responses = responses.clone();
         let (tx_quit, rx_quit) = channel();
@@ -482,7 +1208,8 @@ mod integration_test {
                         TransferType::HTTP((req_info, sender)) => {
                             let _ = sender.send(Output::from(content, req_info.id, req_info.jsonrpc));
                         }
-                        TransferType::WEBSOCKET((req_info, sender)) => {
+                        TransferType::WEBSOCKET((req_info, sender))
+                        | TransferType::SUBSCRIPTION((req_info, sender)) => {
                             let _ = sender.send(
                                 serde_json::to_string(&Output::from(content, req_info.id, req_info.jsonrpc)).unwrap(),
                             );
@@ -548,7 +1275,7 @@ mod integration_test {
         let rpc_call = Call {
             jsonrpc: Some(jsonrpc_types::Version::V2),
             method: "test_method".to_owned(),
-            id: jsonrpc_types::Id::Null,
+            id: Some(jsonrpc_types::Id::Null),
             params: Some(params),
         };
         let data = serde_json::to_string(&RpcRequest::Single(rpc_call)).unwrap();
@@ -635,14 +1362,355 @@ mod integration_test {
                 })
         });
 
+        // The notification gets no output, the unknown method fails alone
+        let data = format!(
+            "{}",
+            json!([
+                {"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":76},
+                {"jsonrpc":"2.0","method":"net_peerCount","params":[]},
+                {"jsonrpc":"2.0","method":"test_method","params":[],"id":77}
+            ])
+        );
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri.clone());
+        req.set_body(data);
+        let work_batch_with_notification = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.body().concat2().and_then(|body| {
+                let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let outputs = rv.as_array().unwrap();
+                assert_eq!(outputs.len(), 2);
+                assert_eq!(outputs[0]["id"], json!(76));
+                assert!(outputs[0].get("error").is_none());
+                assert_eq!(outputs[1]["id"], json!(77));
+                assert_eq!(outputs[1]["error"]["code"], json!(-32601));
+                Ok(())
+            })
+        });
+
+        let data = format!(
+            "{}",
+            json!([{"jsonrpc":"2.0","method":"net_peerCount","params":[]}])
+        );
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri.clone());
+        req.set_body(data);
+        let work_notifications_only = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 204);
+            Ok(())
+        });
+
+        let calls: Vec<serde_json::Value> = (0..4)
+            .map(|id| json!({"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":id}))
+            .collect();
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri.clone());
+        req.set_body(serde_json::to_string(&calls).unwrap());
+        let work_batch_too_large = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.body().concat2().and_then(|body| {
+                let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(rv["error"]["code"], json!(-32600));
+                Ok(())
+            })
+        });
+
+        let metrics_uri =
+            hyper::Uri::from_str(format!("http://{}:{}/metrics", serve.addr.ip(), serve.addr.port()).as_str()).unwrap();
+        let req = hyper::Request::<hyper::Body>::new(Method::Get, metrics_uri);
+        let work_metrics = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.body().concat2().and_then(|body| {
+                let text = String::from_utf8_lossy(&body).into_owned();
+                assert!(text.contains("\njsonrpc_new_tx_buffered 0\n"));
+                assert!(text.contains("\njsonrpc_new_tx_batches_total 0\n"));
+                Ok(())
+            })
+        });
+
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri.clone());
+        req.set_body(vec![b' '; 2048]);
+        let work_too_large = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 413);
+            Ok(())
+        });
+
         works.push(Box::new(work_empty));
         works.push(Box::new(work_options));
         works.push(Box::new(work_method_not_found));
         works.push(Box::new(work_net_peercount));
         works.push(Box::new(work_net_peercount_batch));
+        works.push(Box::new(work_batch_with_notification));
+        works.push(Box::new(work_notifications_only));
+        works.push(Box::new(work_batch_too_large));
+        works.push(Box::new(work_metrics));
+        works.push(Box::new(work_too_large));
         core.run(futures::future::join_all(works)).unwrap();
 
         tx_quit.send(()).unwrap();
         receiver.join().unwrap();
     }
+
+    #[test]
+    fn test_backend_timeout() {
+        // Requests are relayed but never answered
+        let (tx_relay, rx_relay) = sync_channel(256);
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let serve = start_server(Arc::clone(&responses), tx_relay, 1, &["*"]);
+
+        let mut core = Core::new().unwrap();
+        let client = hyper::Client::new(&core.handle());
+        let uri = hyper::Uri::from_str(format!("http://{}:{}/", serve.addr.ip(), serve.addr.port()).as_str()).unwrap();
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri);
+        req.set_body(json!({"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":74}).to_string());
+        let work = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.body().concat2().and_then(|body| {
+                let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(rv["id"], json!(74));
+                assert_eq!(rv["error"], json!({"code": -32603, "message": "backend timeout"}));
+                Ok(())
+            })
+        });
+        core.run(work).unwrap();
+
+        assert!(rx_relay.try_recv().is_ok());
+        assert!(responses.lock().is_empty());
+    }
+
+    #[test]
+    fn test_batch_backend_timeout() {
+        // Requests are relayed but never answered
+        let (tx_relay, rx_relay) = sync_channel(256);
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let serve = start_server(Arc::clone(&responses), tx_relay, 1, &["*"]);
+
+        let mut core = Core::new().unwrap();
+        let client = hyper::Client::new(&core.handle());
+        let uri = hyper::Uri::from_str(format!("http://{}:{}/", serve.addr.ip(), serve.addr.port()).as_str()).unwrap();
+        let mut req = hyper::Request::<hyper::Body>::new(Method::Post, uri);
+        req.set_body(
+            json!([
+                {"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":74},
+                {"jsonrpc":"2.0","method":"net_peerCount","params":[]},
+                {"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":null}
+            ]).to_string(),
+        );
+        let work = client.request(req).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.body().concat2().and_then(|body| {
+                let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let timeout = json!({"code": -32603, "message": "backend timeout"});
+                assert_eq!(rv.as_array().map(|failures| failures.len()), Some(2));
+                assert_eq!(rv[0]["id"], json!(74));
+                assert_eq!(rv[0]["error"], timeout);
+                assert_eq!(rv[1]["id"], json!(null));
+                assert_eq!(rv[1]["error"], timeout);
+                Ok(())
+            })
+        });
+        core.run(work).unwrap();
+
+        assert_eq!(rx_relay.try_iter().count(), 3);
+        assert!(responses.lock().is_empty());
+    }
+
+    /// Runs a worker as main does, on the listener `listen` makes
+    fn start_worker<F>(
+        listen: F,
+        tx: mpsc::SyncSender<(String, reqlib::Request)>,
+        responses: RpcMap,
+        shutdown: oneshot::Receiver<()>,
+    ) -> thread::JoinHandle<()>
+    where
+        F: FnOnce(&Handle) -> Listener + Send + 'static,
+    {
+        thread::spawn(move || {
+            let core = Core::new().unwrap();
+            let listener = listen(&core.handle());
+            let timeout = Duration::from_secs(3);
+            Server::start(
+                core,
+                listener,
+                tx,
+                responses,
+                timeout,
+                vec!["*".to_owned()],
+                None,
+                None,
+                Arc::new(NewTxStats::new()),
+                Arc::new(Mutex::new(BatchPositions::new(16))),
+                1024,
+                3,
+                None,
+                Arc::new(Mutex::new(IdempotencyKeys::new(Duration::from_secs(600), timeout, 16))),
+                DuplicateRequestId::Reject,
+                shutdown,
+            );
+        })
+    }
+
+    /// Posts `body` on a connection closed once it's answered, returns the raw response
+    fn post_raw<S: Read + Write>(mut stream: S, body: &str) -> String {
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Connection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_in_flight_request_answered_on_shutdown() {
+        let path = ::std::env::temp_dir().join(format!("jsonrpc-{}.sock", Uuid::new_v4()));
+        for &unix in &[false, true] {
+            let (tx_relay, rx_relay) = sync_channel(16);
+            let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            let (addr_tx, addr_rx) = channel();
+            let worker = if unix {
+                let std_listener = unix_listener(&path).unwrap();
+                let listen = move |handle: &Handle| {
+                    Listener::Unix(UnixListener::from_listener(std_listener, handle).unwrap())
+                };
+                start_worker(listen, tx_relay, Arc::clone(&responses), shutdown_rx)
+            } else {
+                let listen = move |handle: &Handle| {
+                    let listener = listener(&"127.0.0.1:0".parse().unwrap(), true, handle).unwrap();
+                    addr_tx.send(listener.local_addr().unwrap()).unwrap();
+                    Listener::Tcp(listener)
+                };
+                start_worker(listen, tx_relay, Arc::clone(&responses), shutdown_rx)
+            };
+
+            // Shutdown is signaled while the request waits for its backend, which answers later
+            let backend_responses = Arc::clone(&responses);
+            let backend = thread::spawn(move || {
+                let (_, req) = rx_relay.recv().unwrap();
+                shutdown_tx.send(()).unwrap();
+                thread::sleep(Duration::from_millis(300));
+                let mut content = protos::response::Response::new();
+                content.set_request_id(req.request_id.clone());
+                content.set_peercount(3);
+                match backend_responses.lock().remove(&req.request_id) {
+                    Some(TransferType::HTTP((req_info, sender))) => {
+                        let _ = sender.send(Output::from(content, req_info.id, req_info.jsonrpc));
+                    }
+                    _ => panic!("expected an HTTP request"),
+                }
+            });
+
+            let body = r#"{"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":74}"#;
+            let client_path = path.clone();
+            let client = thread::spawn(move || {
+                if unix {
+                    post_raw(StdUnixStream::connect(&client_path).unwrap(), body)
+                } else {
+                    post_raw(::std::net::TcpStream::connect(addr_rx.recv().unwrap()).unwrap(), body)
+                }
+            });
+
+            // The worker is done only once the request in flight has been answered
+            worker.join().unwrap();
+            backend.join().unwrap();
+            let response = client.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(response.contains(r#""result":"0x3""#), "{}", response);
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_disallowed_origin_varies() {
+        let (tx_relay, _rx_relay) = sync_channel(256);
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let serve = start_server(responses, tx_relay, 1, &["https://wallet.io"]);
+
+        let mut core = Core::new().unwrap();
+        let client = hyper::Client::new(&core.handle());
+        let uri = hyper::Uri::from_str(format!("http://{}:{}/", serve.addr.ip(), serve.addr.port()).as_str()).unwrap();
+        let preflight = |origin: &str| {
+            let mut req = hyper::Request::<hyper::Body>::new(Method::Options, uri.clone());
+            req.headers_mut().set_raw("Origin", origin.to_owned());
+            client.request(req)
+        };
+        let work_disallowed = preflight("https://wallet.io:8443").and_then(|resp| {
+            assert!(resp.headers().get::<AccessControlAllowOrigin>().is_none());
+            assert_eq!(resp.headers().get::<Vary>(), Some(&Vary::Items(vec![Ascii::new("Origin".to_owned())])));
+            Ok(())
+        });
+        let work_allowed = preflight("https://wallet.io").and_then(|resp| {
+            assert_eq!(
+                resp.headers().get::<AccessControlAllowOrigin>(),
+                Some(&AccessControlAllowOrigin::Value("https://wallet.io".to_owned()))
+            );
+            assert_eq!(resp.headers().get::<Vary>(), Some(&Vary::Items(vec![Ascii::new("Origin".to_owned())])));
+            Ok(())
+        });
+        core.run(work_disallowed.join(work_allowed)).unwrap();
+    }
+
+    #[test]
+    fn test_read_body_past_limit() {
+        // No Content-Length to reject it up front, so it's cut off while reading
+        assert_eq!(read_body(Body::from(vec![0u8; 512]), 1024).wait().unwrap(), Some(vec![0u8; 512]));
+        assert_eq!(read_body(Body::from(vec![0u8; 2048]), 1024).wait().unwrap(), None);
+    }
+
+    #[test]
+    fn test_accepted_encoding() {
+        assert_eq!(accepted_encoding(None), None);
+        assert_eq!(accepted_encoding(Some("identity")), None);
+        assert_eq!(accepted_encoding(Some("gzip")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(Some("deflate, GZIP;q=0.5")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(Some("gzip;q=0, deflate")), Some(Encoding::Deflate));
+        assert_eq!(accepted_encoding(Some("*")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(Some("gzip;q=0, *;q=0")), None);
+    }
+
+    #[test]
+    fn test_compress() {
+        use flate2::read::{GzDecoder, ZlibDecoder};
+
+        let compressor = Compressor {
+            min_size: 1024,
+            pool: ThreadPool::new(1),
+        };
+        let body = vec![b'a'; 2048];
+        let resp = Response::new().with_header(ContentType::json()).with_body(body.clone());
+        let resp = compress(resp, Encoding::Gzip, compressor.clone()).wait().unwrap();
+        assert_eq!(resp.headers().get::<ContentEncoding>(), Some(&ContentEncoding(vec![Encoding::Gzip])));
+        assert_eq!(resp.headers().get::<ContentType>(), Some(&ContentType::json()));
+        let compressed = resp.body().concat2().wait().unwrap();
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+
+        let resp = compress(Response::new().with_body(body.clone()), Encoding::Deflate, compressor.clone())
+            .wait()
+            .unwrap();
+        let compressed = resp.body().concat2().wait().unwrap();
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+
+        // Small ones pass through
+        let resp = compress(Response::new().with_body(b"{}".to_vec()), Encoding::Gzip, compressor)
+            .wait()
+            .unwrap();
+        assert_eq!(resp.headers().get::<ContentEncoding>(), None);
+        assert_eq!(&resp.body().concat2().wait().unwrap()[..], b"{}");
+    }
+
+    #[test]
+    fn test_window_seconds() {
+        assert_eq!(window_seconds(None), Some(DEFAULT_THROUGHPUT_WINDOW));
+        assert_eq!(window_seconds(Some("pretty=1")), Some(DEFAULT_THROUGHPUT_WINDOW));
+        assert_eq!(window_seconds(Some("seconds=10")), Some(10));
+        assert_eq!(window_seconds(Some("pretty=1&seconds=300")), Some(300));
+        assert_eq!(window_seconds(Some("seconds=0")), None);
+        assert_eq!(window_seconds(Some("seconds=abc")), None);
+        assert_eq!(window_seconds(Some("seconds=3601")), None);
+    }
 }
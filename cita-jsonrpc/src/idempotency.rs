@@ -0,0 +1,274 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Idempotency keys of new txs, so a client retrying a submission gets the
+//! result of its first one instead of sending the tx again.
+
+use config::NewTxFlowConfig;
+use libproto::response::Response;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use util::H256;
+
+/// Seconds a key is remembered if `idempotency_window` isn't configured
+pub const DEFAULT_IDEMPOTENCY_WINDOW: u64 = 600;
+/// Keys remembered at most if `idempotency_keys` isn't configured
+pub const DEFAULT_IDEMPOTENCY_KEYS: usize = 10_000;
+
+pub enum Claim {
+    /// First submission with the key, relay it
+    New,
+    /// The first submission is still waiting, it's answered along with that
+    Pending,
+    /// Answer with the result of the first submission
+    Done(Response),
+    /// The key was used for another tx, fail the submission
+    Conflict,
+}
+
+enum Outcome {
+    Pending {
+        request_id: Vec<u8>,
+        followers: Vec<Vec<u8>>,
+    },
+    Done(Response),
+}
+
+struct Entry {
+    /// Hash of the tx submitted with the key
+    payload: H256,
+    outcome: Outcome,
+    created_at: Instant,
+}
+
+pub struct IdempotencyKeys {
+    window: Duration,
+    /// A first submission unanswered for this long is taken as lost
+    timeout: Duration,
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    /// Keys from the oldest
+    order: VecDeque<String>,
+    /// Keys of the submissions waiting for their result
+    pending: HashMap<Vec<u8>, String>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(window: Duration, timeout: Duration, capacity: usize) -> Self {
+        IdempotencyKeys {
+            window: window,
+            timeout: timeout,
+            capacity: capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &NewTxFlowConfig, timeout: Duration) -> Self {
+        let window = config.idempotency_window.unwrap_or(DEFAULT_IDEMPOTENCY_WINDOW);
+        let capacity = config.idempotency_keys.unwrap_or(DEFAULT_IDEMPOTENCY_KEYS);
+        IdempotencyKeys::new(Duration::from_secs(window), timeout, capacity)
+    }
+
+    /// Submit a new tx of hash `payload` with `key`, waiting for its response as `request_id`.
+    /// Also returns the resubmissions whose first submission was forgotten, to expire it or to
+    /// make room, they won't be answered.
+    pub fn claim(&mut self, key: &str, payload: H256, request_id: &[u8]) -> (Claim, Vec<Vec<u8>>) {
+        let mut dropped = self.expire();
+        let lost = match self.entries.get_mut(key) {
+            Some(entry) => {
+                if entry.payload != payload {
+                    return (Claim::Conflict, dropped);
+                }
+                match entry.outcome {
+                    Outcome::Done(ref response) => return (Claim::Done(response.clone()), dropped),
+                    Outcome::Pending { ref mut followers, .. } => {
+                        if entry.created_at.elapsed() < self.timeout {
+                            followers.push(request_id.to_vec());
+                            return (Claim::Pending, dropped);
+                        }
+                        true
+                    }
+                }
+            }
+            None => false,
+        };
+        // The resubmissions waiting for a lost submission wait for this one instead
+        let followers = if lost { self.forget(key) } else { Vec::new() };
+
+        self.entries.insert(
+            key.to_owned(),
+            Entry {
+                payload: payload,
+                outcome: Outcome::Pending {
+                    request_id: request_id.to_vec(),
+                    followers: followers,
+                },
+                created_at: Instant::now(),
+            },
+        );
+        self.order.push_back(key.to_owned());
+        self.pending.insert(request_id.to_vec(), key.to_owned());
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => dropped.extend(self.forget(&oldest)),
+                None => break,
+            }
+        }
+        (Claim::New, dropped)
+    }
+
+    /// The response of `request_id` came back. Remember it for the key it was
+    /// submitted with, and return the resubmissions waiting for it.
+    pub fn complete(&mut self, request_id: &[u8], response: &Response) -> Vec<Vec<u8>> {
+        let key = match self.pending.remove(request_id) {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                let outcome = ::std::mem::replace(&mut entry.outcome, Outcome::Done(response.clone()));
+                match outcome {
+                    Outcome::Pending { followers, .. } => followers,
+                    Outcome::Done(_) => Vec::new(),
+                }
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// `request_id` was never relayed, forget its key so the next submission with it
+    /// is relayed. Returns the resubmissions waiting for it, which won't be answered either.
+    pub fn abandon(&mut self, request_id: &[u8]) -> Vec<Vec<u8>> {
+        let key = match self.pending.remove(request_id) {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+        match self.entries.remove(&key).map(|entry| entry.outcome) {
+            Some(Outcome::Pending { followers, .. }) => followers,
+            _ => Vec::new(),
+        }
+    }
+
+    fn expire(&mut self) -> Vec<Vec<u8>> {
+        let mut dropped = Vec::new();
+        while let Some(oldest) = self.order.front().cloned() {
+            let expired = self.entries
+                .get(&oldest)
+                .map_or(true, |entry| entry.created_at.elapsed() >= self.window);
+            if !expired {
+                break;
+            }
+            self.order.pop_front();
+            dropped.extend(self.forget(&oldest));
+        }
+        dropped
+    }
+
+    /// Forget `key`, returns the resubmissions waiting for its first submission
+    fn forget(&mut self, key: &str) -> Vec<Vec<u8>> {
+        match self.entries.remove(key).map(|entry| entry.outcome) {
+            Some(Outcome::Pending { request_id, followers }) => {
+                self.pending.remove(&request_id);
+                followers
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(capacity: usize) -> IdempotencyKeys {
+        IdempotencyKeys::new(Duration::from_secs(600), Duration::from_secs(10), capacity)
+    }
+
+    fn claim(keys: &mut IdempotencyKeys, key: &str, request_id: u8) -> Claim {
+        let (claim, dropped) = keys.claim(key, H256::from(1), &[request_id]);
+        assert!(dropped.is_empty());
+        claim
+    }
+
+    fn response(code: i64) -> Response {
+        let mut response = Response::new();
+        response.set_code(code);
+        response
+    }
+
+    #[test]
+    fn test_claim_and_complete() {
+        let mut keys = keys(10);
+        match claim(&mut keys, "a", 1) {
+            Claim::New => {}
+            _ => panic!("first submission must be relayed"),
+        }
+        match claim(&mut keys, "a", 2) {
+            Claim::Pending => {}
+            _ => panic!("resubmission must wait for the first one"),
+        }
+        assert_eq!(keys.complete(&[1], &response(7)), vec![vec![2]]);
+        match claim(&mut keys, "a", 3) {
+            Claim::Done(response) => assert_eq!(response.get_code(), 7),
+            _ => panic!("resubmission must get the first result"),
+        }
+        // Other keys aren't affected
+        match claim(&mut keys, "b", 4) {
+            Claim::New => {}
+            _ => panic!("another key must be relayed"),
+        }
+    }
+
+    #[test]
+    fn test_forget_oldest_key() {
+        let mut keys = keys(1);
+        claim(&mut keys, "a", 1);
+        claim(&mut keys, "b", 2);
+        assert!(keys.complete(&[1], &response(0)).is_empty());
+        match claim(&mut keys, "a", 3) {
+            Claim::New => {}
+            _ => panic!("a forgotten key is relayed again"),
+        }
+    }
+
+    #[test]
+    fn test_abandon() {
+        let mut keys = keys(10);
+        claim(&mut keys, "a", 1);
+        claim(&mut keys, "a", 2);
+        assert_eq!(keys.abandon(&[1]), vec![vec![2]]);
+        match claim(&mut keys, "a", 3) {
+            Claim::New => {}
+            _ => panic!("an abandoned key is relayed again"),
+        }
+        assert!(keys.abandon(&[1]).is_empty());
+    }
+
+    #[test]
+    fn test_lost_submission() {
+        let mut keys = IdempotencyKeys::new(Duration::from_secs(600), Duration::from_secs(0), 10);
+        claim(&mut keys, "a", 1);
+        match claim(&mut keys, "a", 2) {
+            Claim::New => {}
+            _ => panic!("a submission unanswered past the timeout is relayed again"),
+        }
+        assert!(keys.complete(&[1], &response(0)).is_empty());
+        assert!(keys.complete(&[2], &response(0)).is_empty());
+    }
+}
@@ -1,9 +1,11 @@
 #![feature(try_from)]
 extern crate bytes;
+extern crate chan_signal;
 extern crate clap;
 extern crate cpuprofiler;
 extern crate dotenv;
 extern crate error;
+extern crate flate2;
 extern crate futures;
 extern crate http;
 extern crate httparse;
@@ -13,8 +15,10 @@ extern crate libproto;
 #[macro_use]
 extern crate log;
 extern crate logger;
+extern crate native_tls;
 extern crate net2;
 extern crate num_cpus;
+extern crate openssl;
 extern crate protobuf;
 extern crate pubsub;
 extern crate serde;
@@ -29,6 +33,8 @@ extern crate threadpool;
 extern crate time;
 extern crate tokio_core;
 extern crate tokio_io;
+extern crate tokio_tls;
+extern crate tokio_uds;
 extern crate toml;
 extern crate unicase;
 #[macro_use]
@@ -36,36 +42,66 @@ extern crate util;
 extern crate uuid;
 extern crate ws;
 
+mod batch_position;
 mod config;
 mod helper;
+mod idempotency;
 mod ws_handler;
 mod mq_handler;
 mod http_server;
+mod metrics;
 mod response;
+mod rate_limit;
+mod subscription;
+mod supervisor;
 
+use batch_position::BatchPositions;
+use chan_signal::Signal;
 use clap::App;
-use config::{NewTxFlowConfig, ProfileConfig};
+use config::{ListenTarget, NewTxFlowConfig, ProfileConfig};
 use cpuprofiler::PROFILER;
-use http_server::Server;
+use error::ErrorCode;
+use futures::sync::oneshot;
+use helper::{fail_request, fail_request_with, reap_expired, take_shed_requests, RpcMap};
+use http_server::{Listener, Server};
+use idempotency::IdempotencyKeys;
+use jsonrpc_types::Error;
 use libproto::Message;
 use libproto::request::{self as reqlib, BatchRequest};
+use metrics::NewTxStats;
 use protobuf::RepeatedField;
 use pubsub::start_pubsub;
-use std::collections::HashMap;
+use rate_limit::RateLimiter;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::sync::Arc;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, RecvTimeoutError, Sender};
 use std::thread;
 use std::time::{Duration, SystemTime};
+use subscription::{Subscriptions, DEFAULT_MAX_SUBSCRIPTIONS, DEFAULT_SUBSCRIPTION_QUEUE, DEFAULT_SUBSCRIPTION_THREADS};
+use supervisor::supervise;
+use threadpool::ThreadPool;
 use tokio_core::reactor::Core;
-use util::{set_panic_handler, Mutex};
+use util::{set_panic_handler, Mutex, H256};
 use uuid::Uuid;
 use ws_handler::WsFactory;
 
 pub const TOPIC_NEW_TX: &str = "jsonrpc.new_tx";
 pub const TOPIC_NEW_TX_BATCH: &str = "jsonrpc.new_tx_batch";
+const DEFAULT_DRAIN_TIMEOUT: u64 = 5;
+/// Requests queued for relaying if `relay_queue_capacity` isn't configured
+const DEFAULT_RELAY_QUEUE_CAPACITY: usize = 10_000;
+/// Seconds between two sweeps for requests nobody answered
+const REAP_INTERVAL: u64 = 1;
+/// Time given to pubsub to publish the last batch before exiting
+const PUBSUB_FLUSH_MILLIS: u64 = 500;
+/// How long the unbatched dispatch thread blocks for a request before checking for shutdown
+const UNBATCHED_RECV_MILLIS: u64 = 100;
 
 fn main() {
+    // Must be called before any thread is spawned, so the signals are blocked in all of them
+    let shutdown_signal = chan_signal::notify(&[Signal::INT, Signal::TERM]);
     micro_service_init!("cita-jsonrpc", "CITA:jsonrpc");
 
     // todo load config
@@ -82,7 +118,13 @@ fn main() {
         config_path = c;
     }
 
-    let config = config::Config::new(config_path);
+    let config = match config::Config::load(config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(-1);
+        }
+    };
     info!(
         "CITA:jsonrpc config \n {:?}",
         toml::to_string_pretty(&config).unwrap()
@@ -99,97 +141,373 @@ fn main() {
     // init pubsub
     let (tx_sub, rx_sub) = channel();
     let (tx_pub, rx_pub) = channel();
-    //used for buffer message
-    let (tx_relay, rx_relay) = channel();
-    start_pubsub("jsonrpc", vec!["auth.rpc", "chain.rpc"], tx_sub, rx_pub);
+    //used for buffer message, requests coming while it's full are shed as busy
+    let relay_queue_capacity = config
+        .relay_queue_capacity
+        .unwrap_or(DEFAULT_RELAY_QUEUE_CAPACITY);
+    let (tx_relay, rx_relay) = sync_channel(relay_queue_capacity);
+    start_pubsub(
+        "jsonrpc",
+        vec!["auth.rpc", "chain.rpc", "chain.subscription"],
+        tx_sub,
+        rx_pub,
+    );
 
     let backlog_capacity = config.backlog_capacity;
 
     let responses = Arc::new(Mutex::new(HashMap::with_capacity(backlog_capacity)));
     let http_responses = Arc::clone(&responses);
     let ws_responses = Arc::clone(&responses);
-    let mut mq_handle = mq_handler::MqHandler::new(responses);
+    let dispatch_responses = Arc::clone(&responses);
+    let reaper_responses = Arc::clone(&responses);
+    let batch_positions = Arc::new(Mutex::new(BatchPositions::new(backlog_capacity)));
+    let http_batch_positions = Arc::clone(&batch_positions);
+    let subscription_queue = config
+        .ws_config
+        .subscription_queue
+        .unwrap_or(DEFAULT_SUBSCRIPTION_QUEUE);
+    let max_subscriptions = config
+        .ws_config
+        .max_subscriptions
+        .unwrap_or(DEFAULT_MAX_SUBSCRIPTIONS);
+    let subscription_threads = config
+        .ws_config
+        .subscription_threads
+        .unwrap_or(DEFAULT_SUBSCRIPTION_THREADS);
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::new(
+        subscription_queue,
+        max_subscriptions,
+        subscription_threads,
+    )));
+
+    let rate_limiter = RateLimiter::from_config(&config.new_tx_flow_config)
+        .map(|limiter| Arc::new(Mutex::new(limiter)));
+
+    // Shared by HTTP and websocket, a retry may come over either of them
+    let idempotency_keys = Arc::new(Mutex::new(IdempotencyKeys::from_config(
+        &config.new_tx_flow_config,
+        Duration::from_secs(config.http_config.timeout),
+    )));
+
+    let on_duplicate = config.duplicate_request_id.unwrap_or_default();
+
+    let new_tx_stats = Arc::new(NewTxStats::new());
+    let http_new_tx_stats = Arc::clone(&new_tx_stats);
+    let mut mq_handle = mq_handler::MqHandler::new(
+        responses,
+        Arc::clone(&batch_positions),
+        Arc::clone(&subscriptions),
+        tx_relay.clone(),
+        Arc::clone(&new_tx_stats),
+        Arc::clone(&idempotency_keys),
+    );
 
     //dispatch
     let tx_flow_config = config.new_tx_flow_config;
-    thread::spawn(move || {
+    let draining = Arc::new(AtomicBool::new(false));
+    let dispatch_draining = Arc::clone(&draining);
+    let (drained_tx, drained_rx) = channel();
+    // Owned by whichever dispatch thread runs, so nothing relayed is lost when one panics
+    let rx_relay = Mutex::new(rx_relay);
+    let tx_pub = Mutex::new(tx_pub);
+    let drained_tx = Mutex::new(drained_tx);
+    let max_restarts = tx_flow_config
+        .dispatch_max_restarts
+        .unwrap_or(supervisor::DEFAULT_MAX_RESTARTS);
+    let batch_enable = tx_flow_config.batch_enable.unwrap_or(true);
+    if !batch_enable {
+        info!("new tx batching is disabled, each new tx is forwarded as it comes");
+    }
+    let dispatch = move || {
+        let rx_relay = rx_relay.lock();
+        let tx_pub = tx_pub.lock().clone();
+        // The buffer of a panicked thread is gone, its requests time out in the reaper
         let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
         let mut time_stamp = SystemTime::now();
+        let buffer_duration = Duration::new(0, tx_flow_config.buffer_duration);
         loop {
-            if let Ok(res) = rx_relay.try_recv() {
-                let (topic, req): (String, reqlib::Request) = res;
-                forward_service(
-                    topic,
-                    req,
-                    &mut new_tx_request_buffer,
-                    &mut time_stamp,
-                    &tx_pub,
-                    &tx_flow_config,
-                );
+            // Blocks until the next request comes or the buffered txs are due
+            let wait = if batch_enable {
+                flush_wait(&new_tx_request_buffer, &time_stamp, buffer_duration)
             } else {
-                if !new_tx_request_buffer.is_empty() {
-                    batch_forward_new_tx(&mut new_tx_request_buffer, &mut time_stamp, &tx_pub);
+                Duration::from_millis(UNBATCHED_RECV_MILLIS)
+            };
+            match rx_relay.recv_timeout(wait) {
+                Ok((topic, req)) => {
+                    forward_service(
+                        topic,
+                        req,
+                        &mut new_tx_request_buffer,
+                        &mut new_tx_hashes,
+                        &mut time_stamp,
+                        &tx_pub,
+                        &tx_flow_config,
+                        &batch_positions,
+                        &new_tx_stats,
+                        &dispatch_responses,
+                    );
+                }
+                Err(err) => {
+                    if !new_tx_request_buffer.is_empty() {
+                        batch_forward_new_tx(
+                            &mut new_tx_request_buffer,
+                            &mut time_stamp,
+                            &tx_pub,
+                            &batch_positions,
+                            &new_tx_stats,
+                            &dispatch_responses,
+                        );
+                    }
+                    // Once every relaying sender is gone nothing ever will be relayed,
+                    // and waiting again would return at once
+                    let disconnected = err == RecvTimeoutError::Disconnected;
+                    if disconnected {
+                        warn!("nothing relays requests any more, dispatching stopped");
+                    }
+                    // Nothing is relayed any more and the buffer has just been flushed
+                    if disconnected || dispatch_draining.load(Ordering::SeqCst) {
+                        let _ = drained_tx.lock().send(());
+                        break;
+                    }
                 }
-                thread::sleep(Duration::new(0, tx_flow_config.buffer_duration));
             }
         }
+    };
+    thread::spawn(move || {
+        let window = Duration::from_secs(supervisor::RESTART_WINDOW);
+        if !supervise("dispatch", max_restarts, window, dispatch) {
+            error!("new txs can't be dispatched any more, exiting");
+            std::process::exit(-1);
+        }
     });
 
     //ws
+    let mut ws_broadcaster = None;
     if config.ws_config.enable {
         let ws_config = config.ws_config.clone();
         let tx = tx_relay.clone();
+        let rate_limiter = rate_limiter.clone();
+        let idempotency_keys = Arc::clone(&idempotency_keys);
+        let (broadcaster_tx, broadcaster_rx) = channel();
         thread::spawn(move || {
-            let url = ws_config.listen_ip.clone() + ":" + &ws_config.listen_port.clone().to_string();
+            let url = ws_config.listen_addr().unwrap_or_else(|err| {
+                error!("{}", err);
+                std::process::exit(-1);
+            });
             //let factory = WsFactory::new(ws_responses, tx_pub, 0);
-            let factory = WsFactory::new(ws_responses, tx, 0);
+            let factory = WsFactory::new(
+                ws_responses,
+                tx,
+                0,
+                rate_limiter,
+                subscriptions,
+                idempotency_keys,
+                on_duplicate,
+            );
             info!("WebSocket Listening on {}", url);
             let mut ws_build = ws::Builder::new();
             ws_build.with_settings(ws_config.into());
             let ws_server = ws_build.build(factory).unwrap();
+            let _ = broadcaster_tx.send(ws_server.broadcaster());
             let _ = ws_server.listen(url);
         });
+        ws_broadcaster = broadcaster_rx.recv().ok();
     }
 
+    let mut http_workers = Vec::new();
+    let mut http_socket = None;
+
     if config.http_config.enable {
         let http_config = config.http_config.clone();
-        let addr = http_config.listen_ip.clone() + ":" + &http_config.listen_port.clone().to_string();
-        info!("Http Listening on {}", &addr);
+        let listen_target = http_config.listen_target().unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(-1);
+        });
+        let dual_stack = http_config.dual_stack.unwrap_or(true);
+        info!("Http Listening on {:?}", listen_target);
+        // Workers share the one socket file, each watching it from its own reactor
+        let unix_listener = match listen_target {
+            ListenTarget::Unix(ref path) => {
+                let unix_listener = http_server::unix_listener(path).unwrap_or_else(|err| {
+                    error!("can't listen on {}: {}", path.display(), err);
+                    std::process::exit(-1);
+                });
+                http_socket = Some(path.clone());
+                Some(unix_listener)
+            }
+            ListenTarget::Tcp(_) => None,
+        };
 
         let threads: usize = config
             .http_config
             .thread_number
             .unwrap_or_else(num_cpus::get);
 
+        let tls = http_config
+            .tls_config()
+            .and_then(|tls| match tls {
+                Some(tls) => http_server::load_tls_config(&tls).map(Some),
+                None => Ok(None),
+            })
+            .unwrap_or_else(|err| {
+                error!("{}", err);
+                std::process::exit(-1);
+            });
+        if tls.is_some() {
+            info!("Http serves over TLS");
+        }
+
+        let compressor = if http_config.compression_enable.unwrap_or(false) {
+            let compression_threads = http_config
+                .compression_threads
+                .unwrap_or(http_server::DEFAULT_COMPRESSION_THREADS);
+            Some(http_server::Compressor {
+                min_size: http_config
+                    .compression_min_size
+                    .unwrap_or(http_server::DEFAULT_COMPRESSION_MIN_SIZE),
+                pool: ThreadPool::with_name("compression_pool".to_string(), compression_threads),
+            })
+        } else {
+            None
+        };
+
         for i in 0..threads {
-            let addr = addr.clone().parse().unwrap();
+            let listen_target = listen_target.clone();
+            let unix_listener = unix_listener
+                .as_ref()
+                .map(|unix_listener| unix_listener.try_clone().unwrap());
             let tx = tx_relay.clone();
             let timeout = http_config.timeout;
             let http_responses = Arc::clone(&http_responses);
-            let allow_origin = http_config.allow_origin.clone();
-            let _ = thread::Builder::new()
+            let allow_origins = http_config.allow_origins();
+            let tls = tls.clone();
+            let rate_limiter = rate_limiter.clone();
+            let new_tx_stats = Arc::clone(&http_new_tx_stats);
+            let batch_positions = Arc::clone(&http_batch_positions);
+            let idempotency_keys = Arc::clone(&idempotency_keys);
+            let max_body_size = http_config
+                .max_body_size
+                .unwrap_or(http_server::DEFAULT_MAX_BODY_SIZE);
+            let max_batch_size = http_config
+                .max_batch_size
+                .unwrap_or(http_server::DEFAULT_MAX_BATCH_SIZE);
+            let compressor = compressor.clone();
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            let worker = thread::Builder::new()
                 .name(format!("worker{}", i))
                 .spawn(move || {
                     let core = Core::new().unwrap();
                     let handle = core.handle();
                     let timeout = Duration::from_secs(timeout);
-                    let listener = http_server::listener(&addr, &handle).unwrap();
-                    Server::start(core, listener, tx, http_responses, timeout, &allow_origin);
+                    let listener = match (listen_target, unix_listener) {
+                        (ListenTarget::Tcp(addr), _) => {
+                            Listener::Tcp(http_server::listener(&addr, dual_stack, &handle).unwrap())
+                        }
+                        (ListenTarget::Unix(_), unix_listener) => Listener::Unix(
+                            tokio_uds::UnixListener::from_listener(
+                                unix_listener.expect("socket is bound before workers start"),
+                                &handle,
+                            ).unwrap(),
+                        ),
+                    };
+                    Server::start(
+                        core,
+                        listener,
+                        tx,
+                        http_responses,
+                        timeout,
+                        allow_origins,
+                        tls,
+                        rate_limiter,
+                        new_tx_stats,
+                        batch_positions,
+                        max_body_size,
+                        max_batch_size,
+                        compressor,
+                        idempotency_keys,
+                        on_duplicate,
+                        shutdown_rx,
+                    );
                 })
                 .unwrap();
+            http_workers.push((shutdown_tx, worker));
         }
     }
 
-    loop {
+    // Requests whose answer was lost would otherwise stay in the map for good
+    let reaper_keys = Arc::clone(&idempotency_keys);
+    let reap_timeout = Duration::from_secs(config.http_config.timeout);
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(REAP_INTERVAL));
+        let evicted = reap_expired(&reaper_responses, &reaper_keys, reap_timeout);
+        if evicted > 0 {
+            warn!(
+                "evicted {} requests unanswered in {:?}, is a backend stalled?",
+                evicted, reap_timeout
+            );
+        }
+        let shed = take_shed_requests();
+        if shed > 0 {
+            warn!("shed {} requests as server busy in the last {}s", shed, REAP_INTERVAL);
+        }
+    });
+
+    thread::spawn(move || loop {
         let (key, msg) = rx_sub.recv().unwrap();
         mq_handle.handle(&key, &msg);
+    });
+
+    let signal = shutdown_signal.recv();
+    info!("{:?} received, shutting down", signal);
+    let drain_timeout = Duration::from_secs(tx_flow_config.drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+
+    // Stop taking requests first, so nothing is relayed after the final flush
+    if let Some(ws_broadcaster) = ws_broadcaster {
+        let _ = ws_broadcaster.shutdown();
     }
+    for (shutdown_tx, worker) in http_workers {
+        let _ = shutdown_tx.send(());
+        let _ = worker.join();
+    }
+    if let Some(path) = http_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    draining.store(true, Ordering::SeqCst);
+    match drained_rx.recv_timeout(drain_timeout) {
+        Ok(_) => {
+            info!("new tx buffer is drained");
+            thread::sleep(Duration::from_millis(PUBSUB_FLUSH_MILLIS));
+        }
+        Err(_) => warn!("new tx buffer isn't drained in {:?}", drain_timeout),
+    }
+    std::process::exit(0);
+}
+
+/// How long the dispatch thread may wait for a request before the buffered txs are due
+fn flush_wait(
+    new_tx_request_buffer: &[reqlib::Request],
+    time_stamp: &SystemTime,
+    buffer_duration: Duration,
+) -> Duration {
+    if new_tx_request_buffer.is_empty() {
+        return buffer_duration;
+    }
+    time_stamp
+        .elapsed()
+        .ok()
+        .and_then(|elapsed| buffer_duration.checked_sub(elapsed))
+        .unwrap_or_else(|| Duration::new(0, 0))
 }
 
 fn batch_forward_new_tx(
     new_tx_request_buffer: &mut Vec<reqlib::Request>,
     time_stamp: &mut SystemTime,
     tx_pub: &Sender<(String, Vec<u8>)>,
+    batch_positions: &Mutex<BatchPositions>,
+    new_tx_stats: &NewTxStats,
+    responses: &RpcMap,
 ) {
     trace!(
         "Going to send new tx batch to auth with {} new tx and buffer time cost is {:?} ",
@@ -200,14 +518,34 @@ fn batch_forward_new_tx(
     batch_request.set_new_tx_requests(RepeatedField::from_slice(&new_tx_request_buffer[..]));
 
     let request_id = Uuid::new_v4().as_bytes().to_vec();
+    // Hashed before locking, `GET /batch_position` waits on the lock meanwhile
+    let tx_hashes: Vec<H256> = new_tx_request_buffer
+        .iter()
+        .map(|tx_req| tx_req.get_un_tx().tx_verify_req_msg().get_tx_hash().into())
+        .collect();
+    {
+        let mut positions = batch_positions.lock();
+        for (index, tx_hash) in tx_hashes.into_iter().enumerate() {
+            positions.record(tx_hash, &request_id, index);
+        }
+    }
     let mut request = reqlib::Request::new();
     request.set_batch_req(batch_request);
     request.set_request_id(request_id);
 
     let data: Message = request.into();
-    tx_pub
+    if tx_pub
         .send((String::from(TOPIC_NEW_TX_BATCH), data.try_into().unwrap()))
-        .unwrap();
+        .is_err()
+    {
+        error!("pubsub is disconnected, {} new tx are dropped", new_tx_request_buffer.len());
+        for tx_req in new_tx_request_buffer.iter() {
+            fail_request(responses, &tx_req.request_id);
+        }
+        new_tx_stats.record_dropped(new_tx_request_buffer.len());
+    } else {
+        new_tx_stats.record_flush(new_tx_request_buffer.len());
+    }
     *time_stamp = SystemTime::now();
     new_tx_request_buffer.clear();
 }
@@ -216,24 +554,68 @@ fn forward_service(
     topic: String,
     req: reqlib::Request,
     new_tx_request_buffer: &mut Vec<reqlib::Request>,
+    new_tx_hashes: &mut HashSet<H256>,
     time_stamp: &mut SystemTime,
     tx_pub: &Sender<(String, Vec<u8>)>,
     config: &NewTxFlowConfig,
+    batch_positions: &Mutex<BatchPositions>,
+    new_tx_stats: &NewTxStats,
+    responses: &RpcMap,
 ) {
     if topic.as_str() != TOPIC_NEW_TX {
+        let request_id = req.request_id.clone();
         let data: Message = req.into();
-        tx_pub.send((topic, data.try_into().unwrap())).unwrap();
+        if tx_pub.send((topic, data.try_into().unwrap())).is_err() {
+            error!("pubsub is disconnected, request is dropped");
+            fail_request(responses, &request_id);
+        }
+    } else if !config.batch_enable.unwrap_or(true) {
+        // Auth verifies a tx alone as it does one from a peer
+        let request_id = req.request_id.clone();
+        let data: Message = req.into();
+        if tx_pub.send((topic, data.try_into().unwrap())).is_err() {
+            error!("pubsub is disconnected, new tx is dropped");
+            fail_request(responses, &request_id);
+            new_tx_stats.record_dropped(1);
+        } else {
+            new_tx_stats.record_flush(1);
+        }
     } else {
+        if config.dedup_new_txs.unwrap_or(true) {
+            // The hashes of a flushed buffer are forgotten with it
+            if new_tx_request_buffer.is_empty() {
+                new_tx_hashes.clear();
+            }
+            let tx_hash: H256 = req.get_un_tx().tx_verify_req_msg().get_tx_hash().into();
+            if !new_tx_hashes.insert(tx_hash) {
+                trace!("new tx {:?} is buffered already", tx_hash);
+                // The same as auth answers a tx it has already
+                fail_request_with(
+                    responses,
+                    &req.request_id,
+                    Error::server_error(ErrorCode::tx_auth_error(), "Dup"),
+                );
+                return;
+            }
+        }
         new_tx_request_buffer.push(req);
+        new_tx_stats.set_buffered(new_tx_request_buffer.len());
         trace!(
             "New tx is pushed and has {} new tx and buffer time cost is {:?}",
             new_tx_request_buffer.len(),
             time_stamp.elapsed().unwrap()
         );
         if new_tx_request_buffer.len() > config.count_per_batch
-            || time_stamp.elapsed().unwrap().subsec_nanos() > config.buffer_duration
+            || time_stamp.elapsed().unwrap() > Duration::new(0, config.buffer_duration)
         {
-            batch_forward_new_tx(new_tx_request_buffer, time_stamp, tx_pub);
+            batch_forward_new_tx(
+                new_tx_request_buffer,
+                time_stamp,
+                tx_pub,
+                batch_positions,
+                new_tx_stats,
+                responses,
+            );
         }
     }
 }
@@ -254,3 +636,310 @@ fn start_profile(config: &ProfileConfig) {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helper::{ReqInfo, TransferType};
+    use jsonrpc_types::Id;
+    use jsonrpc_types::bytes::Bytes;
+    use jsonrpc_types::response::Output;
+    use libproto::blockchain::{Transaction, UnverifiedTransaction};
+    use std::convert::TryFrom;
+
+    fn new_tx_request(nonce: usize) -> reqlib::Request {
+        let mut tx = Transaction::new();
+        tx.set_nonce(format!("{}", nonce));
+        let mut un_tx = UnverifiedTransaction::new();
+        un_tx.set_transaction(tx);
+        let mut request = reqlib::Request::new();
+        request.set_un_tx(un_tx);
+        request.set_request_id(vec![nonce as u8]);
+        request
+    }
+
+    #[test]
+    fn txs_flushed_together_share_batch() {
+        let (tx_pub, rx_pub) = channel();
+        let config = NewTxFlowConfig {
+            count_per_batch: 2,
+            buffer_duration: 1_000_000_000,
+            ..Default::default()
+        };
+        let batch_positions = Mutex::new(BatchPositions::new(10));
+        let new_tx_stats = NewTxStats::new();
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
+        let mut time_stamp = SystemTime::now();
+
+        let requests: Vec<reqlib::Request> = (0..3).map(new_tx_request).collect();
+        for req in requests.iter() {
+            forward_service(
+                String::from(TOPIC_NEW_TX),
+                req.clone(),
+                &mut new_tx_request_buffer,
+                &mut new_tx_hashes,
+                &mut time_stamp,
+                &tx_pub,
+                &config,
+                &batch_positions,
+                &new_tx_stats,
+                &responses,
+            );
+        }
+        assert!(new_tx_request_buffer.is_empty());
+        assert_eq!(new_tx_stats.forwarded_txs(), 3);
+        assert_eq!(new_tx_stats.flushed_batches(), 1);
+        assert_eq!(new_tx_stats.throughput(60).forwarded_txs, 3);
+
+        let (topic, data) = rx_pub.try_recv().unwrap();
+        assert_eq!(topic, TOPIC_NEW_TX_BATCH);
+        let mut msg = Message::try_from(&data[..]).unwrap();
+        let batch_id = match msg.take_content() {
+            libproto::MsgClass::Request(request) => request.get_request_id().to_vec(),
+            _ => panic!("expect a batch request"),
+        };
+
+        let positions = batch_positions.lock();
+        for (index, req) in requests.iter().enumerate() {
+            let tx_hash: H256 = req.get_un_tx().tx_verify_req_msg().get_tx_hash().into();
+            let position = positions.position(&tx_hash).unwrap();
+            assert_eq!(position.batch_id, Bytes::from(batch_id.clone()));
+            assert_eq!(position.index, index);
+        }
+    }
+
+    #[test]
+    fn tx_buffered_twice_is_forwarded_once() {
+        let (tx_pub, rx_pub) = channel();
+        let config = NewTxFlowConfig {
+            count_per_batch: 10,
+            buffer_duration: 1_000_000_000,
+            ..Default::default()
+        };
+        let batch_positions = Mutex::new(BatchPositions::new(10));
+        let new_tx_stats = NewTxStats::new();
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
+        let mut time_stamp = SystemTime::now();
+
+        let mut resubmitted = new_tx_request(1);
+        resubmitted.set_request_id(vec![2]);
+        let (tx, mut rx) = oneshot::channel();
+        let req_info = ReqInfo::new(None, Id::Num(2));
+        responses.lock().insert(vec![2], TransferType::HTTP((req_info, tx)));
+        for req in vec![new_tx_request(1), resubmitted] {
+            forward_service(
+                String::from(TOPIC_NEW_TX),
+                req,
+                &mut new_tx_request_buffer,
+                &mut new_tx_hashes,
+                &mut time_stamp,
+                &tx_pub,
+                &config,
+                &batch_positions,
+                &new_tx_stats,
+                &responses,
+            );
+        }
+        match rx.try_recv() {
+            Ok(Some(Output::Failure(failure))) => assert_eq!(failure.error.message, "Dup"),
+            _ => panic!("expect the resubmitted tx to be a Dup"),
+        }
+
+        batch_forward_new_tx(
+            &mut new_tx_request_buffer,
+            &mut time_stamp,
+            &tx_pub,
+            &batch_positions,
+            &new_tx_stats,
+            &responses,
+        );
+        let (_, data) = rx_pub.try_recv().unwrap();
+        let mut msg = Message::try_from(&data[..]).unwrap();
+        match msg.take_content() {
+            libproto::MsgClass::Request(mut request) => {
+                let batch = request.take_batch_req();
+                assert_eq!(batch.get_new_tx_requests().len(), 1);
+                assert_eq!(batch.get_new_tx_requests()[0].get_request_id(), &[1][..]);
+            }
+            _ => panic!("expect a batch request"),
+        }
+
+        // Submitted again after the flush, it's left to auth
+        forward_service(
+            String::from(TOPIC_NEW_TX),
+            new_tx_request(1),
+            &mut new_tx_request_buffer,
+            &mut new_tx_hashes,
+            &mut time_stamp,
+            &tx_pub,
+            &config,
+            &batch_positions,
+            &new_tx_stats,
+            &responses,
+        );
+        assert_eq!(new_tx_request_buffer.len(), 1);
+    }
+
+    #[test]
+    fn new_tx_forwarded_alone_when_unbatched() {
+        let (tx_pub, rx_pub) = channel();
+        let config = NewTxFlowConfig {
+            count_per_batch: 10,
+            buffer_duration: 1_000_000_000,
+            batch_enable: Some(false),
+            ..Default::default()
+        };
+        let batch_positions = Mutex::new(BatchPositions::new(10));
+        let new_tx_stats = NewTxStats::new();
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
+        let mut time_stamp = SystemTime::now();
+
+        for nonce in 1..3 {
+            forward_service(
+                String::from(TOPIC_NEW_TX),
+                new_tx_request(nonce),
+                &mut new_tx_request_buffer,
+                &mut new_tx_hashes,
+                &mut time_stamp,
+                &tx_pub,
+                &config,
+                &batch_positions,
+                &new_tx_stats,
+                &responses,
+            );
+            assert!(new_tx_request_buffer.is_empty());
+
+            let (topic, data) = rx_pub.try_recv().unwrap();
+            assert_eq!(topic, TOPIC_NEW_TX);
+            let mut msg = Message::try_from(&data[..]).unwrap();
+            match msg.take_content() {
+                libproto::MsgClass::Request(request) => {
+                    assert!(request.has_un_tx());
+                    assert_eq!(request.get_request_id(), &[nonce as u8][..]);
+                }
+                _ => panic!("expect a new tx request"),
+            }
+        }
+        assert!(rx_pub.try_recv().is_err());
+        assert_eq!(new_tx_stats.forwarded_txs(), 2);
+    }
+
+    #[test]
+    fn txs_flushed_after_buffer_duration() {
+        let (tx_pub, rx_pub) = channel();
+        let config = NewTxFlowConfig {
+            count_per_batch: 10,
+            buffer_duration: 500_000_000,
+            ..Default::default()
+        };
+        let batch_positions = Mutex::new(BatchPositions::new(10));
+        let new_tx_stats = NewTxStats::new();
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
+
+        // Just buffered, the tx waits for more
+        let mut time_stamp = SystemTime::now();
+        forward_service(
+            String::from(TOPIC_NEW_TX),
+            new_tx_request(1),
+            &mut new_tx_request_buffer,
+            &mut new_tx_hashes,
+            &mut time_stamp,
+            &tx_pub,
+            &config,
+            &batch_positions,
+            &new_tx_stats,
+            &responses,
+        );
+        assert_eq!(new_tx_request_buffer.len(), 1);
+        assert!(rx_pub.try_recv().is_err());
+
+        // Buffered for whole seconds, the subsecond part alone is below the duration
+        time_stamp = SystemTime::now() - Duration::new(2, 100_000_000);
+        forward_service(
+            String::from(TOPIC_NEW_TX),
+            new_tx_request(2),
+            &mut new_tx_request_buffer,
+            &mut new_tx_hashes,
+            &mut time_stamp,
+            &tx_pub,
+            &config,
+            &batch_positions,
+            &new_tx_stats,
+            &responses,
+        );
+        assert!(new_tx_request_buffer.is_empty());
+        let (topic, _) = rx_pub.try_recv().unwrap();
+        assert_eq!(topic, TOPIC_NEW_TX_BATCH);
+        assert_eq!(new_tx_stats.forwarded_txs(), 2);
+    }
+
+    #[test]
+    fn flush_wait_ends_when_buffer_is_due() {
+        let buffer_duration = Duration::from_millis(500);
+        let time_stamp = SystemTime::now() - Duration::from_millis(200);
+        assert_eq!(flush_wait(&[], &time_stamp, buffer_duration), buffer_duration);
+
+        let buffer = vec![new_tx_request(1)];
+        let wait = flush_wait(&buffer, &time_stamp, buffer_duration);
+        assert!(wait <= Duration::from_millis(300));
+        assert!(wait > Duration::from_millis(200));
+
+        let time_stamp = SystemTime::now() - Duration::from_secs(1);
+        assert_eq!(flush_wait(&buffer, &time_stamp, buffer_duration), Duration::new(0, 0));
+    }
+
+    #[test]
+    fn requests_fail_when_pubsub_is_gone() {
+        let (tx_pub, rx_pub) = channel();
+        drop(rx_pub);
+        let config = NewTxFlowConfig {
+            count_per_batch: 0,
+            buffer_duration: 1_000_000_000,
+            ..Default::default()
+        };
+        let batch_positions = Mutex::new(BatchPositions::new(10));
+        let new_tx_stats = NewTxStats::new();
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut new_tx_request_buffer = Vec::new();
+        let mut new_tx_hashes = HashSet::new();
+        let mut time_stamp = SystemTime::now();
+
+        for (nonce, topic) in vec![(1, TOPIC_NEW_TX), (2, "jsonrpc.request")] {
+            let (tx, mut rx) = oneshot::channel();
+            let req_info = ReqInfo::new(None, Id::Num(nonce as u64));
+            responses
+                .lock()
+                .insert(vec![nonce as u8], TransferType::HTTP((req_info, tx)));
+
+            forward_service(
+                String::from(topic),
+                new_tx_request(nonce),
+                &mut new_tx_request_buffer,
+                &mut new_tx_hashes,
+                &mut time_stamp,
+                &tx_pub,
+                &config,
+                &batch_positions,
+                &new_tx_stats,
+                &responses,
+            );
+            match rx.try_recv() {
+                Ok(Some(Output::Failure(failure))) => assert_eq!(failure.error.code.code(), -32_603),
+                _ => panic!("expect an internal error for {}", topic),
+            }
+        }
+        assert!(responses.lock().is_empty());
+        // The failed new tx isn't counted as forwarded
+        assert_eq!(new_tx_stats.forwarded_txs(), 0);
+        assert_eq!(new_tx_stats.flushed_batches(), 0);
+        assert_eq!(new_tx_stats.dropped_txs(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use util::Mutex;
+
+/// Longest window `GET /throughput` can be asked about
+pub const MAX_THROUGHPUT_WINDOW: u64 = 3600;
+
+/// How the new tx buffer of the dispatch thread is doing, served by `GET /metrics`.
+#[derive(Debug)]
+pub struct NewTxStats {
+    buffered: AtomicUsize,
+    flushed_at: Mutex<Instant>,
+    forwarded_txs: AtomicUsize,
+    flushed_batches: AtomicUsize,
+    /// New txs which couldn't be sent to auth and were failed
+    dropped_txs: AtomicUsize,
+    started_at: Instant,
+    /// Txs forwarded in each of the recent seconds since `started_at`, oldest first
+    recent: Mutex<VecDeque<(u64, usize)>>,
+}
+
+/// Txs forwarded over the last `seconds`, served by `GET /throughput`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Throughput {
+    pub seconds: u64,
+    #[serde(rename = "forwardedTxs")]
+    pub forwarded_txs: usize,
+    #[serde(rename = "txsPerSecond")]
+    pub txs_per_second: f64,
+}
+
+impl Default for NewTxStats {
+    fn default() -> Self {
+        NewTxStats {
+            buffered: AtomicUsize::new(0),
+            flushed_at: Mutex::new(Instant::now()),
+            forwarded_txs: AtomicUsize::new(0),
+            flushed_batches: AtomicUsize::new(0),
+            dropped_txs: AtomicUsize::new(0),
+            started_at: Instant::now(),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl NewTxStats {
+    pub fn new() -> Self {
+        NewTxStats::default()
+    }
+
+    pub fn set_buffered(&self, len: usize) {
+        self.buffered.store(len, Ordering::Relaxed);
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    /// A batch of `txs` new txs was sent to auth and the buffer emptied
+    pub fn record_flush(&self, txs: usize) {
+        self.forwarded_txs.fetch_add(txs, Ordering::Relaxed);
+        self.flushed_batches.fetch_add(1, Ordering::Relaxed);
+        self.buffered.store(0, Ordering::Relaxed);
+        *self.flushed_at.lock() = Instant::now();
+        self.record_forwarded_at(txs, Instant::now());
+    }
+
+    /// A batch of `txs` new txs couldn't be sent to auth, the buffer is emptied all the same
+    pub fn record_dropped(&self, txs: usize) {
+        self.dropped_txs.fetch_add(txs, Ordering::Relaxed);
+        self.buffered.store(0, Ordering::Relaxed);
+        *self.flushed_at.lock() = Instant::now();
+    }
+
+    fn record_forwarded_at(&self, txs: usize, now: Instant) {
+        let second = self.second_of(now);
+        let mut recent = self.recent.lock();
+        let counted = match recent.back_mut() {
+            Some(&mut (last, ref mut cnt)) if last == second => {
+                *cnt += txs;
+                true
+            }
+            _ => false,
+        };
+        if !counted {
+            recent.push_back((second, txs));
+        }
+        while recent
+            .front()
+            .map_or(false, |&(first, _)| first + MAX_THROUGHPUT_WINDOW <= second)
+        {
+            recent.pop_front();
+        }
+    }
+
+    fn second_of(&self, now: Instant) -> u64 {
+        now.duration_since(self.started_at).as_secs()
+    }
+
+    /// Txs forwarded over the last `seconds`, the current one included
+    pub fn throughput(&self, seconds: u64) -> Throughput {
+        self.throughput_at(seconds, Instant::now())
+    }
+
+    fn throughput_at(&self, seconds: u64, now: Instant) -> Throughput {
+        let seconds = ::std::cmp::min(::std::cmp::max(seconds, 1), MAX_THROUGHPUT_WINDOW);
+        let second = self.second_of(now);
+        let forwarded_txs: usize = self.recent
+            .lock()
+            .iter()
+            .rev()
+            .take_while(|&&(at, _)| at + seconds > second)
+            .map(|&(_, cnt)| cnt)
+            .sum();
+        Throughput {
+            seconds: seconds,
+            forwarded_txs: forwarded_txs,
+            txs_per_second: forwarded_txs as f64 / seconds as f64,
+        }
+    }
+
+    pub fn forwarded_txs(&self) -> usize {
+        self.forwarded_txs.load(Ordering::Relaxed)
+    }
+
+    pub fn flushed_batches(&self) -> usize {
+        self.flushed_batches.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_txs(&self) -> usize {
+        self.dropped_txs.load(Ordering::Relaxed)
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let since_flush = self.flushed_at.lock().elapsed();
+        let since_flush = since_flush.as_secs() as f64 + f64::from(since_flush.subsec_nanos()) / 1_000_000_000f64;
+        let mut out = String::new();
+        let metrics = vec![
+            (
+                "jsonrpc_new_tx_buffered",
+                "gauge",
+                "New txs waiting in the buffer",
+                self.buffered().to_string(),
+            ),
+            (
+                "jsonrpc_new_tx_buffer_age_seconds",
+                "gauge",
+                "Seconds since the buffer was last flushed",
+                since_flush.to_string(),
+            ),
+            (
+                "jsonrpc_new_tx_forwarded_total",
+                "counter",
+                "New txs forwarded to auth",
+                self.forwarded_txs().to_string(),
+            ),
+            (
+                "jsonrpc_new_tx_batches_total",
+                "counter",
+                "Batches of new txs flushed to auth",
+                self.flushed_batches().to_string(),
+            ),
+            (
+                "jsonrpc_new_tx_dropped_total",
+                "counter",
+                "New txs failed as they couldn't be sent to auth",
+                self.dropped_txs().to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn render_new_tx_stats() {
+        let stats = NewTxStats::new();
+        stats.set_buffered(3);
+        assert!(stats.render().contains("\njsonrpc_new_tx_buffered 3\n"));
+
+        stats.record_flush(3);
+        let text = stats.render();
+        assert!(text.contains("\njsonrpc_new_tx_buffered 0\n"));
+        assert!(text.contains("\njsonrpc_new_tx_forwarded_total 3\n"));
+        assert!(text.contains("\njsonrpc_new_tx_batches_total 1\n"));
+        assert!(text.contains("\njsonrpc_new_tx_dropped_total 0\n"));
+        assert!(text.contains("# TYPE jsonrpc_new_tx_buffer_age_seconds gauge\n"));
+
+        stats.set_buffered(2);
+        stats.record_dropped(2);
+        let text = stats.render();
+        assert!(text.contains("\njsonrpc_new_tx_buffered 0\n"));
+        assert!(text.contains("\njsonrpc_new_tx_forwarded_total 3\n"));
+        assert!(text.contains("\njsonrpc_new_tx_batches_total 1\n"));
+        assert!(text.contains("\njsonrpc_new_tx_dropped_total 2\n"));
+    }
+
+    #[test]
+    fn throughput_over_window() {
+        let stats = NewTxStats::new();
+        let at = |secs| stats.started_at + Duration::from_secs(secs);
+        stats.record_forwarded_at(10, at(0));
+        stats.record_forwarded_at(5, at(30));
+        stats.record_forwarded_at(3, at(30));
+        stats.record_forwarded_at(2, at(59));
+
+        assert_eq!(
+            stats.throughput_at(60, at(59)),
+            Throughput {
+                seconds: 60,
+                forwarded_txs: 20,
+                txs_per_second: 20f64 / 60f64,
+            }
+        );
+        assert_eq!(stats.throughput_at(30, at(59)).forwarded_txs, 10);
+        assert_eq!(stats.throughput_at(29, at(59)).forwarded_txs, 2);
+        assert_eq!(stats.throughput_at(10, at(100)).forwarded_txs, 0);
+        assert_eq!(stats.throughput_at(0, at(59)).seconds, 1);
+
+        // Seconds out of the longest window are dropped
+        stats.record_forwarded_at(1, at(MAX_THROUGHPUT_WINDOW + 30));
+        assert_eq!(stats.recent.lock().len(), 2);
+        assert_eq!(
+            stats
+                .throughput_at(MAX_THROUGHPUT_WINDOW, at(MAX_THROUGHPUT_WINDOW + 30))
+                .forwarded_txs,
+            3
+        );
+    }
+}
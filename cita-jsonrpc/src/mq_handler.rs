@@ -15,21 +15,48 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use helper::{RpcMap, TransferType};
-use jsonrpc_types::response::Output;
+use batch_position::BatchPositions;
+use helper::{answer_request, RpcMap, TransferType};
+use idempotency::IdempotencyKeys;
+use jsonrpc_types::method::MethodHandler;
+use jsonrpc_types::response::{Output, RpcFailure};
+use jsonrpc_types::rpctypes::{ChainStats, TxResponse};
 use libproto::{Message, MsgClass};
+use libproto::request as reqlib;
+use libproto::response::Response;
+use metrics::NewTxStats;
 use serde_json;
 use std::convert::TryFrom;
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::TrySendError;
+use subscription::{too_many_subscriptions, Subscriptions};
+use util::{Mutex, U256};
 
-#[derive(Default)]
 pub struct MqHandler {
     responses: RpcMap,
+    batch_positions: Arc<Mutex<BatchPositions>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    tx: mpsc::SyncSender<(String, reqlib::Request)>,
+    new_tx_stats: Arc<NewTxStats>,
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
 }
 
 impl MqHandler {
-    pub fn new(responses: RpcMap) -> Self {
+    pub fn new(
+        responses: RpcMap,
+        batch_positions: Arc<Mutex<BatchPositions>>,
+        subscriptions: Arc<Mutex<Subscriptions>>,
+        tx: mpsc::SyncSender<(String, reqlib::Request)>,
+        new_tx_stats: Arc<NewTxStats>,
+        idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    ) -> Self {
         MqHandler {
             responses: responses,
+            batch_positions: batch_positions,
+            subscriptions: subscriptions,
+            tx: tx,
+            new_tx_stats: new_tx_stats,
+            idempotency_keys: idempotency_keys,
         }
     }
 
@@ -39,8 +66,32 @@ impl MqHandler {
         trace!("get msg from routint_key {}", key);
 
         match content_ext {
-            MsgClass::Response(content) => {
+            MsgClass::Response(ref content) if key == "chain.subscription" => {
+                self.notify_subscription(content);
+            }
+            MsgClass::Response(mut content) => {
                 trace!("from response request_id {:?}", content.request_id);
+                if content.has_chain_stats() {
+                    fill_pending_txs(&mut content, self.new_tx_stats.buffered());
+                }
+                if content.has_tx_state() {
+                    if let Ok(tx_response) = serde_json::from_str::<TxResponse>(content.get_tx_state()) {
+                        trace!(
+                            "tx {:?} was forwarded at {:?}",
+                            tx_response.hash,
+                            self.batch_positions.lock().position(&tx_response.hash)
+                        );
+                    }
+                }
+                // Resubmissions with the idempotency key of this request get the same answer
+                let followers = {
+                    self.idempotency_keys
+                        .lock()
+                        .complete(&content.request_id, &content)
+                };
+                for follower in followers {
+                    answer_request(&self.responses, &follower, content.clone());
+                }
                 let value = { self.responses.lock().remove(&content.request_id) };
                 if let Some(val) = value {
                     match val {
@@ -52,6 +103,28 @@ impl MqHandler {
                                 serde_json::to_string(&Output::from(content, req_info.id, req_info.jsonrpc)).unwrap(),
                             );
                         }
+                        TransferType::SUBSCRIPTION((req_info, sender)) => {
+                            let id = content.get_filter_id();
+                            let sink = sender.clone();
+                            let subscribed = !content.has_filter_id()
+                                || self.subscriptions.lock().subscribe(
+                                    id,
+                                    sender.token().0,
+                                    Box::new(move |msg| sink.send(msg).is_ok()),
+                                );
+                            let output = if subscribed {
+                                serde_json::to_string(&Output::from(content, req_info.id, req_info.jsonrpc))
+                            } else {
+                                // Other subscriptions were confirmed while this one was in flight
+                                uninstall_filter(&self.tx, id);
+                                serde_json::to_string(&RpcFailure::from_options(
+                                    req_info.id,
+                                    req_info.jsonrpc,
+                                    too_many_subscriptions(),
+                                ))
+                            };
+                            let _ = sender.send(output.unwrap());
+                        }
                     }
                 } else {
                     warn!("receive lost request_id {:?}", content.request_id);
@@ -62,4 +135,120 @@ impl MqHandler {
             }
         }
     }
+
+    fn notify_subscription(&self, content: &Response) {
+        let id = content.get_subscription();
+        let keep = serde_json::from_str(content.get_filter_changes())
+            .map(|changes| self.subscriptions.lock().notify(id, changes))
+            .unwrap_or(true);
+        if !keep {
+            uninstall_filter(&self.tx, id);
+        }
+    }
+}
+
+/// Chain can't see the new txs buffered here, so add them to its stats
+fn fill_pending_txs(content: &mut Response, buffered: usize) {
+    if let Ok(mut stats) = serde_json::from_str::<ChainStats>(content.get_chain_stats()) {
+        stats.pending_transactions = U256::from(buffered as u64);
+        content.set_chain_stats(serde_json::to_string(&stats).unwrap());
+    }
+}
+
+/// Ask chain to drop the filter of a subscription nobody listens to any more.
+/// Never blocks on a full relay queue, the filter is left to expire then.
+pub fn uninstall_filter(tx: &mpsc::SyncSender<(String, reqlib::Request)>, id: u64) {
+    let mut request = MethodHandler.create_request();
+    request.set_uninstall_filter(id);
+    if let Err(TrySendError::Full(_)) = tx.try_send(("jsonrpc.request".to_string(), request)) {
+        warn!("relay queue is full, filter {} is left to expire", id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use futures::sync::oneshot;
+    use helper::{relay_request, DuplicateRequestId, ReqInfo};
+    use jsonrpc_types::Id;
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fill_pending_txs() {
+        let stats = ChainStats {
+            height: U256::from(16),
+            total_transactions: U256::from(100),
+            average_block_time: Some(U256::from(3000)),
+            pending_transactions: U256::zero(),
+        };
+        let mut content = Response::new();
+        content.set_chain_stats(serde_json::to_string(&stats).unwrap());
+
+        let new_tx_stats = NewTxStats::new();
+        new_tx_stats.set_buffered(7);
+        fill_pending_txs(&mut content, new_tx_stats.buffered());
+
+        let filled: ChainStats = serde_json::from_str(content.get_chain_stats()).unwrap();
+        assert_eq!(filled.pending_transactions, U256::from(7));
+        assert_eq!(filled.height, stats.height);
+        assert_eq!(filled.total_transactions, stats.total_transactions);
+        assert_eq!(filled.average_block_time, stats.average_block_time);
+    }
+
+    #[test]
+    fn test_resubmit_with_idempotency_key() {
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::sync_channel(16);
+        let keys = Arc::new(Mutex::new(IdempotencyKeys::new(
+            Duration::from_secs(600),
+            Duration::from_secs(10),
+            16,
+        )));
+        let mut handler = MqHandler::new(
+            Arc::clone(&responses),
+            Arc::new(Mutex::new(BatchPositions::new(16))),
+            Arc::new(Mutex::new(Subscriptions::new(16))),
+            tx.clone(),
+            Arc::new(NewTxStats::new()),
+            Arc::clone(&keys),
+        );
+        let submit = || {
+            let (output_tx, output_rx) = oneshot::channel();
+            let req_info = ReqInfo::new(None, Id::Num(1));
+            relay_request(
+                &responses,
+                &tx,
+                "jsonrpc.new_tx".to_owned(),
+                MethodHandler.create_request(),
+                TransferType::HTTP((req_info, output_tx)),
+                Some((&*keys, "retry-1".to_owned())),
+                DuplicateRequestId::Reject,
+            );
+            output_rx
+        };
+
+        let first = submit();
+        let second = submit();
+        let (_, forwarded) = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let mut content = Response::new();
+        content.set_request_id(forwarded.request_id.clone());
+        content.set_tx_state(r#"{"status":"OK","hash":"0x01"}"#.to_owned());
+        let msg: Message = content.into();
+        let msg_bytes: Vec<u8> = msg.try_into().unwrap();
+        handler.handle("auth.rpc", &msg_bytes);
+
+        // Resubmitted once answered, it's answered right away
+        let third = submit();
+        assert!(rx.try_recv().is_err());
+
+        let first = serde_json::to_string(&first.wait().unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&second.wait().unwrap()).unwrap(), first);
+        assert_eq!(serde_json::to_string(&third.wait().unwrap()).unwrap(), first);
+        assert!(responses.lock().is_empty());
+    }
 }
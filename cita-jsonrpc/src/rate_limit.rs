@@ -0,0 +1,237 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use TOPIC_NEW_TX;
+use config::NewTxFlowConfig;
+use helper::select_topic;
+use jsonrpc_types::Error;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use util::Mutex;
+
+/// Error code returned to clients sending new txs faster than allowed
+pub const RATE_LIMITED_ERROR: i64 = -32_005;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token buckets of new tx submissions keyed by client IP. Each bucket holds
+/// up to `burst` tokens and gets `rate` tokens back every `window`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    window: Duration,
+    buckets: HashMap<IpAddr, Bucket>,
+    swept_at: Instant,
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000f64
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32, window: Duration) -> Self {
+        RateLimiter {
+            rate: f64::from(rate),
+            burst: f64::from(::std::cmp::max(burst, 1)),
+            window: window,
+            buckets: HashMap::new(),
+            swept_at: Instant::now(),
+        }
+    }
+
+    /// Built from the new tx flow config, or None if per IP limiting is off.
+    /// A rate of 0 turns it off too, buckets which never refill would never be swept.
+    pub fn from_config(config: &NewTxFlowConfig) -> Option<Self> {
+        config.per_ip_rate.and_then(|rate| {
+            if rate == 0 {
+                return None;
+            }
+            let burst = config.per_ip_burst.unwrap_or(rate);
+            let window = Duration::from_secs(::std::cmp::max(config.per_ip_window.unwrap_or(1), 1));
+            Some(RateLimiter::new(rate, burst, window))
+        })
+    }
+
+    /// Take a token for `ip`, returns false if its bucket is empty
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        self.allow_at(ip, Instant::now())
+    }
+
+    /// Take `cost` tokens for `ip` at once, or none if its bucket holds fewer
+    pub fn allow_n(&mut self, ip: IpAddr, cost: usize) -> bool {
+        self.allow_n_at(ip, cost, Instant::now())
+    }
+
+    fn allow_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        self.allow_n_at(ip, 1, now)
+    }
+
+    fn allow_n_at(&mut self, ip: IpAddr, cost: usize, now: Instant) -> bool {
+        if now.duration_since(self.swept_at) >= self.window {
+            self.sweep(now);
+        }
+        let (rate, burst, window) = (self.rate, self.burst, as_secs_f64(self.window));
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        let refill = as_secs_f64(now.duration_since(bucket.refilled_at)) / window * rate;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.refilled_at = now;
+        let cost = cost as f64;
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drop buckets which have refilled completely, they are the same as
+    /// a new bucket so IPs that went quiet don't pile up.
+    fn sweep(&mut self, now: Instant) {
+        let (rate, burst, window) = (self.rate, self.burst, as_secs_f64(self.window));
+        self.buckets.retain(|_, bucket| {
+            let refill = as_secs_f64(now.duration_since(bucket.refilled_at)) / window * rate;
+            bucket.tokens + refill < burst
+        });
+        self.swept_at = now;
+    }
+
+    pub fn tracked_ips(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+/// Whether a call from `ip` has to be refused, only new txs are limited
+pub fn over_limit(limiter: &Option<Arc<Mutex<RateLimiter>>>, ip: Option<IpAddr>, method: &str) -> bool {
+    match (limiter.as_ref(), ip) {
+        (Some(limiter), Some(ip)) if select_topic(method) == TOPIC_NEW_TX => !limiter.lock().allow(ip),
+        _ => false,
+    }
+}
+
+/// Whether a batch from `ip` calling `methods` has to be refused. Its new txs are
+/// paid for together, so a refused batch takes no token.
+pub fn batch_over_limit<'a, I>(limiter: &Option<Arc<Mutex<RateLimiter>>>, ip: Option<IpAddr>, methods: I) -> bool
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    match (limiter.as_ref(), ip) {
+        (Some(limiter), Some(ip)) => {
+            let cost = methods
+                .into_iter()
+                .filter(|method| select_topic(method) == TOPIC_NEW_TX)
+                .count();
+            cost > 0 && !limiter.lock().allow_n(ip, cost)
+        }
+        _ => false,
+    }
+}
+
+pub fn rate_limited_error() -> Error {
+    Error::server_error(RATE_LIMITED_ERROR, "too many new transactions, please retry later")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn limit_per_ip() {
+        let mut limiter = RateLimiter::new(2, 3, Duration::from_secs(1));
+        let flooder: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.allow_at(flooder, now));
+        }
+        assert!(!limiter.allow_at(flooder, now));
+        // Another client isn't starved by the flooder
+        assert!(limiter.allow_at(other, now));
+
+        // Half a window gives one token back
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.allow_at(flooder, later));
+        assert!(!limiter.allow_at(flooder, later));
+    }
+
+    #[test]
+    fn only_new_txs_are_limited() {
+        let limiter = Some(Arc::new(Mutex::new(RateLimiter::new(1, 1, Duration::from_secs(60)))));
+        let ip = Some("10.0.0.1".parse().unwrap());
+        assert!(!over_limit(&limiter, ip, "cita_sendTransaction"));
+        assert!(over_limit(&limiter, ip, "cita_sendTransaction"));
+        assert!(!over_limit(&limiter, ip, "cita_blockNumber"));
+        // Without a known address or a limiter nothing is refused
+        assert!(!over_limit(&limiter, None, "cita_sendTransaction"));
+        assert!(!over_limit(&None, ip, "cita_sendTransaction"));
+    }
+
+    #[test]
+    fn refused_batch_takes_no_token() {
+        let limiter = Some(Arc::new(Mutex::new(RateLimiter::new(1, 3, Duration::from_secs(60)))));
+        let ip = Some("10.0.0.1".parse().unwrap());
+        let batch = vec!["cita_sendTransaction", "cita_blockNumber", "cita_sendTransaction"];
+        assert!(!batch_over_limit(&limiter, ip, batch.clone()));
+        // One token left, the next batch needs two
+        assert!(batch_over_limit(&limiter, ip, batch.clone()));
+        assert!(batch_over_limit(&limiter, ip, batch));
+        assert!(!over_limit(&limiter, ip, "cita_sendTransaction"));
+        assert!(over_limit(&limiter, ip, "cita_sendTransaction"));
+        // A batch without new txs costs nothing
+        assert!(!batch_over_limit(&limiter, ip, vec!["cita_blockNumber"]));
+    }
+
+    #[test]
+    fn zero_rate_is_off() {
+        let config = |per_ip_rate: &str| {
+            let json = format!(r#"{{"count_per_batch":1,"buffer_duration":1,"per_ip_rate":{}}}"#, per_ip_rate);
+            serde_json::from_str::<NewTxFlowConfig>(&json).unwrap()
+        };
+        assert!(RateLimiter::from_config(&config("null")).is_none());
+        assert!(RateLimiter::from_config(&config("0")).is_none());
+        assert!(RateLimiter::from_config(&config("1")).is_some());
+    }
+
+    #[test]
+    fn evict_idle_buckets() {
+        let mut limiter = RateLimiter::new(1, 2, Duration::from_secs(1));
+        let now = Instant::now();
+        for i in 0..100 {
+            let ip: IpAddr = format!("10.0.1.{}", i).parse().unwrap();
+            assert!(limiter.allow_at(ip, now));
+        }
+        assert_eq!(limiter.tracked_ips(), 100);
+
+        let busy: IpAddr = "10.0.2.1".parse().unwrap();
+        assert!(limiter.allow_at(busy, now));
+        assert!(limiter.allow_at(busy, now));
+        // Everyone but the busy client has refilled after a window
+        assert!(limiter.allow_at(busy, now + Duration::from_secs(1)));
+        assert_eq!(limiter.tracked_ips(), 1);
+    }
+}
@@ -2,7 +2,8 @@ use futures::{Async, Poll};
 use futures::future::Future;
 use futures::stream::{Collect, FuturesOrdered};
 use futures::sync::oneshot;
-use hyper;
+use helper::SERVER_BUSY_ERROR;
+use hyper::{self, StatusCode};
 use hyper::header::Headers;
 use hyper::server::Response;
 use jsonrpc_types::response::Output;
@@ -35,7 +36,15 @@ impl Future for SingleFutureResponse {
         };
 
         e.map(|resp_body| {
+            // A request shed for overload, 503 lets proxies and clients back off
+            let status = match resp_body {
+                Output::Failure(ref failure) if failure.error.code.code() == SERVER_BUSY_ERROR => {
+                    StatusCode::ServiceUnavailable
+                }
+                _ => StatusCode::Ok,
+            };
             Response::new()
+                .with_status(status)
                 .with_headers(
                     self.headers
                         .take()
@@ -0,0 +1,412 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use jsonrpc_types::Error;
+use jsonrpc_types::rpctypes::FilterChanges;
+use serde::Serialize;
+use serde_json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use threadpool::ThreadPool;
+use util::{Mutex, U256};
+
+/// Notifications queued for a connection before its subscription is dropped as too slow
+pub const DEFAULT_SUBSCRIPTION_QUEUE: usize = 1024;
+
+/// Subscriptions one connection may have if `max_subscriptions` isn't configured
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 16;
+
+/// Threads writing notifications out if `subscription_threads` isn't configured
+pub const DEFAULT_SUBSCRIPTION_THREADS: usize = 2;
+
+#[derive(Serialize)]
+struct Notification<'a, T: 'a + Serialize> {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: NotificationParams<'a, T>,
+}
+
+#[derive(Serialize)]
+struct NotificationParams<'a, T: 'a + Serialize> {
+    subscription: U256,
+    result: &'a T,
+}
+
+/// An `eth_subscription` message carrying one log, or one reverted tx hash, of the subscription `id`
+pub fn notification<T: Serialize>(id: u64, result: &T) -> String {
+    serde_json::to_string(&Notification {
+        jsonrpc: "2.0",
+        method: "eth_subscription",
+        params: NotificationParams {
+            subscription: U256::from(id),
+            result: result,
+        },
+    }).unwrap()
+}
+
+/// Answer to a subscription beyond the cap of its connection
+pub fn too_many_subscriptions() -> Error {
+    Error::invalid_params("too many subscriptions on this connection")
+}
+
+/// Writes one notification to a connection, false once it is closed
+pub type Sink = Box<FnMut(String) -> bool + Send>;
+
+struct Pending {
+    queue: VecDeque<String>,
+    /// Whether a task of the pool is writing the queue out
+    draining: bool,
+}
+
+/// Notifications of one connection, written out on the pool by one task at a time
+/// so they keep their order and a slow connection only holds up its own.
+struct Outbox {
+    pending: Mutex<Pending>,
+    sink: Mutex<Sink>,
+}
+
+impl Outbox {
+    fn drain(&self) {
+        loop {
+            let msg = {
+                let mut pending = self.pending.lock();
+                match pending.queue.pop_front() {
+                    Some(msg) => msg,
+                    None => {
+                        pending.draining = false;
+                        return;
+                    }
+                }
+            };
+            let sent = {
+                let mut sink = self.sink.lock();
+                (&mut **sink)(msg)
+            };
+            if !sent {
+                let mut pending = self.pending.lock();
+                pending.queue.clear();
+                pending.draining = false;
+                return;
+            }
+        }
+    }
+}
+
+/// Log and reverted tx subscriptions keyed by the id of the filter installed in chain for them.
+/// They live as long as the connection which made them, a client reconnecting
+/// has to subscribe again.
+pub struct Subscriptions {
+    /// The connection of each subscription
+    subscribers: HashMap<u64, usize>,
+    outboxes: HashMap<usize, Arc<Outbox>>,
+    /// Notifications of filters which got changes before anyone subscribed to them
+    unclaimed: HashMap<u64, Vec<String>>,
+    queue_size: usize,
+    max_per_connection: usize,
+    pool: ThreadPool,
+}
+
+impl Subscriptions {
+    pub fn new(queue_size: usize, max_per_connection: usize, threads: usize) -> Self {
+        Subscriptions {
+            subscribers: HashMap::new(),
+            outboxes: HashMap::new(),
+            unclaimed: HashMap::new(),
+            queue_size: queue_size,
+            max_per_connection: max_per_connection,
+            pool: ThreadPool::with_name("subscription_pool".to_string(), threads),
+        }
+    }
+
+    /// Whether `connection` may subscribe once more
+    pub fn has_room(&self, connection: usize) -> bool {
+        self.subscribers
+            .values()
+            .filter(|subscriber| **subscriber == connection)
+            .count() < self.max_per_connection
+    }
+
+    /// Subscribe `connection` to the changes of filter `id`, written to `sink`, along with
+    /// those which came before. Returns false if the connection has too many subscriptions.
+    pub fn subscribe(&mut self, id: u64, connection: usize, sink: Sink) -> bool {
+        if !self.has_room(connection) {
+            self.unclaimed.remove(&id);
+            return false;
+        }
+        self.subscribers.insert(id, connection);
+        self.outboxes.entry(connection).or_insert_with(|| {
+            Arc::new(Outbox {
+                pending: Mutex::new(Pending {
+                    queue: VecDeque::new(),
+                    draining: false,
+                }),
+                sink: Mutex::new(sink),
+            })
+        });
+        if let Some(msgs) = self.unclaimed.remove(&id) {
+            self.queue(id, msgs);
+        }
+        true
+    }
+
+    /// Cancel the subscription `id` if it belongs to `connection`
+    pub fn unsubscribe(&mut self, id: u64, connection: usize) -> bool {
+        let owned = self.subscribers.get(&id) == Some(&connection);
+        if owned {
+            self.remove(id);
+        }
+        owned
+    }
+
+    /// Cancel all subscriptions of a closed connection, returning their ids
+    pub fn close_connection(&mut self, connection: usize) -> Vec<u64> {
+        let ids: Vec<u64> = self.subscribers
+            .iter()
+            .filter(|&(_, subscriber)| *subscriber == connection)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            self.subscribers.remove(id);
+        }
+        self.outboxes.remove(&connection);
+        ids
+    }
+
+    /// Queue the changes of filter `id` for its subscriber. Returns false once the filter
+    /// should be uninstalled: its subscriber fell too far behind and has been dropped, or
+    /// nobody has subscribed to it by its second notification.
+    pub fn notify(&mut self, id: u64, changes: FilterChanges) -> bool {
+        let msgs: Vec<String> = match changes {
+            FilterChanges::Logs(logs) => logs.iter().map(|log| notification(id, log)).collect(),
+            FilterChanges::Hashes(hashes) => hashes.iter().map(|hash| notification(id, hash)).collect(),
+            _ => Vec::new(),
+        };
+        if !self.subscribers.contains_key(&id) {
+            // The changes may have been published just before the subscription was confirmed,
+            // they are kept for it
+            if self.unclaimed.remove(&id).is_some() {
+                return false;
+            }
+            let mut msgs = msgs;
+            msgs.truncate(self.queue_size);
+            self.unclaimed.insert(id, msgs);
+            return true;
+        }
+        self.queue(id, msgs)
+    }
+
+    /// Queue `msgs` on the outbox of the subscriber of `id`, dropping it if they overflow
+    fn queue(&mut self, id: u64, msgs: Vec<String>) -> bool {
+        let outbox = match self.subscribers
+            .get(&id)
+            .and_then(|connection| self.outboxes.get(connection))
+        {
+            Some(outbox) => Arc::clone(outbox),
+            None => return false,
+        };
+        let (overflowed, start) = {
+            let mut pending = outbox.pending.lock();
+            let overflowed = pending.queue.len() + msgs.len() > self.queue_size;
+            if !overflowed {
+                pending.queue.extend(msgs);
+            }
+            let start = !overflowed && !pending.draining && !pending.queue.is_empty();
+            if start {
+                pending.draining = true;
+            }
+            (overflowed, start)
+        };
+        if start {
+            self.pool.execute(move || outbox.drain());
+        }
+        if overflowed {
+            warn!("subscription {} can't keep up with its logs, dropping it", id);
+            self.remove(id);
+        }
+        !overflowed
+    }
+
+    /// Forget the subscription `id`, and the outbox of its connection once it has no other
+    fn remove(&mut self, id: u64) {
+        if let Some(connection) = self.subscribers.remove(&id) {
+            if !self.subscribers.values().any(|subscriber| *subscriber == connection) {
+                self.outboxes.remove(&connection);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_types::bytes::Bytes;
+    use jsonrpc_types::rpctypes::Log;
+    use std::sync::mpsc::{channel, Receiver};
+    use util::{H160, H256};
+
+    fn logs(n: usize) -> FilterChanges {
+        let log = Log {
+            address: H160::from(1),
+            topics: Vec::new(),
+            data: Bytes::default(),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+        };
+        FilterChanges::Logs(vec![log; n])
+    }
+
+    /// A sink collecting what is written to it
+    fn sink() -> (Sink, Arc<Mutex<Vec<String>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let written = Arc::clone(&sent);
+        let sink: Sink = Box::new(move |msg| {
+            written.lock().push(msg);
+            true
+        });
+        (sink, sent)
+    }
+
+    /// A sink writing only as `gate` lets it
+    fn gated_sink() -> (Sink, ::std::sync::mpsc::Sender<()>, Receiver<String>) {
+        let (open, gate) = channel();
+        let (written, sent) = channel();
+        let sink: Sink = Box::new(move |msg| gate.recv().is_ok() && written.send(msg).is_ok());
+        (sink, open, sent)
+    }
+
+    #[test]
+    fn notify_subscriber() {
+        let mut subscriptions = Subscriptions::new(4, 4, 1);
+        let (sink, sent) = sink();
+        assert!(subscriptions.subscribe(1, 7, sink));
+
+        assert!(subscriptions.notify(1, logs(2)));
+        subscriptions.pool.join();
+        let msgs = sent.lock().clone();
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs[0].starts_with(r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0x1","#));
+    }
+
+    #[test]
+    fn notify_reverted_transactions() {
+        let mut subscriptions = Subscriptions::new(4, 4, 1);
+        let (sink, sent) = sink();
+        assert!(subscriptions.subscribe(2, 7, sink));
+
+        assert!(subscriptions.notify(2, FilterChanges::Hashes(vec![H256::from(1), H256::from(2)])));
+        subscriptions.pool.join();
+        let msgs = sent.lock().clone();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(
+            msgs[1],
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"eth_subscription","params":{{"subscription":"0x2","result":"0x{}"}}}}"#,
+                "0".repeat(63) + "2"
+            )
+        );
+    }
+
+    #[test]
+    fn drop_slow_subscriber() {
+        let mut subscriptions = Subscriptions::new(2, 4, 1);
+        let (sink, open, sent) = gated_sink();
+        assert!(subscriptions.subscribe(1, 7, sink));
+
+        // The first is taken out to be written, the next two fill the queue
+        assert!(subscriptions.notify(1, logs(1)));
+        let outbox = Arc::clone(&subscriptions.outboxes[&7]);
+        while !outbox.pending.lock().queue.is_empty() {
+            ::std::thread::yield_now();
+        }
+        assert!(subscriptions.notify(1, logs(2)));
+        // Nothing was written yet, so the queue is full and the subscriber is dropped
+        assert!(!subscriptions.notify(1, logs(1)));
+        for _ in 0..3 {
+            open.send(()).unwrap();
+        }
+        subscriptions.pool.join();
+        assert_eq!(sent.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn slow_connection_holds_up_only_itself() {
+        let mut subscriptions = Subscriptions::new(4, 4, 2);
+        let (slow_sink, open, slow_sent) = gated_sink();
+        let (sink, sent) = sink();
+        assert!(subscriptions.subscribe(1, 7, slow_sink));
+        assert!(subscriptions.subscribe(2, 8, sink));
+
+        assert!(subscriptions.notify(1, logs(1)));
+        assert!(subscriptions.notify(2, logs(3)));
+        while sent.lock().len() < 3 {
+            ::std::thread::yield_now();
+        }
+        assert!(slow_sent.try_recv().is_err());
+        open.send(()).unwrap();
+        subscriptions.pool.join();
+        assert_eq!(slow_sent.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn unclaimed_filter_is_uninstalled() {
+        let mut subscriptions = Subscriptions::new(2, 4, 1);
+        assert!(subscriptions.notify(1, logs(1)));
+        assert!(!subscriptions.notify(1, logs(1)));
+
+        // Confirmed in between, so it's kept and gets the changes which came first
+        assert!(subscriptions.notify(2, logs(1)));
+        let (sink, sent) = sink();
+        assert!(subscriptions.subscribe(2, 7, sink));
+        assert!(subscriptions.notify(2, logs(1)));
+        subscriptions.pool.join();
+        assert_eq!(sent.lock().len(), 2);
+    }
+
+    #[test]
+    fn subscriptions_per_connection_are_capped() {
+        let mut subscriptions = Subscriptions::new(2, 2, 1);
+        assert!(subscriptions.subscribe(1, 7, sink().0));
+        assert!(subscriptions.has_room(7));
+        assert!(subscriptions.subscribe(2, 7, sink().0));
+        assert!(!subscriptions.has_room(7));
+        assert!(!subscriptions.subscribe(3, 7, sink().0));
+        assert!(subscriptions.subscribe(3, 8, sink().0));
+
+        assert!(subscriptions.unsubscribe(1, 7));
+        assert!(subscriptions.has_room(7));
+    }
+
+    #[test]
+    fn subscriptions_end_with_connection() {
+        let mut subscriptions = Subscriptions::new(2, 4, 1);
+        for (id, connection) in vec![(1, 7), (2, 7), (3, 8)] {
+            assert!(subscriptions.subscribe(id, connection, sink().0));
+        }
+
+        // Only the connection which subscribed can cancel it
+        assert!(!subscriptions.unsubscribe(3, 7));
+        assert!(subscriptions.unsubscribe(2, 7));
+        assert!(!subscriptions.unsubscribe(2, 7));
+
+        assert_eq!(subscriptions.close_connection(7), vec![1]);
+        assert!(subscriptions.close_connection(7).is_empty());
+        assert_eq!(subscriptions.close_connection(8), vec![3]);
+    }
+}
@@ -0,0 +1,138 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Keeps a worker thread running: it is spawned again whenever it panics,
+//! until it panics too often in a short while.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Restarts allowed within `RESTART_WINDOW` if `dispatch_max_restarts` isn't configured
+pub const DEFAULT_MAX_RESTARTS: usize = 3;
+/// In unit of seconds
+pub const RESTART_WINDOW: u64 = 60;
+
+/// Runs `work` on a thread named `name` and spawns it again each time it panics.
+/// Returns true once `work` returns, false if it panicked more than `max_restarts`
+/// times within `window` or the thread can't be spawned.
+pub fn supervise<F>(name: &str, max_restarts: usize, window: Duration, work: F) -> bool
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let work = Arc::new(work);
+    let mut panics: VecDeque<Instant> = VecDeque::new();
+    loop {
+        let work = Arc::clone(&work);
+        let worker = match thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || work())
+        {
+            Ok(worker) => worker,
+            Err(err) => {
+                error!("failed to spawn {} thread: {}", name, err);
+                return false;
+            }
+        };
+        if worker.join().is_ok() {
+            return true;
+        }
+
+        let now = Instant::now();
+        while panics
+            .front()
+            .map_or(false, |panicked_at| now.duration_since(*panicked_at) > window)
+        {
+            panics.pop_front();
+        }
+        panics.push_back(now);
+        if panics.len() > max_restarts {
+            error!(
+                "{} thread panicked {} times within {:?}, giving up",
+                name,
+                panics.len(),
+                window
+            );
+            return false;
+        }
+        error!("{} thread panicked, restarting it", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use util::Mutex;
+
+    #[test]
+    fn restart_after_panic() {
+        let (tx, rx) = channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let (done_tx, done_rx) = channel();
+        let done_tx = Mutex::new(done_tx);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let worker_runs = Arc::clone(&runs);
+
+        tx.send("boom").unwrap();
+        tx.send("tx").unwrap();
+        let finished = supervise("dispatch", 1, Duration::from_secs(RESTART_WINDOW), move || {
+            worker_runs.fetch_add(1, Ordering::SeqCst);
+            match rx.lock().recv() {
+                Ok("boom") => panic!("forced dispatch panic"),
+                Ok(msg) => done_tx.lock().send(msg).unwrap(),
+                Err(_) => {}
+            }
+        });
+
+        assert!(finished);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        // Nothing relayed after the panic is lost
+        assert_eq!(done_rx.try_recv(), Ok("tx"));
+    }
+
+    #[test]
+    fn give_up_after_max_restarts() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let worker_runs = Arc::clone(&runs);
+
+        let finished = supervise("dispatch", 2, Duration::from_secs(RESTART_WINDOW), move || {
+            worker_runs.fetch_add(1, Ordering::SeqCst);
+            panic!("forced dispatch panic");
+        });
+
+        assert!(!finished);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn forget_panics_out_of_window() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let worker_runs = Arc::clone(&runs);
+
+        let finished = supervise("dispatch", 1, Duration::from_millis(10), move || {
+            if worker_runs.fetch_add(1, Ordering::SeqCst) < 3 {
+                thread::sleep(Duration::from_millis(20));
+                panic!("forced dispatch panic");
+            }
+        });
+
+        assert!(finished);
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+}
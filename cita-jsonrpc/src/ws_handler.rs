@@ -15,25 +15,44 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use helper::{encode_request, select_topic, ReqInfo, RpcMap, TransferType};
-use jsonrpc_types::{method, Id};
+use helper::{admin_only_error, admin_refused, encode_request, relay_request, select_topic, DuplicateRequestId, ReqInfo,
+             RpcMap, TransferType};
+use idempotency::IdempotencyKeys;
+use jsonrpc_types::{method, Error, Id};
 use jsonrpc_types::response::RpcFailure;
 use libproto::request as reqlib;
+use mq_handler::uninstall_filter;
 use num_cpus;
+use rate_limit::{over_limit, rate_limited_error, RateLimiter};
 use serde_json;
+use std::net::IpAddr;
 use std::sync::{mpsc, Arc};
+use subscription::{too_many_subscriptions, Subscriptions};
 use threadpool::ThreadPool;
-use ws::{self as ws, CloseCode, Factory, Handler};
+use util::Mutex;
+use ws::{self as ws, CloseCode, Factory, Handler, Handshake};
 
 pub struct WsFactory {
     //TODO 定时清理工作
     responses: RpcMap,
     thread_pool: ThreadPool,
-    tx: mpsc::Sender<(String, reqlib::Request)>,
+    tx: mpsc::SyncSender<(String, reqlib::Request)>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    on_duplicate: DuplicateRequestId,
 }
 
 impl WsFactory {
-    pub fn new(responses: RpcMap, tx: mpsc::Sender<(String, reqlib::Request)>, thread_num: usize) -> WsFactory {
+    pub fn new(
+        responses: RpcMap,
+        tx: mpsc::SyncSender<(String, reqlib::Request)>,
+        thread_num: usize,
+        rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+        subscriptions: Arc<Mutex<Subscriptions>>,
+        idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+        on_duplicate: DuplicateRequestId,
+    ) -> WsFactory {
         let thread_number = if thread_num == 0 {
             num_cpus::get()
         } else {
@@ -44,6 +63,10 @@ impl WsFactory {
             responses: responses,
             thread_pool: thread_pool,
             tx: tx,
+            rate_limiter: rate_limiter,
+            subscriptions: subscriptions,
+            idempotency_keys: idempotency_keys,
+            on_duplicate: on_duplicate,
         }
     }
 }
@@ -57,11 +80,21 @@ impl Factory for WsFactory {
             tx: self.tx.clone(),
             thread_pool: self.thread_pool.clone(),
             method_handler: method::MethodHandler,
+            rate_limiter: self.rate_limiter.clone(),
+            remote_ip: None,
+            subscriptions: Arc::clone(&self.subscriptions),
+            idempotency_keys: Arc::clone(&self.idempotency_keys),
+            on_duplicate: self.on_duplicate,
         }
     }
 }
 
 impl Handler for WsHandler {
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()> {
+        self.remote_ip = shake.peer_addr.map(|addr| addr.ip());
+        Ok(())
+    }
+
     fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
         trace!("Server got message '{}'  post thread_pool deal task ", msg);
         // let this = self.clone();
@@ -69,6 +102,11 @@ impl Handler for WsHandler {
         let tx = self.tx.clone();
         let response = Arc::clone(&self.responses);
         let sender = self.sender.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let remote_ip = self.remote_ip;
+        let subscriptions = Arc::clone(&self.subscriptions);
+        let idempotency_keys = Arc::clone(&self.idempotency_keys);
+        let on_duplicate = self.on_duplicate;
 
         self.thread_pool.execute(move || {
             let mut req_id = Id::Null;
@@ -76,23 +114,53 @@ impl Handler for WsHandler {
             let err = match encode_request(&msg.into_text().unwrap()) {
                 Err(err) => Err(err),
                 Ok(rpc) => {
-                    req_id = rpc.id.clone();
+                    req_id = rpc.answer_id();
                     jsonrpc_version = rpc.jsonrpc.clone();
                     let topic = select_topic(&rpc.method);
-                    let req_info = ReqInfo {
-                        jsonrpc: jsonrpc_version.clone(),
-                        id: req_id.clone(),
-                    };
-                    method_handler.request(&rpc).map(|req| {
-                        let request_id = req.request_id.clone();
-                        let _ = tx.send((topic, req));
-                        let value = (req_info, sender.clone());
-                        {
-                            response
-                                .lock()
-                                .insert(request_id, TransferType::WEBSOCKET(value));
-                        }
-                    })
+                    let req_info = ReqInfo::new(jsonrpc_version.clone(), req_id.clone());
+                    if admin_refused(remote_ip, &rpc.method) {
+                        Err(admin_only_error())
+                    } else if over_limit(&rate_limiter, remote_ip, &rpc.method) {
+                        Err(rate_limited_error())
+                    } else {
+                        let connection = sender.token().0;
+                        let request = match rpc.method.as_str() {
+                            method::ETH_SUBSCRIBE => if subscriptions.lock().has_room(connection) {
+                                method_handler.subscribe(&rpc).map(|req| (req, true))
+                            } else {
+                                Err(too_many_subscriptions())
+                            },
+                            method::ETH_UNSUBSCRIBE => method_handler.uninstall_filter(&rpc).and_then(|req| {
+                                if subscriptions
+                                    .lock()
+                                    .unsubscribe(req.get_uninstall_filter(), connection)
+                                {
+                                    Ok((req, false))
+                                } else {
+                                    Err(Error::invalid_params("unknown subscription"))
+                                }
+                            }),
+                            _ => method_handler.request(&rpc).map(|req| (req, false)),
+                        };
+                        let key = method_handler.idempotency_key(&rpc);
+                        request.map(|(req, subscribe)| {
+                            let value = (req_info, sender.clone());
+                            let transfer = if subscribe {
+                                TransferType::SUBSCRIPTION(value)
+                            } else {
+                                TransferType::WEBSOCKET(value)
+                            };
+                            relay_request(
+                                &response,
+                                &tx,
+                                topic,
+                                req,
+                                transfer,
+                                key.map(|key| (&*idempotency_keys, key)),
+                                on_duplicate,
+                            );
+                        })
+                    }
                 }
             };
             //TODO 错误返回
@@ -112,6 +180,11 @@ impl Handler for WsHandler {
             reason,
             self.sender.token().0
         );
+        // Subscriptions don't outlive the connection, a client reconnecting has to subscribe again
+        let ids = self.subscriptions.lock().close_connection(self.sender.token().0);
+        for id in ids {
+            uninstall_filter(&self.tx, id);
+        }
     }
 }
 
@@ -121,5 +194,10 @@ pub struct WsHandler {
     thread_pool: ThreadPool,
     method_handler: method::MethodHandler,
     sender: ws::Sender,
-    tx: mpsc::Sender<(String, reqlib::Request)>,
+    tx: mpsc::SyncSender<(String, reqlib::Request)>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    remote_ip: Option<IpAddr>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    on_duplicate: DuplicateRequestId,
 }
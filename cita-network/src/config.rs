@@ -24,7 +24,12 @@ pub struct NetConfig {
     pub id_card: Option<u32>,
     pub port: Option<u64>,
     pub max_peer: Option<u64>,
+    pub chain_id: Option<u32>,
     pub peers: Option<Vec<PeerConfig>>,
+    /// Ask peers for contiguous heights as a range, only once all of them understand it
+    pub compress_sync_ranges: Option<bool>,
+    /// Pull blocks from one peer this many a time, only once all of them answer pull requests
+    pub pull_sync_window: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -79,5 +84,18 @@ mod test {
         let value: NetConfig = toml::from_str(toml).unwrap();
         println!("{:?}", value);
         assert_eq!(value.port, Some(40000));
+        assert_eq!(value.chain_id, None);
+    }
+
+    #[test]
+    fn chain_id() {
+        let toml = r#"
+            port = 40000
+            max_peer = 0
+            chain_id = 7
+        "#;
+
+        let value: NetConfig = toml::from_str(toml).unwrap();
+        assert_eq!(value.chain_id, Some(7));
     }
 }
@@ -57,7 +57,7 @@ use config::NetConfig;
 use connection::{manage_connect, Connection};
 use libproto::Message;
 use netserver::NetServer;
-use network::NetWork;
+use network::{NetWork, DEFAULT_CHAIN_ID};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use pubsub::start_pubsub;
 use std::convert::TryFrom;
@@ -144,6 +144,7 @@ fn main() {
         sync_tx,
         ctx_pub_tx,
         ctx_pub_consensus,
+        config.chain_id.unwrap_or(DEFAULT_CHAIN_ID),
     );
     manage_connect(&Arc::clone(&con), config_path, rx);
 
@@ -156,6 +157,8 @@ fn main() {
 
     // Sync loop
     let mut synchronizer = Synchronizer::new(ctx_pub, Arc::clone(&con));
+    synchronizer.set_compress_sync_ranges(config.compress_sync_ranges.unwrap_or(false));
+    synchronizer.set_pull_sync_window(config.pull_sync_window.unwrap_or(0));
     thread::spawn(move || loop {
         if let Ok((source, body)) = sync_rx.recv() {
             synchronizer.receive(source, body);
@@ -5,12 +5,16 @@ use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
 
+/// Chain id answered to `net_version` when the network config doesn't set one
+pub const DEFAULT_CHAIN_ID: u32 = 1;
+
 pub struct NetWork {
     con: Arc<Connection>,
     tx_pub: Sender<(String, Vec<u8>)>,
     tx_sync: Sender<(Source, Vec<u8>)>,
     tx_new_tx: Sender<(String, Vec<u8>)>,
     tx_consensus: Sender<(String, Vec<u8>)>,
+    chain_id: u32,
 }
 
 impl NetWork {
@@ -20,6 +24,7 @@ impl NetWork {
         tx_sync: Sender<(Source, Vec<u8>)>,
         tx_new_tx: Sender<(String, Vec<u8>)>,
         tx_consensus: Sender<(String, Vec<u8>)>,
+        chain_id: u32,
     ) -> Self {
         NetWork {
             con: con,
@@ -27,6 +32,7 @@ impl NetWork {
             tx_sync: tx_sync,
             tx_new_tx: tx_new_tx,
             tx_consensus: tx_consensus,
+            chain_id: chain_id,
         }
     }
 
@@ -86,11 +92,16 @@ impl NetWork {
                         .filter(|x| x.2.is_some())
                         .count();
                     response.set_peercount(peercount as u32);
-                    let ms: Message = response.into();
-                    self.tx_pub
-                        .send(("chain.rpc".to_string(), ms.try_into().unwrap()))
-                        .unwrap();
+                } else if ts.has_net_version() {
+                    response.set_net_version(self.chain_id);
+                } else {
+                    warn!("receive: unexpected request = {:?}", ts);
+                    return;
                 }
+                let ms: Message = response.into();
+                self.tx_pub
+                    .send(("chain.rpc".to_string(), ms.try_into().unwrap()))
+                    .unwrap();
             }
             _ => {
                 warn!("receive: unexpected data type = {:?}", content);
@@ -4,7 +4,7 @@ use libproto::{Message, MsgClass, OperateType, SyncRequest, SyncResponse};
 use libproto::blockchain::{Block, Status};
 use protobuf::RepeatedField;
 use rand::{thread_rng, Rng, ThreadRng};
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::{Into, TryFrom, TryInto};
 use std::sync::{mpsc, Arc};
 use std::time::{Duration, Instant};
@@ -23,6 +23,13 @@ pub struct Synchronizer {
     block_lists: BTreeMap<u64, Block>,
     rand: ThreadRng,
     sync_time_out: Instant,
+    /// Ask for contiguous heights as a range instead of listing them
+    compress_sync_ranges: bool,
+    /// Pull this many blocks a time from one peer, following its cursor, 0 to ask by heights
+    pull_sync_window: u64,
+    /// Cursor of the window pulled from each peer and when it was asked for,
+    /// so only one chain of pull requests goes to a peer at a time
+    pull_cursors: HashMap<u32, (u64, Instant)>,
 }
 
 unsafe impl Sync for Synchronizer {}
@@ -41,9 +48,22 @@ impl Synchronizer {
             block_lists: BTreeMap::new(),
             rand: thread_rng(),
             sync_time_out: (Instant::now() - Duration::from_secs(SYNC_TIME_OUT)),
+            compress_sync_ranges: false,
+            pull_sync_window: 0,
+            pull_cursors: HashMap::new(),
         }
     }
 
+    /// Peers have to understand the range form, so it's off unless every chain is new enough
+    pub fn set_compress_sync_ranges(&mut self, compress: bool) {
+        self.compress_sync_ranges = compress;
+    }
+
+    /// Peers have to answer pull requests, so it's off unless every chain is new enough
+    pub fn set_pull_sync_window(&mut self, window: u64) {
+        self.pull_sync_window = window;
+    }
+
     pub fn update_current_status(&mut self, latest_status: Status) {
         debug!(
             "sync: update_current_status: current height = {}, \
@@ -139,7 +159,16 @@ impl Synchronizer {
         self.is_synchronizing
     }
 
-    pub fn process_sync(&mut self, mut blocks: SyncResponse) {
+    pub fn process_sync(&mut self, mut blocks: SyncResponse, origin: u32) {
+        // A pulled window points at the next one until the peer's height is reached
+        let pulled_cursor = self.pull_cursors.get(&origin).map(|&(cursor, _)| cursor);
+        if let Some(cursor) = pulled_cursor {
+            if !blocks.get_has_next() {
+                self.pull_cursors.remove(&origin);
+            } else if blocks.get_next_cursor() > cursor {
+                self.send_pull_req(blocks.get_next_cursor(), origin);
+            }
+        }
         let blocks = blocks.take_blocks();
         debug!("sync: process_sync: blocks len = {}", blocks.len());
 
@@ -172,7 +201,7 @@ impl Synchronizer {
                     error!("sync: msg not parse!");
                 }
                 Source::REMOTE => {
-                    self.process_sync(blocks);
+                    self.process_sync(blocks, origin);
                 }
             },
             _ => {
@@ -217,7 +246,7 @@ impl Synchronizer {
         }
     }
 
-    fn sync_strategy(&self, start_height: u64, end_height: u64, origin: u32) {
+    fn sync_strategy(&mut self, start_height: u64, end_height: u64, origin: u32) {
         //current height = 155,start_height = 156, end height = 160, to origin = 1
         debug!(
             "sync: sync_strategy: current height = {}, \
@@ -230,6 +259,14 @@ impl Synchronizer {
             origin
         );
         if start_height >= self.current_status.get_height() && start_height <= end_height {
+            if self.pull_sync_window > 0 {
+                // The peer's replies lead on to its height, whatever the end asked,
+                // so a peer already pulled from isn't asked again until it stops answering
+                if !self.is_pulling_from(origin) {
+                    self.send_pull_req(start_height, origin);
+                }
+                return;
+            }
             let mut start_height = start_height;
             let mut step_sum = SYNC_STEP;
             let mut heights = vec![];
@@ -262,12 +299,41 @@ impl Synchronizer {
                 OperateType::SINGLE
             );
             let mut sync_req = SyncRequest::new();
-            sync_req.set_heights(heights);
+            // The heights of one request are always contiguous
+            if self.compress_sync_ranges {
+                sync_req.set_range_start(heights[0]);
+                sync_req.set_range_end(heights[heights.len() - 1]);
+            } else {
+                sync_req.set_heights(heights);
+            }
             let msg = Message::init(OperateType::SINGLE, origin, MsgClass::SyncRequest(sync_req));
             self.con.broadcast("net.sync_req".to_string(), msg);
         }
     }
 
+    /// Whether a window pulled from `origin` is still waited for
+    fn is_pulling_from(&self, origin: u32) -> bool {
+        self.pull_cursors
+            .get(&origin)
+            .map_or(false, |&(_, asked)| asked.elapsed().as_secs() <= SYNC_TIME_OUT)
+    }
+
+    fn send_pull_req(&mut self, cursor: u64, origin: u32) {
+        debug!(
+            "sync: send_pull_req: current height = {}, cursor = {}, window = {}, origin {:?}",
+            self.current_status.get_height(),
+            cursor,
+            self.pull_sync_window,
+            origin
+        );
+        let mut sync_req = SyncRequest::new();
+        sync_req.set_cursor(cursor);
+        sync_req.set_window(self.pull_sync_window);
+        self.pull_cursors.insert(origin, (cursor, Instant::now()));
+        let msg = Message::init(OperateType::SINGLE, origin, MsgClass::SyncRequest(sync_req));
+        self.con.broadcast("net.sync_req".to_string(), msg);
+    }
+
     fn broadcast_status(&mut self) {
         debug!(
             "sync: broadcast status {:?}, {:?} to other nodes",
@@ -142,6 +142,18 @@ impl Error {
         }
     }
 
+    /// Creates new `InvalidRequest` with the reason it's invalid
+    pub fn invalid_request_with_message<M>(message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        Error {
+            code: ErrorCode::InvalidRequest,
+            message: message.into(),
+            data: None,
+        }
+    }
+
     /// Creates new `InternalError`
     pub fn internal_error() -> Self {
         Self::new(ErrorCode::InternalError)
@@ -25,3 +25,9 @@ pub enum Id {
     /// Numeric id
     Num(u64),
 }
+
+impl Default for Id {
+    fn default() -> Self {
+        Id::Null
+    }
+}
@@ -18,9 +18,11 @@
 use super::{Call, Error, Params};
 //#[warn(non_snake_case)]
 use libproto::{request as reqlib, UnverifiedTransaction};
-use rpctypes::{BlockNumber, BlockParamsByHash, BlockParamsByNumber, CallRequest, CountOrCode, Filter};
+use rpctypes::{BlockNumber, BlockParamsByHash, BlockParamsByNumber, CallRequest, CountOrCode, Filter, HasLogsParams,
+               Index, PositionBlock, ReceiptParams, StateRootsParams, TransactionPosition, BLOCK_FIELDS};
 use rustc_serialize::hex::FromHex;
 use serde_json;
+use serde_json::Value;
 use std::convert::TryFrom;
 use util::{H160, H256, U256};
 use util::ToPretty;
@@ -34,6 +36,8 @@ pub mod method {
     pub const CITA_GET_TRANSACTION: &str = "cita_getTransaction";
     pub const CITA_SEND_TRANSACTION: &str = "cita_sendTransaction";
     pub const NET_PEER_COUNT: &str = "net_peerCount";
+    /// Returns the chain id configured for the network, as a decimal string.
+    pub const NET_VERSION: &str = "net_version";
     /// Executes a new message call immediately without creating a transaction on the block chain.
     /// Parameters
     /// 1. Object - The transaction call object
@@ -53,10 +57,99 @@ pub mod method {
     pub const ETH_UNINSTALL_FILTER: &str = "eth_uninstallFilter";
     pub const ETH_GET_FILTER_CHANGES: &str = "eth_getFilterChanges";
     pub const ETH_GET_FILTER_LOGS: &str = "eth_getFilterLogs";
+
+    /// Returns the current consensus authority set.
+    pub const CITA_GET_VALIDATORS: &str = "cita_getValidators";
+
+    /// Recomputes a block's transactions root and checks it against the header.
+    pub const CITA_CHECK_TRANSACTIONS_ROOT: &str = "cita_checkTransactionsRoot";
+
+    /// Returns the gas limits which applied at a given height.
+    pub const CITA_GET_GAS_LIMITS: &str = "cita_getGasLimits";
+
+    /// Returns the origin id of the node and the peers it recently heard from.
+    pub const CITA_GET_PEERS: &str = "cita_getPeers";
+
+    /// Returns the state roots of two heights and whether they differ.
+    pub const CITA_COMPARE_STATE_ROOTS: &str = "cita_compareStateRoots";
+
+    /// Returns the merkle proof that a transaction is included in its block.
+    pub const CITA_GET_TRANSACTION_PROOF: &str = "cita_getTransactionProof";
+
+    /// Returns the height, total txs, average block time and buffered new txs.
+    pub const CITA_GET_CHAIN_STATS: &str = "cita_getChainStats";
+
+    /// Returns every block known at a height, including the ones replaced before being executed.
+    pub const CITA_GET_BLOCKS_AT_HEIGHT: &str = "cita_getBlocksAtHeight";
+
+    /// Returns the new txs auth rejected lately and why, answered by auth.
+    pub const CITA_GET_REJECTED_TRANSACTIONS: &str = "cita_getRejectedTransactions";
+
+    /// Returns the fraction of the latest verified txs auth rejected, answered by auth.
+    pub const CITA_GET_REJECTION_RATE: &str = "cita_getRejectionRate";
+
+    /// Verifies a signed tx again bypassing the cached result, answered by auth.
+    pub const CITA_REVERIFY_TRANSACTION: &str = "cita_reverifyTransaction";
+
+    /// Returns how many verify requests auth answered with each outcome, answered by auth.
+    pub const CITA_GET_VERIFY_STATS: &str = "cita_getVerifyStats";
+
+    /// Returns the max store height, max height and current height, without any fallback.
+    pub const CITA_GET_HEIGHTS: &str = "cita_getHeights";
+
+    /// Returns the encoded size of a block in bytes, without transferring the block.
+    pub const CITA_GET_BLOCK_SIZE: &str = "cita_getBlockSize";
+
+    /// Returns the encoded size of a packed transaction in bytes, without transferring it.
+    pub const CITA_GET_TRANSACTION_SIZE: &str = "cita_getTransactionSize";
+
+    /// Returns the number of txs in the block of the hash, without transferring the block.
+    pub const CITA_GET_BLOCK_TRANSACTION_COUNT_BY_HASH: &str = "cita_getBlockTransactionCountByHash";
+
+    /// Returns the number of txs in the block at the height, without transferring the block.
+    pub const CITA_GET_BLOCK_TRANSACTION_COUNT_BY_NUMBER: &str = "cita_getBlockTransactionCountByNumber";
+
+    /// Returns the tx at an index of the block of the hash, or null.
+    pub const CITA_GET_TRANSACTION_BY_BLOCK_HASH_AND_INDEX: &str = "cita_getTransactionByBlockHashAndIndex";
+
+    /// Returns the tx at an index of the block at the height, or null.
+    pub const CITA_GET_TRANSACTION_BY_BLOCK_NUMBER_AND_INDEX: &str = "cita_getTransactionByBlockNumberAndIndex";
+
+    /// Runs a call like `eth_call` and returns the storage it would change, nothing is committed.
+    pub const CITA_SIMULATE_TRANSACTION: &str = "cita_simulateTransaction";
+
+    /// Returns whether an address logged anything in a block range, without transferring the logs.
+    pub const CITA_HAS_LOGS: &str = "cita_hasLogs";
+
+    /// Returns the genesis block, the consensus type and the validators the chain started with.
+    pub const CITA_GET_GENESIS: &str = "cita_getGenesis";
+
+    /// Returns the receipts of a list of txs in order, null for the ones without a receipt.
+    pub const CITA_GET_TRANSACTION_RECEIPTS: &str = "cita_getTransactionReceipts";
+
+    /// Returns the chain id, the genesis hash, the consensus type and the version of the chain.
+    pub const CITA_GET_META_DATA: &str = "cita_getMetaData";
+
+    /// Streams the logs matching a filter, or the hashes of reverted txs, only served over websocket.
+    pub const ETH_SUBSCRIBE: &str = "eth_subscribe";
+
+    /// Cancels a subscription, only served over websocket.
+    pub const ETH_UNSUBSCRIBE: &str = "eth_unsubscribe";
 }
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct MethodHandler;
+
+fn check_block_fields(fields: Vec<String>) -> Result<Vec<String>, Error> {
+    if let Some(field) = fields
+        .iter()
+        .find(|field| !BLOCK_FIELDS.contains(&field.as_str()))
+    {
+        return Err(Error::invalid_params(format!("unknown block field: {}", field)));
+    }
+    Ok(fields)
+}
+
 impl MethodHandler {
     pub fn params_len(&self, params: &Option<Params>) -> usize {
         if let &Some(ref params) = params {
@@ -84,6 +177,7 @@ impl MethodHandler {
         match rpc.method.as_str() {
             method::CITA_BLOCK_BUMBER => self.block_number(rpc),
             method::NET_PEER_COUNT => self.peer_count(rpc),
+            method::NET_VERSION => self.net_version(rpc),
             method::CITA_GET_BLOCK_BY_HASH => self.get_block_by_hash(rpc),
             method::CITA_GET_BLOCK_BY_NUMBER => self.get_block_by_number(rpc),
             method::CITA_GET_TRANSACTION => self.get_transaction(rpc),
@@ -101,35 +195,55 @@ impl MethodHandler {
             method::ETH_UNINSTALL_FILTER => self.uninstall_filter(rpc),
             method::ETH_GET_FILTER_CHANGES => self.get_filter_changes(rpc),
             method::ETH_GET_FILTER_LOGS => self.get_filter_logs(rpc),
+            method::CITA_GET_VALIDATORS => self.get_validators(rpc),
+            method::CITA_CHECK_TRANSACTIONS_ROOT => self.check_transactions_root(rpc),
+            method::CITA_GET_GAS_LIMITS => self.get_gas_limits(rpc),
+            method::CITA_GET_PEERS => self.get_peers(rpc),
+            method::CITA_COMPARE_STATE_ROOTS => self.compare_state_roots(rpc),
+            method::CITA_GET_TRANSACTION_PROOF => self.get_transaction_proof(rpc),
+            method::CITA_GET_CHAIN_STATS => self.get_chain_stats(rpc),
+            method::CITA_GET_BLOCKS_AT_HEIGHT => self.get_blocks_at_height(rpc),
+            method::CITA_GET_REJECTED_TRANSACTIONS => self.get_rejected_transactions(rpc),
+            method::CITA_GET_REJECTION_RATE => self.get_rejection_rate(rpc),
+            method::CITA_REVERIFY_TRANSACTION => self.reverify_transaction(rpc),
+            method::CITA_GET_VERIFY_STATS => self.get_verify_stats(rpc),
+            method::CITA_GET_HEIGHTS => self.get_heights(rpc),
+            method::CITA_GET_BLOCK_SIZE => self.get_block_size(rpc),
+            method::CITA_GET_TRANSACTION_SIZE => self.get_transaction_size(rpc),
+            method::CITA_GET_BLOCK_TRANSACTION_COUNT_BY_HASH => self.get_block_transaction_count_by_hash(rpc),
+            method::CITA_GET_BLOCK_TRANSACTION_COUNT_BY_NUMBER => self.get_block_transaction_count_by_number(rpc),
+            method::CITA_SIMULATE_TRANSACTION => self.simulate_transaction(rpc),
+            method::CITA_GET_TRANSACTION_BY_BLOCK_HASH_AND_INDEX => self.get_transaction_by_block_hash_and_index(rpc),
+            method::CITA_GET_TRANSACTION_BY_BLOCK_NUMBER_AND_INDEX => {
+                self.get_transaction_by_block_number_and_index(rpc)
+            }
+            method::CITA_HAS_LOGS => self.has_logs(rpc),
+            method::CITA_GET_GENESIS => self.get_genesis(rpc),
+            method::CITA_GET_TRANSACTION_RECEIPTS => self.get_transaction_receipts(rpc),
+            method::CITA_GET_META_DATA => self.get_meta_data(rpc),
 
             _ => Err(Error::method_not_found()),
         }
     }
 
+    /// The optional second param is an idempotency key, see `idempotency_key`.
     pub fn send_transaction(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
         let mut request = self.create_request();
-        if 1 != self.params_len(&req_rpc.params) {
+        let params_len = self.params_len(&req_rpc.params);
+        if 1 != params_len && 2 != params_len {
             return Err(Error::invalid_params_len());
         }
 
         let params = self.detach_requeired_params(req_rpc)?;
-        let params: (String,) = params.parse()?;
+        let signed_tx = if params_len == 2 {
+            let (signed_tx, _key): (String, String) = params.parse()?;
+            signed_tx
+        } else {
+            let (signed_tx,): (String,) = params.parse()?;
+            signed_tx
+        };
 
-        let data = clean_0x(&params.0);
-        let un_tx = data.from_hex()
-            .map_err(|_err| {
-                let err_msg = format!("param not hex string : {:?}", _err);
-                Error::parse_error_with_message(err_msg)
-            })
-            .and_then(|content| {
-                UnverifiedTransaction::try_from(&content[..]).map_err(|_err| {
-                    let err_msg = format!(
-                        "parse protobuf UnverifiedTransaction data error : {:?}",
-                        _err
-                    );
-                    Error::parse_error_with_message(err_msg)
-                })
-            })?;
+        let un_tx = self.parse_signed_tx(&signed_tx)?;
 
         {
             let tx = un_tx.get_transaction();
@@ -158,6 +272,18 @@ impl MethodHandler {
         Ok(request)
     }
 
+    /// Key a client retrying `cita_sendTransaction` passes to get the result of its first submission
+    pub fn idempotency_key(&self, req_rpc: &Call) -> Option<String> {
+        if req_rpc.method != method::CITA_SEND_TRANSACTION || 2 != self.params_len(&req_rpc.params) {
+            return None;
+        }
+        req_rpc
+            .params
+            .clone()
+            .and_then(|params| params.parse::<(String, String)>().ok())
+            .map(|(_, key)| key)
+    }
+
     pub fn peer_count(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
         if 0 != self.params_len(&req_rpc.params) {
             return Err(Error::invalid_params_len());
@@ -168,25 +294,53 @@ impl MethodHandler {
         Ok(request)
     }
 
-    pub fn block_number(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+    pub fn net_version(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
         if 0 != self.params_len(&req_rpc.params) {
             return Err(Error::invalid_params_len());
         }
 
         let mut request = self.create_request();
-        request.set_block_number(true);
+        request.set_net_version(true);
+        Ok(request)
+    }
+
+    pub fn block_number(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        let include_header = match self.params_len(&req_rpc.params) {
+            0 => false,
+            1 => {
+                let params = self.detach_requeired_params(req_rpc)?;
+                let (include_header,): (bool,) = params.parse()?;
+                include_header
+            }
+            _ => return Err(Error::invalid_params_len()),
+        };
+
+        let mut request = self.create_request();
+        // The head block with its hash and header, saving a query for the block
+        if include_header {
+            request.set_block_head(true);
+        } else {
+            request.set_block_number(true);
+        }
         Ok(request)
     }
 
     pub fn get_block_by_hash(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
-        if 2 != self.params_len(&req_rpc.params) {
+        let len = self.params_len(&req_rpc.params);
+        if 2 != len && 3 != len {
             return Err(Error::invalid_params_len());
         }
         let params = self.detach_requeired_params(req_rpc)?;
-        let (hash, is_block): (H256, bool) = params.parse()?;
+        let (hash, is_block, fields) = if 2 == len {
+            let (hash, is_block): (H256, bool) = params.parse()?;
+            (hash, is_block, None)
+        } else {
+            let (hash, is_block, fields): (H256, bool, Vec<String>) = params.parse()?;
+            (hash, is_block, Some(check_block_fields(fields)?))
+        };
         let mut request = self.create_request();
 
-        serde_json::to_string(&BlockParamsByHash::new(hash.to_vec(), is_block))
+        serde_json::to_string(&BlockParamsByHash::new(hash.to_vec(), is_block).set_fields(fields))
             .map_err(|err| Error::invalid_params(err.to_string()))
             .map(|block_hash| {
                 request.set_block_by_hash(block_hash);
@@ -195,14 +349,21 @@ impl MethodHandler {
     }
 
     pub fn get_block_by_number(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
-        if 2 != self.params_len(&req_rpc.params) {
+        let len = self.params_len(&req_rpc.params);
+        if 2 != len && 3 != len {
             return Err(Error::invalid_params_len());
         }
         let params = self.detach_requeired_params(req_rpc)?;
-        let params: (BlockNumber, bool) = params.parse()?;
+        let (number, is_block, fields) = if 2 == len {
+            let (number, is_block): (BlockNumber, bool) = params.parse()?;
+            (number, is_block, None)
+        } else {
+            let (number, is_block, fields): (BlockNumber, bool, Vec<String>) = params.parse()?;
+            (number, is_block, Some(check_block_fields(fields)?))
+        };
         let mut request = self.create_request();
 
-        serde_json::to_string(&BlockParamsByNumber::new(params.0, params.1))
+        serde_json::to_string(&BlockParamsByNumber::new(number, is_block).set_fields(fields))
             .map_err(|err| Error::invalid_params(err.to_string()))
             .map(|block_height| {
                 request.set_block_by_height(block_height);
@@ -210,6 +371,23 @@ impl MethodHandler {
             })
     }
 
+    pub fn get_blocks_at_height(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 2 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number, is_block): (BlockNumber, bool) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&BlockParamsByNumber::new(number, is_block))
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|params| {
+                request.set_blocks_at_height(params);
+                request
+            })
+    }
+
     pub fn get_transaction(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
         if 1 != self.params_len(&req_rpc.params) {
             return Err(Error::invalid_params_len());
@@ -224,6 +402,18 @@ impl MethodHandler {
 
     pub fn call(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
         let mut request = self.create_request();
+        request.set_call(self.call_params(req_rpc)?);
+        Ok(request)
+    }
+
+    pub fn simulate_transaction(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        let mut request = self.create_request();
+        request.set_simulate(self.call_params(req_rpc)?);
+        Ok(request)
+    }
+
+    /// The `CallRequest` and optional block of `eth_call`
+    fn call_params(&self, req_rpc: &Call) -> Result<reqlib::Call, Error> {
         let len = self.params_len(&req_rpc.params);
 
         let params = self.detach_requeired_params(req_rpc)?;
@@ -252,8 +442,7 @@ impl MethodHandler {
             .map_err(|err| Error::invalid_params(err.to_string()))
             .map(|height| {
                 call.set_height(height);
-                request.set_call(call);
-                request
+                call
             })
     }
 
@@ -270,17 +459,48 @@ impl MethodHandler {
         Ok(request)
     }
 
+    /// The optional second param is an ABI, or only its events, to decode the logs against
     pub fn get_transaction_receipt(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
-        if 1 != self.params_len(&req_rpc.params) {
+        let params_len = self.params_len(&req_rpc.params);
+        if 1 != params_len && 2 != params_len {
             return Err(Error::invalid_params_len());
         }
 
         let params = self.detach_requeired_params(req_rpc)?;
-        let (hash,): (H256,) = params.parse()?;
+        let mut request = self.create_request();
+        if params_len == 1 {
+            let (hash,): (H256,) = params.parse()?;
+            request.set_transaction_receipt(hash.to_vec());
+            return Ok(request);
+        }
+
+        let (hash, abi): (H256, Value) = params.parse()?;
+        let abi = match abi {
+            Value::String(abi) => abi,
+            abi => abi.to_string(),
+        };
+        serde_json::to_string(&ReceiptParams { hash: hash, abi: abi })
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|params| {
+                request.set_decoded_receipt(params);
+                request
+            })
+    }
 
+    pub fn get_transaction_receipts(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (hashes,): (Vec<H256>,) = params.parse()?;
         let mut request = self.create_request();
-        request.set_transaction_receipt(hash.to_vec());
-        Ok(request)
+        serde_json::to_string(&hashes)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|hashes| {
+                request.set_transaction_receipts(hashes);
+                request
+            })
     }
 
     pub fn get_transaction_count(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
@@ -373,6 +593,333 @@ impl MethodHandler {
         request.set_filter_logs(filter_id.into());
         Ok(request)
     }
+
+    pub fn get_validators(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_validators(true);
+        Ok(request)
+    }
+
+    pub fn check_transactions_root(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number,): (BlockNumber,) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&number)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|number| {
+                request.set_check_transactions_root(number);
+                request
+            })
+    }
+
+    pub fn get_gas_limits(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number,): (BlockNumber,) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&number)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|number| {
+                request.set_gas_limits(number);
+                request
+            })
+    }
+
+    pub fn get_peers(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_peers(true);
+        Ok(request)
+    }
+
+    pub fn compare_state_roots(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 2 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (from, to): (BlockNumber, BlockNumber) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&StateRootsParams { from: from, to: to })
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|heights| {
+                request.set_compare_state_roots(heights);
+                request
+            })
+    }
+
+    pub fn has_logs(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 3 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (address, from_block, to_block): (H160, BlockNumber, BlockNumber) = params.parse()?;
+        let params = HasLogsParams {
+            address: address,
+            from_block: from_block,
+            to_block: to_block,
+        };
+        let mut request = self.create_request();
+
+        serde_json::to_string(&params)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|params| {
+                request.set_has_logs(params);
+                request
+            })
+    }
+
+    pub fn get_genesis(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_genesis(true);
+        Ok(request)
+    }
+
+    pub fn get_meta_data(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_meta_data(true);
+        Ok(request)
+    }
+
+    pub fn get_transaction_proof(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (hash,): (H256,) = params.parse()?;
+
+        let mut request = self.create_request();
+        request.set_transaction_proof(hash.to_vec());
+        Ok(request)
+    }
+
+    pub fn get_chain_stats(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_chain_stats(true);
+        Ok(request)
+    }
+
+    pub fn get_rejected_transactions(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_rejected_txs(true);
+        Ok(request)
+    }
+
+    pub fn get_rejection_rate(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_rejection_rate(true);
+        Ok(request)
+    }
+
+    pub fn get_verify_stats(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_verify_stats(true);
+        Ok(request)
+    }
+
+    pub fn reverify_transaction(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (signed_tx,): (String,) = params.parse()?;
+        let mut request = self.create_request();
+        request.set_reverify_tx(self.parse_signed_tx(&signed_tx)?);
+        Ok(request)
+    }
+
+    fn parse_signed_tx(&self, signed_tx: &str) -> Result<UnverifiedTransaction, Error> {
+        let data = clean_0x(signed_tx);
+        data.from_hex()
+            .map_err(|_err| {
+                let err_msg = format!("param not hex string : {:?}", _err);
+                Error::parse_error_with_message(err_msg)
+            })
+            .and_then(|content| {
+                UnverifiedTransaction::try_from(&content[..]).map_err(|_err| {
+                    let err_msg = format!(
+                        "parse protobuf UnverifiedTransaction data error : {:?}",
+                        _err
+                    );
+                    Error::parse_error_with_message(err_msg)
+                })
+            })
+    }
+
+    pub fn get_heights(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 0 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let mut request = self.create_request();
+        request.set_heights(true);
+        Ok(request)
+    }
+
+    pub fn get_block_size(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number,): (BlockNumber,) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&number)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|number| {
+                request.set_block_size(number);
+                request
+            })
+    }
+
+    pub fn get_transaction_size(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (hash,): (H256,) = params.parse()?;
+
+        let mut request = self.create_request();
+        request.set_transaction_size(hash.to_vec());
+        Ok(request)
+    }
+
+    pub fn get_block_transaction_count_by_hash(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (hash,): (H256,) = params.parse()?;
+
+        let mut request = self.create_request();
+        request.set_block_tx_count_by_hash(hash.to_vec());
+        Ok(request)
+    }
+
+    pub fn get_block_transaction_count_by_number(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 1 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number,): (BlockNumber,) = params.parse()?;
+        let mut request = self.create_request();
+
+        serde_json::to_string(&number)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|number| {
+                request.set_block_tx_count_by_height(number);
+                request
+            })
+    }
+
+    pub fn get_transaction_by_block_hash_and_index(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 2 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (hash, index): (H256, Index) = params.parse()?;
+        self.transaction_by_position(PositionBlock::Hash(hash), index)
+    }
+
+    pub fn get_transaction_by_block_number_and_index(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        if 2 != self.params_len(&req_rpc.params) {
+            return Err(Error::invalid_params_len());
+        }
+
+        let params = self.detach_requeired_params(req_rpc)?;
+        let (number, index): (BlockNumber, Index) = params.parse()?;
+        self.transaction_by_position(PositionBlock::Number(number), index)
+    }
+
+    fn transaction_by_position(&self, block: PositionBlock, index: Index) -> Result<reqlib::Request, Error> {
+        let position = TransactionPosition {
+            block: block,
+            index: index.value() as u64,
+        };
+        let mut request = self.create_request();
+        serde_json::to_string(&position)
+            .map_err(|err| Error::invalid_params(err.to_string()))
+            .map(|position| {
+                request.set_transaction_by_position(position);
+                request
+            })
+    }
+
+    /// Subscriptions are streamed over websocket, so they aren't dispatched by `request`
+    pub fn subscribe(&self, req_rpc: &Call) -> Result<reqlib::Request, Error> {
+        let len = self.params_len(&req_rpc.params);
+        if 1 != len && 2 != len {
+            return Err(Error::invalid_params_len());
+        }
+        let params = self.detach_requeired_params(req_rpc)?;
+        let mut request = self.create_request();
+        if 1 == len {
+            // Hashes of txs dropped from the chain when the block holding them was replaced
+            let (kind,): (String,) = params.parse()?;
+            if kind != "revertedTransactions" {
+                return Err(Error::invalid_params(format!("unsupported subscription {}", kind)));
+            }
+            request.set_subscribe_reverted_txs(true);
+            return Ok(request);
+        }
+
+        let (kind, filter): (String, Filter) = params.parse()?;
+        if kind != "logs" {
+            return Err(Error::invalid_params(format!("unsupported subscription {}", kind)));
+        }
+        let filter = serde_json::to_string(&filter).map_err(|err| Error::invalid_params(format!("{:?}", err)))?;
+        request.set_subscribe_filter(filter);
+        Ok(request)
+    }
 }
 
 //以后把这种测试，放到单独的测试文件。
@@ -397,18 +944,33 @@ mod tests {
         let rpc_body = r#"{"jsonrpc":"2.0","method":"cita_blockNumber","params":[],"id":"1"}"#;
         let rpc: Call = serde_json::from_str(rpc_body).unwrap();
 
-        assert_eq!(rpc.id, Id::Str("1".to_string()));
+        assert_eq!(rpc.id, Some(Id::Str("1".to_string())));
         assert_eq!(rpc.jsonrpc, Some(Version::V2));
         assert_eq!(rpc.method, "cita_blockNumber".to_string());
         assert_eq!(rpc.params.unwrap(), Params::None);
     }
 
+    #[test]
+    fn test_notification_has_no_id() {
+        let call: Call = serde_json::from_str(r#"{"jsonrpc":"2.0","method":"cita_blockNumber","params":[]}"#).unwrap();
+        assert_eq!(call.id, None);
+        assert!(call.is_notification());
+        assert_eq!(call.answer_id(), Id::Null);
+
+        // A null id is still answered
+        let call: Call =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","method":"cita_blockNumber","params":[],"id":null}"#).unwrap();
+        assert_eq!(call.id, Some(Id::Null));
+        assert!(!call.is_notification());
+        assert!(serde_json::to_string(&call).unwrap().contains(r#""id":null"#));
+    }
+
     #[test]
     fn test_rpc_deserialize() {
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: method::CITA_BLOCK_BUMBER.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![])),
         };
 
@@ -424,7 +986,7 @@ mod tests {
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: method::CITA_BLOCK_BUMBER.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::None),
         };
 
@@ -440,7 +1002,7 @@ mod tests {
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: method::CITA_BLOCK_BUMBER.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![])),
         };
 
@@ -452,12 +1014,191 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_block_number_include_header() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_BLOCK_BUMBER.to_owned(),
+            id: Some(Id::Str("2".to_string())),
+            params: Some(Params::Array(params)),
+        };
+
+        let handler = MethodHandler;
+        assert!(handler.request(&rpc(vec![])).unwrap().has_block_number());
+        assert!(handler.request(&rpc(vec![Value::from(false)])).unwrap().has_block_number());
+        assert!(handler.request(&rpc(vec![Value::from(true)])).unwrap().has_block_head());
+        assert!(handler.request(&rpc(vec![Value::from(1)])).is_err());
+        assert!(handler.request(&rpc(vec![Value::from(true), Value::from(true)])).is_err());
+    }
+
+    #[test]
+    fn test_net_version() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::NET_VERSION.to_owned(),
+            id: Some(Id::Str("2".to_string())),
+            params: Some(Params::Array(params)),
+        };
+
+        let handler = MethodHandler;
+        assert!(handler.request(&rpc(vec![])).unwrap().has_net_version());
+        assert!(handler.request(&rpc(vec![Value::from(1)])).is_err());
+    }
+
+    #[test]
+    fn test_subscribe_logs() {
+        let subscribe = |kind: &str| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::ETH_SUBSCRIBE.to_owned(),
+            id: Some(Id::Str("2".to_string())),
+            params: Some(Params::Array(vec![
+                Value::from(kind),
+                serde_json::from_str(r#"{"address":"0x0000000000000000000000000000000000000001"}"#).unwrap(),
+            ])),
+        };
+
+        let handler = MethodHandler;
+        let request = handler.subscribe(&subscribe("logs")).unwrap();
+        assert!(request.has_subscribe_filter());
+        assert!(handler.subscribe(&subscribe("newPendingTransactions")).is_err());
+        // Only served over websocket
+        assert!(handler.request(&subscribe("logs")).is_err());
+    }
+
+    #[test]
+    fn test_subscribe_reverted_transactions() {
+        let subscribe = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::ETH_SUBSCRIBE.to_owned(),
+            id: Some(Id::Str("3".to_string())),
+            params: Some(Params::Array(params)),
+        };
+
+        let handler = MethodHandler;
+        let request = handler
+            .subscribe(&subscribe(vec![Value::from("revertedTransactions")]))
+            .unwrap();
+        assert!(request.get_subscribe_reverted_txs());
+        assert!(handler.subscribe(&subscribe(vec![Value::from("logs")])).is_err());
+        assert!(handler.subscribe(&subscribe(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_receipt_with_abi() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::ETH_GET_TRANSACTION_RECEIPT.to_owned(),
+            id: Some(Id::Str("5".to_string())),
+            params: Some(Params::Array(params)),
+        };
+        let hash = Value::from("0x0000000000000000000000000000000000000000000000000000000000000001");
+        let abi: Value = serde_json::from_str(r#"[{"type":"event","name":"A","inputs":[]}]"#).unwrap();
+
+        let handler = MethodHandler;
+        assert!(handler.request(&rpc(vec![hash.clone()])).unwrap().has_transaction_receipt());
+        let request = handler.request(&rpc(vec![hash, abi.clone()])).unwrap();
+        let params: ReceiptParams = serde_json::from_str(request.get_decoded_receipt()).unwrap();
+        assert_eq!(params.hash, H256::from(1));
+        assert_eq!(serde_json::from_str::<Value>(&params.abi).unwrap(), abi);
+    }
+
+    #[test]
+    fn test_has_logs() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_HAS_LOGS.to_owned(),
+            id: Some(Id::Str("6".to_string())),
+            params: Some(Params::Array(params)),
+        };
+        let address = Value::from("0x0000000000000000000000000000000000000005");
+
+        let handler = MethodHandler;
+        let request = handler
+            .request(&rpc(vec![address.clone(), Value::from("0x1"), Value::from("latest")]))
+            .unwrap();
+        let params: HasLogsParams = serde_json::from_str(request.get_has_logs()).unwrap();
+        assert_eq!(params.address, H160::from(5));
+        assert_eq!(params.from_block, BlockNumber::Height(1));
+        assert_eq!(params.to_block, BlockNumber::Tag(::rpctypes::BlockTag::Latest));
+        assert!(handler.request(&rpc(vec![address])).is_err());
+    }
+
+    #[test]
+    fn test_get_transaction_receipts() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_GET_TRANSACTION_RECEIPTS.to_owned(),
+            id: Some(Id::Str("8".to_string())),
+            params: Some(Params::Array(params)),
+        };
+        let hashes: Value = serde_json::from_str(
+            r#"["0x0000000000000000000000000000000000000000000000000000000000000001",
+                "0x0000000000000000000000000000000000000000000000000000000000000002"]"#,
+        ).unwrap();
+
+        let handler = MethodHandler;
+        let request = handler.request(&rpc(vec![hashes.clone()])).unwrap();
+        let params: Vec<H256> = serde_json::from_str(request.get_transaction_receipts()).unwrap();
+        assert_eq!(params, vec![H256::from(1), H256::from(2)]);
+        assert!(handler.request(&rpc(vec![Value::from("0x1")])).is_err());
+        assert!(handler.request(&rpc(vec![hashes.clone(), hashes])).is_err());
+    }
+
+    #[test]
+    fn test_get_meta_data() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_GET_META_DATA.to_owned(),
+            id: Some(Id::Str("9".to_string())),
+            params: Some(Params::Array(params)),
+        };
+
+        let handler = MethodHandler;
+        assert!(handler.request(&rpc(vec![])).unwrap().get_meta_data());
+        assert!(handler.request(&rpc(vec![Value::from("latest")])).is_err());
+    }
+
+    #[test]
+    fn test_get_genesis() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_GET_GENESIS.to_owned(),
+            id: Some(Id::Str("7".to_string())),
+            params: Some(Params::Array(params)),
+        };
+
+        let handler = MethodHandler;
+        assert!(handler.request(&rpc(vec![])).unwrap().get_genesis());
+        assert!(handler.request(&rpc(vec![Value::from("0x0")])).is_err());
+    }
+
+    #[test]
+    fn test_simulate_transaction() {
+        let rpc = |params: Vec<Value>| Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_SIMULATE_TRANSACTION.to_owned(),
+            id: Some(Id::Str("4".to_string())),
+            params: Some(Params::Array(params)),
+        };
+        let call_request: Value =
+            serde_json::from_str(r#"{"to":"0x0000000000000000000000000000000000000001","data":"0x60fe47b1"}"#).unwrap();
+
+        let handler = MethodHandler;
+        let request = handler
+            .request(&rpc(vec![call_request.clone(), Value::from("latest")]))
+            .unwrap();
+        assert!(request.has_simulate());
+        assert_eq!(request.get_simulate().get_data(), &[0x60, 0xfe, 0x47, 0xb1]);
+        assert!(handler.request(&rpc(vec![call_request])).unwrap().has_simulate());
+        assert!(handler.request(&rpc(vec![])).is_err());
+    }
+
     #[test]
     fn test_rpc_into_err() {
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: method::ETH_GET_TRANSACTION_RECEIPT.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![Value::from(2)])),
         };
 
@@ -471,7 +1212,7 @@ mod tests {
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: "cita_xxx".to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![])),
         };
 
@@ -493,7 +1234,7 @@ mod tests {
         let rpc1 = Call {
             jsonrpc: Some(Version::V2),
             method: method::CITA_SEND_TRANSACTION.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![
                 Value::from(utx_string.to_hex().to_owned()),
             ])),
@@ -502,16 +1243,29 @@ mod tests {
         let rpc2 = Call {
             jsonrpc: Some(Version::V2),
             method: method::CITA_SEND_TRANSACTION.to_owned(),
-            id: Id::Str("2".to_string()),
+            id: Some(Id::Str("2".to_string())),
             params: Some(Params::Array(vec![
                 Value::from(clean_0x(&utx_string.to_hex()).to_owned()),
             ])),
         };
+        let rpc3 = Call {
+            jsonrpc: Some(Version::V2),
+            method: method::CITA_SEND_TRANSACTION.to_owned(),
+            id: Some(Id::Str("3".to_string())),
+            params: Some(Params::Array(vec![
+                Value::from(utx_string.to_hex().to_owned()),
+                Value::from("retry-1"),
+            ])),
+        };
         let handler = MethodHandler;
         let result1: Result<reqlib::Request, Error> = handler.send_transaction(&rpc1);
         let result2: Result<reqlib::Request, Error> = handler.send_transaction(&rpc2);
+        let result3: Result<reqlib::Request, Error> = handler.send_transaction(&rpc3);
         assert!(result1.is_ok());
         assert!(result2.is_ok());
+        assert!(result3.is_ok());
+        assert_eq!(handler.idempotency_key(&rpc1), None);
+        assert_eq!(handler.idempotency_key(&rpc3), Some("retry-1".to_owned()));
     }
 
     #[test]
@@ -30,10 +30,31 @@ pub enum Version {
 pub struct Call {
     pub jsonrpc: Option<Version>,
     pub method: String,
-    pub id: Id,
+    /// A call without `id` is a notification, one with a `null` id is answered
+    #[serde(default, deserialize_with = "deserialize_present_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<Id>,
     pub params: Option<Params>,
 }
 
+/// An `id` which is present, `null` included, as only a missing one is None
+fn deserialize_present_id<'a, D>(deserializer: D) -> Result<Option<Id>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    Id::deserialize(deserializer).map(Some)
+}
+
+impl Call {
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+
+    /// The id the call is answered with, `null` if it has none
+    pub fn answer_id(&self) -> Id {
+        self.id.clone().unwrap_or_default()
+    }
+}
+
 /// Represents jsonrpc request.
 #[derive(Debug, Clone, PartialEq)]
 pub enum RpcRequest {
@@ -20,13 +20,15 @@ use bytes::Bytes;
 use error::Error;
 use libproto::response::{Response, Response_oneof_data};
 use request::Version;
-use rpctypes::{Block, FilterChanges, Log, Receipt, RpcBlock, RpcTransaction, TxResponse};
+use rpctypes::{Block, BlockHead, ChainStats, FilterChanges, GasLimits, Genesis, Heights, Log, LogsWithBloom, MetaData,
+               Peers, Receipt, RejectedTransaction, RejectionRate, Reverification, RpcBlock, RpcTransaction, Simulation,
+               StateRoots, TransactionProof, TxResponse, VerifyStats};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::Error as SError;
 use serde_json;
 use serde_json::{from_value, Value};
 use std::vec::Vec;
-use util::U256;
+use util::{H160, U256};
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -46,6 +48,31 @@ pub enum ResultBody {
     UninstallFliter(bool),
     FilterChanges(FilterChanges),
     FilterLog(Vec<Log>),
+    Validators(Vec<H160>),
+    TransactionsRootMatched(bool),
+    GasLimits(GasLimits),
+    Peers(Peers),
+    StateRoots(StateRoots),
+    TransactionProof(TransactionProof),
+    ChainStats(ChainStats),
+    NetVersion(String),
+    Blocks(Vec<Block>),
+    RejectedTransactions(Vec<RejectedTransaction>),
+    Heights(Heights),
+    LogsWithBloom(LogsWithBloom),
+    BlockHead(BlockHead),
+    EncodedSize(U256),
+    BlockTransactionCount(U256),
+    RejectionRate(RejectionRate),
+    Reverification(Reverification),
+    VerifyStats(VerifyStats),
+    Simulation(Simulation),
+    HasLogs(bool),
+    Genesis(Genesis),
+    Receipts(Vec<Option<Receipt>>),
+    MetaData(MetaData),
+    /// Block with only the fields selected by the client, keep it last as it matches anything
+    BlockFields(Value),
 }
 
 impl Default for ResultBody {
@@ -113,12 +140,31 @@ impl Output {
                     Response_oneof_data::block_number(bn) => success
                         .set_result(ResultBody::BlockNumber(U256::from(bn)))
                         .output(),
+                    Response_oneof_data::block_tx_count(count) => success
+                        .set_result(ResultBody::BlockTransactionCount(U256::from(count)))
+                        .output(),
+                    Response_oneof_data::encoded_size(size) => success
+                        .set_result(ResultBody::EncodedSize(U256::from(size)))
+                        .output(),
+                    Response_oneof_data::block_head(head) => success
+                        .set_result(ResultBody::BlockHead(
+                            serde_json::from_str::<BlockHead>(&head).unwrap(),
+                        ))
+                        .output(),
                     Response_oneof_data::none(_) => success.output(),
                     Response_oneof_data::block(rpc_block) => {
                         let rpc_block: RpcBlock = serde_json::from_str(&rpc_block).unwrap();
-                        success
-                            .set_result(ResultBody::FullBlock(rpc_block.into()))
-                            .output()
+                        match rpc_block.fields.clone() {
+                            Some(fields) => {
+                                let block: Block = rpc_block.into();
+                                success
+                                    .set_result(ResultBody::BlockFields(block.select_fields(&fields)))
+                                    .output()
+                            }
+                            None => success
+                                .set_result(ResultBody::FullBlock(rpc_block.into()))
+                                .output(),
+                        }
                     }
                     Response_oneof_data::ts(x) => success
                         .set_result(ResultBody::Transaction(RpcTransaction::from(x)))
@@ -129,11 +175,16 @@ impl Output {
                     Response_oneof_data::call_result(x) => success
                         .set_result(ResultBody::CallResult(Bytes::from(x)))
                         .output(),
-                    Response_oneof_data::logs(serialized) => success
-                        .set_result(ResultBody::Logs(
-                            serde_json::from_str::<Vec<Log>>(&serialized).unwrap(),
-                        ))
-                        .output(),
+                    Response_oneof_data::logs(serialized) => {
+                        // Logs come with the bloom of the range if the filter asked for it
+                        let result = match serde_json::from_str::<Vec<Log>>(&serialized) {
+                            Ok(logs) => ResultBody::Logs(logs),
+                            Err(_) => ResultBody::LogsWithBloom(
+                                serde_json::from_str::<LogsWithBloom>(&serialized).unwrap(),
+                            ),
+                        };
+                        success.set_result(result).output()
+                    }
                     Response_oneof_data::receipt(serialized) => success
                         .set_result(
                             serde_json::from_str::<Receipt>(&serialized)
@@ -165,6 +216,94 @@ impl Output {
                             serde_json::from_str::<Vec<Log>>(&log).unwrap(),
                         ))
                         .output(),
+                    Response_oneof_data::validators(validators) => success
+                        .set_result(ResultBody::Validators(
+                            serde_json::from_str::<Vec<H160>>(&validators).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::transactions_root_matched(matched) => success
+                        .set_result(ResultBody::TransactionsRootMatched(matched))
+                        .output(),
+                    Response_oneof_data::has_logs(has_logs) => success
+                        .set_result(ResultBody::HasLogs(has_logs))
+                        .output(),
+                    Response_oneof_data::receipts(receipts) => success
+                        .set_result(ResultBody::Receipts(
+                            serde_json::from_str::<Vec<Option<Receipt>>>(&receipts).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::meta_data(metadata) => success
+                        .set_result(ResultBody::MetaData(
+                            serde_json::from_str::<MetaData>(&metadata).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::genesis(genesis) => success
+                        .set_result(ResultBody::Genesis(
+                            serde_json::from_str::<Genesis>(&genesis).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::gas_limits(limits) => success
+                        .set_result(ResultBody::GasLimits(
+                            serde_json::from_str::<GasLimits>(&limits).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::peers(peers) => success
+                        .set_result(ResultBody::Peers(serde_json::from_str::<Peers>(&peers).unwrap()))
+                        .output(),
+                    Response_oneof_data::state_roots(roots) => success
+                        .set_result(ResultBody::StateRoots(
+                            serde_json::from_str::<StateRoots>(&roots).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::transaction_proof(proof) => success
+                        .set_result(ResultBody::TransactionProof(
+                            serde_json::from_str::<TransactionProof>(&proof).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::chain_stats(stats) => success
+                        .set_result(ResultBody::ChainStats(
+                            serde_json::from_str::<ChainStats>(&stats).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::blocks(blocks) => {
+                        let blocks: Vec<RpcBlock> = serde_json::from_str(&blocks).unwrap();
+                        success
+                            .set_result(ResultBody::Blocks(blocks.into_iter().map(Block::from).collect()))
+                            .output()
+                    }
+                    Response_oneof_data::rejected_txs(rejected) => success
+                        .set_result(ResultBody::RejectedTransactions(
+                            serde_json::from_str::<Vec<RejectedTransaction>>(&rejected).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::rejection_rate(rate) => success
+                        .set_result(ResultBody::RejectionRate(
+                            serde_json::from_str::<RejectionRate>(&rate).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::reverification(reverification) => success
+                        .set_result(ResultBody::Reverification(
+                            serde_json::from_str::<Reverification>(&reverification).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::verify_stats(stats) => success
+                        .set_result(ResultBody::VerifyStats(
+                            serde_json::from_str::<VerifyStats>(&stats).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::simulation(simulation) => success
+                        .set_result(ResultBody::Simulation(
+                            serde_json::from_str::<Simulation>(&simulation).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::heights(heights) => success
+                        .set_result(ResultBody::Heights(
+                            serde_json::from_str::<Heights>(&heights).unwrap(),
+                        ))
+                        .output(),
+                    Response_oneof_data::net_version(version) => success
+                        .set_result(ResultBody::NetVersion(version.to_string()))
+                        .output(),
                     Response_oneof_data::error_msg(err_msg) => Output::Failure(RpcFailure::from_options(
                         id.clone(),
                         jsonrpc.clone(),
@@ -304,4 +443,16 @@ mod tests {
         let rpc_body = serde_json::to_string(&rpc).unwrap();
         assert_eq!(rpc_body, r#"{"jsonrpc":"2.0","id":"2","result":"0x3"}"#);
     }
+
+    #[test]
+    fn test_net_version_serialize() {
+        let rpc = RpcSuccess {
+            jsonrpc: Some(Version::V2),
+            id: Id::Num(67),
+            result: ResultBody::NetVersion("1".to_string()),
+        };
+
+        let rpc_body = serde_json::to_string(&rpc).unwrap();
+        assert_eq!(rpc_body, r#"{"jsonrpc":"2.0","id":67,"result":"1"}"#);
+    }
 }
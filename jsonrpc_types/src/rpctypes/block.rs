@@ -19,6 +19,7 @@ use super::{BlockTransaction, FullTransaction};
 use super::Proof;
 use super::RpcBlock;
 use libproto::{Block as ProtoBlock, BlockHeader as ProtoBlockHeader};
+use serde_json::{self, Map, Value};
 use std::convert::TryFrom;
 use util::{H256, U256};
 
@@ -39,6 +40,23 @@ pub struct BlockHeader {
     pub proof: Option<Proof>,
 }
 
+/// Fields of a block which clients can select, including the ones nested in header and body
+pub const BLOCK_FIELDS: &[&str] = &[
+    "version",
+    "hash",
+    "header",
+    "body",
+    "timestamp",
+    "prevHash",
+    "number",
+    "stateRoot",
+    "transactionsRoot",
+    "receiptsRoot",
+    "gasUsed",
+    "proof",
+    "transactions",
+];
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub version: u32,
@@ -47,6 +65,38 @@ pub struct Block {
     pub body: BlockBody,
 }
 
+/// The head block returned by `cita_blockNumber` when its header is asked for
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BlockHead {
+    pub number: U256,
+    pub hash: H256,
+    pub header: BlockHeader,
+}
+
+impl Block {
+    /// Render only the given fields, header and body fields stay nested in their parent
+    pub fn select_fields(&self, fields: &[String]) -> Value {
+        let wanted = |name: &str| fields.iter().any(|field| field == name);
+        let mut selected = Map::new();
+        if let Value::Object(block) = serde_json::to_value(self).expect("block can be serialized") {
+            for (key, value) in block {
+                if wanted(&key) {
+                    selected.insert(key, value);
+                } else if let Value::Object(inner) = value {
+                    let inner: Map<String, Value> = inner
+                        .into_iter()
+                        .filter(|&(ref name, _)| wanted(name))
+                        .collect();
+                    if !inner.is_empty() {
+                        selected.insert(key, Value::Object(inner));
+                    }
+                }
+            }
+        }
+        Value::Object(selected)
+    }
+}
+
 impl From<ProtoBlockHeader> for BlockHeader {
     fn from(proto_header: ProtoBlockHeader) -> Self {
         let proof: Option<Proof> = match proto_header.get_height() {
@@ -100,3 +150,36 @@ impl From<RpcBlock> for Block {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn select_block_fields() {
+        let block = Block {
+            version: 0,
+            hash: H256::from(U256::from(1)),
+            header: BlockHeader {
+                timestamp: 1516000000000,
+                prev_hash: H256::from(U256::from(2)),
+                number: U256::from(10),
+                state_root: H256::default(),
+                transactions_root: H256::default(),
+                receipts_root: H256::default(),
+                gas_used: U256::zero(),
+                proof: None,
+            },
+            body: BlockBody {
+                transactions: vec![],
+            },
+        };
+        let fields = vec!["hash".to_owned(), "timestamp".to_owned()];
+        let selected = block.select_fields(&fields);
+        assert_eq!(
+            serde_json::to_string(&selected).unwrap(),
+            r#"{"hash":"0x0000000000000000000000000000000000000000000000000000000000000001","header":{"timestamp":1516000000000}}"#
+        );
+    }
+}
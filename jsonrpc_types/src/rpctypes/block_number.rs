@@ -25,6 +25,8 @@ use util::clean_0x;
 pub enum BlockTag {
     #[serde(rename = "latest")] Latest,
     #[serde(rename = "earliest")] Earliest,
+    /// Blocks are only visible once executed, so this is the latest block
+    #[serde(rename = "pending")] Pending,
 }
 
 /// Represents rpc api block height param.
@@ -69,7 +71,7 @@ impl<'a> Visitor<'a> for BlockNumberVisitor {
     type Value = BlockNumber;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "a hex block number or 'latest', 'earliest'")
+        write!(formatter, "a hex block number or 'latest', 'earliest', 'pending'")
     }
 
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
@@ -79,6 +81,7 @@ impl<'a> Visitor<'a> for BlockNumberVisitor {
         match value {
             "latest" => Ok(BlockNumber::Tag(BlockTag::Latest)),
             "earliest" => Ok(BlockNumber::Tag(BlockTag::Earliest)),
+            "pending" => Ok(BlockNumber::Tag(BlockTag::Pending)),
             _ => {
                 let val = clean_0x(value);
                 u64::from_str_radix(&val[0..], 16)
@@ -100,7 +103,7 @@ impl Into<BlockId> for BlockNumber {
     fn into(self) -> BlockId {
         match self {
             BlockNumber::Height(n) => BlockId::Number(n),
-            BlockNumber::Tag(BlockTag::Latest) => BlockId::Latest,
+            BlockNumber::Tag(BlockTag::Latest) | BlockNumber::Tag(BlockTag::Pending) => BlockId::Latest,
             BlockNumber::Tag(BlockTag::Earliest) => BlockId::Earliest,
         }
     }
@@ -129,6 +132,23 @@ mod tests {
         )
     }
 
+    #[test]
+    fn tag_deserialization() {
+        let s3 = r#"["latest", "earliest", "pending"]"#;
+        let deserialized3: Vec<BlockNumber> = serde_json::from_str(s3).unwrap();
+        assert_eq!(
+            deserialized3,
+            vec![
+                BlockNumber::Tag(BlockTag::Latest),
+                BlockNumber::Tag(BlockTag::Earliest),
+                BlockNumber::Tag(BlockTag::Pending),
+            ]
+        );
+        let ids: Vec<BlockId> = deserialized3.into_iter().map(Into::into).collect();
+        assert_eq!(ids, vec![BlockId::Latest, BlockId::Earliest, BlockId::Latest]);
+        assert!(serde_json::from_str::<BlockNumber>(r#""safe""#).is_err());
+    }
+
     #[test]
     fn upper_hex_number_deserialization() {
         let s3 = r#"["0XA", "latest"]"#;
@@ -0,0 +1,53 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use util::U256;
+
+/// Key figures of the chain for dashboards
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ChainStats {
+    pub height: U256,
+    #[serde(rename = "totalTransactions")]
+    pub total_transactions: U256,
+    /// Average interval of the latest blocks in milliseconds, null until there are two blocks
+    #[serde(rename = "averageBlockTime")]
+    pub average_block_time: Option<U256>,
+    /// New txs buffered by jsonrpc and not yet sent to auth
+    #[serde(rename = "pendingTransactions")]
+    pub pending_transactions: U256,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn chain_stats_serialization() {
+        let stats = ChainStats {
+            height: U256::from(16),
+            total_transactions: U256::from(100),
+            average_block_time: Some(U256::from(3000)),
+            pending_transactions: U256::from(2),
+        };
+        let serialized = serde_json::to_string(&stats).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"height":"0x10","totalTransactions":"0x64","averageBlockTime":"0xbb8","pendingTransactions":"0x2"}"#
+        );
+        assert_eq!(serde_json::from_str::<ChainStats>(&serialized).unwrap(), stats);
+    }
+}
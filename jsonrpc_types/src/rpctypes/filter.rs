@@ -83,6 +83,9 @@ pub struct Filter {
     pub topics: Option<Vec<Topic>>,
     /// Limit
     pub limit: Option<usize>,
+    /// Return the combined logs bloom of the range with the logs, only for `eth_getLogs`
+    #[serde(rename = "includeBloom")]
+    pub include_bloom: Option<bool>,
 }
 
 impl Into<EthFilter> for Filter {
@@ -122,6 +125,14 @@ impl Into<EthFilter> for Filter {
     }
 }
 
+/// New block hashes of a block filter which fell too far behind.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct HashesWithGap {
+    /// Number of blocks skipped before the returned hashes
+    pub gap: u64,
+    pub hashes: Vec<H256>,
+}
+
 // Results of the filter_changes RPC.
 #[derive(Debug, PartialEq, Clone)]
 pub enum FilterChanges {
@@ -129,6 +140,8 @@ pub enum FilterChanges {
     Logs(Vec<Log>),
     /// New hashes (block or transactions)
     Hashes(Vec<H256>),
+    /// Latest block hashes, older ones were dropped
+    HashesWithGap(HashesWithGap),
     /// Empty result,
     Empty,
 }
@@ -141,6 +154,7 @@ impl Serialize for FilterChanges {
         match *self {
             FilterChanges::Logs(ref logs) => logs.serialize(s),
             FilterChanges::Hashes(ref hashes) => hashes.serialize(s),
+            FilterChanges::HashesWithGap(ref hashes) => hashes.serialize(s),
             FilterChanges::Empty => (&[] as &[Value]).serialize(s),
         }
     }
@@ -163,6 +177,9 @@ impl<'de> Deserialize<'de> for FilterChanges {
                         .map_err(|_| D::Error::custom("Invalid type."))
                 }
             }
+            Value::Object(_) => from_value(v)
+                .map(FilterChanges::HashesWithGap)
+                .map_err(|_| D::Error::custom("Invalid type.")),
             Value::Null => Ok(FilterChanges::Empty),
             _ => Err(D::Error::custom("Invalid type.")),
         }
@@ -171,7 +188,7 @@ impl<'de> Deserialize<'de> for FilterChanges {
 
 #[cfg(test)]
 mod tests {
-    use super::{Filter, FilterChanges, Log, Topic, VariadicValue};
+    use super::{Filter, FilterChanges, HashesWithGap, Log, Topic, VariadicValue};
     use rpctypes::block_number::{BlockNumber, BlockTag};
     use serde_json;
     use std::str::FromStr;
@@ -219,6 +236,7 @@ mod tests {
                 address: None,
                 topics: None,
                 limit: None,
+                include_bloom: None,
             }
         );
     }
@@ -241,6 +259,7 @@ mod tests {
                     ),
                 ]),
                 limit: None,
+                include_bloom: None,
             }
         );
     }
@@ -261,6 +280,7 @@ mod tests {
                 VariadicValue::Null,
             ]),
             limit: None,
+            include_bloom: None,
         };
 
         let eth_filter: EthFilter = filter.into();
@@ -302,6 +322,14 @@ mod tests {
             serde_json::from_str("[\"0x000000000000000000000000a94f5374fce5edbc8e2a8697c15331677e6ebf0b\"]").unwrap()
         );
 
+        let gap = FilterChanges::HashesWithGap(HashesWithGap {
+            gap: 3,
+            hashes: vec!["000000000000000000000000a94f5374fce5edbc8e2a8697c15331677e6ebf0b".into()],
+        });
+        let s = "{\"gap\":3,\"hashes\":[\"0x000000000000000000000000a94f5374fce5edbc8e2a8697c15331677e6ebf0b\"]}";
+        assert_eq!(s, serde_json::to_string(&gap).unwrap());
+        assert_eq!(gap, serde_json::from_str(s).unwrap());
+
         let s = "[{\"address\":\"0x33990122638b9132ca29c723bdf037f1a891a70c\",\
                  \"topics\":[\"0xa6697e974e6a320f454390be03f74955e8978f1a6971ea6730542e37b66179bc\",\
                  \"0x4861736852656700000000000000000000000000000000000000000000000000\"],\
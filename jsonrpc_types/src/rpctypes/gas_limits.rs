@@ -0,0 +1,31 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use util::U256;
+
+/// Gas limits which applied at a block
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct GasLimits {
+    #[serde(rename = "blockGasLimit")]
+    pub block_gas_limit: U256,
+    #[serde(rename = "accountGasLimit")]
+    pub account_gas_limit: U256,
+    /// Limits of the accounts which don't use the common account gas limit
+    #[serde(rename = "specificGasLimit")]
+    pub specific_gas_limit: HashMap<String, U256>,
+}
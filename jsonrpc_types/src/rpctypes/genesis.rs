@@ -0,0 +1,56 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use util::{H160, H256, U256};
+
+/// Genesis block of the chain and the setup it started with
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Genesis {
+    pub hash: H256,
+    pub timestamp: U256,
+    pub prevhash: H256,
+    #[serde(rename = "stateRoot")]
+    pub state_root: H256,
+    /// Proof type checked by the chain, null if it's not a known one
+    pub consensus: Option<String>,
+    /// Validators of the genesis block, null if it was written before they were recorded
+    pub validators: Option<Vec<H160>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn genesis_serialization() {
+        let genesis = Genesis {
+            hash: H256::from(1),
+            timestamp: U256::from(1_524_000_000_000u64),
+            prevhash: H256::zero(),
+            state_root: H256::from(2),
+            consensus: Some("Tendermint".to_owned()),
+            validators: Some(vec![H160::from(3)]),
+        };
+        let serialized = serde_json::to_string(&genesis).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"hash":"0x0000000000000000000000000000000000000000000000000000000000000001","timestamp":"0x162d57a8800","prevhash":"0x0000000000000000000000000000000000000000000000000000000000000000","stateRoot":"0x0000000000000000000000000000000000000000000000000000000000000002","consensus":"Tendermint","validators":["0x0000000000000000000000000000000000000003"]}"#
+        );
+        assert_eq!(serde_json::from_str::<Genesis>(&serialized).unwrap(), genesis);
+    }
+}
@@ -0,0 +1,52 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use util::U256;
+
+/// Both notions of height the chain keeps, `blockNumber` falls back from one to the other
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Heights {
+    /// Highest block stored for execution, null before any block is stored
+    #[serde(rename = "maxStoreHeight")]
+    pub max_store_height: Option<U256>,
+    /// Highest block executed
+    #[serde(rename = "maxHeight")]
+    pub max_height: U256,
+    /// Height of the current header
+    #[serde(rename = "currentHeight")]
+    pub current_height: U256,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn heights_serialization() {
+        let heights = Heights {
+            max_store_height: None,
+            max_height: U256::from(16),
+            current_height: U256::from(16),
+        };
+        let serialized = serde_json::to_string(&heights).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"maxStoreHeight":null,"maxHeight":"0x10","currentHeight":"0x10"}"#
+        );
+        assert_eq!(serde_json::from_str::<Heights>(&serialized).unwrap(), heights);
+    }
+}
@@ -17,7 +17,7 @@
 
 use bytes::Bytes;
 use types::log_entry::{LocalizedLogEntry, LogEntry};
-use util::{H160, H256, U256};
+use util::{Bloom, H160, H256, U256};
 
 /// Log
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
@@ -48,6 +48,31 @@ pub struct Log {
     pub transaction_log_index: Option<U256>,
 }
 
+/// Logs of a filter with the logs bloom of every block in its range, returned if `includeBloom` is set
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct LogsWithBloom {
+    pub logs: Vec<Log>,
+    /// All the block blooms of the range combined, a block missing from the logs would show up in it
+    #[serde(rename = "logsBloom")]
+    pub logs_bloom: Bloom,
+}
+
+/// A log decoded against an event of an ABI
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DecodedLog {
+    /// Name of the event
+    pub event: String,
+    pub params: Vec<DecodedParam>,
+}
+
+/// A param of a decoded log, in the order the event declares them
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DecodedParam {
+    pub name: String,
+    /// The value as ethabi prints it
+    pub value: String,
+}
+
 impl From<LocalizedLogEntry> for Log {
     fn from(e: LocalizedLogEntry) -> Log {
         Log {
@@ -0,0 +1,54 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use util::{H256, U256};
+
+/// Static facts of the chain a client needs before sending txs
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MetaData {
+    /// Id of the chain shared with the network, null if it isn't configured
+    #[serde(rename = "chainId")]
+    pub chain_id: Option<U256>,
+    #[serde(rename = "genesisHash")]
+    pub genesis_hash: H256,
+    /// Proof type checked by the chain, null if it's not a known one
+    pub consensus: Option<String>,
+    /// Version of the chain service
+    pub version: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn metadata_serialization() {
+        let metadata = MetaData {
+            chain_id: Some(U256::from(1)),
+            genesis_hash: H256::from(2),
+            consensus: Some("Tendermint".to_owned()),
+            version: "0.6.0".to_owned(),
+        };
+        let serialized = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"chainId":"0x1","genesisHash":"0x0000000000000000000000000000000000000000000000000000000000000002","consensus":"Tendermint","version":"0.6.0"}"#
+        );
+        assert_eq!(serde_json::from_str::<MetaData>(&serialized).unwrap(), metadata);
+    }
+}
@@ -17,6 +17,7 @@
 
 use super::BlockNumber;
 use std::default::Default;
+use util::{H160, H256};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct CountOrCode {
@@ -46,6 +47,7 @@ impl Default for CountOrCode {
 pub struct BlockParamsByHash {
     pub hash: ::std::vec::Vec<u8>,
     pub include_txs: bool,
+    pub fields: Option<Vec<String>>,
 }
 
 impl BlockParamsByHash {
@@ -53,8 +55,14 @@ impl BlockParamsByHash {
         BlockParamsByHash {
             hash: hash,
             include_txs: include_txs,
+            fields: None,
         }
     }
+
+    pub fn set_fields(mut self, fields: Option<Vec<String>>) -> Self {
+        self.fields = fields;
+        self
+    }
 }
 
 impl Default for BlockParamsByHash {
@@ -62,6 +70,7 @@ impl Default for BlockParamsByHash {
         BlockParamsByHash {
             hash: vec![],
             include_txs: false,
+            fields: None,
         }
     }
 }
@@ -71,6 +80,7 @@ pub struct BlockParamsByNumber {
     // message fields
     pub block_id: BlockNumber,
     pub include_txs: bool,
+    pub fields: Option<Vec<String>>,
 }
 
 impl BlockParamsByNumber {
@@ -78,8 +88,14 @@ impl BlockParamsByNumber {
         BlockParamsByNumber {
             block_id: block_id,
             include_txs: include_txs,
+            fields: None,
         }
     }
+
+    pub fn set_fields(mut self, fields: Option<Vec<String>>) -> Self {
+        self.fields = fields;
+        self
+    }
 }
 
 impl Default for BlockParamsByNumber {
@@ -87,6 +103,7 @@ impl Default for BlockParamsByNumber {
         BlockParamsByNumber {
             block_id: BlockNumber::default(),
             include_txs: false,
+            fields: None,
         }
     }
 }
@@ -96,6 +113,8 @@ pub struct RpcBlock {
     pub block: ::std::vec::Vec<u8>,
     pub include_txs: bool,
     pub hash: ::std::vec::Vec<u8>,
+    /// Only render these fields of the block, all of them if None
+    pub fields: Option<Vec<String>>,
 }
 
 impl RpcBlock {
@@ -104,6 +123,41 @@ impl RpcBlock {
             block: block,
             include_txs: include_txs,
             hash: hash,
+            fields: None,
         }
     }
+
+    pub fn set_fields(mut self, fields: Option<Vec<String>>) -> Self {
+        self.fields = fields;
+        self
+    }
+}
+
+/// The block of `TransactionPosition`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum PositionBlock {
+    Hash(H256),
+    Number(BlockNumber),
+}
+
+/// A tx looked up by its block and its index in the block
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TransactionPosition {
+    pub block: PositionBlock,
+    pub index: u64,
+}
+
+/// A receipt with the ABI its logs are decoded against
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ReceiptParams {
+    pub hash: H256,
+    pub abi: String,
+}
+
+/// An address and the block range its logs are looked for in
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HasLogsParams {
+    pub address: H160,
+    pub from_block: BlockNumber,
+    pub to_block: BlockNumber,
 }
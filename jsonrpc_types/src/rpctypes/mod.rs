@@ -26,15 +26,35 @@ pub mod middle_modle;
 pub mod index;
 pub mod proof;
 pub mod tx_response;
+pub mod gas_limits;
+pub mod peers;
+pub mod state_roots;
+pub mod transaction_proof;
+pub mod chain_stats;
+pub mod rejected_transaction;
+pub mod heights;
+pub mod simulation;
+pub mod genesis;
+pub mod metadata;
 
 pub use self::block::*;
 pub use self::block_number::*;
 pub use self::call_request::*;
+pub use self::chain_stats::*;
 pub use self::filter::*;
+pub use self::gas_limits::*;
+pub use self::genesis::*;
+pub use self::heights::*;
 pub use self::index::Index;
 pub use self::log::*;
+pub use self::metadata::*;
 pub use self::middle_modle::*;
+pub use self::peers::*;
 pub use self::proof::*;
 pub use self::receipt::*;
+pub use self::rejected_transaction::*;
+pub use self::simulation::*;
+pub use self::state_roots::*;
 pub use self::transaction::*;
+pub use self::transaction_proof::*;
 pub use self::tx_response::*;
@@ -0,0 +1,25 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Identity of this node and the peers it heard from recently
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Peers {
+    /// Origin id of this node, None if it isn't configured
+    pub origin: Option<u32>,
+    /// Origins of the peers this node recently got messages from
+    pub peers: Vec<u32>,
+}
@@ -15,8 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use rpctypes::log::Log;
-use types::receipt::{LocalizedReceipt, Receipt as EthReceipt, RichReceipt};
+use rpctypes::log::{DecodedLog, Log};
+use types::receipt::{LocalizedReceipt, Receipt as EthReceipt, ReceiptError, RichReceipt};
 use util::{Bloom, H160, H256, U256};
 
 /// Receipt
@@ -54,6 +54,14 @@ pub struct Receipt {
     /// Receipt error message
     #[serde(rename = "errorMessage")]
     pub error_message: Option<String>,
+    /// Gas price paid by the transaction, there is no base fee on top of it
+    #[serde(rename = "effectiveGasPrice")]
+    pub effective_gas_price: Option<U256>,
+    /// `0x1` if the transaction succeeded, `0x0` if it failed
+    pub status: Option<U256>,
+    /// Logs decoded against the ABI given with the query, `null` for a log none of its events match
+    #[serde(rename = "decodedLogs")]
+    pub decoded_logs: Option<Vec<Option<DecodedLog>>>,
 }
 
 impl From<LocalizedReceipt> for Receipt {
@@ -69,7 +77,10 @@ impl From<LocalizedReceipt> for Receipt {
             logs: r.logs.into_iter().map(Into::into).collect(),
             state_root: r.state_root.map(Into::into),
             logs_bloom: r.log_bloom,
+            effective_gas_price: Some(r.gas_price),
+            status: Some(status(&r.error)),
             error_message: r.error.map(|error| error.description()),
+            decoded_logs: None,
         }
     }
 }
@@ -87,7 +98,10 @@ impl From<RichReceipt> for Receipt {
             logs: r.logs.into_iter().map(Into::into).collect(),
             state_root: r.state_root.map(Into::into),
             logs_bloom: r.log_bloom,
+            effective_gas_price: None,
+            status: Some(status(&r.error)),
             error_message: r.error.map(|error| error.description()),
+            decoded_logs: None,
         }
     }
 }
@@ -105,11 +119,22 @@ impl From<EthReceipt> for Receipt {
             logs: r.logs.into_iter().map(Into::into).collect(),
             state_root: r.state_root.map(Into::into),
             logs_bloom: r.log_bloom,
+            effective_gas_price: None,
+            status: Some(status(&r.error)),
             error_message: r.error.map(|error| error.description()),
+            decoded_logs: None,
         }
     }
 }
 
+fn status(error: &Option<ReceiptError>) -> U256 {
+    if error.is_none() {
+        U256::one()
+    } else {
+        U256::zero()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,6 +183,9 @@ mod tests {
             logs_bloom: Hash2048::from(15).into(),
             state_root: Some(Hash256::from(10).into()),
             error_message: None,
+            effective_gas_price: Some(0.into()),
+            status: Some(1.into()),
+            decoded_logs: None,
         };
 
         let serialized = serde_json::to_string(&receipt).unwrap();
@@ -165,6 +193,37 @@ mod tests {
         assert_eq!(deserialized, receipt);
     }
 
+    #[test]
+    fn localized_receipt_json_shape() {
+        let receipt: Receipt = LocalizedReceipt {
+            transaction_hash: Hash256::from(1),
+            transaction_index: 0,
+            block_hash: Hash256::from(2),
+            block_number: 3,
+            cumulative_gas_used: 0x20.into(),
+            gas_used: 0x10.into(),
+            contract_address: None,
+            gas_price: 0.into(),
+            logs: vec![],
+            log_bloom: Hash2048::from(15),
+            state_root: None,
+            error: Some(ReceiptError::OutOfGas),
+        }.into();
+
+        let json = serde_json::to_value(&receipt).unwrap();
+        let json = json.as_object().unwrap();
+        // Fields without a value are explicit nulls rather than absent keys
+        assert_eq!(json["contractAddress"], serde_json::Value::Null);
+        assert_eq!(json["root"], serde_json::Value::Null);
+        assert_eq!(json["cumulativeGasUsed"], "0x20");
+        assert_eq!(json["gasUsed"], "0x10");
+        assert_eq!(json["effectiveGasPrice"], "0x0");
+        assert_eq!(json["status"], "0x0");
+        assert_eq!(json["logs"], json!([]));
+        assert_eq!(json["logsBloom"], json!(Bloom::from(Hash2048::from(15))));
+        assert_eq!(json["blockNumber"], "0x3");
+    }
+
     #[test]
     fn test_bincode_deserialization() {
         let receipt = Receipt {
@@ -206,6 +265,9 @@ mod tests {
             logs_bloom: Hash2048::from(15).into(),
             state_root: Some(Hash256::from(10).into()),
             error_message: None,
+            effective_gas_price: Some(0.into()),
+            status: Some(1.into()),
+            decoded_logs: None,
         };
 
         println!("{:?}", receipt);
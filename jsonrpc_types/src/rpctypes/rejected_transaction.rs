@@ -0,0 +1,99 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use util::{H256, U256};
+
+/// A new tx auth refused, with the reason it gave
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RejectedTransaction {
+    pub hash: H256,
+    /// The `Ret` of the verification, e.g. `BadSig` or `Dup`
+    pub reason: String,
+    /// Unix time of the rejection in milliseconds
+    pub timestamp: U256,
+}
+
+/// How many of the latest verify requests auth rejected, with any reason, cached answers included
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RejectionRate {
+    pub rejected: U256,
+    /// Txs counted, at most the window auth keeps
+    pub total: U256,
+    /// `rejected / total`, 0 before any tx
+    pub rate: f64,
+}
+
+/// Outcome of verifying a tx again, regardless of the result auth cached for it
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Reverification {
+    pub hash: H256,
+    /// The `Ret` cached before, which was bypassed and replaced
+    pub cached_reason: Option<String>,
+    /// Whether the signer was cached before, which was bypassed and replaced
+    pub signer_cached: bool,
+    /// The `Ret` of verifying it again
+    pub reason: String,
+    /// Public key recovered from the signature, if it could be
+    pub signer: Option<Bytes>,
+}
+
+/// How auth answered the verify requests since it started, cached answers included
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyStats {
+    /// Requests answered with each `Ret`, e.g. `OK` or `BadSig`
+    pub by_reason: BTreeMap<String, U256>,
+    /// Requests of each verify type, `SingleVerify` for new txs and `BlockVerify` for txs in a proposal
+    pub by_type: BTreeMap<String, U256>,
+    /// Effectiveness of the verify result cache
+    pub cache: VerifyCacheStats,
+    /// Estimated milliseconds until the verifier holds the tx hashes of all the recent
+    /// blocks, 0 once it does, `None` before the backfill rate is known
+    pub init_eta: Option<U256>,
+}
+
+/// Lookups of the verify result cache since auth started
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VerifyCacheStats {
+    pub hits: U256,
+    pub misses: U256,
+    /// Number of cached results at the latest lookup
+    pub size: U256,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn rejected_transaction_serialization() {
+        let rejected = RejectedTransaction {
+            hash: H256::from(1),
+            reason: "BadSig".to_owned(),
+            timestamp: U256::from(1000),
+        };
+        let serialized = serde_json::to_string(&rejected).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"hash":"0x0000000000000000000000000000000000000000000000000000000000000001","reason":"BadSig","timestamp":"0x3e8"}"#
+        );
+        assert_eq!(serde_json::from_str::<RejectedTransaction>(&serialized).unwrap(), rejected);
+    }
+}
@@ -0,0 +1,64 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use bytes::Bytes;
+use util::{H160, H256, U256};
+
+/// A storage slot a simulated tx would write
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct StorageChange {
+    pub address: H160,
+    pub key: H256,
+    pub before: H256,
+    pub after: H256,
+}
+
+/// What a tx would do if it were executed on top of a block, nothing is committed.
+/// Only storage is covered, the sender's nonce bump from running the call is left out.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Simulation {
+    pub output: Bytes,
+    #[serde(rename = "gasUsed")] pub gas_used: U256,
+    /// Ordered by address, then key
+    #[serde(rename = "storageChanges")] pub storage_changes: Vec<StorageChange>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn simulation_serialization() {
+        let simulation = Simulation {
+            output: Bytes::new(vec![1]),
+            gas_used: U256::from(21000),
+            storage_changes: vec![
+                StorageChange {
+                    address: H160::from(1),
+                    key: H256::from(0),
+                    before: H256::from(0),
+                    after: H256::from(5),
+                },
+            ],
+        };
+        let serialized = serde_json::to_string(&simulation).unwrap();
+        assert!(serialized.contains(r#""gasUsed":"0x5208""#));
+        assert!(serialized.contains(r#""storageChanges":[{"address":"0x0000000000000000000000000000000000000001""#));
+        assert_eq!(serde_json::from_str::<Simulation>(&serialized).unwrap(), simulation);
+    }
+}
@@ -0,0 +1,67 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use rpctypes::BlockNumber;
+use util::H256;
+
+/// The two heights whose state roots are compared
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct StateRootsParams {
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+}
+
+/// State roots of two blocks and whether the state changed between them
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct StateRoots {
+    #[serde(rename = "fromStateRoot")]
+    pub from_state_root: H256,
+    #[serde(rename = "toStateRoot")]
+    pub to_state_root: H256,
+    pub changed: bool,
+}
+
+impl StateRoots {
+    pub fn new(from_state_root: H256, to_state_root: H256) -> Self {
+        StateRoots {
+            from_state_root: from_state_root,
+            to_state_root: to_state_root,
+            changed: from_state_root != to_state_root,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn state_roots_serialization() {
+        let unchanged = StateRoots::new(H256::from(1), H256::from(1));
+        assert!(!unchanged.changed);
+
+        let changed = StateRoots::new(H256::from(1), H256::from(2));
+        assert!(changed.changed);
+        let serialized = serde_json::to_string(&changed).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"fromStateRoot":"0x0000000000000000000000000000000000000000000000000000000000000001","toStateRoot":"0x0000000000000000000000000000000000000000000000000000000000000002","changed":true}"#
+        );
+        assert_eq!(serde_json::from_str::<StateRoots>(&serialized).unwrap(), changed);
+    }
+}
@@ -0,0 +1,68 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use util::{H256, U256};
+
+/// A sibling on the path from a tx hash to the transactions root
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MerkleNode {
+    pub hash: H256,
+    /// Whether the sibling is the left child, i.e. hashed before the running node
+    #[serde(rename = "isLeft")]
+    pub is_left: bool,
+}
+
+/// Merkle proof that a transaction is included in a block
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TransactionProof {
+    #[serde(rename = "blockNumber")]
+    pub block_number: U256,
+    #[serde(rename = "blockHash")]
+    pub block_hash: H256,
+    #[serde(rename = "transactionsRoot")]
+    pub transactions_root: H256,
+    pub index: U256,
+    /// Siblings ordered from the leaf up to the root
+    pub proof: Vec<MerkleNode>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn transaction_proof_serialization() {
+        let proof = TransactionProof {
+            block_number: U256::from(1),
+            block_hash: H256::from(2),
+            transactions_root: H256::from(3),
+            index: U256::from(1),
+            proof: vec![
+                MerkleNode {
+                    hash: H256::from(4),
+                    is_left: true,
+                },
+            ],
+        };
+        let serialized = serde_json::to_string(&proof).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"blockNumber":"0x1","blockHash":"0x0000000000000000000000000000000000000000000000000000000000000002","transactionsRoot":"0x0000000000000000000000000000000000000000000000000000000000000003","index":"0x1","proof":[{"hash":"0x0000000000000000000000000000000000000000000000000000000000000004","isLeft":true}]}"#
+        );
+        assert_eq!(serde_json::from_str::<TransactionProof>(&serialized).unwrap(), proof);
+    }
+}
@@ -1,14 +1,35 @@
-use util::H256;
+use util::{H256, U256};
 
 //TODO respone contain error
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct TxResponse {
     pub hash: H256,
     pub status: String,
+    /// Estimated height of the block that will include the transaction
+    #[serde(rename = "predictedHeight", skip_serializing_if = "Option::is_none")]
+    pub predicted_height: Option<U256>,
+    /// Height of the block that already includes the transaction
+    #[serde(rename = "blockNumber", skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<U256>,
 }
 
 impl TxResponse {
     pub fn new(hash: H256, status: String) -> Self {
-        TxResponse { hash, status }
+        TxResponse {
+            hash,
+            status,
+            predicted_height: None,
+            block_number: None,
+        }
+    }
+
+    pub fn set_predicted_height(mut self, height: u64) -> Self {
+        self.predicted_height = Some(U256::from(height));
+        self
+    }
+
+    pub fn set_block_number(mut self, height: u64) -> Self {
+        self.block_number = Some(U256::from(height));
+        self
     }
 }
@@ -37,6 +37,9 @@ display_help()
     echo
     echo "-k start with kafka"
     echo
+    echo "-i chain id shared by network and chain"
+    echo "   default value is '1'"
+    echo
     echo "-Q node id"
     echo
     exit 0
@@ -47,7 +50,7 @@ BINARY_DIR=$(readlink -f $(dirname $(readlink -f $0))/../..)
 export PATH=${PATH}:${BINARY_DIR}/bin
 
 # parse options
-while getopts 'a:l:n:m:d:t:h:w:H:W:Q:k' OPT; do
+while getopts 'a:l:n:m:d:t:h:w:H:W:Q:i:k' OPT; do
     case $OPT in
         a)
             ADMIN_ID="$OPTARG";;
@@ -73,6 +76,8 @@ while getopts 'a:l:n:m:d:t:h:w:H:W:Q:k' OPT; do
             WS_PORT="$OPTARG";;
         Q)
             NODE="$OPTARG";;
+        i)
+            CHAIN_ID="$OPTARG";;
         ?)
             display_help
     esac
@@ -96,6 +101,8 @@ SIZE=${#TMP}
 
 : ${IS_TEST:=false}
 
+: ${CHAIN_ID:=1}
+
 sed -i "s/tendermint/$CONSENSUS_NAME/g" ${BINARY_DIR}/bin/cita
 
 create_genesis(){
@@ -130,7 +137,7 @@ auth(){
 }
 
 network(){
-    python ${BINARY_DIR}/scripts/admintool/create_network_config.py ${CONFIG_DIR} ${1} $SIZE $IP_LIST
+    python ${BINARY_DIR}/scripts/admintool/create_network_config.py ${CONFIG_DIR} ${1} $SIZE $IP_LIST $CHAIN_ID
     mv ${CONFIG_DIR}/network.toml ${CONFIG_DIR}/node${1}/
 }
 
@@ -140,6 +147,7 @@ chain(){
         cp -rf resource ${CONFIG_DIR}/node${1}/
     fi
     cp -f ${BINARY_DIR}/scripts/admintool/chain_check_example.toml      ${CONFIG_DIR}/node${1}/chain.toml
+    echo "chain_id = $CHAIN_ID"                                         >> ${CONFIG_DIR}/node${1}/chain.toml
 }
 
 executor(){
@@ -181,7 +189,7 @@ node(){
     consensus $1
     chain $1
     executor  $1
-    python ${BINARY_DIR}/scripts/admintool/create_network_config.py ${CONFIG_DIR} 1 $SIZE $IP_LIST
+    python ${BINARY_DIR}/scripts/admintool/create_network_config.py ${CONFIG_DIR} 1 $SIZE $IP_LIST $CHAIN_ID
     mv ${CONFIG_DIR}/network.toml ${CONFIG_DIR}/node${1}/
     auth $1
     env $1
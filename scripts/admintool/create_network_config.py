@@ -17,6 +17,8 @@ def main():
         f.write("id_card = " + str(nid) + "\n")
         f.write("port = " + port + "\n")
         f.write("max_peer = " + str(size - 1) + "\n")
+        if len(sys.argv) > 5:
+            f.write("chain_id = " + sys.argv[5] + "\n")
         ids = range(size)
         ip_list = zip(ids, ip_list)
         del ip_list[nid]
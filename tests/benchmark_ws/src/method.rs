@@ -39,7 +39,7 @@ where
         let rpc = Call {
             jsonrpc: Some(Version::V2),
             method: method.to_string(),
-            id: Id::Str(request_id.clone()),
+            id: Some(Id::Str(request_id.clone())),
             params: Some(params),
         };
         let _ = serde_json::to_string(&rpc).map(|data| {